@@ -2,46 +2,91 @@ use anyhow::Result;
 
 use crate::{
     config::Settings,
-    feed_handler::FeedHandler,
-    feed_handler::Tob,
-    hft_strategy::HftStrategy,
+    feed_handler::{subscribed_markets, FeedHandler, Tob},
+    flatten::{cancel_all_resting, flatten_all, FlattenRx},
+    health::{spawn_cancel_on_disconnect, spawn_health_checker, CancelAllRx, HealthState},
+    heartbeat::Heartbeat,
+    hft_strategy::{HftStrategy, OrderSize, QuoteIntent},
+    live_params::SharedSettings,
+    loop_pacer::LoopPacer,
     market_selector::MarketSelector,
     market_selector::SelectedMarket,
-    paper_broker::{PaperBroker, Side},
+    paper_broker::{PaperBroker, Side, FLAT_QTY_EPS},
+    probe::ProbeScheduler,
+    rescan::{reply_all, RescanRx, ScanCounts},
     risk_engine::RiskEngine,
-    store::SqliteStore,
-    utils::now_ts,
+    shadow::ShadowBook,
+    store::{QuoteStateRow, ShadowIntentRow, SqliteStore},
+    utils::{mono_ts, now_ts, percentiles, round_dp, round_to_tick, tick_decimals},
 };
 
 use std::sync::Arc;
 use tokio::sync::watch;
 
-pub async fn run(settings: Settings, store: SqliteStore) -> Result<()> {
+pub async fn run(
+    settings: Settings,
+    store: SqliteStore,
+    mut rescan: RescanRx,
+    flatten: FlattenRx,
+    live: SharedSettings,
+) -> Result<()> {
     let feed = FeedHandler::new(settings.clone());
     let feed_state = feed.state();
-    let selector = MarketSelector::new(settings.clone(), store.clone(), feed_state.clone());
+    // Scanner writes run in the background so refresh ticks never delay the trade loop's.
+    let selector = MarketSelector::new(settings.clone(), store.background(), feed_state.clone());
 
     let (selected_tx, selected_rx) =
         watch::channel::<Arc<Vec<crate::market_selector::SelectedMarket>>>(Arc::new(Vec::new()));
 
-    // Start live feeds (WS + periodic orderbook polling).
-    feed.spawn(selected_rx.clone(), store.clone());
+    // API health checks run on their own cadence and report to runtime status; with
+    // SAFE_MODE=1 the broker also holds orders while they are stale.
+    let health = HealthState::new();
+    spawn_health_checker(settings.clone(), store.clone(), health.clone());
+
+    // CANCEL_ON_DISCONNECT: a sustained WS outage pulls every resting order.
+    let cancel_all = spawn_cancel_on_disconnect(&settings, store.clone(), feed.ws_link());
+
+    // Start live feeds (WS + periodic orderbook polling). The WS task is supervised.
+    feed.spawn(selected_rx.clone(), store.clone(), health.clone());
 
     // Scanner loop: refresh Gamma markets every N seconds and update watchlist selection.
     {
-        let store = store.clone();
+        let store = store.background();
         let selected_tx = selected_tx.clone();
         let refresh_secs = settings.market_refresh_secs;
         tokio::spawn(async move {
             let mut scan = tokio::time::interval(std::time::Duration::from_secs(refresh_secs));
             scan.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
             let mut last_tokens: Vec<String> = Vec::new();
+            let mut rescan_open = true;
             loop {
-                scan.tick().await;
+                // A manual rescan (dashboard) runs now and restarts the periodic timer.
+                let waiters = tokio::select! {
+                    _ = scan.tick() => Vec::new(),
+                    req = rescan.recv(), if rescan_open => match req {
+                        Some(w) => {
+                            scan.reset();
+                            log::info!("scanner.rescan_requested waiters={}", w.len());
+                            w
+                        }
+                        None => {
+                            rescan_open = false;
+                            continue;
+                        }
+                    },
+                };
                 store
                     .upsert_runtime_status("scanner", "ok", "running", None, now_ts())
                     .ok();
-                match selector.select().await {
+                let res = selector.select().await;
+                if !waiters.is_empty() {
+                    let reply = match &res {
+                        Ok(_) => latest_scan_counts(&store),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    reply_all(waiters, reply);
+                }
+                match res {
                     Ok(markets) => {
                         store
                             .upsert_runtime_status(
@@ -83,65 +128,235 @@ pub async fn run(settings: Settings, store: SqliteStore) -> Result<()> {
     }
 
     if settings.run_mode == "scanner" {
-        loop {
-            tokio::time::sleep(std::time::Duration::from_secs(3600)).await;
+        let reason = shutdown_signal(settings.max_runtime_secs).await;
+        store
+            .upsert_runtime_status(
+                "scanner",
+                "ok",
+                &format!("stopped ({reason})"),
+                None,
+                now_ts(),
+            )
+            .ok();
+        log::info!("shutdown reason={} run_mode=scanner", reason);
+        return Ok(());
+    }
+
+    run_paper_trader(
+        settings,
+        store,
+        feed_state,
+        selected_rx,
+        health,
+        flatten,
+        cancel_all,
+        live,
+    )
+    .await
+}
+
+/// Counts of the scan that just finished, as recorded by `MarketSelector::select`.
+fn latest_scan_counts(store: &SqliteStore) -> std::result::Result<ScanCounts, String> {
+    let snap = store
+        .fetch_latest_scanner_snapshot()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "no_scanner_snapshot".to_string())?;
+    let get = |k: &str| snap.get(k).and_then(|v| v.as_i64()).unwrap_or(0);
+    Ok(ScanCounts {
+        eligible: get("eligible_count"),
+        top: get("top_count"),
+    })
+}
+
+/// Startup precedence for the paper book. `PAPER_RESET_ON_START` and
+/// `PAPER_REHYDRATE_PORTFOLIO` are mutually exclusive (see `Settings::validate`);
+/// with neither set the broker starts empty but the stored history is kept.
+/// Returns the path taken: "reset", "rehydrate" or "fresh".
+pub fn restore_paper_state(
+    settings: &Settings,
+    store: &SqliteStore,
+    broker: &mut PaperBroker,
+) -> Result<&'static str> {
+    if settings.paper_reset_on_start {
+        store.clear_trading_state()?;
+        broker.orders.clear();
+        broker.positions.clear();
+        log::warn!("paper_state.reset_on_start sqlite={}", store.path());
+        Ok("reset")
+    } else if settings.paper_rehydrate_portfolio {
+        broker.positions = rehydrated_positions(store)?;
+        Ok("rehydrate")
+    } else {
+        Ok("fresh")
+    }
+}
+
+/// Latest stored position per market, skipping markets that are flat with no realized PnL.
+pub fn rehydrated_positions(
+    store: &SqliteStore,
+) -> Result<std::collections::HashMap<String, crate::paper_broker::Position>> {
+    let mut out = std::collections::HashMap::new();
+    for r in store.fetch_latest_positions(5000)? {
+        let mid = r
+            .get("market_id")
+            .and_then(|x| x.as_str())
+            .unwrap_or("")
+            .to_string();
+        if mid.is_empty() {
+            continue;
+        }
+        let qty = r.get("position").and_then(|x| x.as_f64()).unwrap_or(0.0);
+        let avg = r.get("avg_price").and_then(|x| x.as_f64()).unwrap_or(0.0);
+        let realized = r
+            .get("realized_pnl")
+            .and_then(|x| x.as_f64())
+            .unwrap_or(0.0);
+        if qty == 0.0 && realized == 0.0 {
+            continue;
+        }
+        out.insert(
+            mid,
+            crate::paper_broker::Position {
+                qty,
+                avg_price: avg,
+                realized_pnl: realized,
+            },
+        );
+    }
+    Ok(out)
+}
+
+/// Save each quoted market's requote memory. `last_quote_ts` holds `mono_ts`, so the
+/// quote time is stored as wall clock (`now` less its age) for the next process.
+fn persist_quote_state(
+    store: &SqliteStore,
+    now: f64,
+    mono: f64,
+    last_quote_ts: &std::collections::HashMap<String, f64>,
+    last_fair: &std::collections::HashMap<String, f64>,
+    last_imb_sign: &std::collections::HashMap<String, i32>,
+) -> Result<()> {
+    let rows: Vec<QuoteStateRow> = last_quote_ts
+        .iter()
+        .filter_map(|(mid, &quoted)| {
+            Some(QuoteStateRow {
+                market_id: mid.clone(),
+                ts: now - (mono - quoted).max(0.0),
+                fair: *last_fair.get(mid)?,
+                imb_sign: last_imb_sign.get(mid).copied().unwrap_or(0),
+            })
+        })
+        .collect();
+    if rows.is_empty() {
+        return Ok(());
+    }
+    store.upsert_quote_states(&rows)
+}
+
+/// Load the previous run's requote memory onto this run's monotonic clock, skipping
+/// markets last requoted more than `max_age_secs` ago. Returns the markets loaded.
+pub fn rehydrate_quote_state(
+    store: &SqliteStore,
+    max_age_secs: f64,
+    now: f64,
+    mono: f64,
+    last_quote_ts: &mut std::collections::HashMap<String, f64>,
+    last_fair: &mut std::collections::HashMap<String, f64>,
+    last_imb_sign: &mut std::collections::HashMap<String, i32>,
+) -> Result<usize> {
+    let mut n = 0;
+    for r in store.fetch_quote_states()? {
+        let age = (now - r.ts).max(0.0);
+        if age > max_age_secs || !r.fair.is_finite() {
+            continue;
         }
+        last_quote_ts.insert(r.market_id.clone(), mono - age);
+        last_fair.insert(r.market_id.clone(), r.fair);
+        last_imb_sign.insert(r.market_id, r.imb_sign);
+        n += 1;
+    }
+    Ok(n)
+}
+
+/// Text report for `--inspect-state`: the startup path the current config would take and
+/// the positions rehydration would load. Nothing is cleared or traded.
+pub fn inspect_state(settings: &Settings, store: &SqliteStore) -> Result<String> {
+    let path = if settings.paper_reset_on_start {
+        "reset"
+    } else if settings.paper_rehydrate_portfolio {
+        "rehydrate"
+    } else {
+        "fresh"
+    };
+    let positions = rehydrated_positions(store)?;
+    let mut ids: Vec<&String> = positions.keys().collect();
+    ids.sort();
+    let mut out = format!(
+        "startup path: {path}\nsqlite: {}\nstored positions: {}\n",
+        store.path(),
+        positions.len()
+    );
+    if path != "rehydrate" {
+        out.push_str("(not loaded at startup with the current config)\n");
+    }
+    out.push_str(&format!(
+        "{:<48} {:>12} {:>10} {:>12}\n",
+        "market_id", "qty", "avg_price", "realized"
+    ));
+    for id in ids {
+        let p = &positions[id];
+        out.push_str(&format!(
+            "{:<48} {:>12.4} {:>10.4} {:>12.4}\n",
+            id, p.qty, p.avg_price, p.realized_pnl
+        ));
     }
+    Ok(out)
+}
 
-    run_paper_trader(settings, store, feed_state, selected_rx).await
+/// Copy dashboard changes to the live params into the loop's settings and strategy.
+fn apply_live_params(
+    live: &SharedSettings,
+    settings: &mut Settings,
+    strat: &mut HftStrategy,
+) -> bool {
+    let live = live.read();
+    if !crate::live_params::apply(&live, settings) {
+        return false;
+    }
+    strat.apply_live_params(&live);
+    log::info!(
+        "settings.live_applied base_order_size={} mm_reprice_threshold={} max_inventory_usd={} mm_quote_width={}",
+        settings.base_order_size,
+        settings.mm_reprice_threshold,
+        settings.max_inventory_usd,
+        settings.mm_quote_width
+    );
+    true
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn run_paper_trader(
-    settings: Settings,
+    mut settings: Settings,
     store: SqliteStore,
     feed: crate::feed_handler::FeedState,
     mut selected_rx: watch::Receiver<Arc<Vec<SelectedMarket>>>,
+    health: HealthState,
+    mut flatten: FlattenRx,
+    mut cancel_all: CancelAllRx,
+    live: SharedSettings,
 ) -> Result<()> {
     let start_ts = now_ts();
     let mut broker = PaperBroker::new(settings.clone(), store.clone());
-    let strat = HftStrategy::new(settings.clone());
+    broker.set_health_gate(health.clone());
+    let mut strat = HftStrategy::new(settings.clone());
     let risk = RiskEngine::new(settings.clone());
 
-    if settings.paper_reset_on_start {
-        store.clear_trading_state().ok();
-        broker.orders.clear();
-        broker.positions.clear();
-        log::warn!("paper_state.reset_on_start sqlite={}", store.path());
-    } else if settings.paper_rehydrate_portfolio {
-        if let Ok(rows) = store.fetch_latest_positions(5000) {
-            for r in rows {
-                let mid = r
-                    .get("market_id")
-                    .and_then(|x| x.as_str())
-                    .unwrap_or("")
-                    .to_string();
-                if mid.is_empty() {
-                    continue;
-                }
-                let qty = r.get("position").and_then(|x| x.as_f64()).unwrap_or(0.0);
-                let avg = r.get("avg_price").and_then(|x| x.as_f64()).unwrap_or(0.0);
-                let realized = r
-                    .get("realized_pnl")
-                    .and_then(|x| x.as_f64())
-                    .unwrap_or(0.0);
-                if qty == 0.0 && realized == 0.0 {
-                    continue;
-                }
-                broker.positions.insert(
-                    mid,
-                    crate::paper_broker::Position {
-                        qty,
-                        avg_price: avg,
-                        realized_pnl: realized,
-                    },
-                );
-            }
-            log::info!(
-                "paper_state.rehydrated positions={}",
-                broker.positions.len()
-            );
-        }
-    }
+    let path = restore_paper_state(&settings, &store, &mut broker)?;
+    log::info!(
+        "paper_state.start path={} positions={}",
+        path,
+        broker.positions.len()
+    );
 
     // Per-market quote state.
     let mut last_quote_ts: std::collections::HashMap<String, f64> =
@@ -149,9 +364,35 @@ async fn run_paper_trader(
     let mut last_fair: std::collections::HashMap<String, f64> = std::collections::HashMap::new();
     let mut last_imb_sign: std::collections::HashMap<String, i32> =
         std::collections::HashMap::new();
+    if path == "rehydrate" {
+        let n = rehydrate_quote_state(
+            &store,
+            settings.quote_state_max_age_secs,
+            now_ts(),
+            mono_ts(),
+            &mut last_quote_ts,
+            &mut last_fair,
+            &mut last_imb_sign,
+        )?;
+        log::info!("paper_state.quote_state markets={}", n);
+    }
+    // Last book ts written to the tape per market (only new books are recorded).
+    let mut last_tape_ts: std::collections::HashMap<String, f64> = std::collections::HashMap::new();
+    // Selected markets the WS feed is subscribed to (MAX_WS_SUBSCRIPTIONS); only these are quoted.
+    // Seeded from the current selection so a watch that never changes again still quotes.
+    let mut quoting: Vec<SelectedMarket> = {
+        let selected = selected_rx.borrow().clone();
+        quotable_markets(&settings, selected.as_slice())
+    };
+    let mut shadow = ShadowBook::default();
+    let mut probe = ProbeScheduler::from_settings(&settings);
+    let mut heartbeat = Heartbeat::new(&settings.heartbeat_path);
 
     let mut loop_tick = tokio::time::interval(std::time::Duration::from_millis(settings.loop_ms));
     loop_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut pacer = settings
+        .adaptive_loop_enabled
+        .then(|| LoopPacer::new(settings.loop_ms, settings.loop_ms_min, settings.loop_ms_max));
 
     let mut snap_tick = tokio::time::interval(std::time::Duration::from_secs(1));
     snap_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
@@ -163,51 +404,195 @@ async fn run_paper_trader(
     let mut arb_tick = tokio::time::interval(std::time::Duration::from_secs(5));
     arb_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
-    loop {
-        tokio::select! {
-            _ = loop_tick.tick() => {
-                let now = now_ts();
-               let selected = selected_rx.borrow().clone(); // Arc clone (cheap)
-
-               let mut ctx = TraderCtx {
-                   settings: &settings,
-                   store: &store,
-                   risk: &risk,
-                   strat: &strat,
-                   broker: &mut broker,
-                   now,
-                   last_quote_ts: &mut last_quote_ts,
-                   last_fair: &mut last_fair,
-                   last_imb_sign: &mut last_imb_sign,
-               };
-
-               for m in selected.iter() {
-                    if let Some(tob) = feed.get(&m.market_id) {
-                       trade_one_market(&mut ctx, m, &tob)?;
-                    }
+    // Explicit WAL checkpoints are scheduled here but run on a blocking thread, one at a
+    // time, so a slow checkpoint never stalls a tick; 0 leaves WAL size to
+    // SQLITE_WAL_AUTOCHECKPOINT_PAGES.
+    let mut checkpoint_tick = tokio::time::interval(std::time::Duration::from_secs(
+        settings.sqlite_checkpoint_interval_secs.max(1),
+    ));
+    checkpoint_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    checkpoint_tick.reset();
+    let mut checkpoint_job: Option<tokio::task::JoinHandle<()>> = None;
+
+    let shutdown = shutdown_signal(settings.max_runtime_secs);
+    tokio::pin!(shutdown);
+    let mut flatten_open = true;
+    let mut cancel_all_open = true;
+
+    // Run the loop in a block so the session summary is written on error exits too.
+    let outcome: Result<&'static str> = async {
+        loop {
+            tokio::select! {
+                reason = &mut shutdown => {
+                   let selected = selected_rx.borrow().clone();
+                   shutdown_paper_trader(
+                       &store,
+                       &feed,
+                       &mut broker,
+                       selected.as_slice(),
+                       &settings.mark_source,
+                       reason,
+                   )?;
+                   log_eval(&settings, &feed, &broker, selected.as_slice());
+                   if settings.execution_mode == "shadow" {
+                       let (fills, pnl) = shadow.totals();
+                       log::info!("shadow.summary hypo_fills={} hypo_pnl={:.4}", fills, pnl);
+                   }
+                   return Ok(reason);
                 }
+                _ = loop_tick.tick() => {
+                    let work_start = std::time::Instant::now();
+                    let now = now_ts();
+                    let mono = mono_ts();
+                    heartbeat.touch(now, mono);
+                   let selected = selected_rx.borrow().clone(); // Arc clone (cheap)
+
+                   // The feed supervisor gave up: stop quoting against a frozen book.
+                   if let Some(why) = health.halted() {
+                       log::error!("trading halted: {}", why);
+                       shutdown_paper_trader(
+                           &store,
+                           &feed,
+                           &mut broker,
+                           selected.as_slice(),
+                           &settings.mark_source,
+                           "feed_halted",
+                       )?;
+                       return Ok("feed_halted");
+                   }
+                   apply_live_params(&live, &mut settings, &mut strat);
+
+                   let mut ctx = TraderCtx {
+                       settings: &settings,
+                       store: &store,
+                       risk: &risk,
+                       strat: &strat,
+                       broker: &mut broker,
+                       now,
+                       mono,
+                       last_quote_ts: &mut last_quote_ts,
+                       last_fair: &mut last_fair,
+                       last_imb_sign: &mut last_imb_sign,
+                       shadow: &mut shadow,
+                   };
+
+                   quote_markets(&mut ctx, &feed, &quoting, &mut last_tape_ts)?;
+                    if !probe.step(ctx.broker, &strat, &feed, &quoting, now, mono)?.is_empty() {
+                        let st = probe.stats();
+                        let msg = format!(
+                            "placed={} filled={} unfilled={} fill_rate={:.3} mean_fill_secs={:.3}",
+                            st.placed,
+                            st.filled,
+                            st.unfilled,
+                            st.fill_rate(),
+                            st.mean_fill_secs().unwrap_or(0.0)
+                        );
+                        store.upsert_runtime_status("probe", "ok", &msg, None, now).ok();
+                    }
+                    // Closed markets usually drop out of the selection; still pull their orders.
+                    cancel_closed_markets(&feed, ctx.broker)?;
 
-                // Very light heartbeat so the dashboard can surface errors quickly.
-                store.upsert_runtime_status("trader", "ok", "running", None, now).ok();
-            }
-            _ = snap_tick.tick() => {
-               let selected = selected_rx.borrow().clone();
-               persist_snapshots(&store, &feed, &broker, selected.as_slice())?;
-            }
-            _ = eval_tick.tick() => {
-               let selected = selected_rx.borrow().clone();
-               log_eval(&settings, &feed, &broker, selected.as_slice(), start_ts);
-               persist_telemetry_markdown(&store, &feed, &broker, selected.as_slice()).ok();
-            }
-            _ = arb_tick.tick() => {
-               let selected = selected_rx.borrow().clone();
-               try_event_basket_arb(&settings, &feed, &mut broker, selected.as_slice()).ok();
-            }
-            _ = selected_rx.changed() => {
-                // market list changed; next loop tick will react.
+                    // Very light heartbeat so the dashboard can surface errors quickly.
+                    store.upsert_runtime_status("trader", "ok", "running", None, now).ok();
+                    if let Some(p) = pacer.as_mut() {
+                        loop_tick.reset_after(p.record(work_start.elapsed()));
+                    }
+                }
+                _ = snap_tick.tick() => {
+                   let selected = selected_rx.borrow().clone();
+                   persist_snapshots(&store, &feed, &broker, selected.as_slice(), &settings.mark_source)?;
+                   persist_quote_state(&store, now_ts(), mono_ts(), &last_quote_ts, &last_fair, &last_imb_sign)?;
+                }
+                _ = eval_tick.tick() => {
+                   let selected = selected_rx.borrow().clone();
+                   log_eval(&settings, &feed, &broker, selected.as_slice());
+                   persist_telemetry_markdown(
+                       &store,
+                       &feed,
+                       &broker,
+                       selected.as_slice(),
+                       &settings.mark_source,
+                   )
+                   .ok();
+                }
+                _ = arb_tick.tick() => {
+                   let selected = selected_rx.borrow().clone();
+                   try_event_basket_arb(&settings, &feed, &mut broker, selected.as_slice()).ok();
+                }
+                _ = checkpoint_tick.tick(), if settings.sqlite_checkpoint_interval_secs > 0 => {
+                    if checkpoint_job.as_ref().is_some_and(|j| !j.is_finished()) {
+                        log::debug!("sqlite.checkpoint_skipped previous still running");
+                    } else {
+                        let store = store.clone();
+                        let truncate = settings.sqlite_checkpoint_truncate;
+                        checkpoint_job = Some(tokio::task::spawn_blocking(move || {
+                            match store.wal_checkpoint(truncate) {
+                                Ok(c) => log::debug!(
+                                    "sqlite.checkpoint busy={} log_frames={} checkpointed={}",
+                                    c.busy,
+                                    c.log_frames,
+                                    c.checkpointed_frames
+                                ),
+                                Err(e) => log::warn!("sqlite.checkpoint_failed {}", e),
+                            }
+                        }));
+                    }
+                }
+                req = flatten.recv(), if flatten_open => match req {
+                    Some(waiters) => {
+                        log::warn!("flatten.requested waiters={}", waiters.len());
+                        let res = flatten_all(&mut broker, &feed);
+                        let selected = selected_rx.borrow().clone();
+                        persist_snapshots(&store, &feed, &broker, selected.as_slice(), &settings.mark_source)?;
+                        crate::flatten::reply_all(waiters, res.map_err(|e| format!("{e:#}")));
+                    }
+                    None => flatten_open = false,
+                },
+                req = cancel_all.recv(), if cancel_all_open => match req {
+                    Some(reply) => {
+                        let n = cancel_all_resting(&mut broker)?;
+                        log::warn!("cancel_on_disconnect.cancelled orders={}", n);
+                        let _ = reply.send(n);
+                    }
+                    None => cancel_all_open = false,
+                },
+                _ = selected_rx.changed() => {
+                    // market list changed; next loop tick will react.
+                    let selected = selected_rx.borrow().clone();
+                    quoting = quotable_markets(&settings, selected.as_slice());
+                }
             }
         }
     }
+    .await;
+
+    if let Err(e) = persist_quote_state(
+        &store,
+        now_ts(),
+        mono_ts(),
+        &last_quote_ts,
+        &last_fair,
+        &last_imb_sign,
+    ) {
+        log::warn!("quote state not saved: {e:#}");
+    }
+    let (reason, error) = match &outcome {
+        Ok(reason) => (*reason, None),
+        Err(e) => ("error", Some(format!("{e:#}"))),
+    };
+    if let Err(e) = write_session_summary(
+        SESSION_SUMMARY_PATH,
+        &settings,
+        &store,
+        &feed,
+        &broker,
+        start_ts,
+        reason,
+        error.as_deref(),
+    ) {
+        log::warn!("session summary not written: {e:#}");
+    }
+    outcome.map(|_| ())
 }
 
 fn try_event_basket_arb(
@@ -294,13 +679,9 @@ fn log_eval(
     feed: &crate::feed_handler::FeedState,
     broker: &PaperBroker,
     selected: &[SelectedMarket],
-    start_ts: f64,
 ) {
     let now = now_ts();
-    let elapsed_h = ((now - start_ts).max(1.0)) / 3600.0;
-
-    let fills = broker.counters.fills as f64;
-    let trades_per_hour = fills / elapsed_h;
+    let mark_source = settings.mark_source.as_str();
 
     let open_orders: Vec<_> = broker
         .orders
@@ -336,8 +717,7 @@ fn log_eval(
     // Average spread/edge across selected markets.
     let mut spread_bps_sum = 0.0;
     let mut spread_n = 0.0;
-    let mut lag_ms_sum = 0.0;
-    let mut lag_n = 0.0;
+    let mut lags_ms: Vec<f64> = vec![];
     for m in selected {
         if let Some(tob) = feed.get(&m.market_id) {
             if let (Some(b), Some(a)) = (tob.best_bid, tob.best_ask) {
@@ -349,8 +729,7 @@ fn log_eval(
                     }
                 }
             }
-            lag_ms_sum += (now - tob.ts).max(0.0) * 1000.0;
-            lag_n += 1.0;
+            lags_ms.push((now - tob.ts).max(0.0) * 1000.0);
         }
     }
     let avg_spread_bps = if spread_n > 0.0 {
@@ -358,97 +737,190 @@ fn log_eval(
     } else {
         0.0
     };
-    let avg_lag_ms = if lag_n > 0.0 { lag_ms_sum / lag_n } else { 0.0 };
+    let avg_lag_ms = if lags_ms.is_empty() {
+        0.0
+    } else {
+        lags_ms.iter().sum::<f64>() / lags_ms.len() as f64
+    };
+    let (p50_lag_ms, p99_lag_ms) = match percentiles(&mut lags_ms, &[50.0, 99.0])[..] {
+        [p50, p99] => (p50, p99),
+        _ => (0.0, 0.0),
+    };
 
     let total_r = broker.realized_pnl_total();
     let (total_u, total_p) = {
         let mut u = 0.0;
         for (mid, p) in broker.positions.iter() {
-            let mark = feed.get(mid).and_then(|t| t.mid()).unwrap_or(p.avg_price);
+            let mark = position_mark(feed, mark_source, mid, p);
             u += (mark - p.avg_price) * p.qty;
         }
         (u, u + total_r)
     };
 
     log::info!(
-         "eval pnl_total=${:.2} pnl_u=${:.2} pnl_r=${:.2} fills={} tph={:.1} open_orders={} time_at_touch={:.2} avg_spread_bps={:.1} cost_bps={:.1} avg_feed_lag_ms={:.1} churn/h={:.1}",
+         "eval pnl_total=${:.2} pnl_u=${:.2} pnl_r=${:.2} fills={} tph={:.1} open_orders={} time_at_touch={:.2} avg_spread_bps={:.1} cost_bps={:.1} avg_feed_lag_ms={:.1} p50_feed_lag_ms={:.1} p99_feed_lag_ms={:.1} churn/h={:.1}",
          total_p,
          total_u,
          total_r,
          broker.counters.fills,
-         trades_per_hour,
+         broker.fill_rate_per_hour(),
          open_orders.len(),
          time_at_touch,
          avg_spread_bps,
          settings.cost_bps(),
          avg_lag_ms,
-         (broker.counters.orders_cancelled as f64) / elapsed_h
+         p50_lag_ms,
+         p99_lag_ms,
+         broker.cancel_rate_per_hour()
      );
 }
 
-struct TraderCtx<'a> {
-    settings: &'a Settings,
-    store: &'a SqliteStore,
-    risk: &'a RiskEngine,
-    strat: &'a HftStrategy,
-    broker: &'a mut PaperBroker,
-    now: f64,
-    last_quote_ts: &'a mut std::collections::HashMap<String, f64>,
-    last_fair: &'a mut std::collections::HashMap<String, f64>,
-    last_imb_sign: &'a mut std::collections::HashMap<String, i32>,
+pub(crate) struct TraderCtx<'a> {
+    pub(crate) settings: &'a Settings,
+    pub(crate) store: &'a SqliteStore,
+    pub(crate) risk: &'a RiskEngine,
+    pub(crate) strat: &'a HftStrategy,
+    pub(crate) broker: &'a mut PaperBroker,
+    /// Wall clock (`now_ts`, or tape time in replay): persisted timestamps only.
+    pub(crate) now: f64,
+    /// Monotonic clock (`mono_ts`, or tape time in replay): all interval math.
+    pub(crate) mono: f64,
+    /// Last requote per market, on the `mono` clock.
+    pub(crate) last_quote_ts: &'a mut std::collections::HashMap<String, f64>,
+    pub(crate) last_fair: &'a mut std::collections::HashMap<String, f64>,
+    pub(crate) last_imb_sign: &'a mut std::collections::HashMap<String, i32>,
+    /// Hypothetical book; only used when `EXECUTION_MODE=shadow`.
+    pub(crate) shadow: &'a mut ShadowBook,
+}
+
+/// Selected markets the feed is subscribed to. Markets past the WS subscription
+/// cap have no live book, so they are skipped (with a warning) instead of quoted.
+fn quotable_markets(settings: &Settings, selected: &[SelectedMarket]) -> Vec<SelectedMarket> {
+    let (subscribed, dropped) = subscribed_markets(selected, settings.max_ws_subscriptions);
+    if dropped > 0 {
+        let skipped: Vec<&str> = selected
+            .iter()
+            .filter(|m| !subscribed.iter().any(|s| s.market_id == m.market_id))
+            .map(|m| m.market_id.as_str())
+            .collect();
+        log::warn!(
+            "trader skipping {} unsubscribed markets (MAX_WS_SUBSCRIPTIONS={}): {}",
+            skipped.len(),
+            settings.max_ws_subscriptions,
+            skipped.join(",")
+        );
+    }
+    subscribed
+}
+
+fn quote_markets(
+    ctx: &mut TraderCtx<'_>,
+    feed: &crate::feed_handler::FeedState,
+    markets: &[SelectedMarket],
+    last_tape_ts: &mut std::collections::HashMap<String, f64>,
+) -> Result<()> {
+    for m in markets {
+        // Drained every tick so the buffer only ever holds prints since our last look.
+        let prints = feed.drain_trades(&m.market_id);
+        if ctx.settings.paper_fill_queue_model {
+            ctx.broker.note_trades(&m.market_id, &prints);
+            if let Some(levels) = feed.get_book(&m.market_id) {
+                ctx.broker.note_book(&m.market_id, levels);
+            }
+        }
+        if let Some(tob) = feed.get(&m.market_id) {
+            if ctx.settings.tape_record_enabled {
+                record_tape(ctx.store, last_tape_ts, &m.market_id, &tob);
+            }
+            if !tob.closed
+                && !feed.is_stabilized(&m.market_id, ctx.settings.feed_stabilize_min_samples)
+            {
+                // Just (re)subscribed: the update rate is not yet a usable estimate.
+                continue;
+            }
+            let reference = ctx
+                .strat
+                .reference_for(&m.market_id)
+                .and_then(|(r, beta)| feed.get(r).map(|t| (t, beta)));
+            trade_one_market(ctx, m, &tob, reference.as_ref().map(|(t, b)| (t, *b)))?;
+        }
+    }
+    Ok(())
 }
 
-fn trade_one_market(ctx: &mut TraderCtx<'_>, m: &SelectedMarket, tob: &Tob) -> Result<()> {
+pub(crate) fn trade_one_market(
+    ctx: &mut TraderCtx<'_>,
+    m: &SelectedMarket,
+    tob: &Tob,
+    reference: Option<(&Tob, f64)>,
+) -> Result<()> {
+    if tob.closed {
+        // Market closed: nothing can rest or fill here anymore.
+        cancel_all_open_for_market(ctx.broker, &m.market_id)?;
+        return Ok(());
+    }
     let (Some(bid), Some(ask)) = (tob.best_bid, tob.best_ask) else {
         return Ok(());
     };
     if ask <= bid {
         return Ok(());
     }
+    let is_active_market = tob.updates_ewma_per_min >= ctx.settings.min_updates_min;
+    if let Some(reason) = ctx.risk.feed_lag(tob, ctx.now, is_active_market) {
+        // Exits included: a stop or target priced off a stale touch is not acted on.
+        stand_down(ctx, &m.market_id, reason)?;
+        return Ok(());
+    }
+    if exit_position(ctx, &m.market_id, tob, (bid, ask))? {
+        return Ok(());
+    }
 
     let mid = 0.5 * (ask + bid);
     if !mid.is_finite() || mid <= 0.0 {
         return Ok(());
     }
+    if !ctx.settings.mid_in_tradeable_band(mid) {
+        // Near 0/1 the edge is thin and gap risk high: no quotes here.
+        cancel_all_open_for_market(ctx.broker, &m.market_id)?;
+        return Ok(());
+    }
+    if tob.bid_depth_n.min(tob.ask_depth_n) < ctx.settings.min_depth_to_quote {
+        // Liquid at selection but thin now: pause until the book refills.
+        cancel_all_open_for_market(ctx.broker, &m.market_id)?;
+        ctx.store
+            .upsert_runtime_status("risk", "warn", "thin_book", Some(&m.market_id), ctx.now)
+            .ok();
+        return Ok(());
+    }
 
     let spread_bps = ((ask - bid) / mid) * 10_000.0;
     let min_profitable_spread_bps = 1.5 * ctx.settings.cost_bps();
 
-    let total_depth = tob.bid_depth_5 + tob.ask_depth_5;
-    let imbalance = if total_depth > 0.0 {
-        (tob.bid_depth_5 - tob.ask_depth_5) / total_depth
-    } else {
-        0.0
-    };
-
-    let is_active_market = tob.updates_ewma_per_min >= ctx.settings.min_updates_min;
+    let imbalance = tob.imbalance();
 
     let decision = ctx
         .risk
         .can_quote(tob, ctx.now, is_active_market, min_profitable_spread_bps);
     if !decision.ok {
         // Pull orders if we can't trust the feed / risk says no.
-        cancel_all_open_for_market(ctx.broker, &m.market_id)?;
-        ctx.store
-            .upsert_runtime_status(
-                "risk",
-                "warn",
-                decision.reason.unwrap_or("reject"),
-                Some(&m.market_id),
-                ctx.now,
-            )
-            .ok();
+        stand_down(ctx, &m.market_id, decision.reason.unwrap_or("reject"))?;
         return Ok(());
     }
 
     let inv_qty = ctx.broker.position_qty(&m.market_id);
-    let (fair, fair_source) = match ctx.strat.compute_fair(tob, tob.last_trade_ema) {
+    let (fair, fair_source) = match ctx.strat.compute_fair(tob, tob.last_trade_ema, reference) {
         Some(x) => x,
         None => return Ok(()),
     };
+    if ctx.settings.execution_mode == "shadow" {
+        shadow_one_market(ctx, m, tob, (bid, ask), imbalance, (fair, fair_source));
+        return Ok(());
+    }
+    ctx.broker.note_fair(&m.market_id, fair);
+    ctx.broker.note_tick(&m.market_id, ctx.strat.tick_for(tob));
 
     // Quote update conditions.
-    let prev_ts = ctx.last_quote_ts.get(&m.market_id).copied().unwrap_or(0.0);
+    let prev_ts = ctx.last_quote_ts.get(&m.market_id).copied();
     let prev_fair = ctx.last_fair.get(&m.market_id).copied().unwrap_or(fair);
     let prev_sign = ctx.last_imb_sign.get(&m.market_id).copied().unwrap_or(0);
     let sign = if imbalance > 0.05 {
@@ -459,14 +931,15 @@ fn trade_one_market(ctx: &mut TraderCtx<'_>, m: &SelectedMarket, tob: &Tob) -> R
         0
     };
 
-    let should_requote = (ctx.now - prev_ts) >= 0.10
+    let should_requote = prev_ts.is_none_or(|p| ctx.mono - p >= 0.10)
         && ((fair - prev_fair).abs() >= ctx.settings.mm_reprice_threshold || sign != prev_sign);
 
     // Always simulate fills, even if we don't requote this tick.
     let activity_score = (0.5 + (tob.updates_ewma_per_min / 10.0)).clamp(0.1, 5.0);
-    let _fills = ctx
+    let fills = ctx
         .broker
         .simulate_fills_for_market(&m.market_id, tob, activity_score)?;
+    ctx.strat.note_maker_fills(ctx.mono, fair, &fills);
 
     // Snipe mode (internal microstructure only): imbalance spike.
     if imbalance.abs() > 0.3 && spread_bps >= min_profitable_spread_bps {
@@ -477,30 +950,22 @@ fn trade_one_market(ctx: &mut TraderCtx<'_>, m: &SelectedMarket, tob: &Tob) -> R
         };
         let px = if side == Side::Buy { ask } else { bid };
         let sz = (ctx.settings.base_order_size * 0.5).max(ctx.settings.base_order_size.min(1.0));
-        let _ = ctx
-            .broker
-            .execute_ioc(&m.market_id, side, px, sz, "snipe", tob)?;
+        let sz = ctx
+            .risk
+            .check_portfolio(ctx.broker)
+            .allowed_size(side, inv_qty, sz, px);
+        if sz > 0.0 {
+            let _ = ctx
+                .broker
+                .execute_ioc(&m.market_id, side, px, sz, "snipe", tob)?;
+        }
     }
 
+    let size = ctx.strat.order_size(m.liquidity_usd, fair);
+
     if !should_requote {
         // Still persist quote telemetry (helps dashboard explain decisions).
-        ctx.store
-            .insert_quote_snapshot(
-                ctx.now,
-                &m.market_id,
-                m.event_id.as_deref().unwrap_or("event:unknown"),
-                tob.best_bid,
-                tob.best_ask,
-                Some(mid),
-                Some(fair),
-                fair_source,
-                inv_qty,
-                ask - bid,
-                0.0,
-                None,
-                None,
-            )
-            .ok();
+        record_quote_snapshot(ctx, m, tob, fair, fair_source, inv_qty, (None, None), size);
         return Ok(());
     }
 
@@ -513,22 +978,63 @@ fn trade_one_market(ctx: &mut TraderCtx<'_>, m: &SelectedMarket, tob: &Tob) -> R
     )?;
 
     // Compute and place grid.
-    let intents = ctx
-        .strat
-        .quote_grid(fair, inv_qty, imbalance, tob.updates_ewma_per_min);
+    let intents = ctx.strat.quotes(
+        fair,
+        inv_qty,
+        ctx.strat.inventory_target_for(&m.market_id),
+        imbalance,
+        tob,
+    );
     let mut target_bid: Option<f64> = None;
     let mut target_ask: Option<f64> = None;
+    let portfolio = ctx.risk.check_portfolio(ctx.broker);
+    if let Some(reason) = portfolio.capped_reason() {
+        ctx.store
+            .upsert_runtime_status("risk", "warn", reason, Some(&m.market_id), ctx.now)
+            .ok();
+    }
+    if portfolio.blocks_new_market(inv_qty) {
+        // A resting quote here would open one market too many if it filled.
+        cancel_all_open_for_market(ctx.broker, &m.market_id)?;
+    }
+
+    let improved = ctx.strat.improved_touch(
+        bid,
+        ask,
+        fair,
+        ctx.strat.tick_for(tob),
+        min_profitable_spread_bps,
+    );
+
+    let mut feasible: Vec<QuoteIntent> = Vec::with_capacity(intents.len());
+    for mut qi in intents {
+        qi.size = size.qty;
 
-    for qi in intents {
         // Profitability: don't quote inside the profitable spread band.
         // (Maker capture needs room for slippage/latency modeled in paper).
-        let allow = match qi.side {
-            Side::Buy => qi.price <= bid,
-            Side::Sell => qi.price >= ask,
+        // JOIN_OR_IMPROVE may pull a level at/inside the touch one tick in front of it.
+        let allow = match (qi.side, improved) {
+            (Side::Buy, Some((improved_bid, _))) if qi.price >= bid => {
+                qi.price = improved_bid;
+                true
+            }
+            (Side::Sell, Some((_, improved_ask))) if qi.price <= ask => {
+                qi.price = improved_ask;
+                true
+            }
+            (Side::Buy, _) => qi.price <= bid,
+            (Side::Sell, _) => qi.price >= ask,
         };
         if !allow {
             continue;
         }
+        // Several levels can collapse onto the improved price; one order is enough.
+        if feasible
+            .iter()
+            .any(|f| f.side == qi.side && f.price == qi.price)
+        {
+            continue;
+        }
 
         // Inventory guardrail.
         if qi.side == Side::Buy && (inv_qty + qi.size) > ctx.settings.max_inventory_usd {
@@ -537,56 +1043,478 @@ fn trade_one_market(ctx: &mut TraderCtx<'_>, m: &SelectedMarket, tob: &Tob) -> R
         if qi.side == Side::Sell && (inv_qty - qi.size) < -ctx.settings.max_inventory_usd {
             continue;
         }
+        // Whole-book exposure: at a cap only the position-reducing part goes out.
+        qi.size = portfolio.allowed_size(qi.side, inv_qty, qi.size, qi.price);
+        if qi.size <= 0.0 {
+            continue;
+        }
+        feasible.push(qi);
+    }
 
+    // REQUIRE_TWO_SIDED: a side suppressed above (profitability, inventory near a cap,
+    // exposure) means no quote at all, and any lone side still resting is pulled.
+    let two_sided = feasible.iter().any(|q| q.side == Side::Buy)
+        && feasible.iter().any(|q| q.side == Side::Sell);
+    if ctx.settings.require_two_sided && !two_sided {
+        cancel_all_open_for_market(ctx.broker, &m.market_id)?;
+        ctx.store
+            .upsert_runtime_status(
+                "risk",
+                "warn",
+                "two_sided_required",
+                Some(&m.market_id),
+                ctx.now,
+            )
+            .ok();
+        feasible.clear();
+    }
+
+    for qi in feasible {
         // Avoid duplicate prices on same side.
         if has_open_order_at(ctx.broker, &m.market_id, qi.side, qi.price) {
             continue;
         }
+        // Global resting-order ceiling: stop adding levels rather than collect rejections.
+        if ctx.broker.open_order_capacity() == 0 {
+            ctx.store
+                .upsert_runtime_status(
+                    "risk",
+                    "warn",
+                    "max_open_orders_global",
+                    Some(&m.market_id),
+                    ctx.now,
+                )
+                .ok();
+            break;
+        }
 
-        let oid = ctx
-            .broker
-            .place_limit(&m.market_id, qi.side, qi.price, qi.size, "mm")?;
-        let _ = oid;
+        let ttl = ctx.settings.mm_order_ttl_secs;
+        if ttl > 0.0 {
+            ctx.broker.place_gtd(
+                &m.market_id,
+                qi.side,
+                qi.price,
+                qi.size,
+                "mm",
+                ctx.now + ttl,
+            )?;
+        } else {
+            ctx.broker
+                .place_limit(&m.market_id, qi.side, qi.price, qi.size, "mm")?;
+        }
         match qi.side {
             Side::Buy => target_bid.get_or_insert(qi.price),
             Side::Sell => target_ask.get_or_insert(qi.price),
         };
     }
 
+    record_quote_snapshot(
+        ctx,
+        m,
+        tob,
+        fair,
+        fair_source,
+        inv_qty,
+        (target_bid, target_ask),
+        size,
+    );
+
+    ctx.last_quote_ts.insert(m.market_id.clone(), ctx.mono);
+    ctx.last_fair.insert(m.market_id.clone(), fair);
+    ctx.last_imb_sign.insert(m.market_id.clone(), sign);
+
+    Ok(())
+}
+
+/// Shadow evaluation: at most one divergence log line per market per this many seconds.
+const SHADOW_DIVERGENCE_LOG_SECS: f64 = 60.0;
+
+/// Shadow evaluation: work out the quotes the strategy wants against the live book,
+/// score them on the hypothetical book and record both. The broker is never touched.
+fn shadow_one_market(
+    ctx: &mut TraderCtx<'_>,
+    m: &SelectedMarket,
+    tob: &Tob,
+    (bid, ask): (f64, f64),
+    imbalance: f64,
+    (fair, fair_source): (f64, &str),
+) {
+    let inv_qty = ctx.shadow.position(&m.market_id);
+    let size = ctx.strat.order_size(m.liquidity_usd, fair);
+    let tick = ctx.strat.tick_for(tob);
+    let intents = ctx.strat.quotes(
+        fair,
+        inv_qty,
+        ctx.strat.inventory_target_for(&m.market_id),
+        imbalance,
+        tob,
+    );
+    let (mut target_bid, mut target_ask) = (None, None);
+    for qi in intents {
+        // Same filters as the paper path: never inside the book, inside inventory limits.
+        match qi.side {
+            Side::Buy
+                if qi.price <= bid && inv_qty + size.qty <= ctx.settings.max_inventory_usd =>
+            {
+                target_bid.get_or_insert(round_to_tick(qi.price, tick));
+            }
+            Side::Sell
+                if qi.price >= ask && inv_qty - size.qty >= -ctx.settings.max_inventory_usd =>
+            {
+                target_ask.get_or_insert(round_to_tick(qi.price, tick));
+            }
+            _ => {}
+        }
+    }
+    if ctx.settings.require_two_sided && (target_bid.is_none() || target_ask.is_none()) {
+        (target_bid, target_ask) = (None, None);
+    }
+
+    let step = ctx.shadow.step(
+        &m.market_id,
+        (bid, ask),
+        (tob.touch_size(true), tob.touch_size(false)),
+        (target_bid, target_ask),
+        size.qty,
+    );
+    let mid = 0.5 * (bid + ask);
+    let divergence = fair - mid;
+    let flagged = divergence.abs() >= ctx.settings.shadow_divergence_flag;
+    if flagged
+        && ctx
+            .shadow
+            .log_due(&m.market_id, ctx.mono, SHADOW_DIVERGENCE_LOG_SECS)
+    {
+        log::info!(
+            "shadow.divergence market={} fair={:.4} mid={:.4} div={:+.4} source={}",
+            m.market_id,
+            fair,
+            mid,
+            divergence,
+            fair_source
+        );
+    }
+    let record = step.fills > 0
+        || ctx.shadow.record_due(
+            &m.market_id,
+            ctx.mono,
+            ctx.settings.shadow_record_interval_secs,
+        );
+    if !record {
+        return;
+    }
+    ctx.store
+        .insert_shadow_intent(&ShadowIntentRow {
+            ts: ctx.now,
+            market_id: &m.market_id,
+            best_bid: bid,
+            best_ask: ask,
+            mid,
+            fair,
+            fair_source,
+            divergence,
+            flagged,
+            target_bid,
+            target_ask,
+            size: size.qty,
+            hypo_fills: step.fills,
+            hypo_position: step.position,
+            hypo_pnl: step.pnl,
+        })
+        .ok();
+    record_quote_snapshot(
+        ctx,
+        m,
+        tob,
+        fair,
+        fair_source,
+        step.position,
+        (target_bid, target_ask),
+        size,
+    );
+}
+
+/// Persist quote telemetry. Book and target prices are stored on the market's tick grid;
+/// mid and fair are off-grid, so they keep one decimal more than the display precision.
+#[allow(clippy::too_many_arguments)]
+fn record_quote_snapshot(
+    ctx: &TraderCtx<'_>,
+    m: &SelectedMarket,
+    tob: &Tob,
+    fair: f64,
+    fair_source: &str,
+    inv_qty: f64,
+    (target_bid, target_ask): (Option<f64>, Option<f64>),
+    size: OrderSize,
+) {
+    let tick = ctx.strat.tick_for(tob);
+    let on_grid = |p: Option<f64>| p.map(|p| round_to_tick(p, tick));
+    let dp = tick_decimals(tick).max(ctx.settings.price_decimals()) + 1;
+    let (bid, ask) = (on_grid(tob.best_bid), on_grid(tob.best_ask));
+    let mid = tob.mid().map(|x| round_dp(x, dp));
+    let width = match (bid, ask) {
+        (Some(b), Some(a)) => round_to_tick(a - b, tick),
+        _ => 0.0,
+    };
     ctx.store
         .insert_quote_snapshot(
             ctx.now,
             &m.market_id,
-            m.event_id.as_deref().unwrap_or("event:unknown"),
-            tob.best_bid,
-            tob.best_ask,
-            Some(mid),
-            Some(fair),
+            m.event_id.as_deref(),
+            bid,
+            ask,
+            mid,
+            Some(round_dp(fair, dp)),
             fair_source,
             inv_qty,
-            ask - bid,
+            width,
             0.0,
-            target_bid,
-            target_ask,
+            on_grid(target_bid),
+            on_grid(target_ask),
+            Some(size.qty),
+            Some(size.source),
         )
         .ok();
+}
 
-    ctx.last_quote_ts.insert(m.market_id.clone(), ctx.now);
-    ctx.last_fair.insert(m.market_id.clone(), fair);
-    ctx.last_imb_sign.insert(m.market_id.clone(), sign);
+/// Append the book the trader is about to act on to the `tape` table (for replay).
+fn record_tape(
+    store: &SqliteStore,
+    last_tape_ts: &mut std::collections::HashMap<String, f64>,
+    market_id: &str,
+    tob: &Tob,
+) {
+    if last_tape_ts.get(market_id).copied() == Some(tob.ts) {
+        return;
+    }
+    let Ok(payload) = serde_json::to_value(tob) else {
+        return;
+    };
+    if store
+        .insert_tape(tob.ts, market_id, "tob", &payload)
+        .is_ok()
+    {
+        last_tape_ts.insert(market_id.to_string(), tob.ts);
+    }
+}
+
+/// Resolves when the bot should stop: `max_runtime_secs` elapsed (if > 0) or Ctrl-C.
+async fn shutdown_signal(max_runtime_secs: f64) -> &'static str {
+    let deadline = async {
+        if max_runtime_secs > 0.0 {
+            tokio::time::sleep(std::time::Duration::from_secs_f64(max_runtime_secs)).await;
+        } else {
+            std::future::pending::<()>().await;
+        }
+    };
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    tokio::select! {
+        _ = deadline => "max_runtime",
+        _ = ctrl_c => "ctrl_c",
+    }
+}
+
+/// Clean stop: pull every open order, then write a final position/PnL snapshot.
+fn shutdown_paper_trader(
+    store: &SqliteStore,
+    feed: &crate::feed_handler::FeedState,
+    broker: &mut PaperBroker,
+    selected: &[SelectedMarket],
+    mark_source: &str,
+    reason: &str,
+) -> Result<()> {
+    let mut markets: Vec<String> = broker
+        .orders
+        .values()
+        .filter(|o| o.status == "open")
+        .map(|o| o.market_id.clone())
+        .collect();
+    markets.sort();
+    markets.dedup();
+    for market_id in &markets {
+        cancel_all_open_for_market(broker, market_id)?;
+    }
+    broker.flush_pending_cancels()?;
+    broker.flush_dust_fills()?;
+    let still_open = broker
+        .orders
+        .values()
+        .filter(|o| o.status == "open")
+        .count();
+
+    persist_snapshots(store, feed, broker, selected, mark_source)?;
+    store
+        .upsert_runtime_status(
+            "trader",
+            if still_open == 0 { "ok" } else { "warn" },
+            &format!("stopped ({reason})"),
+            Some(&format!("uncancelled_orders={still_open}")),
+            now_ts(),
+        )
+        .ok();
+    log::info!(
+        "shutdown reason={} markets_cancelled={} uncancelled_orders={} fills={} pnl_r=${:.2}",
+        reason,
+        markets.len(),
+        still_open,
+        broker.counters.fills,
+        broker.realized_pnl_total()
+    );
+    Ok(())
+}
+
+const SESSION_SUMMARY_PATH: &str = "ops/telemetry/session_summary.json";
 
+/// Final per-session summary (PnL, activity counters, positions, uptime).
+/// Best-effort: called on every trader exit, including error exits.
+#[allow(clippy::too_many_arguments)]
+fn write_session_summary(
+    path: &str,
+    settings: &Settings,
+    store: &SqliteStore,
+    feed: &crate::feed_handler::FeedState,
+    broker: &PaperBroker,
+    start_ts: f64,
+    reason: &str,
+    error: Option<&str>,
+) -> Result<()> {
+    let now = now_ts();
+    let mark_source = settings.mark_source.as_str();
+    let mut total_u = 0.0;
+    let mut positions: Vec<serde_json::Value> = vec![];
+    let mut mids: Vec<&String> = broker.positions.keys().collect();
+    mids.sort();
+    for mid in mids {
+        let p = &broker.positions[mid];
+        let mark = position_mark(feed, mark_source, mid, p);
+        let u = (mark - p.avg_price) * p.qty;
+        total_u += u;
+        positions.push(serde_json::json!({
+            "market_id": mid,
+            "qty": p.qty,
+            "avg_price": p.avg_price,
+            "mark": mark,
+            "unrealized": u,
+            "realized": p.realized_pnl,
+        }));
+    }
+    let total_r = broker.realized_pnl_total();
+    let c = &broker.counters;
+    let summary = serde_json::json!({
+        "generated_ts": now,
+        "start_ts": start_ts,
+        "uptime_secs": (now - start_ts).max(0.0),
+        "exit_reason": reason,
+        "error": error,
+        "run_mode": settings.run_mode,
+        "trade_mode": settings.trade_mode,
+        "execution_mode": settings.execution_mode,
+        "sqlite_path": store.path(),
+        "pnl": {
+            "total": total_u + total_r,
+            "realized": total_r,
+            "unrealized": total_u,
+        },
+        "fills": c.fills,
+        "filled_qty": c.filled_qty,
+        "orders_placed": c.orders_placed,
+        "orders_cancelled": c.orders_cancelled,
+        "cancel_failures": c.cancel_failures,
+        "rejected_orders": c.rejected_orders,
+        "positions": positions,
+    });
+
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(dir).ok();
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&summary)?)?;
     Ok(())
 }
 
+/// Mark for a held position per `MARK_SOURCE` (see `Tob::mark`), else its avg price.
+fn position_mark(
+    feed: &crate::feed_handler::FeedState,
+    mark_source: &str,
+    market_id: &str,
+    p: &crate::paper_broker::Position,
+) -> f64 {
+    feed.get(market_id)
+        .and_then(|t| t.mark(mark_source))
+        .unwrap_or(p.avg_price)
+}
+
 fn has_open_order_at(broker: &PaperBroker, market_id: &str, side: Side, price: f64) -> bool {
     broker.orders.values().any(|o| {
         o.status == "open"
+            && !broker.cancel_pending(&o.order_id)
             && o.market_id == market_id
             && o.side == side
             && (o.price - price).abs() < 1e-12
     })
 }
 
+/// Pull the market's orders and surface why on the risk status.
+fn stand_down(ctx: &mut TraderCtx<'_>, market_id: &str, reason: &str) -> Result<()> {
+    cancel_all_open_for_market(ctx.broker, market_id)?;
+    ctx.store
+        .upsert_runtime_status("risk", "warn", reason, Some(market_id), ctx.now)
+        .ok();
+    Ok(())
+}
+
+/// `STOP_LOSS_PCT` / `TAKE_PROFIT_PCT`: when the risk engine calls an exit for this
+/// market's position, cancel its quotes and flatten with one IOC at the touch (sized
+/// to the size resting there; deeper levels are at worse prices). Only an exit that
+/// fills starts the `EXIT_DEBOUNCE_SECS` wait. True when an exit fired; the market is
+/// not quoted this tick.
+fn exit_position(
+    ctx: &mut TraderCtx<'_>,
+    market_id: &str,
+    tob: &Tob,
+    (bid, ask): (f64, f64),
+) -> Result<bool> {
+    let Some(pos) = ctx.broker.positions.get(market_id).cloned() else {
+        return Ok(false);
+    };
+    let (side, px, touch) = if pos.qty > 0.0 {
+        (Side::Sell, bid, tob.touch_size(true))
+    } else {
+        (Side::Buy, ask, tob.touch_size(false))
+    };
+    let Some(reason) = ctx.risk.position_exit(market_id, &pos, px, ctx.mono) else {
+        return Ok(false);
+    };
+    cancel_all_open_for_market(ctx.broker, market_id)?;
+    let size = pos.qty.abs().min(touch);
+    let filled = if size > FLAT_QTY_EPS {
+        ctx.broker
+            .execute_exit_ioc(market_id, side, px, size, reason, tob)?
+            .map_or(0.0, |f| f.size)
+    } else {
+        0.0
+    };
+    if filled > FLAT_QTY_EPS {
+        ctx.risk.note_exit(market_id, ctx.mono);
+    }
+    log::warn!(
+        "risk.exit market_id={} reason={} qty={:.4} avg_price={:.4} px={:.4} filled={:.4}",
+        market_id,
+        reason,
+        pos.qty,
+        pos.avg_price,
+        px,
+        filled
+    );
+    ctx.store
+        .upsert_runtime_status("risk", "warn", reason, Some(market_id), ctx.now)
+        .ok();
+    Ok(true)
+}
+
 fn cancel_all_open_for_market(broker: &mut PaperBroker, market_id: &str) -> Result<()> {
     let ids: Vec<String> = broker
         .orders
@@ -600,6 +1528,26 @@ fn cancel_all_open_for_market(broker: &mut PaperBroker, market_id: &str) -> Resu
     Ok(())
 }
 
+fn cancel_closed_markets(
+    feed: &crate::feed_handler::FeedState,
+    broker: &mut PaperBroker,
+) -> Result<()> {
+    let mut markets: Vec<String> = broker
+        .orders
+        .values()
+        .filter(|o| o.status == "open")
+        .map(|o| o.market_id.clone())
+        .collect();
+    markets.sort();
+    markets.dedup();
+    for mid in markets {
+        if feed.get(&mid).is_some_and(|t| t.closed) {
+            cancel_all_open_for_market(broker, &mid)?;
+        }
+    }
+    Ok(())
+}
+
 fn cancel_stale_for_market(
     broker: &mut PaperBroker,
     market_id: &str,
@@ -610,7 +1558,11 @@ fn cancel_stale_for_market(
         .orders
         .iter()
         .filter(|(_, o)| {
-            o.market_id == market_id && o.status == "open" && (now - o.created_ts) >= min_life
+            o.market_id == market_id
+                && o.status == "open"
+                && (now - o.created_ts) >= min_life
+                // Probes run to their own TTL (see `ProbeScheduler`).
+                && o.meta["strategy"] != "probe"
         })
         .map(|(id, _)| id.clone())
         .collect();
@@ -625,6 +1577,7 @@ fn persist_snapshots(
     feed: &crate::feed_handler::FeedState,
     broker: &PaperBroker,
     selected: &[SelectedMarket],
+    mark_source: &str,
 ) -> Result<()> {
     let now = now_ts();
     let mut total_u = 0.0;
@@ -640,16 +1593,13 @@ fn persist_snapshots(
 
     // Persist per-market positions (including flat w/ realized != 0).
     for (mid, p) in broker.positions.iter() {
-        let mark = feed.get(mid).and_then(|t| t.mid()).unwrap_or(p.avg_price);
+        let mark = position_mark(feed, mark_source, mid, p);
         let u = (mark - p.avg_price) * p.qty;
         total_u += u;
         store.insert_position_snapshot(
             now,
             mid,
-            event_by_market
-                .get(mid.as_str())
-                .copied()
-                .unwrap_or("event:unknown"),
+            event_by_market.get(mid.as_str()).copied(),
             p.qty,
             p.avg_price,
             mark,
@@ -673,6 +1623,7 @@ fn persist_telemetry_markdown(
     feed: &crate::feed_handler::FeedState,
     broker: &PaperBroker,
     selected: &[SelectedMarket],
+    mark_source: &str,
 ) -> Result<()> {
     // Lightweight writer: reconstruct similar snapshot to ops/telemetry/latest.md
     let now = now_ts();
@@ -681,7 +1632,7 @@ fn persist_telemetry_markdown(
 
     let mut open_rows: Vec<(String, f64, f64, f64, f64, f64)> = vec![]; // market_id, pos, avg, mark, u, r
     for (mid, p) in broker.positions.iter() {
-        let mark = feed.get(mid).and_then(|t| t.mid()).unwrap_or(p.avg_price);
+        let mark = position_mark(feed, mark_source, mid, p);
         let u = (mark - p.avg_price) * p.qty;
         total_u += u;
         open_rows.push((mid.clone(), p.qty, p.avg_price, mark, u, p.realized_pnl));
@@ -744,3 +1695,663 @@ fn persist_telemetry_markdown(
     let _ = selected;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::temp_store;
+    use std::collections::HashMap;
+
+    /// A paper trader on a fresh temp store, fault-free unless `configure` says
+    /// otherwise, owning everything a `TraderCtx` borrows.
+    struct Trader {
+        settings: Settings,
+        store: SqliteStore,
+        risk: RiskEngine,
+        strat: HftStrategy,
+        broker: PaperBroker,
+        last_quote_ts: HashMap<String, f64>,
+        last_fair: HashMap<String, f64>,
+        last_imb_sign: HashMap<String, i32>,
+        shadow: ShadowBook,
+    }
+
+    impl Trader {
+        fn new(configure: impl FnOnce(&mut Settings)) -> Self {
+            let mut settings = Settings::load().unwrap();
+            settings.paper_fault_rate = 0.0;
+            settings.paper_non_atomic_fail_rate = 0.0;
+            configure(&mut settings);
+            let store = temp_store();
+            Self {
+                risk: RiskEngine::new(settings.clone()),
+                strat: HftStrategy::new(settings.clone()),
+                broker: PaperBroker::with_seed(settings.clone(), store.clone(), 1),
+                settings,
+                store,
+                last_quote_ts: HashMap::new(),
+                last_fair: HashMap::new(),
+                last_imb_sign: HashMap::new(),
+                shadow: ShadowBook::default(),
+            }
+        }
+
+        fn ctx(&mut self, now: f64, mono: f64) -> TraderCtx<'_> {
+            TraderCtx {
+                settings: &self.settings,
+                store: &self.store,
+                risk: &self.risk,
+                strat: &self.strat,
+                broker: &mut self.broker,
+                now,
+                mono,
+                last_quote_ts: &mut self.last_quote_ts,
+                last_fair: &mut self.last_fair,
+                last_imb_sign: &mut self.last_imb_sign,
+                shadow: &mut self.shadow,
+            }
+        }
+
+        /// Drops the per-market quote state, as a restarted process would.
+        fn forget_quotes(&mut self) {
+            self.last_quote_ts.clear();
+            self.last_fair.clear();
+            self.last_imb_sign.clear();
+        }
+
+        /// One `quote_markets` pass at the current clocks, from a clean quote state.
+        fn quote(&mut self, feed: &crate::feed_handler::FeedState, markets: &[SelectedMarket]) {
+            self.forget_quotes();
+            let mut ctx = self.ctx(now_ts(), mono_ts());
+            quote_markets(&mut ctx, feed, markets, &mut HashMap::new()).unwrap();
+        }
+
+        fn hold(&mut self, market_id: &str, qty: f64, avg_price: f64, realized_pnl: f64) {
+            self.broker.positions.insert(
+                market_id.to_string(),
+                crate::paper_broker::Position {
+                    qty,
+                    avg_price,
+                    realized_pnl,
+                },
+            );
+        }
+
+        fn open_orders(&self, market_id: &str) -> Vec<&crate::paper_broker::Order> {
+            self.broker
+                .orders
+                .values()
+                .filter(|o| o.market_id == market_id && o.status == "open")
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn max_runtime_ends_within_budget() {
+        let started = std::time::Instant::now();
+        let reason = tokio::time::timeout(std::time::Duration::from_secs(2), shutdown_signal(0.05))
+            .await
+            .expect("shutdown should fire before the 2s budget");
+        assert_eq!(reason, "max_runtime");
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn shutdown_cancels_open_orders_and_snapshots() {
+        let mut t = Trader::new(|_| {});
+        t.broker
+            .place_limit("m1", Side::Buy, 0.45, 10.0, "mm")
+            .unwrap();
+        t.broker
+            .place_limit("m2", Side::Sell, 0.55, 10.0, "mm")
+            .unwrap();
+
+        let feed = crate::feed_handler::FeedState::new();
+        shutdown_paper_trader(&t.store, &feed, &mut t.broker, &[], "mid", "max_runtime").unwrap();
+
+        assert!(t.broker.orders.values().all(|o| o.status == "cancelled"));
+        assert_eq!(t.broker.counters.orders_cancelled, 2);
+        assert!(t.store.fetch_latest_pnl().unwrap().is_some());
+    }
+
+    #[test]
+    fn clean_shutdown_writes_session_summary() {
+        let mut t = Trader::new(|_| {});
+        t.hold("m1", 5.0, 0.40, 1.25);
+        t.broker
+            .place_limit("m1", Side::Buy, 0.39, 10.0, "mm")
+            .unwrap();
+
+        let feed = crate::feed_handler::FeedState::new();
+        feed.update_book_owned("m1", 1.0, Some(0.49), Some(0.51), 50.0, 50.0, None);
+        shutdown_paper_trader(&t.store, &feed, &mut t.broker, &[], "mid", "max_runtime").unwrap();
+
+        let out = std::env::temp_dir().join(format!("summary-{}.json", uuid::Uuid::new_v4()));
+        let out = out.to_str().unwrap();
+        let start = now_ts() - 10.0;
+        write_session_summary(
+            out,
+            &t.settings,
+            &t.store,
+            &feed,
+            &t.broker,
+            start,
+            "max_runtime",
+            None,
+        )
+        .unwrap();
+
+        let v: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(out).unwrap()).unwrap();
+        std::fs::remove_file(out).ok();
+        assert_eq!(v["exit_reason"], "max_runtime");
+        assert!(v["error"].is_null());
+        assert_eq!(v["run_mode"], t.settings.run_mode.as_str());
+        assert_eq!(v["trade_mode"], t.settings.trade_mode.as_str());
+        assert_eq!(v["execution_mode"], t.settings.execution_mode.as_str());
+        assert_eq!(v["orders_cancelled"], 1);
+        assert!(v["uptime_secs"].as_f64().unwrap() >= 10.0);
+        assert_eq!(v["positions"][0]["market_id"], "m1");
+        assert!((v["pnl"]["total"].as_f64().unwrap() - 1.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn closed_market_cancels_orders_and_marks_at_settle() {
+        let mut t = Trader::new(|_| {});
+        t.hold("m1", 10.0, 0.60, 0.0);
+        t.broker
+            .place_limit("m1", Side::Sell, 0.65, 10.0, "mm")
+            .unwrap();
+
+        let feed = crate::feed_handler::FeedState::new();
+        feed.update_book_owned("m1", 1.0, Some(0.58), Some(0.62), 50.0, 50.0, None);
+        assert_eq!(feed.get("m1").unwrap().mark("mid"), Some(0.60));
+
+        // Scanner sees the market flip to closed, resolved "No".
+        feed.mark_closed("m1", Some(0.0));
+        cancel_closed_markets(&feed, &mut t.broker).unwrap();
+
+        assert!(t.broker.orders.values().all(|o| o.status == "cancelled"));
+        let tob = feed.get("m1").unwrap();
+        assert_eq!(tob.mark("mid"), Some(0.0));
+        persist_snapshots(&t.store, &feed, &t.broker, &[], "mid").unwrap();
+        let pnl = t.store.fetch_latest_pnl().unwrap().unwrap();
+        // Stale book mid would have shown ~0 unrealized; resolution marks the full loss.
+        assert!((pnl["total_unrealized"].as_f64().unwrap() + 6.0).abs() < 1e-9);
+    }
+
+    fn market(id: &str, score: f64) -> SelectedMarket {
+        SelectedMarket {
+            market_id: id.to_string(),
+            question: None,
+            event_id: None,
+            end_ts: None,
+            volume_24h_usd: 0.0,
+            liquidity_usd: 0.0,
+            condition_id: None,
+            clob_token_id: Some(format!("tok-{id}")),
+            microstructure_score: score,
+            tick_size: None,
+            category: "other".to_string(),
+        }
+    }
+
+    #[test]
+    fn markets_past_subscription_cap_are_not_quoted() {
+        let mut t = Trader::new(|s| s.max_ws_subscriptions = 2);
+
+        let now = now_ts();
+        let feed = crate::feed_handler::FeedState::new();
+        let selected = vec![market("m1", 5.0), market("m2", 1.0), market("m3", 9.0)];
+        // A stale poll may still hold a book for the unsubscribed market.
+        for m in &selected {
+            feed.update_book_owned(&m.market_id, now, Some(0.48), Some(0.52), 100.0, 90.0, None);
+        }
+
+        let quoting = quotable_markets(&t.settings, &selected);
+        let ids: Vec<&str> = quoting.iter().map(|m| m.market_id.as_str()).collect();
+        assert_eq!(ids, ["m1", "m3"]);
+
+        t.quote(&feed, &quoting);
+
+        assert!(t.last_quote_ts.contains_key("m1") && t.last_quote_ts.contains_key("m3"));
+        assert!(!t.last_quote_ts.contains_key("m2"));
+        assert!(t.broker.orders.values().all(|o| o.market_id != "m2"));
+    }
+
+    #[test]
+    fn shadow_mode_records_intents_and_hypothetical_pnl_without_orders() {
+        let mut t = Trader::new(|s| {
+            s.execution_mode = "shadow".to_string();
+            s.tape_record_enabled = false;
+        });
+        let feed = crate::feed_handler::FeedState::new();
+        let markets = vec![market("m1", 5.0)];
+
+        let mut last_tape_ts = HashMap::new();
+        let mut tick = |t: &mut Trader, bid: f64, ask: f64| {
+            let now = now_ts();
+            feed.update_book_owned("m1", now, Some(bid), Some(ask), 100.0, 90.0, None);
+            let mut ctx = t.ctx(now, mono_ts());
+            quote_markets(&mut ctx, &feed, &markets, &mut last_tape_ts).unwrap();
+            t.store.fetch_recent_shadow_intents(1).unwrap().remove(0)
+        };
+
+        let first = tick(&mut t, 0.48, 0.52);
+        assert_eq!(first["hypo_fills"], 0);
+        assert_eq!(first["hypo_pnl"], 0.0);
+        let target_ask = first["target_ask"].as_f64().expect("an ask intent");
+        assert!(target_ask >= 0.52);
+
+        // The bid lifts to our standing ask: short at target_ask, marked at the new mid.
+        let second = tick(&mut t, target_ask, target_ask + 0.04);
+        assert_eq!(second["hypo_fills"], 1);
+        let qty = first["size"].as_f64().unwrap();
+        assert_eq!(second["hypo_position"].as_f64().unwrap(), -qty);
+        let expected_pnl = -0.02 * qty;
+        assert!((second["hypo_pnl"].as_f64().unwrap() - expected_pnl).abs() < 1e-9);
+        assert!(second["fair"].is_f64() && second["divergence"].is_f64());
+
+        assert_eq!(t.store.fetch_recent_shadow_intents(10).unwrap().len(), 2);
+        assert_eq!(t.broker.counters.orders_placed, 0);
+        assert_eq!(t.broker.counters.fills, 0);
+        assert!(t.broker.orders.is_empty());
+        assert!(t.store.fetch_recent_orders(10, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn two_sided_requirement_places_nothing_when_one_side_is_infeasible() {
+        let mut t = Trader::new(|s| {
+            s.simulated_latency_ms = 0.0;
+            s.base_order_size = 10.0;
+            s.size_scale_by_liquidity = false;
+            // Long 10 against a 15 cap: another bid would breach it, asks are fine.
+            s.max_inventory_usd = 15.0;
+        });
+        t.hold("m1", 10.0, 0.5, 0.0);
+        let feed = crate::feed_handler::FeedState::new();
+        let m = market("m1", 5.0);
+
+        let run = |t: &mut Trader| {
+            let now = now_ts();
+            feed.update_book_owned("m1", now, Some(0.48), Some(0.52), 100.0, 100.0, None);
+            let tob = feed.get("m1").unwrap();
+            // A far-off previous fair forces a requote.
+            t.forget_quotes();
+            t.last_fair.insert("m1".to_string(), 0.1);
+            trade_one_market(&mut t.ctx(now, mono_ts()), &m, &tob, None).unwrap();
+        };
+        let open_sides = |t: &Trader| {
+            let open = t.open_orders("m1");
+            (
+                open.iter().filter(|o| o.side == Side::Buy).count(),
+                open.iter().filter(|o| o.side == Side::Sell).count(),
+            )
+        };
+
+        // Without the requirement only the ask side goes out.
+        run(&mut t);
+        let (bids, asks) = open_sides(&t);
+        assert_eq!(bids, 0);
+        assert!(asks > 0);
+
+        // With it: nothing is placed and the lone resting asks are pulled.
+        t.settings.require_two_sided = true;
+        let placed = t.broker.counters.orders_placed;
+        run(&mut t);
+        assert_eq!(open_sides(&t), (0, 0));
+        assert_eq!(t.broker.counters.orders_placed, placed);
+        let status = t.store.fetch_runtime_statuses().unwrap();
+        assert_eq!(status["risk"]["message"], "two_sided_required");
+    }
+
+    #[test]
+    fn requote_timing_follows_the_monotonic_clock_across_a_wall_clock_step() {
+        let mut t = Trader::new(|s| s.simulated_latency_ms = 0.0);
+        let feed = crate::feed_handler::FeedState::new();
+        let m = market("m1", 5.0);
+
+        let run = |t: &mut Trader, now: f64, mono: f64| {
+            feed.update_book_owned("m1", now, Some(0.48), Some(0.52), 100.0, 90.0, None);
+            let tob = feed.get("m1").unwrap();
+            // A far-off previous fair forces a requote whenever the interval allows it.
+            t.last_fair.insert("m1".to_string(), 0.1);
+            trade_one_market(&mut t.ctx(now, mono), &m, &tob, None).unwrap();
+            t.last_quote_ts["m1"]
+        };
+
+        let now = now_ts();
+        assert_eq!(run(&mut t, now, 5.0), 5.0);
+        // Inside the requote interval on the monotonic clock: no requote.
+        assert_eq!(run(&mut t, now + 60.0, 5.05), 5.0);
+        // The wall clock steps back an hour; the monotonic clock still says it's time.
+        assert_eq!(run(&mut t, now - 3600.0, 5.5), 5.5);
+    }
+
+    #[test]
+    fn rehydrated_quote_state_suppresses_an_immediate_requote() {
+        let mut t = Trader::new(|s| s.quote_state_max_age_secs = 30.0);
+        let feed = crate::feed_handler::FeedState::new();
+        let markets = vec![market("m1", 5.0)];
+        let now = now_ts();
+
+        // One trader run on a fresh (same-priced) book and a fresh broker: quote once
+        // and return the orders placed.
+        let run = |t: &mut Trader, now: f64, mono: f64| {
+            feed.update_book_owned("m1", now, Some(0.48), Some(0.52), 100.0, 90.0, None);
+            t.broker = PaperBroker::with_seed(t.settings.clone(), t.store.clone(), 1);
+            let mut ctx = t.ctx(now, mono);
+            quote_markets(&mut ctx, &feed, &markets, &mut HashMap::new()).unwrap();
+            t.broker.open_order_count()
+        };
+        let rehydrate = |t: &mut Trader, now: f64, mono: f64| {
+            t.forget_quotes();
+            rehydrate_quote_state(
+                &t.store,
+                30.0,
+                now,
+                mono,
+                &mut t.last_quote_ts,
+                &mut t.last_fair,
+                &mut t.last_imb_sign,
+            )
+            .unwrap()
+        };
+
+        // First process: a fresh book quotes right away; its state is saved.
+        assert!(run(&mut t, now, 100.0) > 0);
+        persist_quote_state(
+            &t.store,
+            now,
+            100.0,
+            &t.last_quote_ts,
+            &t.last_fair,
+            &t.last_imb_sign,
+        )
+        .unwrap();
+
+        // Restart 5s later (a new monotonic origin): same book, nothing to requote.
+        assert_eq!(rehydrate(&mut t, now + 5.0, 0.5), 1);
+        assert_eq!(t.last_quote_ts["m1"], 0.5 - 5.0);
+        assert_eq!(run(&mut t, now + 5.0, 0.5), 0);
+
+        // Without it (or once it is too old) the restart requotes immediately.
+        assert_eq!(rehydrate(&mut t, now + 31.0, 0.5), 0);
+        assert!(run(&mut t, now + 31.0, 0.5) > 0);
+    }
+
+    #[test]
+    fn quoting_stops_and_orders_are_pulled_outside_the_price_band() {
+        let mut t = Trader::new(|_| {});
+        let feed = crate::feed_handler::FeedState::new();
+        let markets = vec![market("m1", 5.0)];
+        // Mid is exactly 0.50.
+        feed.update_book_owned("m1", now_ts(), Some(0.48), Some(0.52), 100.0, 90.0, None);
+
+        let mut run = |band: (f64, f64)| {
+            t.settings.tradeable_price_band = band;
+            t.quote(&feed, &markets);
+            t.broker.open_order_count()
+        };
+
+        // A mid on either bound is inside the band.
+        assert!(run((0.50, 0.97)) > 0);
+        assert!(run((0.03, 0.50)) > 0);
+        // Just past a bound: nothing new, and the resting quotes are cancelled.
+        assert_eq!(run((0.5001, 0.97)), 0);
+        assert!(run((0.03, 0.97)) > 0);
+        assert_eq!(run((0.03, 0.4999)), 0);
+    }
+
+    #[test]
+    fn freshly_subscribed_market_is_not_quoted_until_stabilized() {
+        let mut t = Trader::new(|s| s.feed_stabilize_min_samples = 3);
+        let feed = crate::feed_handler::FeedState::new();
+        let markets = vec![market("m1", 5.0)];
+        let streamed = |feed: &crate::feed_handler::FeedState| {
+            feed.update_book_owned("m1", now_ts(), Some(0.48), Some(0.52), 100.0, 90.0, None)
+        };
+        let run = |t: &mut Trader| {
+            t.quote(&feed, &markets);
+            t.broker.open_order_count()
+        };
+
+        feed.reset_stabilization(&["m1".to_string()]);
+        streamed(&feed);
+        assert_eq!(run(&mut t), 0);
+        // A REST poll is not a rate sample.
+        feed.update_book_polled("m1", now_ts(), Some(0.48), Some(0.52), 100.0, 90.0);
+        streamed(&feed);
+        assert_eq!(run(&mut t), 0);
+        streamed(&feed);
+        assert!(run(&mut t) > 0);
+
+        // A resubscribe starts the count over.
+        cancel_all_open_for_market(&mut t.broker, "m1").unwrap();
+        t.broker.flush_pending_cancels().unwrap();
+        feed.reset_stabilization(&["m1".to_string()]);
+        streamed(&feed);
+        assert_eq!(run(&mut t), 0);
+        streamed(&feed);
+        streamed(&feed);
+        assert!(run(&mut t) > 0);
+    }
+
+    #[test]
+    fn market_inventory_cap_blocks_new_markets_but_not_adds() {
+        let mut t = Trader::new(|s| s.max_markets_with_inventory = 1);
+        let feed = crate::feed_handler::FeedState::new();
+        let markets = vec![market("m1", 5.0), market("m2", 4.0)];
+        for id in ["m1", "m2"] {
+            feed.update_book_owned(id, now_ts(), Some(0.48), Some(0.52), 100.0, 90.0, None);
+        }
+        let open_sides = |t: &Trader, id: &str| {
+            let mut sides: Vec<&str> = t.open_orders(id).iter().map(|o| o.side.as_str()).collect();
+            sides.sort();
+            sides.dedup();
+            sides
+        };
+
+        // Long m1 fills the single slot: m1 still quotes both sides (adds included),
+        // m2 gets nothing.
+        t.hold("m1", 10.0, 0.50, 0.0);
+        t.quote(&feed, &markets);
+        assert_eq!(t.broker.markets_with_inventory(), 1);
+        assert_eq!(open_sides(&t, "m1"), ["buy", "sell"]);
+        assert!(open_sides(&t, "m2").is_empty());
+
+        // Once m1 is flat the slot frees up and m2 is quoted.
+        t.hold("m1", 0.0, 0.50, 0.0);
+        t.quote(&feed, &markets);
+        assert_eq!(t.broker.markets_with_inventory(), 0);
+        assert_eq!(open_sides(&t, "m2"), ["buy", "sell"]);
+    }
+
+    #[test]
+    fn live_param_change_applies_on_next_tick() {
+        let mut t = Trader::new(|s| {
+            s.size_scale_by_liquidity = false;
+            s.base_order_size = 10.0;
+        });
+        let live = crate::live_params::shared(&t.settings);
+        let feed = crate::feed_handler::FeedState::new();
+        for id in ["m1", "m2"] {
+            feed.update_book_owned(id, now_ts(), Some(0.48), Some(0.52), 100.0, 90.0, None);
+        }
+        let tick = |t: &mut Trader, id: &str| {
+            apply_live_params(&live, &mut t.settings, &mut t.strat);
+            t.quote(&feed, &[market(id, 5.0)]);
+            let mut sizes: Vec<f64> = t.open_orders(id).iter().map(|o| o.size).collect();
+            sizes.dedup();
+            sizes
+        };
+
+        assert_eq!(tick(&mut t, "m1"), [10.0]);
+        crate::live_params::set_param(&live, "base_order_size", 25.0).unwrap();
+        assert_eq!(tick(&mut t, "m2"), [25.0]);
+        assert_eq!(t.settings.base_order_size, 25.0);
+    }
+
+    #[test]
+    fn thinning_book_pauses_quoting() {
+        let mut t = Trader::new(|s| s.min_depth_to_quote = 50.0);
+        let feed = crate::feed_handler::FeedState::new();
+        let markets = vec![market("m1", 5.0)];
+        let tick = |t: &mut Trader| {
+            t.quote(&feed, &markets);
+            t.open_orders("m1").len()
+        };
+
+        feed.update_book_owned("m1", now_ts(), Some(0.48), Some(0.52), 100.0, 90.0, None);
+        assert!(tick(&mut t) > 0);
+
+        // The ask side thins below the floor: resting quotes are pulled, none re-placed.
+        feed.update_book_owned("m1", now_ts(), Some(0.48), Some(0.52), 100.0, 20.0, None);
+        assert_eq!(tick(&mut t), 0);
+        assert_eq!(tick(&mut t), 0);
+
+        // Depth returns: quoting resumes.
+        feed.update_book_owned("m1", now_ts(), Some(0.48), Some(0.52), 100.0, 90.0, None);
+        assert!(tick(&mut t) > 0);
+    }
+
+    #[test]
+    fn wide_spread_improves_the_touch_tight_spread_joins() {
+        let mut t = Trader::new(|s| {
+            s.price_tick = 0.01;
+            s.mm_quote_width = 0.02;
+            s.join_or_improve = "improve_if_spread_ge_ticks".to_string();
+            s.improve_min_spread_ticks = 3;
+        });
+        let feed = crate::feed_handler::FeedState::new();
+        feed.update_book_owned("wide", now_ts(), Some(0.45), Some(0.55), 100.0, 90.0, None);
+        feed.update_book_owned("tight", now_ts(), Some(0.49), Some(0.51), 100.0, 90.0, None);
+        t.quote(&feed, &[market("wide", 5.0), market("tight", 4.0)]);
+        // Best resting (bid, ask) in cents.
+        let touch = |id: &str| {
+            let cents = |side: &str| {
+                t.open_orders(id)
+                    .iter()
+                    .filter(|o| o.side.as_str() == side)
+                    .map(|o| (o.price * 100.0).round() as i64)
+                    .collect::<Vec<_>>()
+            };
+            (
+                cents("buy").into_iter().max(),
+                cents("sell").into_iter().min(),
+            )
+        };
+
+        // 10 ticks wide: one tick in front of 0.45 / 0.55.
+        assert_eq!(touch("wide"), (Some(46), Some(54)));
+        // 2 ticks wide: nothing inside the touch.
+        let (bid, ask) = touch("tight");
+        assert!(bid.is_some_and(|b| b <= 49), "{bid:?}");
+        assert!(ask.is_some_and(|a| a >= 51), "{ask:?}");
+    }
+
+    #[test]
+    fn stop_loss_flattens_once_and_debounces() {
+        let mut t = Trader::new(|s| {
+            s.simulated_latency_ms = 0.0;
+            s.stop_loss_pct = 10.0;
+            s.exit_debounce_secs = 60.0;
+        });
+        let feed = crate::feed_handler::FeedState::new();
+        let markets = vec![market("m1", 5.0)];
+
+        // No book for the market: nothing to value the position at, no exit.
+        t.hold("m1", 10.0, 0.60, 0.0);
+        t.quote(&feed, &markets);
+        assert_eq!(t.broker.position_qty("m1"), 10.0);
+
+        // Long 10 @ 0.60, bid 0.48: down 20% at the exit touch, past the 10% stop.
+        let resting = t
+            .broker
+            .place_limit("m1", Side::Buy, 0.40, 5.0, "mm")
+            .unwrap();
+        feed.update_book_owned("m1", now_ts(), Some(0.48), Some(0.52), 100.0, 90.0, None);
+        t.quote(&feed, &markets);
+        assert!(t.broker.position_qty("m1").abs() < 1e-9);
+        let fills = t.store.fetch_recent_fills(10).unwrap();
+        let exit = fills
+            .iter()
+            .find(|f| f["meta"]["reason"] == "stop_loss")
+            .expect("stop-loss fill");
+        assert_eq!(exit["side"], "sell");
+        assert!((exit["price"].as_f64().unwrap() - 0.48).abs() < 1e-9);
+        assert_eq!(t.broker.orders[&resting].status, "cancelled");
+
+        // Still under water on the next tick, but inside the debounce: no second exit.
+        t.hold("m1", 10.0, 0.60, 0.0);
+        t.quote(&feed, &markets);
+        let exits = t
+            .store
+            .fetch_recent_fills(50)
+            .unwrap()
+            .iter()
+            .filter(|f| f["meta"]["reason"] == "stop_loss")
+            .count();
+        assert_eq!(exits, 1);
+    }
+
+    #[test]
+    fn stop_loss_needs_a_fresh_book_and_sells_only_the_touch() {
+        let mut t = Trader::new(|s| {
+            s.simulated_latency_ms = 0.0;
+            s.stop_loss_pct = 10.0;
+        });
+        let feed = crate::feed_handler::FeedState::new();
+        let markets = vec![market("m1", 5.0)];
+        t.hold("m1", 10.0, 0.60, 0.0);
+
+        // 4 shares at the 0.48 bid, 96 more behind it.
+        feed.replace_book("m1", &[(0.48, 4.0), (0.47, 96.0)], &[(0.52, 90.0)]);
+        let stale = now_ts() - 3600.0;
+        feed.update_book_owned("m1", stale, Some(0.48), Some(0.52), 100.0, 90.0, None);
+        t.quote(&feed, &markets);
+        assert_eq!(t.broker.position_qty("m1"), 10.0);
+
+        feed.update_book_owned("m1", now_ts(), Some(0.48), Some(0.52), 100.0, 90.0, None);
+        t.quote(&feed, &markets);
+        assert!((t.broker.position_qty("m1") - 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn stop_loss_retries_until_filled_with_cancels_in_flight() {
+        let mut t = Trader::new(|s| {
+            s.simulated_latency_ms = 500.0;
+            s.stop_loss_pct = 10.0;
+            s.exit_debounce_secs = 60.0;
+        });
+        let feed = crate::feed_handler::FeedState::new();
+        let markets = vec![market("m1", 5.0)];
+        t.hold("m1", 10.0, 0.60, 0.0);
+        // Our bid at the exit touch; its cancel will still be in flight at the IOC.
+        let bid = t
+            .broker
+            .place_limit("m1", Side::Buy, 0.48, 5.0, "mm")
+            .unwrap();
+
+        // Nothing external at the bid: the exit takes nothing and is not debounced.
+        feed.upsert(
+            "m1",
+            Tob {
+                best_bid: Some(0.48),
+                best_ask: Some(0.52),
+                bid_depth_n: 0.0,
+                ask_depth_n: 90.0,
+                ..Tob::empty(now_ts())
+            },
+        );
+        t.quote(&feed, &markets);
+        assert_eq!(t.broker.position_qty("m1"), 10.0);
+
+        // Liquidity returns: the next tick exits, passing over our pending-cancel bid.
+        feed.update_book_owned("m1", now_ts(), Some(0.48), Some(0.52), 100.0, 90.0, None);
+        t.quote(&feed, &markets);
+        assert!(t.broker.position_qty("m1").abs() < 1e-9);
+        assert!(t.broker.cancel_pending(&bid));
+        assert_eq!(t.broker.orders[&bid].filled_size, 0.0);
+    }
+}
@@ -1,24 +1,27 @@
+use std::collections::HashMap;
 use std::env;
 
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 
-fn get_env(key: &str) -> Option<String> {
-    env::var(key)
-        .ok()
+/// Where `Settings::load_from` reads keys: the process env, or overrides layered over it.
+type Lookup<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+fn get_env(env: Lookup, key: &str) -> Option<String> {
+    env(key)
         .map(|s| s.trim().to_string())
         .filter(|s| !s.is_empty())
 }
 
-fn get_env_bool(key: &str, default: bool) -> bool {
-    match get_env(key) {
+fn get_env_bool(env: Lookup, key: &str, default: bool) -> bool {
+    match get_env(env, key) {
         None => default,
         Some(v) => matches!(v.to_lowercase().as_str(), "1" | "true" | "yes" | "y" | "on"),
     }
 }
 
-fn get_env_f64(key: &str, default: f64) -> Result<f64> {
-    match get_env(key) {
+fn get_env_f64(env: Lookup, key: &str, default: f64) -> Result<f64> {
+    match get_env(env, key) {
         None => Ok(default),
         Some(v) => Ok(v
             .parse::<f64>()
@@ -26,8 +29,8 @@ fn get_env_f64(key: &str, default: f64) -> Result<f64> {
     }
 }
 
-fn get_env_usize(key: &str, default: usize) -> Result<usize> {
-    match get_env(key) {
+fn get_env_usize(env: Lookup, key: &str, default: usize) -> Result<usize> {
+    match get_env(env, key) {
         None => Ok(default),
         Some(v) => Ok(v
             .parse::<usize>()
@@ -35,22 +38,195 @@ fn get_env_usize(key: &str, default: usize) -> Result<usize> {
     }
 }
 
-fn get_env_string(key: &str, default: &str) -> String {
-    get_env(key).unwrap_or_else(|| default.to_string())
+fn get_env_string(env: Lookup, key: &str, default: &str) -> String {
+    get_env(env, key).unwrap_or_else(|| default.to_string())
+}
+
+/// Parse dotenv-style `KEY=VALUE` lines (blank lines and `#` comments ignored).
+fn parse_env_file(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| {
+            let l = l.strip_prefix("export ").unwrap_or(l);
+            let (k, v) = l.split_once('=')?;
+            let v = v.trim().trim_matches('"').trim_matches('\'');
+            Some((k.trim().to_string(), v.to_string()))
+        })
+        .collect()
+}
+
+/// Per-tag selector thresholds (`SELECTOR_TAG_OVERRIDES`); unset fields fall back to the globals.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagOverride {
+    pub tag: String,
+    pub min_24h_volume_usd: Option<f64>,
+    pub min_liquidity_usd: Option<f64>,
+    pub max_spread_bps: Option<f64>,
+}
+
+/// Parse `tag:key=value,key=value;tag2:...` where key is one of
+/// min_24h_volume_usd | min_liquidity_usd | max_spread_bps. Order is kept (first match wins).
+fn parse_tag_overrides(text: &str) -> Result<Vec<TagOverride>> {
+    let mut out = Vec::new();
+    for entry in text.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let (tag, kvs) = entry
+            .split_once(':')
+            .ok_or_else(|| anyhow!("SELECTOR_TAG_OVERRIDES entry missing ':' ({entry})"))?;
+        let mut o = TagOverride {
+            tag: tag.trim().to_lowercase(),
+            min_24h_volume_usd: None,
+            min_liquidity_usd: None,
+            max_spread_bps: None,
+        };
+        for kv in kvs.split(',').map(str::trim).filter(|kv| !kv.is_empty()) {
+            let (k, v) = kv.split_once('=').ok_or_else(|| {
+                anyhow!("SELECTOR_TAG_OVERRIDES {tag}: expected key=value ({kv})")
+            })?;
+            let v = v
+                .trim()
+                .parse::<f64>()
+                .map_err(|e| anyhow!("SELECTOR_TAG_OVERRIDES {tag}.{k} invalid float: {e}"))?;
+            match k.trim() {
+                "min_24h_volume_usd" => o.min_24h_volume_usd = Some(v),
+                "min_liquidity_usd" => o.min_liquidity_usd = Some(v),
+                "max_spread_bps" => o.max_spread_bps = Some(v),
+                other => return Err(anyhow!("SELECTOR_TAG_OVERRIDES {tag}: unknown key {other}")),
+            }
+        }
+        out.push(o);
+    }
+    Ok(out)
+}
+
+/// Directional overlay (`INVENTORY_TARGETS`): `market_id`'s inventory skew centres on
+/// `qty` (signed shares) instead of flat.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InventoryTarget {
+    pub market_id: String,
+    pub qty: f64,
+}
+
+/// Parse `market=qty;market2=qty2` (qty signed, long > 0).
+fn parse_inventory_targets(text: &str) -> Result<Vec<InventoryTarget>> {
+    let mut out: Vec<InventoryTarget> = Vec::new();
+    for entry in text.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let (market, qty) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow!("INVENTORY_TARGETS entry missing '=' ({entry})"))?;
+        let market = market.trim();
+        let qty = qty
+            .trim()
+            .parse::<f64>()
+            .map_err(|e| anyhow!("INVENTORY_TARGETS {market} invalid qty: {e}"))?;
+        if market.is_empty() || !qty.is_finite() {
+            return Err(anyhow!("INVENTORY_TARGETS invalid entry ({entry})"));
+        }
+        if out.iter().any(|t| t.market_id == market) {
+            return Err(anyhow!("INVENTORY_TARGETS {market} listed twice"));
+        }
+        out.push(InventoryTarget {
+            market_id: market.to_string(),
+            qty,
+        });
+    }
+    Ok(out)
+}
+
+/// Parse `min,max` (`TRADEABLE_PRICE_BAND`) with `0 <= min < max <= 1`.
+fn parse_price_band(text: &str) -> Result<(f64, f64)> {
+    let (lo, hi) = text
+        .split_once(',')
+        .ok_or_else(|| anyhow!("TRADEABLE_PRICE_BAND must be min,max (got {text})"))?;
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<f64>()
+            .map_err(|e| anyhow!("TRADEABLE_PRICE_BAND invalid bound {v:?}: {e}"))
+    };
+    let (lo, hi) = (parse(lo)?, parse(hi)?);
+    if !(0.0..=1.0).contains(&lo) || !(0.0..=1.0).contains(&hi) || lo >= hi {
+        return Err(anyhow!(
+            "TRADEABLE_PRICE_BAND must satisfy 0 <= min < max <= 1 (got {text})"
+        ));
+    }
+    Ok((lo, hi))
+}
+
+/// Fair-value anchor (`REFERENCE_ANCHORS`): `market_id`'s fair is blended toward the
+/// live mid of `reference_market_id` with weight `beta`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReferenceAnchor {
+    pub market_id: String,
+    pub reference_market_id: String,
+    pub beta: f64,
+}
+
+/// Parse `market:reference=beta;market2:reference2=beta2` (beta in [0, 1]).
+fn parse_reference_anchors(text: &str) -> Result<Vec<ReferenceAnchor>> {
+    let mut out: Vec<ReferenceAnchor> = Vec::new();
+    for entry in text.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let (market, rest) = entry
+            .split_once(':')
+            .ok_or_else(|| anyhow!("REFERENCE_ANCHORS entry missing ':' ({entry})"))?;
+        let (reference, beta) = rest.split_once('=').ok_or_else(|| {
+            anyhow!("REFERENCE_ANCHORS {market}: expected reference=beta ({rest})")
+        })?;
+        let (market, reference) = (market.trim(), reference.trim());
+        let beta = beta
+            .trim()
+            .parse::<f64>()
+            .map_err(|e| anyhow!("REFERENCE_ANCHORS {market} invalid beta: {e}"))?;
+        if market.is_empty() || reference.is_empty() || market == reference {
+            return Err(anyhow!("REFERENCE_ANCHORS invalid pair ({entry})"));
+        }
+        if !(0.0..=1.0).contains(&beta) {
+            return Err(anyhow!(
+                "REFERENCE_ANCHORS {market}: beta must be in [0, 1] (got {beta})"
+            ));
+        }
+        if out.iter().any(|a| a.market_id == market) {
+            return Err(anyhow!("REFERENCE_ANCHORS {market} anchored twice"));
+        }
+        out.push(ReferenceAnchor {
+            market_id: market.to_string(),
+            reference_market_id: reference.to_string(),
+            beta,
+        });
+    }
+    Ok(out)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Settings {
     // Modes
     pub trade_mode: String,     // paper only supported here
-    pub execution_mode: String, // paper|shadow
-    pub run_mode: String,       // paper|scanner
+    pub execution_mode: String, // paper|bookkeeping|shadow
+    pub run_mode: String,       // paper|scanner|backtest
+    /// Shadow evaluation: |fair - mid| at or above this is flagged as a divergence.
+    pub shadow_divergence_flag: f64,
+    /// Shadow evaluation: min seconds between recorded intents per market (rows with a
+    /// hypothetical fill are always kept).
+    pub shadow_record_interval_secs: f64,
 
     pub disallow_mock_data: bool,
 
     // Network
     pub clob_ws_url: String,
+    /// Book data stream(s): clob_ws | rtds | both (newest book wins).
+    pub feed_source: String,
+    pub rtds_ws_url: String,
     pub gamma_base_url: String,
+    pub ws_incremental_subscribe: bool,
+    /// Cap on WS-subscribed markets (top by selector score); 0 = subscribe all selected.
+    pub max_ws_subscriptions: usize,
+    /// Streamed book updates a market needs after each (re)subscribe before it is quoted; 0 = off.
+    pub feed_stabilize_min_samples: u32,
+    /// WS feed task respawns allowed (without a stable run in between) before trading halts.
+    pub feed_max_restarts: u32,
+    pub feed_restart_backoff_ms: u64, // doubles per consecutive restart
+    /// Poll REST books once the WS has been down this long (polling is off while WS is up).
+    pub ws_fallback_after_secs: f64,
+    pub rest_poll_interval_ms: u64,
 
     // Market selection
     pub top_n_markets: usize,
@@ -58,11 +234,27 @@ pub struct Settings {
     pub min_liquidity_usd: f64,
     pub min_spread_bps: f64,
     pub min_updates_min: f64,
+    /// Reject markets whose live spread is wider than this; 0 = no cap.
+    pub max_spread_bps: f64,
+    /// Per-tag threshold overrides in config order; the first tag the market carries wins.
+    pub selector_tag_overrides: Vec<TagOverride>,
+    /// Max |Gamma outcome price - live CLOB mid| in probability points before a market is
+    /// treated as stale; 0 (the default) = off.
+    pub gamma_price_tolerance: f64,
+    pub gamma_price_divergence_action: String, // deprioritize|skip
+    /// Log Gamma market/event fields the client does not model (API drift diagnostics).
+    pub gamma_schema_diagnostics: bool,
     pub market_refresh_secs: u64,
     pub max_markets_subscribed: usize,
+    pub scanner_candidates_max: usize,
+    pub scanner_candidates_retention_secs: f64,
 
     // Costs / profitability guardrail
     pub fees_bps: f64,
+    /// Paper fee on resting (maker) fills, in bps of notional; negative = rebate.
+    pub maker_fee_bps: f64,
+    /// Paper fee on IOC/snipe/arb (taker) fills, in bps of notional.
+    pub taker_fee_bps: f64,
     pub slippage_bps: f64,
     pub latency_bps: f64,
 
@@ -70,135 +262,543 @@ pub struct Settings {
     pub max_feed_lag_secs: f64,
     pub reject_feed_lag_ms: u64,
     pub reject_abs_imbalance: f64,
+    /// Fat-finger / bad-feed guard: refuse to quote a book wider than this; 0 = off.
+    pub reject_max_spread_bps: f64,
+    /// Book levels per side summed into `Tob` depth (imbalance, microprice, snipe gate).
+    pub depth_levels: usize,
+    /// Price levels per side exposed by `FeedState::get_book` (the full WS book is kept).
+    pub book_levels: usize,
     pub max_inventory_usd: f64,
+    /// Cap on sum(|qty * mark|) across all markets (0, the default, = off).
+    pub max_gross_exposure_usd: f64,
+    /// Cap on |sum(qty * mark)| across all markets (0, the default, = off).
+    pub max_net_exposure_usd: f64,
+    /// Cap on distinct markets holding a position; flat markets stay unquoted at it (0 = off).
+    pub max_markets_with_inventory: usize,
+    /// Flatten a position whose unrealized loss reaches this % of its cost (0 = off).
+    pub stop_loss_pct: f64,
+    /// Flatten a position whose unrealized gain reaches this % of its cost (0 = off).
+    pub take_profit_pct: f64,
+    /// After a stop-loss/take-profit exit, that market's exits are not re-checked for this long.
+    pub exit_debounce_secs: f64,
+    /// Cap on resting orders across all markets (0, the default, = off).
+    pub max_open_orders_global: usize,
 
     // Strategy knobs
     pub price_tick: f64,
+    /// Decimals for displayed prices and stored off-grid values (mid, fair); 0 = from PRICE_TICK.
+    pub price_display_decimals: u32,
     pub mm_quote_width: f64,
     pub mm_levels: usize,
+    /// Ticks between consecutive grid levels (level 1 sits at the quote width); 0 repeats
+    /// the inner offset, so level k sits at k times the quote width.
+    pub mm_level_step_ticks: u32,
+    /// Touch policy: `join` keeps quotes at or behind the best prices;
+    /// `improve_if_spread_ge_ticks` quotes one tick inside them when the spread allows.
+    pub join_or_improve: String,
+    /// Spread (in ticks) from which `improve_if_spread_ge_ticks` improves the touch.
+    pub improve_min_spread_ticks: u32,
+    /// Quote model: `grid` (width + inventory skew) or `as` (Avellaneda-Stoikov).
+    pub quote_model: String,
+    /// `as`: risk aversion; scales the inventory shift and the volatility part of the spread.
+    pub as_gamma: f64,
+    /// `as`: order-book intensity k (fill odds decay per unit of price from the mid).
+    pub as_intensity_k: f64,
+    /// `as`: horizon (secs) the mid variance is projected over.
+    pub as_horizon_secs: f64,
+    /// Target spread capture per round trip (bps of fair); 0 disables the width controller.
+    pub target_capture_bps: f64,
+    pub capture_window_secs: f64,
+    /// Relative width change per window per unit of relative capture error.
+    pub capture_gain: f64,
+    pub capture_deadband_bps: f64,
+    /// Largest relative width change in one window.
+    pub capture_max_step: f64,
+    /// Bounds on the controller's multiplier on MM_QUOTE_WIDTH.
+    pub capture_width_min_mult: f64,
+    pub capture_width_max_mult: f64,
     pub mm_min_quote_life_secs: f64,
+    /// Quote a market only when both a bid and an ask pass the checks; otherwise pull it.
+    pub require_two_sided: bool,
     pub mm_reprice_threshold: f64,
     pub inventory_skew_cap: f64,
+    /// Fair model: `blend` (mid/last-trade blend) or `microprice` (depth-weighted touch).
+    pub fair_source: String,
+    /// Fair blend: mid weight ranges from `fair_mid_weight_min` (thin/wide book) to
+    /// `fair_mid_weight_max` (tight/deep book); last trade gets the remainder.
+    pub fair_mid_weight_min: f64,
+    pub fair_mid_weight_max: f64,
+    /// Spread (price units) at which spread confidence in the mid reaches 0.
+    pub fair_wide_spread: f64,
+    /// Touch depth (shares, both sides) giving 0.5 depth confidence.
+    pub fair_depth_half: f64,
+    /// Last trades older than this (vs the book ts) are ignored; 0 = no age limit.
+    pub fair_trade_max_age_secs: f64,
+    /// Markets whose fair is partly anchored to another market's mid.
+    pub reference_anchors: Vec<ReferenceAnchor>,
+    /// A reference book older than this (vs. the anchored market's book) is ignored.
+    pub reference_max_age_secs: f64,
+    /// Per-market inventory the skew works toward (default flat).
+    pub inventory_targets: Vec<InventoryTarget>,
+    /// Mids a market may be quoted at, both ends inclusive (see `mid_in_tradeable_band`).
+    pub tradeable_price_band: (f64, f64),
+    /// Quote a market only while both book sides hold at least this many shares (top
+    /// `DEPTH_LEVELS`), checked every tick; 0 = off. Independent of `min_liquidity_usd`.
+    pub min_depth_to_quote: f64,
     pub base_order_size: f64,
+    /// Scale quote size with the market's liquidity instead of a flat `base_order_size`.
+    pub size_scale_by_liquidity: bool,
+    /// Fraction of market liquidity (USD) quoted per level when scaling is on.
+    pub size_liquidity_frac: f64,
+    pub min_order_notional_usd: f64,
+    pub max_order_notional_usd: f64,
 
     // Paper realism
     pub paper_fill_model: String,
+    pub mark_source: String, // mid|microprice|last_trade|clob_midpoint
     pub paper_min_rest_secs: f64,
+    /// Minimum broker-clock gap between two passive fills of the same order (0, the default, = off).
+    pub paper_min_secs_between_fills: f64,
+    /// IOC/snipe orders take only external book size, skipping our own resting orders.
+    pub self_trade_prevention: bool,
     pub paper_poisson_lambda_per_sec: f64,
+    /// Per-tick exponential decay of fill intensity behind the touch.
+    pub paper_fill_decay_per_tick: f64,
+    /// Lower bound on the activity multiplier so quiet books still fill occasionally.
+    pub paper_fill_activity_floor: f64,
+    /// `queue` fill model: shares per second traded off the touch (scaled by activity).
+    pub paper_queue_depletion_per_sec: f64,
+    /// Fill resting orders from live trade prints: prints at/through an order's price
+    /// first work off `Order::queue_ahead`, the rest fills us (replaces `paper_fill_model`).
+    pub paper_fill_queue_model: bool,
+    /// Passive partials below this notional are merged into the order's next fill record.
+    pub min_fill_notional_usd: f64,
     pub paper_fault_rate: f64,
     pub paper_non_atomic_fail_rate: f64,
+    /// Delay before a placed order can fill and before a cancel takes effect.
+    pub simulated_latency_ms: f64,
+    /// Quotes are placed GTD, expiring this long after placement (0 = GTC).
+    pub mm_order_ttl_secs: f64,
+    /// Off by default: tiny paper probe orders that measure time-to-fill.
+    pub probe_orders: bool,
+    pub probe_interval_secs: f64,
+    pub probe_max_per_hour: usize,
+    pub probe_size: f64,
+    pub probe_max_notional_usd: f64,
+    pub probe_distance_ticks: u32,
+    pub probe_ttl_secs: f64,
     pub paper_rehydrate_portfolio: bool,
+    /// Saved per-market quote state older than this is not rehydrated.
+    pub quote_state_max_age_secs: f64,
     pub paper_reset_on_start: bool,
 
     // Telemetry / storage / dashboard
     pub sqlite_path: String,
+    pub sqlite_blob_encoding: String, // json|msgpack for new meta/payload blobs
+    /// `PRAGMA wal_autocheckpoint` pages (0 disables automatic checkpoints).
+    pub sqlite_wal_autocheckpoint_pages: u32,
+    /// `PRAGMA busy_timeout`, then up to `sqlite_write_retries` backed-off retries on a lock.
+    pub sqlite_busy_timeout_ms: u32,
+    pub sqlite_write_retries: u32,
+    /// Order in-process writes so scanner bulk upserts yield to the trade loop's writes.
+    pub sqlite_write_gate: bool,
+    /// Rows per transaction for scanner bulk writes (smaller = shorter trader waits).
+    pub sqlite_bulk_chunk_rows: usize,
+    /// Explicit checkpoint cadence from the trader loop (0 = rely on autocheckpoint only).
+    pub sqlite_checkpoint_interval_secs: u64,
+    pub sqlite_checkpoint_truncate: bool,
+    /// Tee logs into this size-rotated file as well as stderr (empty = stderr only).
+    pub log_file: String,
+    pub log_file_max_bytes: u64,
+    pub log_file_max_files: u32,
+    /// Liveness file rewritten with the current timestamp by the trade loop (empty = off).
+    pub heartbeat_path: String,
     pub dashboard_enabled: bool,
     pub dashboard_host: String,
     pub dashboard_port: u16,
     pub dashboard_enable_reset: bool,
+    /// Allow `POST /api/admin/rescan` (and show the dashboard's rescan button).
+    pub dashboard_enable_rescan: bool,
+    /// Allow `POST /api/admin/flatten_all` (and `--flatten-all`).
+    pub dashboard_enable_flatten: bool,
+    /// Allow `POST /api/admin/set_param` to change the live params while trading.
+    pub dashboard_enable_set_param: bool,
     pub dashboard_open_browser: bool,
+    pub tape_record_enabled: bool,
+    /// Max gap between a paper fill and a public trade print for `/api/fill_realism` to pair them.
+    pub fill_realism_window_secs: f64,
 
     // Loop timing
     pub loop_ms: u64,
+    pub adaptive_loop_enabled: bool, // stretch loop_ms when work overruns it
+    pub loop_ms_min: u64,
+    pub loop_ms_max: u64,
     pub eval_interval_secs: u64,
+    pub max_runtime_secs: f64, // 0 = run until stopped
+
+    // Aggregated CLOB + Data health check (always runs) and the safe-mode gate on it
+    pub health_check_interval_secs: f64,
+    pub health_max_age_secs: f64,
+    /// Hold new orders while the last passing health check is older than
+    /// `health_max_age_secs`. Off by default, so paper runs are never blocked.
+    pub safe_mode: bool,
+    /// Cancel all resting orders once the WS has been down this long.
+    pub cancel_on_disconnect: bool,
+    pub cancel_on_disconnect_after_secs: f64,
+    /// After firing, the WS must stay up this long before the guard re-arms.
+    pub cancel_on_disconnect_rearm_secs: f64,
 }
 
 impl Settings {
     pub fn load() -> Result<Self> {
-        let trade_mode = get_env_string("TRADE_MODE", "paper").to_lowercase();
-        let execution_mode = get_env_string("EXECUTION_MODE", "paper").to_lowercase();
-        let run_mode = get_env_string("RUN_MODE", "paper").to_lowercase();
+        Self::load_from(&|key: &str| env::var(key).ok())
+    }
+
+    /// Load settings with `KEY=VALUE` lines from `path` layered over the process env.
+    ///
+    /// Used by replay-compare so each arm can be "base config plus overrides". The
+    /// overrides are only looked up, never written into the (process-global) env.
+    pub fn load_with_overrides(path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| anyhow!("read {path}: {e}"))?;
+        let overrides: HashMap<String, String> = parse_env_file(&text).into_iter().collect();
+        Self::load_from(&|key: &str| overrides.get(key).cloned().or_else(|| env::var(key).ok()))
+    }
+
+    fn load_from(env: Lookup) -> Result<Self> {
+        let trade_mode = get_env_string(env, "TRADE_MODE", "paper").to_lowercase();
+        let execution_mode = get_env_string(env, "EXECUTION_MODE", "paper").to_lowercase();
+        let run_mode = get_env_string(env, "RUN_MODE", "paper").to_lowercase();
+        let paper_reset_on_start = get_env_bool(env, "PAPER_RESET_ON_START", false);
 
         if trade_mode != "paper" {
             return Err(anyhow!(
                 "This Rust bot is paper-only. Set TRADE_MODE=paper (got {trade_mode})"
             ));
         }
-        if !matches!(execution_mode.as_str(), "paper" | "shadow") {
-            return Err(anyhow!("EXECUTION_MODE must be paper|shadow"));
+        if !matches!(execution_mode.as_str(), "paper" | "bookkeeping" | "shadow") {
+            return Err(anyhow!("EXECUTION_MODE must be paper|bookkeeping|shadow"));
         }
-        if !matches!(run_mode.as_str(), "paper" | "scanner") {
-            return Err(anyhow!("RUN_MODE must be paper|scanner"));
+        if !matches!(run_mode.as_str(), "paper" | "scanner" | "backtest") {
+            return Err(anyhow!("RUN_MODE must be paper|scanner|backtest"));
         }
 
-        let disallow_mock_data = get_env_bool("DISALLOW_MOCK_DATA", true);
+        let disallow_mock_data = get_env_bool(env, "DISALLOW_MOCK_DATA", true);
 
         let clob_ws_url = get_env_string(
+            env,
             "POLYMARKET_WS",
             "wss://ws-subscriptions-clob.polymarket.com/ws/market",
         );
-        let gamma_base_url = get_env_string("GAMMA_BASE_URL", "https://gamma-api.polymarket.com");
+        let gamma_base_url =
+            get_env_string(env, "GAMMA_BASE_URL", "https://gamma-api.polymarket.com");
+
+        let fees_bps = get_env_f64(env, "FEES_BPS", 0.0)?;
+        let slippage_bps = get_env_f64(env, "SLIPPAGE_BPS", 20.0)?;
+        let latency_bps = get_env_f64(env, "LATENCY_BPS", 10.0)?;
 
-        let fees_bps = get_env_f64("FEES_BPS", 0.0)?;
-        let slippage_bps = get_env_f64("SLIPPAGE_BPS", 20.0)?;
-        let latency_bps = get_env_f64("LATENCY_BPS", 10.0)?;
+        let paper_fill_model =
+            get_env_string(env, "PAPER_FILL_MODEL", "maker_touch").to_lowercase();
 
-        let paper_fill_model = get_env_string("PAPER_FILL_MODEL", "maker_touch").to_lowercase();
-        if paper_fill_model != "maker_touch" {
+        let paper_min_rest_secs = get_env_f64(env, "PAPER_MIN_REST_SECS", 1.0)?;
+
+        let mark_source = get_env_string(env, "MARK_SOURCE", "mid").to_lowercase();
+        if !matches!(
+            mark_source.as_str(),
+            "mid" | "microprice" | "last_trade" | "clob_midpoint"
+        ) {
             return Err(anyhow!(
-                "Only PAPER_FILL_MODEL=maker_touch is supported (got {paper_fill_model})"
+                "MARK_SOURCE must be mid|microprice|last_trade|clob_midpoint (got {mark_source})"
             ));
         }
 
-        let paper_min_rest_secs = get_env_f64("PAPER_MIN_REST_SECS", 1.0)?;
-
         let s = Self {
             trade_mode,
             execution_mode,
             run_mode,
+            shadow_divergence_flag: get_env_f64(env, "SHADOW_DIVERGENCE_FLAG", 0.03)?,
+            shadow_record_interval_secs: get_env_f64(env, "SHADOW_RECORD_INTERVAL_SECS", 1.0)?,
             disallow_mock_data,
             clob_ws_url,
+            feed_source: get_env_string(env, "FEED_SOURCE", "clob_ws").to_lowercase(),
+            rtds_ws_url: get_env_string(env, "RTDS_WS_URL", "wss://ws-live-data.polymarket.com"),
             gamma_base_url,
-            top_n_markets: get_env_usize("TOP_N_MARKETS", 50)?,
-            min_24h_volume_usd: get_env_f64("MIN_24H_VOLUME_USD", 10_000.0)?,
-            min_liquidity_usd: get_env_f64("MIN_LIQUIDITY_USD", 20_000.0)?,
-            min_spread_bps: get_env_f64("MIN_SPREAD_BPS", 10.0)?,
-            min_updates_min: get_env_f64("MIN_UPDATES_MIN", 5.0)?,
-            market_refresh_secs: get_env_f64("MARKET_REFRESH_SECS", 60.0)? as u64,
-            max_markets_subscribed: get_env_usize("MAX_MARKETS_SUBSCRIBED", 30)?,
+            ws_incremental_subscribe: get_env_bool(env, "WS_INCREMENTAL_SUBSCRIBE", true),
+            max_ws_subscriptions: get_env_usize(env, "MAX_WS_SUBSCRIPTIONS", 0)?,
+            feed_stabilize_min_samples: get_env_usize(env, "FEED_STABILIZE_MIN_SAMPLES", 0)? as u32,
+            feed_max_restarts: get_env_usize(env, "FEED_MAX_RESTARTS", 5)? as u32,
+            feed_restart_backoff_ms: get_env_usize(env, "FEED_RESTART_BACKOFF_MS", 1000)? as u64,
+            ws_fallback_after_secs: get_env_f64(env, "WS_FALLBACK_AFTER_SECS", 5.0)?,
+            rest_poll_interval_ms: get_env_usize(env, "REST_POLL_INTERVAL_MS", 1000)? as u64,
+            top_n_markets: get_env_usize(env, "TOP_N_MARKETS", 50)?,
+            min_24h_volume_usd: get_env_f64(env, "MIN_24H_VOLUME_USD", 10_000.0)?,
+            min_liquidity_usd: get_env_f64(env, "MIN_LIQUIDITY_USD", 20_000.0)?,
+            min_spread_bps: get_env_f64(env, "MIN_SPREAD_BPS", 10.0)?,
+            min_updates_min: get_env_f64(env, "MIN_UPDATES_MIN", 5.0)?,
+            max_spread_bps: get_env_f64(env, "MAX_SPREAD_BPS", 0.0)?,
+            selector_tag_overrides: parse_tag_overrides(&get_env_string(
+                env,
+                "SELECTOR_TAG_OVERRIDES",
+                "",
+            ))?,
+            gamma_price_tolerance: get_env_f64(env, "GAMMA_PRICE_TOLERANCE", 0.0)?,
+            gamma_price_divergence_action: get_env_string(
+                env,
+                "GAMMA_PRICE_DIVERGENCE_ACTION",
+                "deprioritize",
+            )
+            .to_lowercase(),
+            gamma_schema_diagnostics: get_env_bool(env, "GAMMA_SCHEMA_DIAGNOSTICS", false),
+            market_refresh_secs: get_env_f64(env, "MARKET_REFRESH_SECS", 60.0)? as u64,
+            max_markets_subscribed: get_env_usize(env, "MAX_MARKETS_SUBSCRIBED", 30)?,
+            scanner_candidates_max: get_env_usize(env, "SCANNER_CANDIDATES_MAX", 100)?,
+            scanner_candidates_retention_secs: get_env_f64(
+                env,
+                "SCANNER_CANDIDATES_RETENTION_SECS",
+                86_400.0,
+            )?,
             fees_bps,
+            maker_fee_bps: get_env_f64(env, "MAKER_FEE_BPS", 0.0)?,
+            taker_fee_bps: get_env_f64(env, "TAKER_FEE_BPS", 0.0)?,
             slippage_bps,
             latency_bps,
-            max_feed_lag_secs: get_env_f64("MAX_FEED_LAG_SECS", 300.0)?,
-            reject_feed_lag_ms: get_env_usize("REJECT_FEED_LAG_MS", 100)? as u64,
-            reject_abs_imbalance: get_env_f64("REJECT_ABS_IMBALANCE", 0.5)?,
-            max_inventory_usd: get_env_f64("MAX_INVENTORY_USD", 5000.0)?,
-            price_tick: get_env_f64("PRICE_TICK", 0.001)?,
-            mm_quote_width: get_env_f64("MM_QUOTE_WIDTH", 0.02)?,
-            mm_levels: get_env_usize("MM_LEVELS", 7)?,
-            mm_min_quote_life_secs: get_env_f64("MM_MIN_QUOTE_LIFE_SECS", 5.0)?,
-            mm_reprice_threshold: get_env_f64("MM_REPRICE_THRESHOLD", 0.005)?,
-            inventory_skew_cap: get_env_f64("INVENTORY_SKEW_CAP", 0.003)?,
-            base_order_size: get_env_f64("BASE_ORDER_SIZE", 10.0)?,
+            max_feed_lag_secs: get_env_f64(env, "MAX_FEED_LAG_SECS", 300.0)?,
+            reject_feed_lag_ms: get_env_usize(env, "REJECT_FEED_LAG_MS", 100)? as u64,
+            reject_abs_imbalance: get_env_f64(env, "REJECT_ABS_IMBALANCE", 0.5)?,
+            reject_max_spread_bps: get_env_f64(env, "REJECT_MAX_SPREAD_BPS", 10_000.0)?,
+            depth_levels: get_env_usize(env, "DEPTH_LEVELS", 5)?,
+            book_levels: get_env_usize(env, "BOOK_LEVELS", 10)?,
+            max_inventory_usd: get_env_f64(env, "MAX_INVENTORY_USD", 5000.0)?,
+            max_gross_exposure_usd: get_env_f64(env, "MAX_GROSS_EXPOSURE_USD", 0.0)?,
+            max_net_exposure_usd: get_env_f64(env, "MAX_NET_EXPOSURE_USD", 0.0)?,
+            max_markets_with_inventory: get_env_usize(env, "MAX_MARKETS_WITH_INVENTORY", 0)?,
+            stop_loss_pct: get_env_f64(env, "STOP_LOSS_PCT", 0.0)?,
+            take_profit_pct: get_env_f64(env, "TAKE_PROFIT_PCT", 0.0)?,
+            exit_debounce_secs: get_env_f64(env, "EXIT_DEBOUNCE_SECS", 30.0)?,
+            max_open_orders_global: get_env_usize(env, "MAX_OPEN_ORDERS_GLOBAL", 0)?,
+            price_tick: get_env_f64(env, "PRICE_TICK", 0.001)?,
+            price_display_decimals: get_env_usize(env, "PRICE_DISPLAY_DECIMALS", 0)? as u32,
+            mm_quote_width: get_env_f64(env, "MM_QUOTE_WIDTH", 0.02)?,
+            mm_levels: get_env_usize(env, "MM_LEVELS", 7)?,
+            mm_level_step_ticks: get_env_usize(env, "MM_LEVEL_STEP_TICKS", 0)? as u32,
+            join_or_improve: get_env_string(env, "JOIN_OR_IMPROVE", "join"),
+            quote_model: get_env_string(env, "QUOTE_MODEL", "grid").to_lowercase(),
+            as_gamma: get_env_f64(env, "AS_GAMMA", 0.1)?,
+            as_intensity_k: get_env_f64(env, "AS_INTENSITY_K", 100.0)?,
+            as_horizon_secs: get_env_f64(env, "AS_HORIZON_SECS", 60.0)?,
+            improve_min_spread_ticks: get_env_usize(env, "IMPROVE_MIN_SPREAD_TICKS", 3)? as u32,
+            target_capture_bps: get_env_f64(env, "TARGET_CAPTURE_BPS", 0.0)?,
+            capture_window_secs: get_env_f64(env, "CAPTURE_WINDOW_SECS", 60.0)?,
+            capture_gain: get_env_f64(env, "CAPTURE_GAIN", 0.5)?,
+            capture_deadband_bps: get_env_f64(env, "CAPTURE_DEADBAND_BPS", 5.0)?,
+            capture_max_step: get_env_f64(env, "CAPTURE_MAX_STEP", 0.1)?,
+            capture_width_min_mult: get_env_f64(env, "CAPTURE_WIDTH_MIN_MULT", 0.5)?,
+            capture_width_max_mult: get_env_f64(env, "CAPTURE_WIDTH_MAX_MULT", 3.0)?,
+            mm_min_quote_life_secs: get_env_f64(env, "MM_MIN_QUOTE_LIFE_SECS", 5.0)?,
+            require_two_sided: get_env_bool(env, "REQUIRE_TWO_SIDED", false),
+            mm_reprice_threshold: get_env_f64(env, "MM_REPRICE_THRESHOLD", 0.005)?,
+            inventory_skew_cap: get_env_f64(env, "INVENTORY_SKEW_CAP", 0.003)?,
+            fair_source: get_env_string(env, "FAIR_SOURCE", "blend").to_lowercase(),
+            fair_mid_weight_min: get_env_f64(env, "FAIR_MID_WEIGHT_MIN", 0.4)?,
+            fair_mid_weight_max: get_env_f64(env, "FAIR_MID_WEIGHT_MAX", 0.9)?,
+            fair_wide_spread: get_env_f64(env, "FAIR_WIDE_SPREAD", 0.06)?,
+            fair_depth_half: get_env_f64(env, "FAIR_DEPTH_HALF", 500.0)?,
+            fair_trade_max_age_secs: get_env_f64(env, "FAIR_TRADE_MAX_AGE_SECS", 300.0)?,
+            reference_anchors: parse_reference_anchors(&get_env_string(
+                env,
+                "REFERENCE_ANCHORS",
+                "",
+            ))?,
+            reference_max_age_secs: get_env_f64(env, "REFERENCE_MAX_AGE_SECS", 10.0)?,
+            inventory_targets: parse_inventory_targets(&get_env_string(
+                env,
+                "INVENTORY_TARGETS",
+                "",
+            ))?,
+            tradeable_price_band: parse_price_band(&get_env_string(
+                env,
+                "TRADEABLE_PRICE_BAND",
+                "0,1",
+            ))?,
+            min_depth_to_quote: get_env_f64(env, "MIN_DEPTH_TO_QUOTE", 0.0)?,
+            base_order_size: get_env_f64(env, "BASE_ORDER_SIZE", 10.0)?,
+            size_scale_by_liquidity: get_env_bool(env, "SIZE_SCALE_BY_LIQUIDITY", false),
+            size_liquidity_frac: get_env_f64(env, "SIZE_LIQUIDITY_FRAC", 0.0005)?,
+            min_order_notional_usd: get_env_f64(env, "MIN_ORDER_NOTIONAL_USD", 1.0)?,
+            max_order_notional_usd: get_env_f64(env, "MAX_ORDER_NOTIONAL_USD", 50.0)?,
             paper_fill_model,
+            mark_source,
             paper_min_rest_secs,
-            paper_poisson_lambda_per_sec: get_env_f64("PAPER_POISSON_LAMBDA_PER_SEC", 0.5)?,
-            paper_fault_rate: get_env_f64("PAPER_FAULT_RATE", 0.08)?,
-            paper_non_atomic_fail_rate: get_env_f64("PAPER_NON_ATOMIC_FAIL_RATE", 0.02)?,
-            paper_rehydrate_portfolio: get_env_bool("PAPER_REHYDRATE_PORTFOLIO", true),
-            paper_reset_on_start: get_env_bool("PAPER_RESET_ON_START", false),
-            sqlite_path: get_env_string("SQLITE_PATH", "./data/polymarket_trader.sqlite"),
-            dashboard_enabled: get_env_bool("DASHBOARD_ENABLED", true),
-            dashboard_host: get_env_string("DASHBOARD_HOST", "127.0.0.1"),
-            dashboard_port: get_env_usize("DASHBOARD_PORT", 8000)? as u16,
-            dashboard_enable_reset: get_env_bool("DASHBOARD_ENABLE_RESET", false),
-            dashboard_open_browser: get_env_bool("DASHBOARD_OPEN_BROWSER", true),
-            loop_ms: get_env_usize("LOOP_MS", 50)? as u64,
-            eval_interval_secs: get_env_usize("EVAL_INTERVAL_SECS", 600)? as u64,
+            paper_min_secs_between_fills: get_env_f64(env, "PAPER_MIN_SECS_BETWEEN_FILLS", 0.0)?,
+            self_trade_prevention: get_env_bool(env, "SELF_TRADE_PREVENTION", true),
+            paper_poisson_lambda_per_sec: get_env_f64(env, "PAPER_POISSON_LAMBDA_PER_SEC", 0.5)?,
+            paper_fill_decay_per_tick: get_env_f64(env, "PAPER_FILL_DECAY_PER_TICK", 0.7)?,
+            paper_fill_activity_floor: get_env_f64(env, "PAPER_FILL_ACTIVITY_FLOOR", 0.05)?,
+            paper_queue_depletion_per_sec: get_env_f64(env, "PAPER_QUEUE_DEPLETION_PER_SEC", 25.0)?,
+            paper_fill_queue_model: get_env_bool(env, "PAPER_FILL_QUEUE_MODEL", false),
+            min_fill_notional_usd: get_env_f64(env, "MIN_FILL_NOTIONAL_USD", 0.0)?,
+            paper_fault_rate: get_env_f64(env, "PAPER_FAULT_RATE", 0.08)?,
+            paper_non_atomic_fail_rate: get_env_f64(env, "PAPER_NON_ATOMIC_FAIL_RATE", 0.02)?,
+            simulated_latency_ms: get_env_f64(env, "SIMULATED_LATENCY_MS", 0.0)?,
+            mm_order_ttl_secs: get_env_f64(env, "MM_ORDER_TTL_SECS", 0.0)?,
+            probe_orders: get_env_bool(env, "PROBE_ORDERS", false),
+            probe_interval_secs: get_env_f64(env, "PROBE_INTERVAL_SECS", 600.0)?,
+            probe_max_per_hour: get_env_usize(env, "PROBE_MAX_PER_HOUR", 4)?,
+            probe_size: get_env_f64(env, "PROBE_SIZE", 5.0)?,
+            probe_max_notional_usd: get_env_f64(env, "PROBE_MAX_NOTIONAL_USD", 2.0)?,
+            probe_distance_ticks: get_env_usize(env, "PROBE_DISTANCE_TICKS", 1)? as u32,
+            probe_ttl_secs: get_env_f64(env, "PROBE_TTL_SECS", 60.0)?,
+            // Mutually exclusive: rehydrate defaults on only when the reset is not requested.
+            paper_rehydrate_portfolio: get_env_bool(
+                env,
+                "PAPER_REHYDRATE_PORTFOLIO",
+                !paper_reset_on_start,
+            ),
+            quote_state_max_age_secs: get_env_f64(env, "QUOTE_STATE_MAX_AGE_SECS", 30.0)?,
+            paper_reset_on_start,
+            sqlite_path: get_env_string(env, "SQLITE_PATH", "./data/polymarket_trader.sqlite"),
+            sqlite_blob_encoding: get_env_string(env, "SQLITE_BLOB_ENCODING", "json")
+                .to_lowercase(),
+            sqlite_wal_autocheckpoint_pages: get_env_usize(
+                env,
+                "SQLITE_WAL_AUTOCHECKPOINT_PAGES",
+                1000,
+            )? as u32,
+            sqlite_busy_timeout_ms: get_env_usize(env, "SQLITE_BUSY_TIMEOUT_MS", 5000)? as u32,
+            sqlite_write_retries: get_env_usize(env, "SQLITE_WRITE_RETRIES", 3)? as u32,
+            sqlite_write_gate: get_env_bool(env, "SQLITE_WRITE_GATE", true),
+            sqlite_bulk_chunk_rows: get_env_usize(env, "SQLITE_BULK_CHUNK_ROWS", 200)?,
+            sqlite_checkpoint_interval_secs: get_env_usize(
+                env,
+                "SQLITE_CHECKPOINT_INTERVAL_SECS",
+                300,
+            )? as u64,
+            sqlite_checkpoint_truncate: get_env_bool(env, "SQLITE_CHECKPOINT_TRUNCATE", false),
+            log_file: get_env_string(env, "LOG_FILE", ""),
+            log_file_max_bytes: get_env_usize(env, "LOG_FILE_MAX_BYTES", 10 * 1024 * 1024)? as u64,
+            log_file_max_files: get_env_usize(env, "LOG_FILE_MAX_FILES", 5)? as u32,
+            heartbeat_path: get_env_string(env, "HEARTBEAT_PATH", ""),
+            dashboard_enabled: get_env_bool(env, "DASHBOARD_ENABLED", true),
+            dashboard_host: get_env_string(env, "DASHBOARD_HOST", "127.0.0.1"),
+            dashboard_port: get_env_usize(env, "DASHBOARD_PORT", 8000)? as u16,
+            dashboard_enable_reset: get_env_bool(env, "DASHBOARD_ENABLE_RESET", false),
+            dashboard_enable_rescan: get_env_bool(env, "DASHBOARD_ENABLE_RESCAN", false),
+            dashboard_enable_flatten: get_env_bool(env, "DASHBOARD_ENABLE_FLATTEN", false),
+            dashboard_enable_set_param: get_env_bool(env, "DASHBOARD_ENABLE_SET_PARAM", false),
+            dashboard_open_browser: get_env_bool(env, "DASHBOARD_OPEN_BROWSER", true),
+            tape_record_enabled: get_env_bool(env, "TAPE_RECORD_ENABLED", false),
+            fill_realism_window_secs: get_env_f64(env, "FILL_REALISM_WINDOW_SECS", 5.0)?,
+            loop_ms: get_env_usize(env, "LOOP_MS", 50)? as u64,
+            adaptive_loop_enabled: get_env_bool(env, "ADAPTIVE_LOOP_ENABLED", false),
+            loop_ms_min: get_env_usize(env, "LOOP_MS_MIN", 10)? as u64,
+            loop_ms_max: get_env_usize(env, "LOOP_MS_MAX", 1000)? as u64,
+            eval_interval_secs: get_env_usize(env, "EVAL_INTERVAL_SECS", 600)? as u64,
+            max_runtime_secs: get_env_f64(env, "MAX_RUNTIME_SECS", 0.0)?,
+            health_check_interval_secs: get_env_f64(env, "HEALTH_CHECK_INTERVAL_SECS", 15.0)?,
+            health_max_age_secs: get_env_f64(env, "HEALTH_MAX_AGE_SECS", 60.0)?,
+            safe_mode: get_env_bool(env, "SAFE_MODE", false),
+            cancel_on_disconnect: get_env_bool(env, "CANCEL_ON_DISCONNECT", false),
+            cancel_on_disconnect_after_secs: get_env_f64(
+                env,
+                "CANCEL_ON_DISCONNECT_AFTER_SECS",
+                10.0,
+            )?,
+            cancel_on_disconnect_rearm_secs: get_env_f64(
+                env,
+                "CANCEL_ON_DISCONNECT_REARM_SECS",
+                30.0,
+            )?,
         };
 
         s.validate()?;
         Ok(s)
     }
 
+    /// True when `mid` is inside `TRADEABLE_PRICE_BAND`: `min <= mid <= max`, so a mid
+    /// sitting exactly on either bound is still quoted.
+    pub fn mid_in_tradeable_band(&self, mid: f64) -> bool {
+        let (lo, hi) = self.tradeable_price_band;
+        mid >= lo && mid <= hi
+    }
+
     pub fn cost_bps(&self) -> f64 {
         self.fees_bps + self.slippage_bps + self.latency_bps
     }
 
+    /// Display precision for prices: `PRICE_DISPLAY_DECIMALS`, else the decimals of `PRICE_TICK`.
+    pub fn price_decimals(&self) -> u32 {
+        if self.price_display_decimals > 0 {
+            self.price_display_decimals
+        } else {
+            crate::utils::tick_decimals(self.price_tick)
+        }
+    }
+
     pub fn validate(&self) -> Result<()> {
         if !self.price_tick.is_finite() || self.price_tick <= 0.0 {
             return Err(anyhow!("PRICE_TICK must be > 0 (got {})", self.price_tick));
         }
+        if !self.shadow_divergence_flag.is_finite() || self.shadow_divergence_flag < 0.0 {
+            return Err(anyhow!(
+                "SHADOW_DIVERGENCE_FLAG must be >= 0 (got {})",
+                self.shadow_divergence_flag
+            ));
+        }
+        if !self.shadow_record_interval_secs.is_finite() || self.shadow_record_interval_secs < 0.0 {
+            return Err(anyhow!(
+                "SHADOW_RECORD_INTERVAL_SECS must be >= 0 (got {})",
+                self.shadow_record_interval_secs
+            ));
+        }
+        if self.price_display_decimals > 10 {
+            return Err(anyhow!(
+                "PRICE_DISPLAY_DECIMALS must be <= 10 (got {})",
+                self.price_display_decimals
+            ));
+        }
+        if self.target_capture_bps < 0.0 || !self.target_capture_bps.is_finite() {
+            return Err(anyhow!(
+                "TARGET_CAPTURE_BPS must be >= 0 (got {})",
+                self.target_capture_bps
+            ));
+        }
+        if !self.maker_fee_bps.is_finite() || self.maker_fee_bps <= -10_000.0 {
+            return Err(anyhow!(
+                "MAKER_FEE_BPS must be > -10000 (got {})",
+                self.maker_fee_bps
+            ));
+        }
+        if !self.taker_fee_bps.is_finite() || self.taker_fee_bps < 0.0 {
+            return Err(anyhow!(
+                "TAKER_FEE_BPS must be >= 0 (got {})",
+                self.taker_fee_bps
+            ));
+        }
+        if self.target_capture_bps > 0.0 {
+            if self.capture_window_secs <= 0.0 || self.capture_gain < 0.0 {
+                return Err(anyhow!(
+                    "CAPTURE_WINDOW_SECS must be > 0 and CAPTURE_GAIN >= 0 (got {} / {})",
+                    self.capture_window_secs,
+                    self.capture_gain
+                ));
+            }
+            if !(0.0..1.0).contains(&self.capture_max_step) || self.capture_deadband_bps < 0.0 {
+                return Err(anyhow!(
+                    "CAPTURE_MAX_STEP must be in [0, 1) and CAPTURE_DEADBAND_BPS >= 0 (got {} / {})",
+                    self.capture_max_step,
+                    self.capture_deadband_bps
+                ));
+            }
+            if !(self.capture_width_min_mult > 0.0
+                && self.capture_width_min_mult <= 1.0
+                && self.capture_width_max_mult >= 1.0)
+            {
+                return Err(anyhow!(
+                    "need 0 < CAPTURE_WIDTH_MIN_MULT <= 1 <= CAPTURE_WIDTH_MAX_MULT (got {} / {})",
+                    self.capture_width_min_mult,
+                    self.capture_width_max_mult
+                ));
+            }
+        }
         if !self.mm_quote_width.is_finite() || self.mm_quote_width < self.price_tick {
             return Err(anyhow!(
                 "MM_QUOTE_WIDTH must be >= PRICE_TICK (mm_quote_width={} price_tick={})",
@@ -206,12 +806,163 @@ impl Settings {
                 self.price_tick
             ));
         }
+        if self.depth_levels < 1 {
+            return Err(anyhow!(
+                "DEPTH_LEVELS must be >= 1 (got {})",
+                self.depth_levels
+            ));
+        }
+        if self.book_levels < 1 {
+            return Err(anyhow!(
+                "BOOK_LEVELS must be >= 1 (got {})",
+                self.book_levels
+            ));
+        }
         if self.mm_levels < 1 {
             return Err(anyhow!("MM_LEVELS must be >= 1 (got {})", self.mm_levels));
         }
+        if !matches!(
+            self.join_or_improve.as_str(),
+            "join" | "improve_if_spread_ge_ticks"
+        ) {
+            return Err(anyhow!(
+                "JOIN_OR_IMPROVE must be join|improve_if_spread_ge_ticks (got {})",
+                self.join_or_improve
+            ));
+        }
+        if !matches!(self.quote_model.as_str(), "grid" | "as") {
+            return Err(anyhow!(
+                "QUOTE_MODEL must be grid|as (got {})",
+                self.quote_model
+            ));
+        }
+        for (name, v) in [
+            ("AS_GAMMA", self.as_gamma),
+            ("AS_INTENSITY_K", self.as_intensity_k),
+            ("AS_HORIZON_SECS", self.as_horizon_secs),
+        ] {
+            if !v.is_finite() || v <= 0.0 {
+                return Err(anyhow!("{name} must be > 0 (got {v})"));
+            }
+        }
+        // Improving both sides by a tick must still leave a tick between them.
+        if self.improve_min_spread_ticks < 3 {
+            return Err(anyhow!(
+                "IMPROVE_MIN_SPREAD_TICKS must be >= 3 (got {})",
+                self.improve_min_spread_ticks
+            ));
+        }
         if self.loop_ms < 1 {
             return Err(anyhow!("LOOP_MS must be >= 1 (got {})", self.loop_ms));
         }
+        if self.sqlite_bulk_chunk_rows < 1 {
+            return Err(anyhow!(
+                "SQLITE_BULK_CHUNK_ROWS must be >= 1 (got {})",
+                self.sqlite_bulk_chunk_rows
+            ));
+        }
+        if self.adaptive_loop_enabled
+            && !(1 <= self.loop_ms_min
+                && self.loop_ms_min <= self.loop_ms
+                && self.loop_ms <= self.loop_ms_max)
+        {
+            return Err(anyhow!(
+                "adaptive loop needs 1 <= LOOP_MS_MIN <= LOOP_MS <= LOOP_MS_MAX (got {} / {} / {})",
+                self.loop_ms_min,
+                self.loop_ms,
+                self.loop_ms_max
+            ));
+        }
+        if !self.max_runtime_secs.is_finite() || self.max_runtime_secs < 0.0 {
+            return Err(anyhow!(
+                "MAX_RUNTIME_SECS must be >= 0 (got {})",
+                self.max_runtime_secs
+            ));
+        }
+        if !self.health_check_interval_secs.is_finite() || self.health_check_interval_secs <= 0.0 {
+            return Err(anyhow!(
+                "HEALTH_CHECK_INTERVAL_SECS must be > 0 (got {})",
+                self.health_check_interval_secs
+            ));
+        }
+        if !crate::fill_model::FILL_MODELS.contains(&self.paper_fill_model.as_str()) {
+            return Err(anyhow!(
+                "PAPER_FILL_MODEL must be one of {} (got {})",
+                crate::fill_model::FILL_MODELS.join("|"),
+                self.paper_fill_model
+            ));
+        }
+        if !self.paper_queue_depletion_per_sec.is_finite()
+            || self.paper_queue_depletion_per_sec < 0.0
+        {
+            return Err(anyhow!(
+                "PAPER_QUEUE_DEPLETION_PER_SEC must be >= 0 (got {})",
+                self.paper_queue_depletion_per_sec
+            ));
+        }
+        if !self.paper_fill_decay_per_tick.is_finite() || self.paper_fill_decay_per_tick < 0.0 {
+            return Err(anyhow!(
+                "PAPER_FILL_DECAY_PER_TICK must be >= 0 (got {})",
+                self.paper_fill_decay_per_tick
+            ));
+        }
+        if !self.paper_fill_activity_floor.is_finite() || self.paper_fill_activity_floor < 0.0 {
+            return Err(anyhow!(
+                "PAPER_FILL_ACTIVITY_FLOOR must be >= 0 (got {})",
+                self.paper_fill_activity_floor
+            ));
+        }
+        if !matches!(self.feed_source.as_str(), "clob_ws" | "rtds" | "both") {
+            return Err(anyhow!(
+                "FEED_SOURCE must be clob_ws|rtds|both (got {})",
+                self.feed_source
+            ));
+        }
+        if !self.ws_fallback_after_secs.is_finite() || self.ws_fallback_after_secs < 0.0 {
+            return Err(anyhow!(
+                "WS_FALLBACK_AFTER_SECS must be >= 0 (got {})",
+                self.ws_fallback_after_secs
+            ));
+        }
+        if self.rest_poll_interval_ms < 1 {
+            return Err(anyhow!(
+                "REST_POLL_INTERVAL_MS must be >= 1 (got {})",
+                self.rest_poll_interval_ms
+            ));
+        }
+        if !self.mm_order_ttl_secs.is_finite() || self.mm_order_ttl_secs < 0.0 {
+            return Err(anyhow!(
+                "MM_ORDER_TTL_SECS must be >= 0 (got {})",
+                self.mm_order_ttl_secs
+            ));
+        }
+        if !self.min_fill_notional_usd.is_finite() || self.min_fill_notional_usd < 0.0 {
+            return Err(anyhow!(
+                "MIN_FILL_NOTIONAL_USD must be >= 0 (got {})",
+                self.min_fill_notional_usd
+            ));
+        }
+        if !self.simulated_latency_ms.is_finite() || self.simulated_latency_ms < 0.0 {
+            return Err(anyhow!(
+                "SIMULATED_LATENCY_MS must be >= 0 (got {})",
+                self.simulated_latency_ms
+            ));
+        }
+        for (name, v) in [
+            ("STOP_LOSS_PCT", self.stop_loss_pct),
+            ("TAKE_PROFIT_PCT", self.take_profit_pct),
+            ("EXIT_DEBOUNCE_SECS", self.exit_debounce_secs),
+        ] {
+            if !v.is_finite() || v < 0.0 {
+                return Err(anyhow!("{name} must be >= 0 (got {v})"));
+            }
+        }
+        if self.stop_loss_pct >= 100.0 {
+            return Err(anyhow!(
+                "STOP_LOSS_PCT must be < 100 (got {})",
+                self.stop_loss_pct
+            ));
+        }
         if self.market_refresh_secs < 1 {
             return Err(anyhow!(
                 "MARKET_REFRESH_SECS must be >= 1 (got {})",
@@ -242,12 +993,313 @@ impl Settings {
                 self.mm_min_quote_life_secs
             ));
         }
+        if !self.min_depth_to_quote.is_finite() || self.min_depth_to_quote < 0.0 {
+            return Err(anyhow!(
+                "MIN_DEPTH_TO_QUOTE must be >= 0 (got {})",
+                self.min_depth_to_quote
+            ));
+        }
         if !self.base_order_size.is_finite() || self.base_order_size <= 0.0 {
             return Err(anyhow!(
                 "BASE_ORDER_SIZE must be > 0 (got {})",
                 self.base_order_size
             ));
         }
+        if !self.max_spread_bps.is_finite() || self.max_spread_bps < 0.0 {
+            return Err(anyhow!(
+                "MAX_SPREAD_BPS must be >= 0 (got {})",
+                self.max_spread_bps
+            ));
+        }
+        if !self.cancel_on_disconnect_after_secs.is_finite()
+            || self.cancel_on_disconnect_after_secs <= 0.0
+        {
+            return Err(anyhow!(
+                "CANCEL_ON_DISCONNECT_AFTER_SECS must be > 0 (got {})",
+                self.cancel_on_disconnect_after_secs
+            ));
+        }
+        if !self.cancel_on_disconnect_rearm_secs.is_finite()
+            || self.cancel_on_disconnect_rearm_secs < 0.0
+        {
+            return Err(anyhow!(
+                "CANCEL_ON_DISCONNECT_REARM_SECS must be >= 0 (got {})",
+                self.cancel_on_disconnect_rearm_secs
+            ));
+        }
+        crate::store::BlobEncoding::parse(&self.sqlite_blob_encoding)?;
+        if !self.reject_max_spread_bps.is_finite() || self.reject_max_spread_bps < 0.0 {
+            return Err(anyhow!(
+                "REJECT_MAX_SPREAD_BPS must be >= 0 (got {})",
+                self.reject_max_spread_bps
+            ));
+        }
+        if !self.fill_realism_window_secs.is_finite() || self.fill_realism_window_secs < 0.0 {
+            return Err(anyhow!(
+                "FILL_REALISM_WINDOW_SECS must be >= 0 (got {})",
+                self.fill_realism_window_secs
+            ));
+        }
+        if !self.size_liquidity_frac.is_finite() || self.size_liquidity_frac <= 0.0 {
+            return Err(anyhow!(
+                "SIZE_LIQUIDITY_FRAC must be > 0 (got {})",
+                self.size_liquidity_frac
+            ));
+        }
+        if !self.min_order_notional_usd.is_finite() || self.min_order_notional_usd <= 0.0 {
+            return Err(anyhow!(
+                "MIN_ORDER_NOTIONAL_USD must be > 0 (got {})",
+                self.min_order_notional_usd
+            ));
+        }
+        if !self.max_order_notional_usd.is_finite()
+            || self.max_order_notional_usd < self.min_order_notional_usd
+        {
+            return Err(anyhow!(
+                "MAX_ORDER_NOTIONAL_USD must be >= MIN_ORDER_NOTIONAL_USD (got {} < {})",
+                self.max_order_notional_usd,
+                self.min_order_notional_usd
+            ));
+        }
+        for (name, v) in [
+            ("PROBE_INTERVAL_SECS", self.probe_interval_secs),
+            ("PROBE_SIZE", self.probe_size),
+            ("PROBE_MAX_NOTIONAL_USD", self.probe_max_notional_usd),
+            ("PROBE_TTL_SECS", self.probe_ttl_secs),
+        ] {
+            if !v.is_finite() || v <= 0.0 {
+                return Err(anyhow!("{name} must be > 0 (got {v})"));
+            }
+        }
+        if self.probe_orders && self.probe_max_per_hour == 0 {
+            return Err(anyhow!(
+                "PROBE_MAX_PER_HOUR must be >= 1 when PROBE_ORDERS=1"
+            ));
+        }
+        if self.probe_orders && self.probe_max_notional_usd < self.min_order_notional_usd {
+            return Err(anyhow!(
+                "PROBE_MAX_NOTIONAL_USD must be >= MIN_ORDER_NOTIONAL_USD when PROBE_ORDERS=1 (got {} < {})",
+                self.probe_max_notional_usd,
+                self.min_order_notional_usd
+            ));
+        }
+        // The per-market cap is applied to share counts (the quoting guard compares
+        // position qty with it), so targets are held to it in shares too.
+        if let Some(t) = self
+            .inventory_targets
+            .iter()
+            .find(|t| t.qty.abs() > self.max_inventory_usd)
+        {
+            return Err(anyhow!(
+                "INVENTORY_TARGETS {}: {} shares exceeds the MAX_INVENTORY_USD position cap {}",
+                t.market_id,
+                t.qty,
+                self.max_inventory_usd
+            ));
+        }
+        if !self.gamma_price_tolerance.is_finite()
+            || !(0.0..=1.0).contains(&self.gamma_price_tolerance)
+        {
+            return Err(anyhow!(
+                "GAMMA_PRICE_TOLERANCE must be in [0, 1] probability points (got {})",
+                self.gamma_price_tolerance
+            ));
+        }
+        if !matches!(
+            self.gamma_price_divergence_action.as_str(),
+            "deprioritize" | "skip"
+        ) {
+            return Err(anyhow!(
+                "GAMMA_PRICE_DIVERGENCE_ACTION must be deprioritize|skip (got {})",
+                self.gamma_price_divergence_action
+            ));
+        }
+        if self.log_file_max_bytes == 0 {
+            return Err(anyhow!("LOG_FILE_MAX_BYTES must be > 0"));
+        }
+        if !matches!(self.fair_source.as_str(), "blend" | "microprice") {
+            return Err(anyhow!(
+                "FAIR_SOURCE must be blend|microprice (got {})",
+                self.fair_source
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.fair_mid_weight_min)
+            || !(0.0..=1.0).contains(&self.fair_mid_weight_max)
+            || self.fair_mid_weight_min > self.fair_mid_weight_max
+        {
+            return Err(anyhow!(
+                "FAIR_MID_WEIGHT_MIN/MAX must satisfy 0 <= min <= max <= 1 (got {} / {})",
+                self.fair_mid_weight_min,
+                self.fair_mid_weight_max
+            ));
+        }
+        if !self.fair_wide_spread.is_finite() || self.fair_wide_spread <= 0.0 {
+            return Err(anyhow!(
+                "FAIR_WIDE_SPREAD must be > 0 (got {})",
+                self.fair_wide_spread
+            ));
+        }
+        if !self.fair_depth_half.is_finite() || self.fair_depth_half <= 0.0 {
+            return Err(anyhow!(
+                "FAIR_DEPTH_HALF must be > 0 (got {})",
+                self.fair_depth_half
+            ));
+        }
+        if !self.fair_trade_max_age_secs.is_finite() || self.fair_trade_max_age_secs < 0.0 {
+            return Err(anyhow!(
+                "FAIR_TRADE_MAX_AGE_SECS must be >= 0 (got {})",
+                self.fair_trade_max_age_secs
+            ));
+        }
+        if !self.reference_max_age_secs.is_finite() || self.reference_max_age_secs <= 0.0 {
+            return Err(anyhow!(
+                "REFERENCE_MAX_AGE_SECS must be > 0 (got {})",
+                self.reference_max_age_secs
+            ));
+        }
+        for (key, v) in [
+            ("MAX_GROSS_EXPOSURE_USD", self.max_gross_exposure_usd),
+            ("MAX_NET_EXPOSURE_USD", self.max_net_exposure_usd),
+        ] {
+            if !v.is_finite() || v < 0.0 {
+                return Err(anyhow!("{key} must be >= 0 (got {v})"));
+            }
+        }
+        if !self.paper_min_secs_between_fills.is_finite() || self.paper_min_secs_between_fills < 0.0
+        {
+            return Err(anyhow!(
+                "PAPER_MIN_SECS_BETWEEN_FILLS must be >= 0 (got {})",
+                self.paper_min_secs_between_fills
+            ));
+        }
+        if !self.quote_state_max_age_secs.is_finite() || self.quote_state_max_age_secs < 0.0 {
+            return Err(anyhow!(
+                "QUOTE_STATE_MAX_AGE_SECS must be >= 0 (got {})",
+                self.quote_state_max_age_secs
+            ));
+        }
+        if self.paper_reset_on_start && self.paper_rehydrate_portfolio {
+            return Err(anyhow!(
+                "PAPER_RESET_ON_START and PAPER_REHYDRATE_PORTFOLIO are mutually exclusive; set one to 0"
+            ));
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_and_rehydrate_are_mutually_exclusive() {
+        let mut s = Settings::load().unwrap();
+        s.paper_reset_on_start = true;
+        s.paper_rehydrate_portfolio = true;
+        let err = s.validate().unwrap_err().to_string();
+        assert!(err.contains("mutually exclusive"), "{err}");
+
+        s.paper_rehydrate_portfolio = false;
+        assert!(s.validate().is_ok());
+        s.paper_reset_on_start = false;
+        s.paper_rehydrate_portfolio = true;
+        assert!(s.validate().is_ok());
+    }
+
+    #[test]
+    fn overrides_layer_over_the_env_without_writing_it() {
+        let path = std::env::temp_dir().join(format!("superspreader-{}.env", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            "# arm b\nBASE_ORDER_SIZE=3\nexport QUOTE_MODEL=\"as\"\n",
+        )
+        .unwrap();
+        let s = Settings::load_with_overrides(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(s.base_order_size, 3.0);
+        assert_eq!(s.quote_model, "as");
+        assert!(env::var("BASE_ORDER_SIZE").is_err());
+        assert_eq!(Settings::load().unwrap().base_order_size, 10.0);
+    }
+
+    #[test]
+    fn tag_overrides_parse_in_config_order() {
+        let o = parse_tag_overrides(
+            "Sports:min_24h_volume_usd=500,max_spread_bps=900; politics:min_liquidity_usd=5000",
+        )
+        .unwrap();
+        assert_eq!(o.len(), 2);
+        assert_eq!(o[0].tag, "sports");
+        assert_eq!(o[0].min_24h_volume_usd, Some(500.0));
+        assert_eq!(o[0].max_spread_bps, Some(900.0));
+        assert_eq!(o[0].min_liquidity_usd, None);
+        assert_eq!(o[1].tag, "politics");
+        assert_eq!(o[1].min_liquidity_usd, Some(5000.0));
+        assert!(parse_tag_overrides("").unwrap().is_empty());
+        assert!(parse_tag_overrides("sports:min_volume=1").is_err());
+        assert!(parse_tag_overrides("sports").is_err());
+    }
+
+    #[test]
+    fn reference_anchors_parse_and_reject_bad_betas() {
+        let a = parse_reference_anchors("state-x:national=0.4; state-y : national = 1").unwrap();
+        assert_eq!(a.len(), 2);
+        assert_eq!(a[0].market_id, "state-x");
+        assert_eq!(a[0].reference_market_id, "national");
+        assert_eq!(a[0].beta, 0.4);
+        assert_eq!(a[1].market_id, "state-y");
+        assert!(parse_reference_anchors("").unwrap().is_empty());
+        assert!(parse_reference_anchors("a:b=1.5").is_err());
+        assert!(parse_reference_anchors("a:a=0.5").is_err());
+        assert!(parse_reference_anchors("a:b").is_err());
+        assert!(parse_reference_anchors("a:b=0.1;a:c=0.2").is_err());
+    }
+
+    #[test]
+    fn inventory_targets_parse_signed_and_reject_duplicates() {
+        let t = parse_inventory_targets("m1=5; m2 = -2.5").unwrap();
+        assert_eq!(t.len(), 2);
+        assert_eq!((t[0].market_id.as_str(), t[0].qty), ("m1", 5.0));
+        assert_eq!((t[1].market_id.as_str(), t[1].qty), ("m2", -2.5));
+        assert!(parse_inventory_targets("").unwrap().is_empty());
+        assert!(parse_inventory_targets("m1").is_err());
+        assert!(parse_inventory_targets("m1=x").is_err());
+        assert!(parse_inventory_targets("m1=1;m1=2").is_err());
+    }
+
+    #[test]
+    fn inventory_targets_are_held_to_the_position_cap() {
+        let mut s = Settings::load().unwrap();
+        s.max_inventory_usd = 10.0;
+        let target = |qty| {
+            vec![InventoryTarget {
+                market_id: "m1".to_string(),
+                qty,
+            }]
+        };
+
+        s.inventory_targets = target(-10.0);
+        assert!(s.validate().is_ok());
+        s.inventory_targets = target(10.5);
+        let err = s.validate().unwrap_err().to_string();
+        assert!(err.contains("10.5 shares"), "{err}");
+    }
+
+    #[test]
+    fn tradeable_price_band_is_inclusive_at_both_bounds() {
+        assert_eq!(parse_price_band("0.03, 0.97").unwrap(), (0.03, 0.97));
+        assert!(parse_price_band("0.97,0.03").is_err());
+        assert!(parse_price_band("0.5,0.5").is_err());
+        assert!(parse_price_band("-0.1,0.9").is_err());
+        assert!(parse_price_band("0.1").is_err());
+
+        let mut s = Settings::load().unwrap();
+        assert_eq!(s.tradeable_price_band, (0.0, 1.0));
+        s.tradeable_price_band = (0.03, 0.97);
+        assert!(s.mid_in_tradeable_band(0.03));
+        assert!(s.mid_in_tradeable_band(0.97));
+        assert!(s.mid_in_tradeable_band(0.5));
+        assert!(!s.mid_in_tradeable_band(0.0299));
+        assert!(!s.mid_in_tradeable_band(0.9701));
+    }
+}
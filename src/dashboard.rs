@@ -4,25 +4,43 @@ use anyhow::Result;
 use axum::{
     extract::{Query, State},
     http::StatusCode,
-    response::{Html, IntoResponse, Json},
+    response::{Html, IntoResponse, Json, Response},
     routing::{get, post},
     Router,
 };
 use serde::Deserialize;
 use serde_json::Value as JsonValue;
 
-use crate::{config::Settings, store::SqliteStore};
+use crate::{
+    config::Settings,
+    flatten::{FlattenError, FlattenHandle},
+    live_params::{SetParamError, SharedSettings, LIVE_PARAMS},
+    rescan::{RescanError, RescanHandle},
+    store::SqliteStore,
+};
 
 #[derive(Clone)]
 pub struct DashboardState {
     pub settings: Settings,
     pub store: SqliteStore,
+    pub rescan: RescanHandle,
+    pub flatten: FlattenHandle,
+    pub live: SharedSettings,
 }
 
-pub async fn serve_dashboard(settings: Settings, store: SqliteStore) -> Result<()> {
+pub async fn serve_dashboard(
+    settings: Settings,
+    store: SqliteStore,
+    rescan: RescanHandle,
+    flatten: FlattenHandle,
+    live: SharedSettings,
+) -> Result<()> {
     let state = DashboardState {
         settings: settings.clone(),
         store,
+        rescan,
+        flatten,
+        live,
     };
 
     let app = Router::new()
@@ -30,13 +48,22 @@ pub async fn serve_dashboard(settings: Settings, store: SqliteStore) -> Result<(
         .route("/api/summary", get(api_summary))
         .route("/api/health", get(api_health))
         .route("/api/watchlist", get(api_watchlist))
+        .route("/api/scanner_candidates", get(api_scanner_candidates))
         .route("/api/positions", get(api_positions))
+        .route("/api/pnl_by_market", get(api_pnl_by_market))
+        .route("/api/pnl_by_event", get(api_pnl_by_event))
         .route("/api/orders", get(api_orders))
         .route("/api/open_orders", get(api_open_orders))
         .route("/api/quotes", get(api_quotes))
+        .route("/api/shadow_intents", get(api_shadow_intents))
         .route("/api/fills", get(api_fills))
+        .route("/api/fill_histogram", get(api_fill_histogram))
+        .route("/api/fill_realism", get(api_fill_realism))
         .route("/api/publishers", get(api_publishers))
         .route("/api/admin/reset_paper_state", post(api_reset_paper_state))
+        .route("/api/admin/rescan", post(api_rescan))
+        .route("/api/admin/flatten_all", post(api_flatten_all))
+        .route("/api/admin/set_param", post(api_set_param))
         .with_state(state);
 
     let addr: SocketAddr = format!("{}:{}", settings.dashboard_host, settings.dashboard_port)
@@ -56,6 +83,8 @@ async fn index(State(st): State<DashboardState>) -> impl IntoResponse {
     let trade_mode = st.settings.trade_mode.clone();
     let sqlite_path = st.store.path().to_string();
     let can_reset = trade_mode == "paper" && st.settings.dashboard_enable_reset;
+    let can_rescan = st.settings.dashboard_enable_rescan;
+    let can_set_param = st.settings.dashboard_enable_set_param;
     Html(render_index_html(
         &host,
         port,
@@ -63,9 +92,13 @@ async fn index(State(st): State<DashboardState>) -> impl IntoResponse {
         &trade_mode,
         &sqlite_path,
         can_reset,
+        can_rescan,
+        can_set_param,
+        st.settings.price_decimals(),
     ))
 }
 
+#[allow(clippy::too_many_arguments)]
 fn render_index_html(
     host: &str,
     port: u16,
@@ -73,14 +106,36 @@ fn render_index_html(
     trade_mode: &str,
     sqlite_path: &str,
     can_reset: bool,
+    can_rescan: bool,
+    can_set_param: bool,
+    price_dp: u32,
 ) -> String {
     // This is intentionally kept as a single-file UI (no build step),
     // lifted from the existing Python dashboard so you can keep the same look & feel.
     let reset_btn = if can_reset {
-        r#"<button class="btn" id="resetBtn" style="border-color: rgba(255,77,77,0.45);">Reset paper state</button>"#
+        r#"<button class="btn" id="resetBtn" style="border-color: rgba(255,77,77,0.45);">Reset paper state</button>
+           <button class="btn" id="fullResetBtn" style="border-color: rgba(255,77,77,0.45);">Full reset</button>"#
+    } else {
+        ""
+    };
+    let rescan_btn = if can_rescan {
+        r#"<button class="btn" id="rescanBtn">Rescan markets</button>"#
     } else {
         ""
     };
+    let set_param_form = if can_set_param {
+        let options: String = LIVE_PARAMS
+            .iter()
+            .map(|p| format!(r#"<option value="{p}">{p}</option>"#))
+            .collect();
+        format!(
+            r#"<select class="btn" id="paramName">{options}</select>
+           <input class="btn mono" id="paramValue" size="8" placeholder="value" />
+           <button class="btn" id="setParamBtn">Set</button>"#
+        )
+    } else {
+        String::new()
+    };
 
     format!(
         r#"<!doctype html>
@@ -237,6 +292,8 @@ fn render_index_html(
            <div class="chip">SQLite: <b class="mono">{sqlite_path}</b></div>
            <div class="chip">Status: <b id="statusText">starting…</b></div>
            <button class="btn" id="refreshBtn">Refresh</button>
+           {rescan_btn}
+           {set_param_form}
            {reset_btn}
          </div>
        </div>
@@ -362,6 +419,7 @@ fn render_index_html(
                  <tr>
                    <th class="mono">market_id</th>
                    <th>rPnL</th>
+                   <th>closed</th>
                  </tr>
                </thead>
                <tbody id="flatRows"></tbody>
@@ -370,6 +428,28 @@ fn render_index_html(
            </div>
          </div>
  
+         <div class="card col-12">
+           <div class="hd">
+             <div class="h">PnL by Market</div>
+             <div class="pill">24h • click a column to sort</div>
+           </div>
+           <div class="bd">
+             <table>
+               <thead>
+                 <tr>
+                   <th class="mono">market_id</th>
+                   <th data-pnl-sort="position" style="cursor:pointer">qty</th>
+                   <th data-pnl-sort="realized_pnl" style="cursor:pointer">rPnL</th>
+                   <th data-pnl-sort="unrealized_pnl" style="cursor:pointer">uPnL</th>
+                   <th data-pnl-sort="total_pnl" style="cursor:pointer">total</th>
+                   <th data-pnl-sort="window_pnl" style="cursor:pointer">24h Δ</th>
+                 </tr>
+               </thead>
+               <tbody id="pnlMarketRows"></tbody>
+             </table>
+           </div>
+         </div>
+
          <div class="card col-12">
            <div class="hd">
              <div class="h">Active Quotes (what the bot is trying to do)</div>
@@ -433,6 +513,7 @@ fn render_index_html(
                    <th>size</th>
                    <th>strategy</th>
                    <th>status</th>
+                   <th>closed</th>
                  </tr>
                </thead>
                <tbody id="orderRows"></tbody>
@@ -483,6 +564,11 @@ fn render_index_html(
          if (!Number.isFinite(n)) return "--";
          return n.toFixed(d);
        }}
+       // Prices (book, orders, fills): PRICE_DISPLAY_DECIMALS, else the PRICE_TICK precision.
+       // Mid/fair sit between ticks, so they get one more decimal.
+       const PRICE_DP = {price_dp};
+       const fmtPx = (x) => fmtNum(x, PRICE_DP);
+       const fmtPxFine = (x) => fmtNum(x, PRICE_DP + 1);
        const fmtAgo = (ts) => {{
          if (!ts) return "--";
          const now = Date.now()/1000;
@@ -597,7 +683,7 @@ fn render_index_html(
              <td><span class="tag">#${{r.rank}}</span></td>
              <td>
                <div style="font-weight:800; line-height:1.2;">${{escapeHtml(q).slice(0, 140)}}</div>
-               <div class="row2">event: <span class="mono">${{escapeHtml((r.event_id||"--").toString())}}</span></div>
+               <div class="row2"><span class="tag">${{escapeHtml((r.category||"other").toString())}}</span> event: <span class="mono">${{escapeHtml((r.event_id||"--").toString())}}</span></div>
              </td>
              <td class="mono">${{escapeHtml((r.market_id||"--").toString())}}</td>
              <td>${{vol === null ? "--" : "$" + Number(vol).toFixed(0)}}</td>
@@ -616,9 +702,9 @@ fn render_index_html(
            const cls = u >= 0 ? "good" : "bad";
            const tr = document.createElement("tr");
            tr.innerHTML = `
-             <td class="mono">${{escapeHtml((r.market_id||"--").toString())}}</td>
+             <td class="mono" title="event: ${{escapeHtml((r.event_id ?? "unknown").toString())}}">${{escapeHtml((r.market_id||"--").toString())}}</td>
              <td>${{fmtNum(r.position, 2)}}</td>
-             <td>${{fmtNum(r.mark_price, 3)}}</td>
+             <td>${{fmtPxFine(r.mark_price)}}</td>
              <td class="${{cls}}">${{fmtUsd(u)}}</td>
            `;
            tb.appendChild(tr);
@@ -635,11 +721,41 @@ fn render_index_html(
            tr.innerHTML = `
              <td class="mono">${{escapeHtml((r.market_id||"--").toString())}}</td>
              <td class="${{cls}}">${{fmtUsd(rp)}}</td>
+             <td>${{fmtAgo(r.closed_ts)}}</td>
            `;
            tb.appendChild(tr);
          }}
        }}
  
+       let pnlSort = "total_pnl";
+       let pnlAsc = false;
+       document.querySelectorAll("[data-pnl-sort]").forEach((th) => {{
+         th.addEventListener("click", () => {{
+           const key = th.getAttribute("data-pnl-sort");
+           pnlAsc = key === pnlSort ? !pnlAsc : false;
+           pnlSort = key;
+           refresh();
+         }});
+       }});
+
+       function renderPnlByMarket(rows) {{
+         const tb = document.getElementById("pnlMarketRows");
+         tb.innerHTML = "";
+         const pnlCell = (v) => `<td class="${{Number(v ?? 0) >= 0 ? "good" : "bad"}}">${{fmtUsd(Number(v ?? 0))}}</td>`;
+         for (const r of rows) {{
+           const tr = document.createElement("tr");
+           tr.innerHTML = `
+             <td class="mono">${{escapeHtml((r.market_id||"--").toString())}}</td>
+             <td>${{fmtNum(r.position, 2)}}</td>
+             ${{pnlCell(r.realized_pnl)}}
+             ${{pnlCell(r.unrealized_pnl)}}
+             ${{pnlCell(r.total_pnl)}}
+             ${{pnlCell(r.window_pnl)}}
+           `;
+           tb.appendChild(tr);
+         }}
+       }}
+
        function renderQuotes(rows) {{
          const tb = document.getElementById("quoteRows");
          tb.innerHTML = "";
@@ -655,13 +771,13 @@ fn render_index_html(
              <td class="mono">${{escapeHtml((r.market_id||"--").toString())}}</td>
              <td>
                <div style="font-weight:800; line-height:1.2;">${{escapeHtml(q).slice(0, 90) || "--"}}</div>
-               <div class="row2">src: <span class="mono">${{escapeHtml(src)}}</span> • updated ${{fmtAgo(r.ts)}}</div>
+               <div class="row2">src: <span class="mono">${{escapeHtml(src)}}</span> • size ${{fmtNum(r.order_size, 1)}} (${{escapeHtml((r.size_source || "--").toString())}}) • updated ${{fmtAgo(r.ts)}}</div>
              </td>
-             <td>${{fmtNum(r.mid, 3)}}</td>
-             <td>${{fmtNum(spread, 3)}}</td>
-             <td>${{fmtNum(r.fair, 3)}}</td>
-             <td>${{fmtNum(r.target_bid, 3)}}</td>
-             <td>${{fmtNum(r.target_ask, 3)}}</td>
+             <td>${{fmtPxFine(r.mid)}}</td>
+             <td>${{fmtPx(spread)}}</td>
+             <td>${{fmtPxFine(r.fair)}}</td>
+             <td>${{fmtPx(r.target_bid)}}</td>
+             <td>${{fmtPx(r.target_ask)}}</td>
              <td>${{fmtNum(inv, 2)}}</td>
            `;
            tb.appendChild(tr);
@@ -680,10 +796,11 @@ fn render_index_html(
              <td>${{fmtTs(r.created_ts)}}</td>
              <td class="mono">${{escapeHtml((r.market_id||"--").toString())}}</td>
              <td class="${{sideCls}}"><b>${{escapeHtml((r.side||"--").toString())}}</b></td>
-             <td>${{fmtNum(r.price, 3)}}</td>
+             <td>${{fmtPx(r.price)}}</td>
              <td>${{fmtNum(r.size, 2)}}</td>
              <td class="mono small">${{escapeHtml(strat)}}</td>
              <td><span class="tag">${{escapeHtml((r.status||"--").toString())}}</span></td>
+             <td>${{fmtTs(r.cancelled_ts ?? r.filled_ts)}}</td>
            `;
            tb.appendChild(tr);
          }}
@@ -701,7 +818,7 @@ fn render_index_html(
              <td>${{fmtTs(r.created_ts)}}</td>
              <td class="mono">${{escapeHtml((r.market_id||"--").toString())}}</td>
              <td class="${{sideCls}}"><b>${{escapeHtml((r.side||"--").toString())}}</b></td>
-             <td>${{fmtNum(r.price, 3)}}</td>
+             <td>${{fmtPx(r.price)}}</td>
              <td>${{fmtNum(r.size, 2)}}</td>
              <td class="mono small">${{escapeHtml(strat)}}</td>
            `;
@@ -720,7 +837,7 @@ fn render_index_html(
              <td>${{fmtTs(r.ts)}}</td>
              <td class="mono">${{escapeHtml((r.market_id||"--").toString())}}</td>
              <td class="${{sideCls}}"><b>${{escapeHtml((r.side||"--").toString())}}</b></td>
-             <td>${{fmtNum(r.price, 3)}}</td>
+             <td>${{fmtPx(r.price)}}</td>
              <td>${{fmtNum(r.size, 2)}}</td>
            `;
            tb.appendChild(tr);
@@ -729,11 +846,12 @@ fn render_index_html(
  
        async function refresh() {{
          try {{
-           const [summary, watch, pos, flat, quotes, openOrders, orders, fills, pubs] = await Promise.all([
+           const [summary, watch, pos, flat, pnlMarkets, quotes, openOrders, orders, fills, pubs] = await Promise.all([
              getJson("/api/summary"),
              getJson("/api/watchlist?limit=30"),
              getJson("/api/positions?limit=20"),
              getJson("/api/positions?limit=20&only_flat=1"),
+             getJson(`/api/pnl_by_market?window_secs=86400&limit=30&sort=${{pnlSort}}&asc=${{pnlAsc ? 1 : 0}}`),
              getJson("/api/quotes?limit=20"),
              getJson("/api/open_orders?limit=25"),
              getJson("/api/orders?limit=25"),
@@ -785,10 +903,11 @@ fn render_index_html(
            renderWatch(watch);
            renderPositions(pos);
            renderFlat(flat);
+           renderPnlByMarket(pnlMarkets);
            renderQuotes(quotes);
-           renderOpenOrders(openOrders);
-           renderOrders(orders);
-           renderFills(fills);
+           renderOpenOrders(openOrders.rows);
+           renderOrders(orders.rows);
+           renderFills(fills.rows);
            renderPublishers(pubs);
          }} catch (e) {{
            setStatus(false, "disconnected");
@@ -802,13 +921,14 @@ fn render_index_html(
        }}
  
        document.getElementById("refreshBtn").addEventListener("click", refresh);
-       const resetBtn = document.getElementById("resetBtn");
-       if (resetBtn) {{
-         resetBtn.addEventListener("click", async () => {{
-           const ok = confirm("Reset paper state? This deletes orders/fills/position snapshots/PnL from SQLite.");
+       function wireReset(id, url, prompt) {{
+         const btn = document.getElementById(id);
+         if (!btn) return;
+         btn.addEventListener("click", async () => {{
+           const ok = confirm(prompt);
            if (!ok) return;
            try {{
-             const r = await fetch("/api/admin/reset_paper_state", {{ method: "POST" }});
+             const r = await fetch(url, {{ method: "POST" }});
              if (!r.ok) {{
                const t = await r.text();
                throw new Error(`reset failed: ${{r.status}} ${{t}}`);
@@ -819,6 +939,49 @@ fn render_index_html(
            }}
          }});
        }}
+       wireReset("resetBtn", "/api/admin/reset_paper_state",
+         "Reset paper state? This deletes orders/fills/position snapshots/PnL from SQLite.");
+       wireReset("fullResetBtn", "/api/admin/reset_paper_state?full=1",
+         "Full reset? This also deletes quote/scanner history and the watchlist (market metadata is kept).");
+       (() => {{
+         const btn = document.getElementById("rescanBtn");
+         if (!btn) return;
+         btn.addEventListener("click", async () => {{
+           btn.disabled = true;
+           try {{
+             const r = await fetch("/api/admin/rescan", {{ method: "POST" }});
+             const j = await r.json().catch(() => ({{}}));
+             if (!r.ok) throw new Error(`rescan failed: ${{r.status}} ${{j.error || ""}}`);
+             setStatus(true, `rescanned: ${{j.eligible}} eligible, ${{j.top}} selected`);
+             await refresh();
+           }} catch (e) {{
+             showBanner("Rescan failed", (e && e.message) ? e.message : String(e));
+           }} finally {{
+             btn.disabled = false;
+           }}
+         }});
+       }})();
+       (() => {{
+         const btn = document.getElementById("setParamBtn");
+         if (!btn) return;
+         btn.addEventListener("click", async () => {{
+           const name = document.getElementById("paramName").value;
+           const value = document.getElementById("paramValue").value.trim();
+           if (!value) return;
+           btn.disabled = true;
+           try {{
+             const q = new URLSearchParams({{ name, value }});
+             const r = await fetch(`/api/admin/set_param?${{q}}`, {{ method: "POST" }});
+             const j = await r.json().catch(() => ({{}}));
+             if (!r.ok) throw new Error(`set_param failed: ${{r.status}} ${{j.error || ""}} ${{j.detail || ""}}`);
+             setStatus(true, `${{j.name}}: ${{j.old}} -> ${{j.new}}`);
+           }} catch (e) {{
+             showBanner("Set param failed", (e && e.message) ? e.message : String(e));
+           }} finally {{
+             btn.disabled = false;
+           }}
+         }});
+       }})();
        tickClock();
        setInterval(tickClock, 1000);
        refresh();
@@ -831,7 +994,10 @@ fn render_index_html(
         mode = mode,
         trade_mode = trade_mode,
         sqlite_path = sqlite_path,
-        reset_btn = reset_btn
+        reset_btn = reset_btn,
+        rescan_btn = rescan_btn,
+        set_param_form = set_param_form,
+        price_dp = price_dp
     )
 }
 
@@ -840,6 +1006,7 @@ async fn api_summary(State(st): State<DashboardState>) -> impl IntoResponse {
     let pnl = st.store.fetch_latest_pnl().unwrap_or(None);
     let scanner = st.store.fetch_latest_scanner_snapshot().unwrap_or(None);
     let positions = st.store.fetch_latest_positions(500).unwrap_or_default();
+    let session = st.store.fetch_session_stats().unwrap_or(JsonValue::Null);
     let health = st
         .store
         .fetch_runtime_statuses()
@@ -872,6 +1039,7 @@ async fn api_summary(State(st): State<DashboardState>) -> impl IntoResponse {
         "pnl": pnl,
         "scanner": scanner,
         "positions_count": positions_count,
+        "session": session,
         "freshness": freshness,
         "health": {
             "components": health,
@@ -905,10 +1073,23 @@ async fn api_watchlist(
     }
 }
 
+async fn api_scanner_candidates(
+    State(st): State<DashboardState>,
+    Query(q): Query<LimitQ>,
+) -> impl IntoResponse {
+    let limit = q.limit.unwrap_or(100);
+    match st.store.fetch_latest_scanner_candidates(limit) {
+        Ok(rows) => Json(JsonValue::Array(rows)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
 #[derive(Deserialize)]
 struct PositionsQ {
     limit: Option<usize>,
     only_flat: Option<i32>,
+    // Lookback for `only_flat=1` (recently closed), default 24h.
+    window_secs: Option<f64>,
 }
 
 async fn api_positions(
@@ -916,50 +1097,129 @@ async fn api_positions(
     Query(q): Query<PositionsQ>,
 ) -> impl IntoResponse {
     let limit = q.limit.unwrap_or(25);
-    let only_flat = q.only_flat.unwrap_or(0) == 1;
+    if q.only_flat.unwrap_or(0) == 1 {
+        let window = q.window_secs.unwrap_or(86_400.0);
+        return match st.store.fetch_recently_closed(limit, window) {
+            Ok(rows) => Json(JsonValue::Array(rows)).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        };
+    }
     match st.store.fetch_latest_positions(limit) {
         Ok(rows) => {
-            let filtered = if only_flat {
-                rows.into_iter()
-                    .filter(|r| {
-                        r.get("position").and_then(|x| x.as_f64()).unwrap_or(0.0) == 0.0
-                            && r.get("realized_pnl")
-                                .and_then(|x| x.as_f64())
-                                .unwrap_or(0.0)
-                                != 0.0
-                    })
-                    .collect::<Vec<_>>()
-            } else {
-                rows.into_iter()
-                    .filter(|r| r.get("position").and_then(|x| x.as_f64()).unwrap_or(0.0) != 0.0)
-                    .collect::<Vec<_>>()
-            };
-            Json(JsonValue::Array(filtered)).into_response()
+            let open = rows
+                .into_iter()
+                .filter(|r| r.get("position").and_then(|x| x.as_f64()).unwrap_or(0.0) != 0.0)
+                .collect::<Vec<_>>();
+            Json(JsonValue::Array(open)).into_response()
         }
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
-async fn api_orders(
+#[derive(Deserialize)]
+struct PnlByMarketQ {
+    window_secs: Option<f64>,
+    sort: Option<String>,
+    asc: Option<i32>,
+    limit: Option<usize>,
+}
+
+async fn api_pnl_by_market(
     State(st): State<DashboardState>,
-    Query(q): Query<LimitQ>,
+    Query(q): Query<PnlByMarketQ>,
 ) -> impl IntoResponse {
-    let limit = q.limit.unwrap_or(50);
-    match st.store.fetch_recent_orders(limit, None) {
-        Ok(rows) => Json(JsonValue::Array(rows)).into_response(),
+    let window_secs = q.window_secs.unwrap_or(86_400.0);
+    let sort = q.sort.clone().unwrap_or_else(|| "total_pnl".to_string());
+    if !matches!(
+        sort.as_str(),
+        "total_pnl" | "window_pnl" | "realized_pnl" | "unrealized_pnl" | "position"
+    ) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"ok": false, "error": format!("unknown sort field: {sort}")})),
+        )
+            .into_response();
+    }
+    sorted_pnl_response(st.store.fetch_pnl_by_market(window_secs), &sort, &q)
+}
+
+/// Same query parameters as `/api/pnl_by_market`, one row per event (correlated legs).
+async fn api_pnl_by_event(
+    State(st): State<DashboardState>,
+    Query(q): Query<PnlByMarketQ>,
+) -> impl IntoResponse {
+    let window_secs = q.window_secs.unwrap_or(86_400.0);
+    let sort = q.sort.clone().unwrap_or_else(|| "total_pnl".to_string());
+    if !matches!(
+        sort.as_str(),
+        "total_pnl" | "window_pnl" | "realized_pnl" | "unrealized_pnl" | "gross_position"
+    ) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"ok": false, "error": format!("unknown sort field: {sort}")})),
+        )
+            .into_response();
+    }
+    sorted_pnl_response(st.store.fetch_pnl_by_event(window_secs), &sort, &q)
+}
+
+fn sorted_pnl_response(rows: Result<Vec<JsonValue>>, sort: &str, q: &PnlByMarketQ) -> Response {
+    match rows {
+        Ok(mut rows) => {
+            let key = |r: &JsonValue| r.get(sort).and_then(|x| x.as_f64()).unwrap_or(0.0);
+            rows.sort_by(|a, b| key(b).total_cmp(&key(a)));
+            if q.asc.unwrap_or(0) == 1 {
+                rows.reverse();
+            }
+            rows.truncate(q.limit.unwrap_or(50));
+            Json(JsonValue::Array(rows)).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct PageQ {
+    limit: Option<usize>,
+    // Opaque `next_cursor` from the previous page; omit for the newest page.
+    cursor: Option<String>,
+}
+
+/// Run a keyset page query; a malformed cursor is a 400, not a store error.
+fn page_response(
+    cursor: Option<&str>,
+    fetch: impl FnOnce(Option<&str>) -> Result<(Vec<JsonValue>, Option<String>)>,
+) -> Response {
+    if let Some(Err(e)) = cursor.map(crate::store::decode_cursor) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"ok": false, "error": e.to_string()})),
+        )
+            .into_response();
+    }
+    match fetch(cursor) {
+        Ok((rows, next_cursor)) => {
+            Json(serde_json::json!({ "rows": rows, "next_cursor": next_cursor })).into_response()
+        }
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
+async fn api_orders(State(st): State<DashboardState>, Query(q): Query<PageQ>) -> impl IntoResponse {
+    let limit = q.limit.unwrap_or(50);
+    page_response(q.cursor.as_deref(), |c| {
+        st.store.fetch_orders_page(limit, None, c)
+    })
+}
+
 async fn api_open_orders(
     State(st): State<DashboardState>,
-    Query(q): Query<LimitQ>,
+    Query(q): Query<PageQ>,
 ) -> impl IntoResponse {
     let limit = q.limit.unwrap_or(50);
-    match st.store.fetch_recent_orders(limit, Some("open")) {
-        Ok(rows) => Json(JsonValue::Array(rows)).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-    }
+    page_response(q.cursor.as_deref(), |c| {
+        st.store.fetch_orders_page(limit, Some("open"), c)
+    })
 }
 
 async fn api_quotes(
@@ -973,21 +1233,95 @@ async fn api_quotes(
     }
 }
 
-async fn api_fills(State(st): State<DashboardState>, Query(q): Query<LimitQ>) -> impl IntoResponse {
-    let limit = q.limit.unwrap_or(100);
-    match st.store.fetch_recent_fills(limit) {
+async fn api_shadow_intents(
+    State(st): State<DashboardState>,
+    Query(q): Query<LimitQ>,
+) -> impl IntoResponse {
+    let limit = q.limit.unwrap_or(50);
+    match st.store.fetch_recent_shadow_intents(limit) {
         Ok(rows) => Json(JsonValue::Array(rows)).into_response(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
+async fn api_fills(State(st): State<DashboardState>, Query(q): Query<PageQ>) -> impl IntoResponse {
+    let limit = q.limit.unwrap_or(100);
+    page_response(q.cursor.as_deref(), |c| st.store.fetch_fills_page(limit, c))
+}
+
+#[derive(Deserialize)]
+struct FillHistogramQ {
+    bucket_width: Option<f64>,
+    window_secs: Option<f64>,
+}
+
+async fn api_fill_histogram(
+    State(st): State<DashboardState>,
+    Query(q): Query<FillHistogramQ>,
+) -> impl IntoResponse {
+    let bucket_width = q.bucket_width.unwrap_or(st.settings.price_tick * 5.0);
+    let window_secs = q.window_secs.unwrap_or(3600.0);
+    if !bucket_width.is_finite() || bucket_width <= 0.0 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"ok": false, "error": "bucket_width must be > 0"})),
+        )
+            .into_response();
+    }
+    match st
+        .store
+        .fetch_fill_markout_histogram(bucket_width, window_secs)
+    {
+        Ok(h) => Json(h).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct FillRealismQ {
+    window_secs: Option<f64>,
+    lookback_secs: Option<f64>,
+}
+
+async fn api_fill_realism(
+    State(st): State<DashboardState>,
+    Query(q): Query<FillRealismQ>,
+) -> impl IntoResponse {
+    let window_secs = q
+        .window_secs
+        .unwrap_or(st.settings.fill_realism_window_secs);
+    let lookback_secs = q.lookback_secs.unwrap_or(3600.0);
+    if !window_secs.is_finite() || window_secs < 0.0 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"ok": false, "error": "window_secs must be >= 0"})),
+        )
+            .into_response();
+    }
+    match st.store.fetch_fill_realism(window_secs, lookback_secs) {
+        Ok(v) => Json(v).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
 async fn api_publishers() -> impl IntoResponse {
     // The old Python app supported optional GitHub gist/repo publishing.
     // This Rust port intentionally keeps the dashboard UI but does not publish by default.
     Json(serde_json::json!({}))
 }
 
-async fn api_reset_paper_state(State(st): State<DashboardState>) -> impl IntoResponse {
+#[derive(Deserialize)]
+struct ResetQ {
+    /// Also clear quote/scanner/watchlist history.
+    full: Option<i32>,
+    /// With `full`, also drop the cached markets metadata.
+    include_markets: Option<i32>,
+}
+
+async fn api_reset_paper_state(
+    State(st): State<DashboardState>,
+    Query(q): Query<ResetQ>,
+) -> impl IntoResponse {
     if st.settings.trade_mode != "paper" {
         return (
             StatusCode::BAD_REQUEST,
@@ -1002,7 +1336,13 @@ async fn api_reset_paper_state(State(st): State<DashboardState>) -> impl IntoRes
         )
             .into_response();
     }
-    if let Err(e) = st.store.clear_trading_state() {
+    let res = if q.full.unwrap_or(0) != 0 {
+        st.store
+            .clear_all_state(q.include_markets.unwrap_or(0) != 0)
+    } else {
+        st.store.clear_trading_state()
+    };
+    if let Err(e) = res {
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({"ok": false, "error": e.to_string()})),
@@ -1012,9 +1352,239 @@ async fn api_reset_paper_state(State(st): State<DashboardState>) -> impl IntoRes
     Json(serde_json::json!({"ok": true, "ts": now_ts()})).into_response()
 }
 
+/// Run the market scanner now instead of waiting for `MARKET_REFRESH_SECS`.
+async fn api_rescan(State(st): State<DashboardState>) -> Response {
+    if !st.settings.dashboard_enable_rescan {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"ok": false, "error": "rescan_disabled"})),
+        )
+            .into_response();
+    }
+    match st.rescan.request().await {
+        Ok(c) => Json(serde_json::json!({
+            "ok": true,
+            "eligible": c.eligible,
+            "top": c.top,
+            "ts": now_ts(),
+        }))
+        .into_response(),
+        Err(RescanError::Busy) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(serde_json::json!({"ok": false, "error": "rescan_already_queued"})),
+        )
+            .into_response(),
+        Err(RescanError::Unavailable) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({"ok": false, "error": "scanner_not_running"})),
+        )
+            .into_response(),
+        Err(RescanError::Failed(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"ok": false, "error": e})),
+        )
+            .into_response(),
+    }
+}
+
+/// Cancel all resting orders and close every position (paper or live).
+async fn api_flatten_all(State(st): State<DashboardState>) -> Response {
+    if !st.settings.dashboard_enable_flatten {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"ok": false, "error": "flatten_disabled"})),
+        )
+            .into_response();
+    }
+    match st.flatten.request().await {
+        Ok(r) => Json(serde_json::json!({
+            "ok": true,
+            "cancelled": r.cancelled,
+            "markets": r.markets,
+            "ts": now_ts(),
+        }))
+        .into_response(),
+        Err(FlattenError::Busy) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(serde_json::json!({"ok": false, "error": "flatten_already_queued"})),
+        )
+            .into_response(),
+        Err(FlattenError::Unavailable) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({"ok": false, "error": "trader_not_running"})),
+        )
+            .into_response(),
+        Err(FlattenError::Failed(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"ok": false, "error": e})),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct SetParamQ {
+    name: String,
+    value: f64,
+}
+
+/// Change one of `LIVE_PARAMS`; the trade loop applies it on its next tick.
+async fn api_set_param(State(st): State<DashboardState>, Query(q): Query<SetParamQ>) -> Response {
+    if !st.settings.dashboard_enable_set_param {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"ok": false, "error": "set_param_disabled"})),
+        )
+            .into_response();
+    }
+    match crate::live_params::set_param(&st.live, &q.name, q.value) {
+        Ok(old) => Json(serde_json::json!({
+            "ok": true,
+            "name": q.name,
+            "old": old,
+            "new": q.value,
+            "ts": now_ts(),
+        }))
+        .into_response(),
+        Err(SetParamError::Unknown) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "ok": false,
+                "error": "unknown_param",
+                "allowed": LIVE_PARAMS,
+            })),
+        )
+            .into_response(),
+        Err(SetParamError::OutOfRange(detail)) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"ok": false, "error": "out_of_range", "detail": detail})),
+        )
+            .into_response(),
+    }
+}
+
 fn now_ts() -> f64 {
     let now = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap_or_default();
     now.as_secs_f64()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::temp_store;
+
+    #[tokio::test]
+    async fn rescan_endpoint_triggers_a_scan_and_returns_counts() {
+        let store = temp_store();
+        let mut settings = Settings::load().unwrap();
+        let (rescan, mut rx) = crate::rescan::rescan_channel();
+        let (flatten, _flatten_rx) = crate::flatten::flatten_channel();
+        let st = DashboardState {
+            settings: settings.clone(),
+            store: store.clone(),
+            rescan,
+            flatten,
+            live: crate::live_params::shared(&settings),
+        };
+
+        settings.dashboard_enable_rescan = false;
+        let off = DashboardState {
+            settings: settings.clone(),
+            ..st.clone()
+        };
+        assert_eq!(api_rescan(State(off)).await.status(), StatusCode::FORBIDDEN);
+
+        // Stand-in scanner loop: one scan per request batch, recorded like `select()` does.
+        let scanner = tokio::spawn(async move {
+            let waiters = rx.recv().await.unwrap();
+            store.insert_scanner_snapshot(now_ts(), 42, 7).unwrap();
+            crate::rescan::reply_all(
+                waiters,
+                Ok(crate::rescan::ScanCounts {
+                    eligible: 42,
+                    top: 7,
+                }),
+            );
+        });
+        settings.dashboard_enable_rescan = true;
+        let on = DashboardState { settings, ..st };
+        let resp = api_rescan(State(on)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), 1 << 16)
+            .await
+            .unwrap();
+        let v: JsonValue = serde_json::from_slice(&body).unwrap();
+        assert_eq!(v["ok"], true);
+        assert_eq!(v["eligible"], 42);
+        assert_eq!(v["top"], 7);
+        scanner.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn flatten_endpoint_is_gated_and_needs_a_running_trader() {
+        let store = temp_store();
+        let mut settings = Settings::load().unwrap();
+        let (rescan, _rescan_rx) = crate::rescan::rescan_channel();
+        let (flatten, flatten_rx) = crate::flatten::flatten_channel();
+        let st = DashboardState {
+            settings: settings.clone(),
+            store,
+            rescan,
+            flatten,
+            live: crate::live_params::shared(&settings),
+        };
+        assert!(!settings.dashboard_enable_flatten);
+        let resp = api_flatten_all(State(st.clone())).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // Scanner-only runs never start the trade loop, so nothing answers.
+        drop(flatten_rx);
+        settings.dashboard_enable_flatten = true;
+        let on = DashboardState { settings, ..st };
+        let resp = api_flatten_all(State(on)).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn set_param_endpoint_is_gated_and_allowlisted() {
+        let store = temp_store();
+        let mut settings = Settings::load().unwrap();
+        let (rescan, _rescan_rx) = crate::rescan::rescan_channel();
+        let (flatten, _flatten_rx) = crate::flatten::flatten_channel();
+        let live = crate::live_params::shared(&settings);
+        let st = DashboardState {
+            settings: settings.clone(),
+            store,
+            rescan,
+            flatten,
+            live: live.clone(),
+        };
+        let q = |name: &str, value: f64| {
+            Query(SetParamQ {
+                name: name.to_string(),
+                value,
+            })
+        };
+        assert!(!settings.dashboard_enable_set_param);
+        let resp = api_set_param(State(st.clone()), q("base_order_size", 25.0)).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        settings.dashboard_enable_set_param = true;
+        let on = DashboardState { settings, ..st };
+        let resp = api_set_param(State(on.clone()), q("dashboard_port", 1.0)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = api_set_param(State(on.clone()), q("mm_reprice_threshold", 2.0)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = api_set_param(State(on), q("base_order_size", 25.0)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), 1 << 16)
+            .await
+            .unwrap();
+        let v: JsonValue = serde_json::from_slice(&body).unwrap();
+        assert_eq!(v["new"], 25.0);
+        assert_eq!(live.read().base_order_size, 25.0);
+    }
+}
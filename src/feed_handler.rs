@@ -3,32 +3,146 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     config::Settings,
+    health::{spawn_supervised, HealthState, RestartPolicy},
     market_selector::SelectedMarket,
-    utils::{ewma, now_ts},
+    utils::{ewma, now_ts, RateTracker},
 };
 
 use polymarket_hft::client::polymarket::clob::orderbook::GetOrderBooksRequestItem;
 use polymarket_hft::client::polymarket::clob::ws::ClobWsClient;
+use polymarket_hft::client::polymarket::clob::ws::ConnectionStatus;
+use polymarket_hft::client::polymarket::clob::ws::LastTradePriceMessage;
+use polymarket_hft::client::polymarket::clob::ws::Side as WsSide;
+use polymarket_hft::client::polymarket::clob::ws::WsErrorMessage;
 use polymarket_hft::client::polymarket::clob::ws::WsMessage;
 use polymarket_hft::client::polymarket::clob::Client as ClobClient;
+use polymarket_hft::client::polymarket::rtds::types::{
+    AggOrderbook, LastTradePrice as RtdsLastTrade, TickSizeChange as RtdsTickSizeChange,
+};
+use polymarket_hft::client::polymarket::rtds::{
+    Message as RtdsMessage, RtdsClient, Subscription as RtdsSubscription,
+};
 use tokio::sync::watch;
 
-#[derive(Debug, Clone)]
+/// Decay window for the per-market book update rate (`Tob::updates_ewma_per_min`).
+const UPDATE_RATE_WINDOW_SECS: f64 = 60.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tob {
     pub best_bid: Option<f64>,
     pub best_ask: Option<f64>,
-    pub bid_depth_5: f64,
-    pub ask_depth_5: f64,
+    /// Summed size of the best `DEPTH_LEVELS` bid levels.
+    #[serde(alias = "bid_depth_5")]
+    pub bid_depth_n: f64,
+    /// Summed size of the best `DEPTH_LEVELS` ask levels.
+    #[serde(alias = "ask_depth_5")]
+    pub ask_depth_n: f64,
     pub ts: f64,
+    /// WS book updates per minute (time-decayed, as of the latest update).
     pub updates_ewma_per_min: f64,
     pub last_trade_ema: Option<f64>,
     pub last_trade_ts: Option<f64>,
+    /// Current price tick: seeded from market metadata, then tracked from WS `tick_size_change`.
+    #[serde(default)]
+    pub tick_size: Option<f64>,
+    /// Set by the scanner once the market is closed / no longer accepting orders.
+    #[serde(default)]
+    pub closed: bool,
+    /// Resolution price (0/1) or last trade, used as the mark after close.
+    #[serde(default)]
+    pub settle_price: Option<f64>,
+    /// Book came from the REST fallback poll (WS down): coarser and higher-latency than WS.
+    #[serde(default)]
+    pub polled: bool,
+    /// EWMA of squared mid moves per second between books (short-horizon variance).
+    #[serde(default)]
+    pub mid_var_ewma: Option<f64>,
+    /// Size at the best bid / ask from the level book; `None` when no level book backs
+    /// these prices (e.g. replayed tapes). Read through `touch_size`.
+    #[serde(default)]
+    pub bid_touch_size: Option<f64>,
+    #[serde(default)]
+    pub ask_touch_size: Option<f64>,
 }
 
 impl Tob {
+    pub(crate) fn empty(ts: f64) -> Self {
+        Tob {
+            best_bid: None,
+            best_ask: None,
+            bid_depth_n: 0.0,
+            ask_depth_n: 0.0,
+            ts,
+            updates_ewma_per_min: 0.0,
+            last_trade_ema: None,
+            last_trade_ts: None,
+            tick_size: None,
+            closed: false,
+            settle_price: None,
+            polled: false,
+            mid_var_ewma: None,
+            bid_touch_size: None,
+            ask_touch_size: None,
+        }
+    }
+
+    /// Size resting at the best bid (`is_bid`) or ask: the level book's touch, else the
+    /// summed top-level depth as the tightest bound known.
+    pub fn touch_size(&self, is_bid: bool) -> f64 {
+        if is_bid {
+            self.bid_touch_size.unwrap_or(self.bid_depth_n)
+        } else {
+            self.ask_touch_size.unwrap_or(self.ask_depth_n)
+        }
+    }
+
+    /// Price to mark positions at for a `MARK_SOURCE`. Fallback chains:
+    ///
+    /// - `mid`: book mid
+    /// - `microprice`: depth-weighted mid -> book mid
+    /// - `last_trade`: last trade EWMA -> book mid
+    /// - `clob_midpoint`: book mid (CLOB `/midpoint`) -> last trade EWMA
+    ///
+    /// Closed markets always mark at the settle price (or last trade), never the stale book.
+    /// Callers fall back to the position's avg price when this returns `None`.
+    pub fn mark(&self, source: &str) -> Option<f64> {
+        if self.closed {
+            return self.settle_price.or(self.last_trade_ema);
+        }
+        match source {
+            "microprice" => self.microprice().or_else(|| self.mid()),
+            "last_trade" => self.last_trade_ema.or_else(|| self.mid()),
+            "clob_midpoint" => self.mid().or(self.last_trade_ema),
+            _ => self.mid(),
+        }
+    }
+
+    /// Top-of-book price weighted toward the thinner side (where the next trade is likelier).
+    pub fn microprice(&self) -> Option<f64> {
+        let (Some(b), Some(a)) = (self.best_bid, self.best_ask) else {
+            return None;
+        };
+        let total = self.bid_depth_n + self.ask_depth_n;
+        if !(a > 0.0 && b > 0.0 && total > 0.0) {
+            return None;
+        }
+        Some((b * self.ask_depth_n + a * self.bid_depth_n) / total)
+    }
+
+    /// Depth imbalance in [-1, 1] over the configured depth window (0 on an empty book).
+    pub fn imbalance(&self) -> f64 {
+        let total = self.bid_depth_n + self.ask_depth_n;
+        if total > 0.0 {
+            (self.bid_depth_n - self.ask_depth_n) / total
+        } else {
+            0.0
+        }
+    }
+
     pub fn mid(&self) -> Option<f64> {
         match (self.best_bid, self.best_ask) {
             (Some(b), Some(a)) if a > 0.0 && b > 0.0 => Some(0.5 * (a + b)),
@@ -37,19 +151,154 @@ impl Tob {
     }
 }
 
+/// Best price levels of one market, best first: `(price, size)` per level.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BookLevels {
+    pub bids: Vec<(f64, f64)>,
+    pub asks: Vec<(f64, f64)>,
+}
+
+impl BookLevels {
+    /// Summed size of the best `n` levels on one side.
+    pub fn depth(&self, is_bid: bool, n: usize) -> f64 {
+        let side = if is_bid { &self.bids } else { &self.asks };
+        side.iter().take(n).map(|l| l.1).sum()
+    }
+}
+
+/// One side of a market's full book, keyed by price in millionths (exact on any tick).
+type Ladder = std::collections::BTreeMap<i64, f64>;
+
+fn price_key(px: f64) -> i64 {
+    (px * 1e6).round() as i64
+}
+
+/// A market's full resting book: replaced by each snapshot, edited by price changes.
+#[derive(Debug, Default)]
+struct FullBook {
+    bids: Ladder,
+    asks: Ladder,
+}
+
+impl FullBook {
+    fn new(bids: &[(f64, f64)], asks: &[(f64, f64)]) -> Self {
+        let ladder = |levels: &[(f64, f64)]| -> Ladder {
+            levels.iter().map(|&(px, sz)| (price_key(px), sz)).collect()
+        };
+        Self {
+            bids: ladder(bids),
+            asks: ladder(asks),
+        }
+    }
+
+    /// Set the size resting at `px`; zero removes the level.
+    fn set(&mut self, is_bid: bool, px: f64, size: f64) {
+        let side = if is_bid {
+            &mut self.bids
+        } else {
+            &mut self.asks
+        };
+        if size > 0.0 {
+            side.insert(price_key(px), size);
+        } else {
+            side.remove(&price_key(px));
+        }
+    }
+
+    fn top(&self, n: usize) -> BookLevels {
+        let level = |(k, sz): (&i64, &f64)| (*k as f64 / 1e6, *sz);
+        BookLevels {
+            bids: self.bids.iter().rev().take(n).map(level).collect(),
+            asks: self.asks.iter().take(n).map(level).collect(),
+        }
+    }
+}
+
+/// `BOOK_LEVELS` when the state is built without settings.
+const DEFAULT_BOOK_LEVELS: usize = 10;
+
 #[derive(Clone)]
 pub struct FeedState {
     inner: std::sync::Arc<RwLock<HashMap<String, Tob>>>,
+    // Streamed book updates per market since its last (re)subscribe.
+    samples: std::sync::Arc<RwLock<HashMap<String, u32>>>,
+    // Two streams write books (`FEED_SOURCE=both`): keep the newer by book ts.
+    newest_book_wins: bool,
+    // Public trade prints per market not yet taken by the trade loop.
+    trades: std::sync::Arc<RwLock<HashMap<String, Vec<TradePrint>>>>,
+    // Full book per market (WS/REST snapshots + WS price changes).
+    books: std::sync::Arc<RwLock<HashMap<String, FullBook>>>,
+    book_levels: usize,
+}
+
+/// One public trade print (price, size) off the last-trade stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TradePrint {
+    pub price: f64,
+    pub size: f64,
 }
 
+/// Smoothing and minimum time step for `Tob::mid_var_ewma`.
+const MID_VAR_ALPHA: f64 = 0.1;
+const MID_VAR_MIN_DT_SECS: f64 = 0.1;
+
+/// Prints kept per market between trade-loop drains; the oldest are dropped past this.
+const MAX_PENDING_TRADES: usize = 256;
+
 impl FeedState {
     pub fn new() -> Self {
         Self {
             inner: std::sync::Arc::new(RwLock::new(HashMap::new())),
+            samples: std::sync::Arc::new(RwLock::new(HashMap::new())),
+            newest_book_wins: false,
+            trades: std::sync::Arc::new(RwLock::new(HashMap::new())),
+            books: std::sync::Arc::new(RwLock::new(HashMap::new())),
+            book_levels: DEFAULT_BOOK_LEVELS,
+        }
+    }
+
+    /// Levels per side returned by `get_book` (`BOOK_LEVELS`).
+    pub fn with_book_levels(mut self, n: usize) -> Self {
+        self.book_levels = n.max(1);
+        self
+    }
+
+    /// Best `BOOK_LEVELS` levels per side of the market's book, if a snapshot was seen.
+    pub fn get_book(&self, market_id: &str) -> Option<BookLevels> {
+        self.books
+            .read()
+            .get(market_id)
+            .map(|b| b.top(self.book_levels))
+    }
+
+    /// Replace the market's book with a full snapshot (valid levels, any order).
+    pub fn replace_book(&self, market_id: &str, bids: &[(f64, f64)], asks: &[(f64, f64)]) {
+        self.books
+            .write()
+            .insert(market_id.to_string(), FullBook::new(bids, asks));
+    }
+
+    /// Apply one price-level change. Ignored (false) until a snapshot exists: a delta
+    /// on an unknown book would leave a partial ladder.
+    pub fn apply_level(&self, market_id: &str, is_bid: bool, px: f64, size: f64) -> bool {
+        match self.books.write().get_mut(market_id) {
+            Some(book) => {
+                book.set(is_bid, px, size);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// State fed by more than one stream: a streamed book older than the one held
+    /// is dropped. REST-polled books are never dropped and never block a stream.
+    pub fn reconciling() -> Self {
+        Self {
+            newest_book_wins: true,
+            ..Self::new()
         }
     }
 
-    #[allow(dead_code)]
     pub fn upsert(&self, market_id: &str, tob: Tob) {
         self.inner.write().insert(market_id.to_string(), tob);
     }
@@ -57,7 +306,7 @@ impl FeedState {
     /// Update book fields in-place under a single write lock.
     ///
     /// - Preserves last trade fields.
-    /// - Optionally updates updates/min EWMA when `inst_updates_per_min` is provided.
+    /// - Optionally sets the update rate when `updates_per_min` is provided.
     #[allow(clippy::too_many_arguments)]
     pub fn update_book_owned(
         &self,
@@ -65,53 +314,174 @@ impl FeedState {
         ts: f64,
         best_bid: Option<f64>,
         best_ask: Option<f64>,
-        bid_depth_5: f64,
-        ask_depth_5: f64,
-        inst_updates_per_min: Option<f64>,
+        bid_depth_n: f64,
+        ask_depth_n: f64,
+        updates_per_min: Option<f64>,
     ) {
-        let mut m = self.inner.write();
-        let e = m.entry(market_id.to_string()).or_insert(Tob {
-            best_bid: None,
-            best_ask: None,
-            bid_depth_5: 0.0,
-            ask_depth_5: 0.0,
+        self.update_book(
+            market_id,
             ts,
-            updates_ewma_per_min: 0.0,
-            last_trade_ema: None,
-            last_trade_ts: None,
-        });
+            (best_bid, best_ask),
+            (bid_depth_n, ask_depth_n),
+            updates_per_min,
+            false,
+        );
+    }
 
+    /// Book from the REST fallback poll; flagged `polled` until the next WS update.
+    pub fn update_book_polled(
+        &self,
+        market_id: &str,
+        ts: f64,
+        best_bid: Option<f64>,
+        best_ask: Option<f64>,
+        bid_depth_n: f64,
+        ask_depth_n: f64,
+    ) {
+        self.update_book(
+            market_id,
+            ts,
+            (best_bid, best_ask),
+            (bid_depth_n, ask_depth_n),
+            None,
+            true,
+        );
+    }
+
+    fn update_book(
+        &self,
+        market_id: &str,
+        ts: f64,
+        (best_bid, best_ask): (Option<f64>, Option<f64>),
+        (bid_depth_n, ask_depth_n): (f64, f64),
+        updates_per_min: Option<f64>,
+        polled: bool,
+    ) {
+        let mut m = self.inner.write();
+        let e = m
+            .entry(market_id.to_string())
+            .or_insert_with(|| Tob::empty(ts));
+        if self.newest_book_wins && !polled && !e.polled && ts < e.ts {
+            return;
+        }
+
+        let prev = e.mid().map(|m| (m, e.ts));
         e.best_bid = best_bid;
         e.best_ask = best_ask;
-        e.bid_depth_5 = bid_depth_5;
-        e.ask_depth_5 = ask_depth_5;
+        e.bid_depth_n = bid_depth_n;
+        e.ask_depth_n = ask_depth_n;
+        // Touch sizes from the level book, where its best level matches this quote.
+        let top = self.books.read().get(market_id).map(|b| b.top(1));
+        let touch = |level: Option<&(f64, f64)>, px: Option<f64>| {
+            level
+                .filter(|l| px.is_some_and(|p| (l.0 - p).abs() < 1e-9))
+                .map(|l| l.1)
+        };
+        e.bid_touch_size = touch(top.as_ref().and_then(|b| b.bids.first()), best_bid);
+        e.ask_touch_size = touch(top.as_ref().and_then(|b| b.asks.first()), best_ask);
+        if let (Some((prev_mid, prev_ts)), Some(mid)) = (prev, e.mid()) {
+            // Floor the gap so a burst of near-simultaneous updates can't blow up the rate.
+            let dt = (ts - prev_ts).max(MID_VAR_MIN_DT_SECS);
+            let sample = (mid - prev_mid).powi(2) / dt;
+            e.mid_var_ewma = Some(ewma(e.mid_var_ewma, sample, MID_VAR_ALPHA));
+        }
         e.ts = ts;
+        e.polled = polled;
+
+        if let Some(rate) = updates_per_min {
+            e.updates_ewma_per_min = rate;
+        }
+        drop(m);
+        if !polled {
+            let mut s = self.samples.write();
+            let n = s.entry(market_id.to_string()).or_insert(0);
+            *n = n.saturating_add(1);
+        }
+    }
 
-        if let Some(inst) = inst_updates_per_min {
-            e.updates_ewma_per_min = ewma(Some(e.updates_ewma_per_min), inst, 0.1);
+    /// Start these markets' stabilization over: a (re)subscribe restarts the stream,
+    /// and the update rate needs fresh samples before it can be trusted.
+    pub fn reset_stabilization(&self, market_ids: &[String]) {
+        let mut s = self.samples.write();
+        for id in market_ids {
+            s.insert(id.clone(), 0);
         }
     }
 
+    /// Whether `market_id` has seen at least `min_samples` streamed book updates since
+    /// its last (re)subscribe. REST-polled books do not count; `min_samples == 0` is off.
+    pub fn is_stabilized(&self, market_id: &str, min_samples: u32) -> bool {
+        min_samples == 0 || self.samples.read().get(market_id).copied().unwrap_or(0) >= min_samples
+    }
+
     /// Update last trade fields in-place under a single write lock.
     ///
     /// Does NOT overwrite `tob.ts` (book freshness).
     pub fn update_last_trade_owned(&self, market_id: &str, px: f64, trade_ts: f64) {
         let mut m = self.inner.write();
-        let e = m.entry(market_id.to_string()).or_insert(Tob {
-            best_bid: None,
-            best_ask: None,
-            bid_depth_5: 0.0,
-            ask_depth_5: 0.0,
-            ts: 0.0,
-            updates_ewma_per_min: 0.0,
-            last_trade_ema: None,
-            last_trade_ts: None,
-        });
+        let e = m
+            .entry(market_id.to_string())
+            .or_insert_with(|| Tob::empty(0.0));
 
         e.last_trade_ema = Some(ewma(e.last_trade_ema, px, 0.2));
         e.last_trade_ts = Some(trade_ts);
     }
 
+    /// Buffer a public trade print for the paper queue model (`PAPER_FILL_QUEUE_MODEL`).
+    pub fn record_trade(&self, market_id: &str, print: TradePrint) {
+        if !(print.price.is_finite() && print.size.is_finite() && print.size > 0.0) {
+            return;
+        }
+        let mut m = self.trades.write();
+        let q = m.entry(market_id.to_string()).or_default();
+        if q.len() >= MAX_PENDING_TRADES {
+            q.remove(0);
+        }
+        q.push(print);
+    }
+
+    /// Take the prints buffered for `market_id` since the last drain, oldest first.
+    pub fn drain_trades(&self, market_id: &str) -> Vec<TradePrint> {
+        self.trades.write().remove(market_id).unwrap_or_default()
+    }
+
+    /// Set the tick from market metadata unless one is already known
+    /// (a WS `tick_size_change` is fresher than Gamma).
+    pub fn seed_tick_size(&self, market_id: &str, tick: f64) {
+        if !(tick.is_finite() && tick > 0.0) {
+            return;
+        }
+        let mut m = self.inner.write();
+        let e = m
+            .entry(market_id.to_string())
+            .or_insert_with(|| Tob::empty(0.0));
+        if e.tick_size.is_none() {
+            e.tick_size = Some(tick);
+        }
+    }
+
+    /// Apply a tick size change from the WS feed. Does NOT overwrite `tob.ts`.
+    pub fn update_tick_size_owned(&self, market_id: &str, tick: f64) {
+        if !(tick.is_finite() && tick > 0.0) {
+            return;
+        }
+        let mut m = self.inner.write();
+        let e = m
+            .entry(market_id.to_string())
+            .or_insert_with(|| Tob::empty(0.0));
+        e.tick_size = Some(tick);
+    }
+
+    /// Flag a market as closed and pin its mark to `settle_price` (if known).
+    pub fn mark_closed(&self, market_id: &str, settle_price: Option<f64>) {
+        let mut m = self.inner.write();
+        let e = m
+            .entry(market_id.to_string())
+            .or_insert_with(|| Tob::empty(0.0));
+        e.closed = true;
+        e.settle_price = settle_price.or(e.settle_price);
+    }
+
     pub fn get(&self, market_id: &str) -> Option<Tob> {
         self.inner.read().get(market_id).cloned()
     }
@@ -122,6 +492,50 @@ impl FeedState {
     }
 }
 
+/// WS connectivity as seen by the reader loop; drives the REST fallback poll.
+#[derive(Debug, Clone, Copy)]
+pub struct WsLink {
+    // None while the socket is delivering messages.
+    down_since: Option<f64>,
+}
+
+impl WsLink {
+    pub fn new(now: f64) -> Self {
+        Self {
+            down_since: Some(now),
+        }
+    }
+
+    pub fn mark_up(&mut self) {
+        self.down_since = None;
+    }
+
+    /// Keeps the earliest outage start if already down.
+    pub fn mark_down(&mut self, now: f64) {
+        self.down_since.get_or_insert(now);
+    }
+
+    pub fn down_since(&self) -> Option<f64> {
+        self.down_since
+    }
+
+    /// True once the WS has been down for at least `after_secs`.
+    pub fn fallback_due(&self, now: f64, after_secs: f64) -> bool {
+        self.down_since
+            .is_some_and(|since| now - since >= after_secs)
+    }
+}
+
+// Marks the link down when the WS task exits (error, panic or abort) so the
+// fallback can start while the supervisor backs off.
+struct LinkDownOnExit(Arc<RwLock<WsLink>>);
+
+impl Drop for LinkDownOnExit {
+    fn drop(&mut self) {
+        self.0.write().mark_down(now_ts());
+    }
+}
+
 #[derive(Default, Clone)]
 struct Routes {
     // condition_id (0x...) -> market_id (gamma numeric string)
@@ -132,18 +546,46 @@ struct Routes {
     token_for_market: HashMap<String, String>,
 }
 
+impl Routes {
+    /// Market id for a feed message: by condition id, else by token id.
+    fn resolve(&self, market: &str, asset_id: &str) -> Option<String> {
+        self.by_condition
+            .get(market.trim())
+            .or_else(|| self.by_asset.get(asset_id.trim()))
+            .cloned()
+    }
+
+    /// True when `asset_id` is the token `market_id`'s book tracks. One `price_change`
+    /// message carries the changes for both outcome tokens of a market.
+    fn is_primary_token(&self, market_id: &str, asset_id: &str) -> bool {
+        self.token_for_market
+            .get(market_id)
+            .is_some_and(|t| t == asset_id.trim())
+    }
+}
+
 pub struct FeedHandler {
     settings: Settings,
     pub state: FeedState,
     routes: std::sync::Arc<RwLock<Routes>>,
+    link: Arc<RwLock<WsLink>>,
+    rtds_link: Arc<RwLock<WsLink>>,
 }
 
 impl FeedHandler {
     pub fn new(settings: Settings) -> Self {
+        let state = if settings.feed_source == "both" {
+            FeedState::reconciling()
+        } else {
+            FeedState::new()
+        }
+        .with_book_levels(settings.book_levels);
         Self {
             settings,
-            state: FeedState::new(),
+            state,
             routes: std::sync::Arc::new(RwLock::new(Routes::default())),
+            link: Arc::new(RwLock::new(WsLink::new(now_ts()))),
+            rtds_link: Arc::new(RwLock::new(WsLink::new(now_ts()))),
         }
     }
 
@@ -151,10 +593,16 @@ impl FeedHandler {
         self.state.clone()
     }
 
+    /// Connectivity of the CLOB market WS, as tracked by its reader loop.
+    pub fn ws_link(&self) -> Arc<RwLock<WsLink>> {
+        self.link.clone()
+    }
+
     pub fn spawn(
         self,
         selected_rx: watch::Receiver<Arc<Vec<SelectedMarket>>>,
         store: crate::store::SqliteStore,
+        health: HealthState,
     ) {
         let routes = self.routes.clone();
         let state = self.state.clone();
@@ -162,13 +610,23 @@ impl FeedHandler {
 
         // Watchlist router updater
         let store_routes = store.clone();
+        let state_routes = state.clone();
+        let max_subs = settings.max_ws_subscriptions;
         let mut selected_routes_rx = selected_rx.clone();
         tokio::spawn(async move {
             loop {
                 if selected_routes_rx.changed().await.is_err() {
                     break;
                 }
-                let markets = selected_routes_rx.borrow().clone(); // Arc clone (cheap)
+                let selected = selected_routes_rx.borrow().clone(); // Arc clone (cheap)
+                let (markets, dropped) = subscribed_markets(&selected, max_subs);
+                if dropped > 0 {
+                    log::warn!(
+                        "feed.subscription_cap max={} dropped={} (lowest-score markets not subscribed)",
+                        max_subs,
+                        dropped
+                    );
+                }
                 let mut r = routes.write();
                 r.by_condition.clear();
                 r.by_asset.clear();
@@ -193,6 +651,9 @@ impl FeedHandler {
                         r.token_for_market
                             .insert(m.market_id.clone(), tid.to_string());
                     }
+                    if let Some(tick) = m.tick_size {
+                        state_routes.seed_tick_size(&m.market_id, tick);
+                    }
                 }
 
                 store_routes
@@ -207,21 +668,62 @@ impl FeedHandler {
             }
         });
 
-        // WS reader loop
-        let routes_ws = self.routes.clone();
-        let state_ws = state.clone();
-        let settings_ws = settings.clone();
-        let store_ws = store.clone();
-        let selected_ws_rx = selected_rx.clone();
-        tokio::spawn(async move {
-            if let Err(e) =
-                run_ws_loop(settings_ws, state_ws, routes_ws, selected_ws_rx, store_ws).await
-            {
-                log::error!("feed.ws.loop.error {}", e);
-            }
-        });
+        let policy = RestartPolicy {
+            max_restarts: settings.feed_max_restarts,
+            backoff: std::time::Duration::from_millis(settings.feed_restart_backoff_ms),
+        };
+        // Links of the streams actually running; the REST poll starts once all are down.
+        let mut links = Vec::new();
+
+        // WS reader loop, respawned with the current selection if it dies.
+        if matches!(settings.feed_source.as_str(), "clob_ws" | "both") {
+            let routes_ws = self.routes.clone();
+            let state_ws = state.clone();
+            let settings_ws = settings.clone();
+            let store_ws = store.clone();
+            let selected_ws_rx = selected_rx.clone();
+            let link_ws = self.link.clone();
+            spawn_supervised(
+                "feed.ws",
+                store.clone(),
+                health.clone(),
+                policy,
+                move || {
+                    run_ws_loop(
+                        settings_ws.clone(),
+                        state_ws.clone(),
+                        routes_ws.clone(),
+                        selected_ws_rx.clone(),
+                        store_ws.clone(),
+                        link_ws.clone(),
+                    )
+                },
+            );
+            links.push(self.link.clone());
+        }
 
-        // CLOB orderbook polling loop (freshness + safety net)
+        // RTDS book stream, supervised the same way.
+        if matches!(settings.feed_source.as_str(), "rtds" | "both") {
+            let routes_rtds = self.routes.clone();
+            let state_rtds = state.clone();
+            let settings_rtds = settings.clone();
+            let store_rtds = store.clone();
+            let selected_rtds_rx = selected_rx.clone();
+            let link_rtds = self.rtds_link.clone();
+            spawn_supervised("feed.rtds", store.clone(), health, policy, move || {
+                run_rtds_loop(
+                    settings_rtds.clone(),
+                    state_rtds.clone(),
+                    routes_rtds.clone(),
+                    selected_rtds_rx.clone(),
+                    store_rtds.clone(),
+                    link_rtds.clone(),
+                )
+            });
+            links.push(self.rtds_link.clone());
+        }
+
+        // REST orderbook polling, only while the streams are down (degraded fallback).
         let routes_poll = self.routes.clone();
         let state_poll = state.clone();
         let settings_poll = settings.clone();
@@ -233,6 +735,7 @@ impl FeedHandler {
                 routes_poll,
                 selected_rx,
                 store_poll,
+                links,
             )
             .await
             {
@@ -242,6 +745,43 @@ impl FeedHandler {
     }
 }
 
+/// Market ids of the `selected` markets whose token is in `tokens`.
+fn markets_for_tokens(selected: &[SelectedMarket], tokens: &[String]) -> Vec<String> {
+    selected
+        .iter()
+        .filter(|m| m.clob_token_id.as_ref().is_some_and(|t| tokens.contains(t)))
+        .map(|m| m.market_id.clone())
+        .collect()
+}
+
+/// Markets the feed subscribes to: those with a CLOB token, capped at `max`
+/// by descending selector score (`max == 0` keeps all). Selection order is
+/// preserved; the second value counts markets dropped by the cap.
+pub fn subscribed_markets(selected: &[SelectedMarket], max: usize) -> (Vec<SelectedMarket>, usize) {
+    let mut ranked: Vec<&SelectedMarket> = selected
+        .iter()
+        .filter(|m| {
+            m.clob_token_id
+                .as_deref()
+                .is_some_and(|t| !t.trim().is_empty())
+        })
+        .collect();
+    if max == 0 || ranked.len() <= max {
+        return (ranked.into_iter().cloned().collect(), 0);
+    }
+    let dropped = ranked.len() - max;
+    ranked.sort_by(|a, b| b.microstructure_score.total_cmp(&a.microstructure_score));
+    ranked.truncate(max);
+    let keep: std::collections::HashSet<&str> =
+        ranked.iter().map(|m| m.market_id.as_str()).collect();
+    let kept = selected
+        .iter()
+        .filter(|m| keep.contains(m.market_id.as_str()))
+        .cloned()
+        .collect();
+    (kept, dropped)
+}
+
 fn ws_base_url(ws_url: &str) -> String {
     // Accept either:
     // - wss://ws-subscriptions-clob.polymarket.com
@@ -260,16 +800,24 @@ async fn run_ws_loop(
     routes: std::sync::Arc<RwLock<Routes>>,
     mut selected_rx: watch::Receiver<Arc<Vec<SelectedMarket>>>,
     store: crate::store::SqliteStore,
+    link: Arc<RwLock<WsLink>>,
 ) -> Result<()> {
+    let _down_on_exit = LinkDownOnExit(link.clone());
+    // Local copy so the shared link is only written on transitions.
+    let mut ws_up = false;
     let mut ws = ClobWsClient::builder()
         .base_url(ws_base_url(&settings.clob_ws_url))
         .build();
     let mut last_subscribed: Vec<String> = vec![];
     let mut needs_resubscribe = true;
     let mut force_resubscribe = true;
+    let mut incremental_ok = settings.ws_incremental_subscribe;
+    // An incremental update was sent but not yet acknowledged; a server error in that window is
+    // taken as a rejection of the update.
+    let mut incremental_unacked = false;
 
     // Per-market last update for EWMA.
-    let mut last_update_ts: HashMap<String, f64> = HashMap::new();
+    let mut update_rates: HashMap<String, RateTracker> = HashMap::new();
 
     loop {
         // Event-driven subscription updates: only (re)calculate and subscribe when selection changes
@@ -277,19 +825,20 @@ async fn run_ws_loop(
         if needs_resubscribe {
             // Clone the Arc out of the watch ref so we don't hold a non-Send borrow across .await.
             let selected = selected_rx.borrow().clone();
-            let mut desired_sorted: Vec<String> = selected
-                .iter()
-                .filter_map(|m| m.clob_token_id.as_deref())
-                .map(|s| s.to_string())
-                .collect();
+            let mut desired_sorted: Vec<String> =
+                subscribed_markets(&selected, settings.max_ws_subscriptions)
+                    .0
+                    .iter()
+                    .filter_map(|m| m.clob_token_id.as_deref())
+                    .map(|s| s.to_string())
+                    .collect();
             desired_sorted.sort();
             desired_sorted.dedup();
 
             let selection_changed = desired_sorted != last_subscribed;
             let should_resubscribe = selection_changed || force_resubscribe;
-            if selection_changed {
-                last_subscribed = desired_sorted;
-            }
+            let prev_subscribed = std::mem::take(&mut last_subscribed);
+            last_subscribed = desired_sorted;
 
             if !should_resubscribe {
                 needs_resubscribe = false;
@@ -303,18 +852,61 @@ async fn run_ws_loop(
                     .upsert_runtime_status("feed.ws", "warn", "no markets selected", None, now_ts())
                     .ok();
             } else {
-                store
-                    .upsert_runtime_status(
-                        "feed.ws",
-                        "ok",
-                        &format!("subscribing tokens={}", last_subscribed.len()),
-                        Some(&format!("base_url={}", ws_base_url(&settings.clob_ws_url))),
-                        now_ts(),
-                    )
-                    .ok();
-                ws.subscribe_market(last_subscribed.clone())
-                    .await
-                    .map_err(|e| anyhow::anyhow!("ws.subscribe_market failed: {e}"))?;
+                // Prefer an in-place add/drop so unchanged markets keep streaming. Reconnect on the
+                // first subscribe, after a connection drop, or once an incremental update failed.
+                let diff = subscription_diff(&prev_subscribed, &last_subscribed);
+                let try_incremental = incremental_ok
+                    && !force_resubscribe
+                    && !prev_subscribed.is_empty()
+                    && ws.status().await == ConnectionStatus::Connected;
+                let mut updated = false;
+                if try_incremental {
+                    match ws
+                        .update_market_subscription(diff.add.clone(), diff.drop.clone())
+                        .await
+                    {
+                        Ok(()) => {
+                            updated = true;
+                            incremental_unacked = true;
+                            state.reset_stabilization(&markets_for_tokens(&selected, &diff.add));
+                            store
+                                .upsert_runtime_status(
+                                    "feed.ws",
+                                    "ok",
+                                    &format!(
+                                        "subscription updated tokens={} (+{} -{})",
+                                        last_subscribed.len(),
+                                        diff.add.len(),
+                                        diff.drop.len()
+                                    ),
+                                    None,
+                                    now_ts(),
+                                )
+                                .ok();
+                        }
+                        Err(e) => {
+                            incremental_ok = false;
+                            log::warn!("feed.ws.incremental_failed err={} fallback=reconnect", e);
+                        }
+                    }
+                }
+
+                if !updated {
+                    store
+                        .upsert_runtime_status(
+                            "feed.ws",
+                            "ok",
+                            &format!("subscribing tokens={}", last_subscribed.len()),
+                            Some(&format!("base_url={}", ws_base_url(&settings.clob_ws_url))),
+                            now_ts(),
+                        )
+                        .ok();
+                    ws.subscribe_market(last_subscribed.clone())
+                        .await
+                        .map_err(|e| anyhow::anyhow!("ws.subscribe_market failed: {e}"))?;
+                    incremental_unacked = false;
+                    state.reset_stabilization(&markets_for_tokens(&selected, &last_subscribed));
+                }
             }
 
             needs_resubscribe = false;
@@ -337,13 +929,58 @@ async fn run_ws_loop(
             }
            msg = ws.next_message() => {
                 let Some(msg) = msg else {
+                    if ws_up {
+                        ws_up = false;
+                        link.write().mark_down(now_ts());
+                    }
                     // Connection dropped and auto-reconnect may have failed; wait for next tick.
                     tokio::time::sleep(std::time::Duration::from_millis(250)).await;
                    needs_resubscribe = true;
                    force_resubscribe = true;
                    continue;
                 };
-                handle_ws_message(&state, &routes, &mut last_update_ts, msg)?;
+                if !ws_up {
+                    ws_up = true;
+                    link.write().mark_up();
+                }
+                match msg {
+                    WsMessage::Error(e) => {
+                        report_ws_error(&store, &e);
+                        if incremental_unacked {
+                            incremental_unacked = false;
+                            incremental_ok = false;
+                            needs_resubscribe = true;
+                            force_resubscribe = true;
+                            log::warn!("feed.ws.incremental_rejected fallback=reconnect");
+                        }
+                    }
+                    WsMessage::SubscriptionAck(a) => {
+                        incremental_unacked = false;
+                        log::debug!("feed.ws.{} assets={}", a.event_type, a.assets_ids.len());
+                    }
+                    WsMessage::Reconnected => {
+                        // The client resubscribed on its own; books restart from the new
+                        // snapshots, so treat it like our own resubscribe.
+                        log::info!("feed.ws.reconnected assets={}", last_subscribed.len());
+                        incremental_unacked = false;
+                        let selected = selected_rx.borrow().clone();
+                        state.reset_stabilization(&markets_for_tokens(&selected, &last_subscribed));
+                    }
+                    msg => {
+                        if settings.tape_record_enabled {
+                            if let WsMessage::LastTradePrice(t) = &msg {
+                                record_public_trade(&store, &routes, t);
+                            }
+                        }
+                        handle_ws_message(
+                            &state,
+                            &routes,
+                            &mut update_rates,
+                            settings.depth_levels,
+                            msg,
+                        )?
+                    }
+                }
             }
         }
     }
@@ -351,10 +988,303 @@ async fn run_ws_loop(
     Ok(())
 }
 
+/// RTDS `clob_market` subscription for these tokens (all message types: books,
+/// last trades, tick size changes).
+fn rtds_market_subscription(tokens: &[String]) -> RtdsSubscription {
+    RtdsSubscription::all("clob_market")
+        .with_filter(serde_json::to_string(tokens).unwrap_or_else(|_| "[]".to_string()))
+}
+
+async fn run_rtds_loop(
+    settings: Settings,
+    state: FeedState,
+    routes: std::sync::Arc<RwLock<Routes>>,
+    mut selected_rx: watch::Receiver<Arc<Vec<SelectedMarket>>>,
+    store: crate::store::SqliteStore,
+    link: Arc<RwLock<WsLink>>,
+) -> Result<()> {
+    let _down_on_exit = LinkDownOnExit(link.clone());
+    let mut rtds = RtdsClient::builder()
+        .host(settings.rtds_ws_url.clone())
+        .auto_reconnect(true)
+        .build();
+    let res = rtds_session(
+        &settings,
+        &state,
+        &routes,
+        &mut selected_rx,
+        &store,
+        &link,
+        &mut rtds,
+    )
+    .await;
+    // Never leave the socket open behind us (restart, source switch, shutdown).
+    rtds.disconnect().await;
+    res
+}
+
+async fn rtds_session(
+    settings: &Settings,
+    state: &FeedState,
+    routes: &std::sync::Arc<RwLock<Routes>>,
+    selected_rx: &mut watch::Receiver<Arc<Vec<SelectedMarket>>>,
+    store: &crate::store::SqliteStore,
+    link: &Arc<RwLock<WsLink>>,
+    rtds: &mut RtdsClient,
+) -> Result<()> {
+    let mut rtds_up = false;
+    let mut connected = false;
+    let mut subscribed: Vec<String> = vec![];
+    let mut update_rates: HashMap<String, RateTracker> = HashMap::new();
+
+    loop {
+        let selected = selected_rx.borrow().clone();
+        let mut tokens: Vec<String> = subscribed_markets(&selected, settings.max_ws_subscriptions)
+            .0
+            .iter()
+            .filter_map(|m| m.clob_token_id.as_deref())
+            .map(|s| s.to_string())
+            .collect();
+        tokens.sort();
+        tokens.dedup();
+
+        if tokens.is_empty() {
+            if connected {
+                rtds.unsubscribe(vec![rtds_market_subscription(&subscribed)])
+                    .await
+                    .ok();
+                rtds.disconnect().await;
+                connected = false;
+                store
+                    .upsert_runtime_status(
+                        "feed.rtds",
+                        "warn",
+                        "no markets selected",
+                        None,
+                        now_ts(),
+                    )
+                    .ok();
+            }
+            subscribed.clear();
+            if selected_rx.changed().await.is_err() {
+                break;
+            }
+            continue;
+        }
+
+        if !connected {
+            // The client re-sends its stored subscription on connect.
+            rtds.connect()
+                .await
+                .map_err(|e| anyhow::anyhow!("rtds.connect failed: {e}"))?;
+            connected = true;
+            state.reset_stabilization(&markets_for_tokens(&selected, &subscribed));
+        }
+        if tokens != subscribed {
+            if !subscribed.is_empty() {
+                rtds.unsubscribe(vec![rtds_market_subscription(&subscribed)])
+                    .await
+                    .map_err(|e| anyhow::anyhow!("rtds.unsubscribe failed: {e}"))?;
+            }
+            rtds.subscribe(vec![rtds_market_subscription(&tokens)])
+                .await
+                .map_err(|e| anyhow::anyhow!("rtds.subscribe failed: {e}"))?;
+            state.reset_stabilization(&markets_for_tokens(&selected, &tokens));
+            store
+                .upsert_runtime_status(
+                    "feed.rtds",
+                    "ok",
+                    &format!("subscribing tokens={}", tokens.len()),
+                    Some(&format!("url={}", settings.rtds_ws_url)),
+                    now_ts(),
+                )
+                .ok();
+            subscribed = tokens;
+        }
+
+        tokio::select! {
+            changed = selected_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+            msg = rtds.next_message() => {
+                let Some(msg) = msg else {
+                    if rtds_up {
+                        rtds_up = false;
+                        link.write().mark_down(now_ts());
+                    }
+                    // Auto-reconnect gave up; reconnect (and resubscribe) on the next pass.
+                    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+                    connected = false;
+                    continue;
+                };
+                if !rtds_up {
+                    rtds_up = true;
+                    link.write().mark_up();
+                }
+                handle_rtds_message(state, routes, &mut update_rates, settings.depth_levels, &msg);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Apply an RTDS `clob_market` message to `FeedState` with the same semantics as the
+/// CLOB WS: book ts is the exchange timestamp (seconds), depth is the best
+/// `depth_levels` levels, last trades and tick changes leave the book ts alone.
+fn handle_rtds_message(
+    state: &FeedState,
+    routes: &std::sync::Arc<RwLock<Routes>>,
+    update_rates: &mut HashMap<String, RateTracker>,
+    depth_levels: usize,
+    msg: &RtdsMessage,
+) {
+    if msg.topic != "clob_market" {
+        return;
+    }
+    let msg_ts = parse_ws_ts(&msg.timestamp.to_string()).unwrap_or_else(now_ts);
+    match msg.message_type.as_str() {
+        "agg_orderbook" => {
+            let Ok(b) = msg.parse_payload::<AggOrderbook>() else {
+                return;
+            };
+            let Some(mid) = routes.read().resolve(&b.market, &b.asset_id) else {
+                return;
+            };
+            let ts = match b.timestamp {
+                0 => msg_ts,
+                t => parse_ws_ts(&t.to_string()).unwrap_or(msg_ts),
+            };
+            let (best_bid, bid_depth_n) = parse_levels(
+                b.bids.iter().map(|l| (l.price.as_str(), l.size.as_str())),
+                true,
+                depth_levels,
+            );
+            let (best_ask, ask_depth_n) = parse_levels(
+                b.asks.iter().map(|l| (l.price.as_str(), l.size.as_str())),
+                false,
+                depth_levels,
+            );
+            let rate = update_rates
+                .entry(mid.clone())
+                .or_insert_with(|| RateTracker::new(UPDATE_RATE_WINDOW_SECS));
+            rate.record(ts);
+            let per_min = rate.per_min(ts);
+            state.update_book_owned(
+                &mid,
+                ts,
+                best_bid,
+                best_ask,
+                bid_depth_n,
+                ask_depth_n,
+                Some(per_min),
+            );
+        }
+        "last_trade_price" => {
+            let Ok(t) = msg.parse_payload::<RtdsLastTrade>() else {
+                return;
+            };
+            let Some(mid) = routes.read().resolve(&t.market, &t.asset_id) else {
+                return;
+            };
+            if let Ok(px) = t.price.trim().parse::<f64>() {
+                state.update_last_trade_owned(&mid, px, msg_ts);
+                if let Ok(size) = t.size.trim().parse::<f64>() {
+                    state.record_trade(&mid, TradePrint { price: px, size });
+                }
+            }
+        }
+        "tick_size_change" => {
+            let Ok(t) = msg.parse_payload::<RtdsTickSizeChange>() else {
+                return;
+            };
+            let Some(mid) = routes.read().resolve(&t.market, &t.asset_id) else {
+                return;
+            };
+            if let Ok(tick) = t.new_tick_size.trim().parse::<f64>() {
+                log::info!(
+                    "feed.tick_size_change market={} old={} new={} source=rtds",
+                    mid,
+                    t.old_tick_size,
+                    t.new_tick_size
+                );
+                state.update_tick_size_owned(&mid, tick);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Surface a server error frame (e.g. a rejected subscription) instead of silently
+/// waiting for books that will never arrive.
+fn report_ws_error(store: &crate::store::SqliteStore, e: &WsErrorMessage) {
+    let detail = format!(
+        "{} (code={} assets={})",
+        e.message,
+        e.code.as_deref().unwrap_or("-"),
+        e.assets_ids.join(",")
+    );
+    log::warn!("feed.ws.error {}", detail);
+    store
+        .upsert_runtime_status("feed.ws", "warn", "ws_error_frame", Some(&detail), now_ts())
+        .ok();
+}
+
+/// Token-level change between two sorted, deduped subscription sets.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SubscriptionDiff {
+    add: Vec<String>,
+    drop: Vec<String>,
+}
+
+fn subscription_diff(prev: &[String], next: &[String]) -> SubscriptionDiff {
+    let prev_set: std::collections::HashSet<&str> = prev.iter().map(|s| s.as_str()).collect();
+    let next_set: std::collections::HashSet<&str> = next.iter().map(|s| s.as_str()).collect();
+    SubscriptionDiff {
+        add: next
+            .iter()
+            .filter(|t| !prev_set.contains(t.as_str()))
+            .cloned()
+            .collect(),
+        drop: prev
+            .iter()
+            .filter(|t| !next_set.contains(t.as_str()))
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Append a public trade print to the tape (`kind = "trade"`), for the fill-realism check.
+fn record_public_trade(
+    store: &crate::store::SqliteStore,
+    routes: &std::sync::Arc<RwLock<Routes>>,
+    t: &LastTradePriceMessage,
+) {
+    let market_id = {
+        let r = routes.read();
+        r.by_condition
+            .get(t.market.trim())
+            .cloned()
+            .or_else(|| r.by_asset.get(t.asset_id.trim()).cloned())
+    };
+    let (Some(mid), Ok(price)) = (market_id, t.price.trim().parse::<f64>()) else {
+        return;
+    };
+    let ts = parse_ws_ts(&t.timestamp).unwrap_or_else(now_ts);
+    let payload = serde_json::json!({
+        "price": price,
+        "size": t.size.trim().parse::<f64>().ok(),
+        "side": format!("{:?}", t.side).to_lowercase(),
+    });
+    store.insert_tape(ts, &mid, "trade", &payload).ok();
+}
+
 fn handle_ws_message(
     state: &FeedState,
     routes: &std::sync::Arc<RwLock<Routes>>,
-    last_update_ts: &mut HashMap<String, f64>,
+    update_rates: &mut HashMap<String, RateTracker>,
+    depth_levels: usize,
     msg: WsMessage,
 ) -> Result<()> {
     match msg {
@@ -371,31 +1301,71 @@ fn handle_ws_message(
             };
 
             let ts = parse_ws_ts(&b.timestamp).unwrap_or_else(now_ts);
-            let (best_bid, bid_depth_5) = parse_side_levels(&b.bids, true);
-            let (best_ask, ask_depth_5) = parse_side_levels(&b.asks, false);
-
-            let inst_per_min = last_update_ts
-                .get(&mid)
-                .and_then(|prev_ts| {
-                    let dt = (ts - *prev_ts).max(0.0);
-                    if dt <= 0.0 {
-                        None
-                    } else {
-                        Some(60.0 / dt)
-                    }
-                })
-                .unwrap_or(0.0);
-            last_update_ts.insert(mid.clone(), ts);
+            state.replace_book(&mid, &ws_levels(&b.bids), &ws_levels(&b.asks));
+            let (best_bid, bid_depth_n) = parse_side_levels(&b.bids, true, depth_levels);
+            let (best_ask, ask_depth_n) = parse_side_levels(&b.asks, false, depth_levels);
+
+            let rate = update_rates
+                .entry(mid.clone())
+                .or_insert_with(|| RateTracker::new(UPDATE_RATE_WINDOW_SECS));
+            rate.record(ts);
+            let per_min = rate.per_min(ts);
             state.update_book_owned(
                 &mid,
                 ts,
                 best_bid,
                 best_ask,
-                bid_depth_5,
-                ask_depth_5,
-                Some(inst_per_min),
+                bid_depth_n,
+                ask_depth_n,
+                Some(per_min),
             );
         }
+        WsMessage::PriceChange(pc) => {
+            let ts = parse_ws_ts(&pc.timestamp).unwrap_or_else(now_ts);
+            let mut changed: Vec<String> = vec![];
+            for c in &pc.price_changes {
+                let market_id = {
+                    let r = routes.read();
+                    r.resolve(&pc.market, &c.asset_id)
+                        .filter(|mid| r.is_primary_token(mid, &c.asset_id))
+                };
+                let Some(mid) = market_id else {
+                    continue;
+                };
+                let (Ok(px), Ok(size)) =
+                    (c.price.trim().parse::<f64>(), c.size.trim().parse::<f64>())
+                else {
+                    continue;
+                };
+                if !px.is_finite() || px <= 0.0 || !size.is_finite() || size < 0.0 {
+                    continue;
+                }
+                let is_bid = c.side == WsSide::Buy;
+                if state.apply_level(&mid, is_bid, px, size) && !changed.contains(&mid) {
+                    changed.push(mid);
+                }
+            }
+            // Re-derive the touch and depth of each changed book from its levels.
+            for mid in changed {
+                let Some(book) = state.books.read().get(&mid).map(|b| b.top(depth_levels)) else {
+                    continue;
+                };
+                let rate = update_rates
+                    .entry(mid.clone())
+                    .or_insert_with(|| RateTracker::new(UPDATE_RATE_WINDOW_SECS));
+                rate.record(ts);
+                let per_min = rate.per_min(ts);
+                state.update_book_owned(
+                    &mid,
+                    ts,
+                    book.bids.first().map(|l| l.0),
+                    book.asks.first().map(|l| l.0),
+                    book.depth(true, depth_levels),
+                    book.depth(false, depth_levels),
+                    Some(per_min),
+                );
+            }
+        }
         WsMessage::LastTradePrice(t) => {
             let market_id = {
                 let r = routes.read();
@@ -411,6 +1381,30 @@ fn handle_ws_message(
             let ts = parse_ws_ts(&t.timestamp).unwrap_or_else(now_ts);
             if let Some(p) = px {
                 state.update_last_trade_owned(&mid, p, ts);
+                if let Ok(size) = t.size.trim().parse::<f64>() {
+                    state.record_trade(&mid, TradePrint { price: p, size });
+                }
+            }
+        }
+        WsMessage::TickSizeChange(t) => {
+            let market_id = {
+                let r = routes.read();
+                r.by_condition
+                    .get(t.market.trim())
+                    .cloned()
+                    .or_else(|| r.by_asset.get(t.asset_id.trim()).cloned())
+            };
+            let Some(mid) = market_id else {
+                return Ok(());
+            };
+            if let Ok(tick) = t.new_tick_size.trim().parse::<f64>() {
+                log::info!(
+                    "feed.tick_size_change market={} old={} new={}",
+                    mid,
+                    t.old_tick_size,
+                    t.new_tick_size
+                );
+                state.update_tick_size_owned(&mid, tick);
             }
         }
         _ => {}
@@ -419,15 +1413,19 @@ fn handle_ws_message(
 }
 
 async fn run_poll_loop(
-    _settings: Settings,
+    settings: Settings,
     state: FeedState,
     routes: std::sync::Arc<RwLock<Routes>>,
     mut selected_rx: watch::Receiver<Arc<Vec<SelectedMarket>>>,
     store: crate::store::SqliteStore,
+    links: Vec<Arc<RwLock<WsLink>>>,
 ) -> Result<()> {
     let clob = ClobClient::new();
-    let mut tick = tokio::time::interval(std::time::Duration::from_secs(15));
+    let mut tick = tokio::time::interval(std::time::Duration::from_millis(
+        settings.rest_poll_interval_ms,
+    ));
     tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut polling = false;
 
     loop {
         tokio::select! {
@@ -435,6 +1433,29 @@ async fn run_poll_loop(
             _ = selected_rx.changed() => {}
         }
 
+        let now = now_ts();
+        let due = links
+            .iter()
+            .all(|l| l.read().fallback_due(now, settings.ws_fallback_after_secs));
+        if !due {
+            if polling {
+                polling = false;
+                log::info!("feed.poll.fallback_off ws recovered");
+                store
+                    .upsert_runtime_status("feed.poll", "ok", "idle (ws healthy)", None, now_ts())
+                    .ok();
+            }
+            continue;
+        }
+        if !polling {
+            polling = true;
+            log::warn!(
+                "feed.poll.fallback_on ws down >= {}s; polling REST books every {}ms",
+                settings.ws_fallback_after_secs,
+                settings.rest_poll_interval_ms
+            );
+        }
+
         let tokens = {
             let r = routes.read();
             r.token_for_market
@@ -472,8 +1493,8 @@ async fn run_poll_loop(
         store
             .upsert_runtime_status(
                 "feed.poll",
-                "ok",
-                &format!("polled {}", books.len()),
+                "warn",
+                &format!("ws fallback: polled {} (REST, higher latency)", books.len()),
                 None,
                 now_ts(),
             )
@@ -492,20 +1513,18 @@ async fn run_poll_loop(
                 continue;
             };
 
-            let (best_bid, bid_depth_5) = parse_side_levels_ob(&b.bids, true);
-            let (best_ask, ask_depth_5) = parse_side_levels_ob(&b.asks, false);
+            state.replace_book(&market_id, &ob_levels(&b.bids), &ob_levels(&b.asks));
+            let (best_bid, bid_depth_n) =
+                parse_side_levels_ob(&b.bids, true, settings.depth_levels);
+            let (best_ask, ask_depth_n) =
+                parse_side_levels_ob(&b.asks, false, settings.depth_levels);
 
             // Preserve updates EWMA and trade fields; refresh only book fields and timestamp.
             let ts = now.max(parse_ws_ts(&b.timestamp).unwrap_or(now));
-            state.update_book_owned(
-                &market_id,
-                ts,
-                best_bid,
-                best_ask,
-                bid_depth_5,
-                ask_depth_5,
-                None,
-            );
+            state.update_book_polled(&market_id, ts, best_bid, best_ask, bid_depth_n, ask_depth_n);
+            if let Ok(tick) = b.tick_size.trim().parse::<f64>() {
+                state.seed_tick_size(&market_id, tick);
+            }
         }
     }
 }
@@ -536,38 +1555,59 @@ fn parse_ws_ts(s: &str) -> Option<f64> {
 fn parse_side_levels(
     levels: &[polymarket_hft::client::polymarket::clob::ws::WsPriceLevel],
     is_bid: bool,
+    depth_levels: usize,
 ) -> (Option<f64>, f64) {
-    top5_by_price(
-        levels.iter().filter_map(|lvl| {
-            let px = lvl.price.parse::<f64>().ok()?;
-            let sz = lvl.size.parse::<f64>().ok()?;
-            if !px.is_finite() || !sz.is_finite() || px <= 0.0 || sz <= 0.0 {
-                return None;
-            }
-            Some((px, sz))
-        }),
-        is_bid,
-    )
+    top_n_by_price(ws_levels(levels), is_bid, depth_levels)
 }
 
 fn parse_side_levels_ob(
     levels: &[polymarket_hft::client::polymarket::clob::orderbook::PriceLevel],
     is_bid: bool,
+    depth_levels: usize,
 ) -> (Option<f64>, f64) {
-    top5_by_price(
-        levels.iter().filter_map(|lvl| {
-            let px = lvl.price.parse::<f64>().ok()?;
-            let sz = lvl.size.parse::<f64>().ok()?;
+    top_n_by_price(ob_levels(levels), is_bid, depth_levels)
+}
+
+fn ws_levels(
+    levels: &[polymarket_hft::client::polymarket::clob::ws::WsPriceLevel],
+) -> Vec<(f64, f64)> {
+    valid_levels(levels.iter().map(|l| (l.price.as_str(), l.size.as_str())))
+}
+
+fn ob_levels(
+    levels: &[polymarket_hft::client::polymarket::clob::orderbook::PriceLevel],
+) -> Vec<(f64, f64)> {
+    valid_levels(levels.iter().map(|l| (l.price.as_str(), l.size.as_str())))
+}
+
+/// String (price, size) levels from any source; unparsable or non-positive levels are skipped.
+fn parse_levels<'a, I>(levels: I, is_bid: bool, depth_levels: usize) -> (Option<f64>, f64)
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    top_n_by_price(valid_levels(levels), is_bid, depth_levels)
+}
+
+/// Parsed (price, size) levels, in input order, without unparsable or non-positive ones.
+fn valid_levels<'a, I>(levels: I) -> Vec<(f64, f64)>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    levels
+        .into_iter()
+        .filter_map(|(px, sz)| {
+            let px = px.parse::<f64>().ok()?;
+            let sz = sz.parse::<f64>().ok()?;
             if !px.is_finite() || !sz.is_finite() || px <= 0.0 || sz <= 0.0 {
                 return None;
             }
             Some((px, sz))
-        }),
-        is_bid,
-    )
+        })
+        .collect()
 }
 
-fn top5_by_price<I>(levels: I, is_bid: bool) -> (Option<f64>, f64)
+/// Best price and summed size of the best `n` levels, regardless of input order.
+fn top_n_by_price<I>(levels: I, is_bid: bool, n: usize) -> (Option<f64>, f64)
 where
     I: IntoIterator<Item = (f64, f64)>,
 {
@@ -580,42 +1620,24 @@ where
         }
     }
 
-    let mut buf: [(f64, f64); 5] = [(0.0, 0.0); 5];
-    let mut n: usize = 0;
+    let n = n.max(1);
+    // Kept sorted best-first; never grows past `n`.
+    let mut buf: Vec<(f64, f64)> = Vec::with_capacity(n + 1);
 
     for (px, sz) in levels {
-        let mut pos = 0usize;
-        while pos < n {
-            if better_price(is_bid, px, buf[pos].0) {
-                break;
-            }
-            pos += 1;
-        }
-
-        if n < 5 {
-            // Insert into [0..n], shifting right.
-            for j in (pos..n).rev() {
-                buf[j + 1] = buf[j];
-            }
-            buf[pos] = (px, sz);
-            n += 1;
-        } else {
-            // Full: only insert if it's better than the current worst (at index 4).
-            if pos >= 5 {
-                continue;
-            }
-            for j in (pos..4).rev() {
-                buf[j + 1] = buf[j];
-            }
-            buf[pos] = (px, sz);
+        let pos = buf
+            .iter()
+            .position(|lvl| better_price(is_bid, px, lvl.0))
+            .unwrap_or(buf.len());
+        if pos >= n {
+            continue;
         }
+        buf.insert(pos, (px, sz));
+        buf.truncate(n);
     }
 
-    if n == 0 {
-        return (None, 0.0);
-    }
-    let best = Some(buf[0].0);
-    let depth = buf.iter().take(n).map(|x| x.1).sum::<f64>();
+    let best = buf.first().map(|x| x.0);
+    let depth = buf.iter().map(|x| x.1).sum::<f64>();
     (best, depth)
 }
 
@@ -623,9 +1645,79 @@ where
 mod tests {
     use super::*;
     use polymarket_hft::client::polymarket::clob::ws::{
-        BookMessage, LastTradePriceMessage, WsPriceLevel,
+        BookMessage, LastTradePriceMessage, PriceChange, PriceChangeMessage, TickSizeChangeMessage,
+        WsPriceLevel,
     };
 
+    fn thin_ask_tob() -> Tob {
+        Tob {
+            best_bid: Some(0.40),
+            best_ask: Some(0.50),
+            bid_depth_n: 300.0,
+            ask_depth_n: 100.0,
+            last_trade_ema: Some(0.48),
+            ..Tob::empty(1.0)
+        }
+    }
+
+    #[test]
+    fn mark_source_mid() {
+        let t = thin_ask_tob();
+        assert!((t.mark("mid").unwrap() - 0.45).abs() < 1e-12);
+        let one_sided = Tob {
+            best_ask: None,
+            ..thin_ask_tob()
+        };
+        assert_eq!(one_sided.mark("mid"), None);
+    }
+
+    #[test]
+    fn mark_source_microprice() {
+        let t = thin_ask_tob();
+        // Heavy bid, thin ask: mark leans toward the ask.
+        assert!((t.mark("microprice").unwrap() - 0.475).abs() < 1e-12);
+        let no_depth = Tob {
+            bid_depth_n: 0.0,
+            ask_depth_n: 0.0,
+            ..thin_ask_tob()
+        };
+        assert!((no_depth.mark("microprice").unwrap() - 0.45).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mark_source_last_trade() {
+        let t = thin_ask_tob();
+        assert_eq!(t.mark("last_trade"), Some(0.48));
+        let no_trade = Tob {
+            last_trade_ema: None,
+            ..thin_ask_tob()
+        };
+        assert!((no_trade.mark("last_trade").unwrap() - 0.45).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mark_source_clob_midpoint() {
+        let t = thin_ask_tob();
+        assert!((t.mark("clob_midpoint").unwrap() - 0.45).abs() < 1e-12);
+        let one_sided = Tob {
+            best_ask: None,
+            ..thin_ask_tob()
+        };
+        assert_eq!(one_sided.mark("clob_midpoint"), Some(0.48));
+    }
+
+    #[test]
+    fn closed_market_marks_at_settle_for_every_source() {
+        let t = Tob {
+            closed: true,
+            settle_price: Some(1.0),
+            ..thin_ask_tob()
+        };
+        for src in ["mid", "microprice", "last_trade", "clob_midpoint"] {
+            assert_eq!(t.mark(src), Some(1.0), "{src}");
+        }
+    }
+
     #[test]
     fn orderbook_poll_request_has_no_side_filter() {
         let tokens = vec!["t1".to_string(), "t2".to_string(), "t3".to_string()];
@@ -636,6 +1728,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn subscription_diff_reports_adds_and_drops_only() {
+        let prev = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let next = vec!["b".to_string(), "c".to_string(), "d".to_string()];
+        let diff = subscription_diff(&prev, &next);
+        assert_eq!(diff.add, vec!["d".to_string()]);
+        assert_eq!(diff.drop, vec!["a".to_string()]);
+
+        assert_eq!(subscription_diff(&next, &next), SubscriptionDiff::default());
+
+        let diff = subscription_diff(&[], &next);
+        assert_eq!(diff.add, next);
+        assert!(diff.drop.is_empty());
+    }
+
     #[test]
     fn ws_book_and_last_trade_events_update_tob_state() {
         let state = FeedState::new();
@@ -676,8 +1783,8 @@ mod tests {
             hash: "h".to_string(),
         });
 
-        let mut last_update_ts: HashMap<String, f64> = HashMap::new();
-        handle_ws_message(&state, &routes, &mut last_update_ts, msg1).unwrap();
+        let mut update_rates: HashMap<String, RateTracker> = HashMap::new();
+        handle_ws_message(&state, &routes, &mut update_rates, 5, msg1).unwrap();
 
         let tob = state.get("516926").expect("tob should be upserted");
         assert_eq!(tob.best_bid, Some(0.49));
@@ -688,7 +1795,7 @@ mod tests {
             "timestamp should be converted to seconds"
         );
 
-        // Second book snapshot slightly later: should bump EWMA updates/min above zero.
+        // Second book snapshot slightly later: should bump updates/min above zero.
         let msg2 = WsMessage::Book(BookMessage {
             event_type: "book".to_string(),
             asset_id: "token_yes".to_string(),
@@ -704,11 +1811,11 @@ mod tests {
             timestamp: "1700000000500".to_string(), // +500ms
             hash: "h2".to_string(),
         });
-        handle_ws_message(&state, &routes, &mut last_update_ts, msg2).unwrap();
+        handle_ws_message(&state, &routes, &mut update_rates, 5, msg2).unwrap();
         let tob2 = state.get("516926").expect("tob should still exist");
         assert!(
             tob2.updates_ewma_per_min > 0.0,
-            "should compute a non-zero updates/min EWMA"
+            "should compute a non-zero updates/min rate"
         );
 
         // Last trade event: should update last_trade_ema/ts but not overwrite book ts.
@@ -722,7 +1829,7 @@ mod tests {
             fee_rate_bps: "0".to_string(),
             timestamp: "1700000000600".to_string(),
         });
-        handle_ws_message(&state, &routes, &mut last_update_ts, trade).unwrap();
+        handle_ws_message(&state, &routes, &mut update_rates, 5, trade).unwrap();
         let tob3 = state.get("516926").expect("tob should still exist");
         assert!(tob3.last_trade_ema.is_some());
         assert!(tob3.last_trade_ts.is_some());
@@ -732,6 +1839,53 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tick_size_change_rerounds_quotes_to_new_tick() {
+        let state = FeedState::new();
+        let routes = std::sync::Arc::new(RwLock::new(Routes::default()));
+        routes
+            .write()
+            .by_asset
+            .insert("token_yes".to_string(), "516926".to_string());
+
+        // Metadata seeds the tick before any book or change message arrives.
+        state.seed_tick_size("516926", 0.001);
+        assert_eq!(state.get("516926").unwrap().tick_size, Some(0.001));
+        state.update_book_owned("516926", 1.0, Some(0.49), Some(0.51), 10.0, 10.0, None);
+
+        let change = WsMessage::TickSizeChange(TickSizeChangeMessage {
+            event_type: "tick_size_change".to_string(),
+            asset_id: "token_yes".to_string(),
+            market: "0xcond".to_string(),
+            old_tick_size: "0.001".to_string(),
+            new_tick_size: "0.01".to_string(),
+            timestamp: "1700000000000".to_string(),
+        });
+        let mut update_rates: HashMap<String, RateTracker> = HashMap::new();
+        handle_ws_message(&state, &routes, &mut update_rates, 5, change).unwrap();
+
+        let tob = state.get("516926").unwrap();
+        assert_eq!(tob.tick_size, Some(0.01));
+        assert_eq!(tob.ts, 1.0, "tick change should not touch book freshness");
+
+        // A later metadata refresh must not clobber the live value.
+        state.seed_tick_size("516926", 0.001);
+        assert_eq!(state.get("516926").unwrap().tick_size, Some(0.01));
+
+        let strat = crate::hft_strategy::HftStrategy::new(Settings::load().unwrap());
+        let tick = strat.tick_for(&tob);
+        let intents = strat.quote_grid(0.5037, 0.0, 0.0, 0.0, 0.0, tick);
+        assert!(!intents.is_empty());
+        for q in intents {
+            let steps = q.price / 0.01;
+            assert!(
+                (steps - steps.round()).abs() < 1e-9,
+                "price {} not on 0.01 grid",
+                q.price
+            );
+        }
+    }
+
     #[test]
     fn parse_side_levels_is_defensive_about_sorting() {
         // Unsorted bids (worst first): should still pick best=max.
@@ -749,9 +1903,9 @@ mod tests {
                 size: "7".to_string(),
             },
         ];
-        let (best_bid, depth_bid_5) = parse_side_levels(&bids, true);
+        let (best_bid, depth_bid) = parse_side_levels(&bids, true, 5);
         assert_eq!(best_bid, Some(0.49));
-        assert!(depth_bid_5 > 0.0);
+        assert!(depth_bid > 0.0);
 
         // Unsorted asks (worst first): should still pick best=min.
         let asks = vec![
@@ -768,9 +1922,9 @@ mod tests {
                 size: "7".to_string(),
             },
         ];
-        let (best_ask, depth_ask_5) = parse_side_levels(&asks, false);
+        let (best_ask, depth_ask) = parse_side_levels(&asks, false, 5);
         assert_eq!(best_ask, Some(0.51));
-        assert!(depth_ask_5 > 0.0);
+        assert!(depth_ask > 0.0);
     }
 
     #[test]
@@ -802,10 +1956,10 @@ mod tests {
                 size: "100".to_string(),
             }, // best bid, appears last
         ];
-        let (best_bid, bid_depth_5) = parse_side_levels(&bids, true);
+        let (best_bid, bid_depth_n) = parse_side_levels(&bids, true, 5);
         assert_eq!(best_bid, Some(0.99));
         assert!(
-            (bid_depth_5 - 104.0).abs() < 1e-12,
+            (bid_depth_n - 104.0).abs() < 1e-12,
             "depth should be sum of top 5 by price"
         );
 
@@ -835,11 +1989,364 @@ mod tests {
                 size: "100".to_string(),
             }, // best ask (lowest), appears last
         ];
-        let (best_ask, ask_depth_5) = parse_side_levels(&asks, false);
+        let (best_ask, ask_depth_n) = parse_side_levels(&asks, false, 5);
         assert_eq!(best_ask, Some(0.01));
         assert!(
-            (ask_depth_5 - 104.0).abs() < 1e-12,
+            (ask_depth_n - 104.0).abs() < 1e-12,
             "depth should be sum of top 5 by price"
         );
     }
+
+    #[test]
+    fn depth_window_follows_configured_levels() {
+        // Bids 0.50 down to 0.41 in 0.01 steps; level i has size i+1.
+        let bids: Vec<WsPriceLevel> = (0..10)
+            .rev()
+            .map(|i| WsPriceLevel {
+                price: format!("{:.2}", 0.50 - 0.01 * i as f64),
+                size: format!("{}", i + 1),
+            })
+            .collect();
+        let asks = vec![WsPriceLevel {
+            price: "0.52".to_string(),
+            size: "6".to_string(),
+        }];
+
+        let (best3, depth3) = parse_side_levels(&bids, true, 3);
+        let (best10, depth10) = parse_side_levels(&bids, true, 10);
+        assert_eq!(best3, Some(0.50));
+        assert_eq!(best10, Some(0.50));
+        assert!((depth3 - (1.0 + 2.0 + 3.0)).abs() < 1e-12, "{depth3}");
+        assert!((depth10 - 55.0).abs() < 1e-12, "{depth10}");
+
+        // Imbalance reads the same window the feed computed.
+        let ask_depth = parse_side_levels(&asks, false, 3).1;
+        let mut tob = Tob::empty(0.0);
+        tob.bid_depth_n = depth3;
+        tob.ask_depth_n = ask_depth;
+        assert_eq!(tob.imbalance(), 0.0);
+        tob.bid_depth_n = depth10;
+        assert!((tob.imbalance() - 49.0 / 61.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn subscriptions_are_capped_to_top_scores() {
+        let market = |id: &str, score: f64, token: Option<&str>| SelectedMarket {
+            market_id: id.to_string(),
+            question: None,
+            event_id: None,
+            end_ts: None,
+            volume_24h_usd: 0.0,
+            liquidity_usd: 0.0,
+            condition_id: None,
+            clob_token_id: token.map(str::to_string),
+            microstructure_score: score,
+            tick_size: None,
+            category: "other".to_string(),
+        };
+        let selected = vec![
+            market("a", 1.0, Some("ta")),
+            market("b", 7.0, Some("tb")),
+            market("c", 3.0, None),
+            market("d", 5.0, Some("td")),
+        ];
+
+        let (all, dropped) = subscribed_markets(&selected, 0);
+        assert_eq!(all.len(), 3);
+        assert_eq!(dropped, 0);
+
+        let (capped, dropped) = subscribed_markets(&selected, 2);
+        let ids: Vec<&str> = capped.iter().map(|m| m.market_id.as_str()).collect();
+        assert_eq!(ids, ["b", "d"]);
+        assert_eq!(dropped, 1);
+
+        assert_eq!(subscribed_markets(&selected, 3).1, 0);
+    }
+
+    #[test]
+    fn rest_fallback_activates_on_ws_disconnect() {
+        let mut link = WsLink::new(0.0);
+        link.mark_up();
+        assert!(!link.fallback_due(100.0, 5.0), "no polling while WS is up");
+
+        link.mark_down(100.0);
+        assert!(!link.fallback_due(104.0, 5.0));
+        assert!(link.fallback_due(105.0, 5.0));
+        // A later drop report doesn't restart the outage clock.
+        link.mark_down(104.0);
+        assert!(link.fallback_due(105.0, 5.0));
+
+        link.mark_up();
+        assert!(!link.fallback_due(200.0, 5.0));
+
+        // Task exit (error/panic) marks the shared link down.
+        let shared = Arc::new(RwLock::new(link));
+        drop(LinkDownOnExit(shared.clone()));
+        assert!(shared.read().fallback_due(now_ts() + 5.0, 5.0));
+
+        // Polled books are flagged; the next WS book clears the flag.
+        let state = FeedState::new();
+        state.update_book_polled("m1", 1.0, Some(0.49), Some(0.51), 10.0, 10.0);
+        assert!(state.get("m1").unwrap().polled);
+        state.update_book_owned("m1", 2.0, Some(0.49), Some(0.51), 10.0, 10.0, Some(1.0));
+        assert!(!state.get("m1").unwrap().polled);
+    }
+
+    fn rtds_book(ts_ms: u64, bid: &str, ask: &str) -> RtdsMessage {
+        serde_json::from_value(serde_json::json!({
+            "topic": "clob_market",
+            "type": "agg_orderbook",
+            "timestamp": ts_ms + 5,
+            "payload": {
+                "market": "0xcond",
+                "asset_id": "token_yes",
+                "bids": [{"price": bid, "size": "10"}, {"price": "0.40", "size": "5"}],
+                "asks": [{"price": ask, "size": "9"}, {"price": "0.60", "size": "4"}],
+                "tick_size": "0.01",
+                "timestamp": ts_ms,
+            },
+        }))
+        .unwrap()
+    }
+
+    fn ws_book(ts_ms: u64, bid: &str, ask: &str) -> WsMessage {
+        WsMessage::Book(BookMessage {
+            event_type: "book".to_string(),
+            asset_id: "token_yes".to_string(),
+            market: "0xcond".to_string(),
+            bids: vec![
+                WsPriceLevel {
+                    price: bid.to_string(),
+                    size: "10".to_string(),
+                },
+                WsPriceLevel {
+                    price: "0.40".to_string(),
+                    size: "5".to_string(),
+                },
+            ],
+            asks: vec![
+                WsPriceLevel {
+                    price: ask.to_string(),
+                    size: "9".to_string(),
+                },
+                WsPriceLevel {
+                    price: "0.60".to_string(),
+                    size: "4".to_string(),
+                },
+            ],
+            timestamp: ts_ms.to_string(),
+            hash: "h".to_string(),
+        })
+    }
+
+    #[test]
+    fn each_feed_source_populates_feed_state_the_same_way() {
+        let routes = std::sync::Arc::new(RwLock::new(Routes::default()));
+        routes
+            .write()
+            .by_condition
+            .insert("0xcond".to_string(), "516926".to_string());
+
+        let ws_state = FeedState::new();
+        let mut ws_rates = HashMap::new();
+        handle_ws_message(
+            &ws_state,
+            &routes,
+            &mut ws_rates,
+            5,
+            ws_book(1_700_000_000_000, "0.49", "0.51"),
+        )
+        .unwrap();
+        let rtds_state = FeedState::new();
+        let mut rtds_rates = HashMap::new();
+        handle_rtds_message(
+            &rtds_state,
+            &routes,
+            &mut rtds_rates,
+            5,
+            &rtds_book(1_700_000_000_000, "0.49", "0.51"),
+        );
+
+        let a = ws_state.get("516926").unwrap();
+        let b = rtds_state.get("516926").unwrap();
+        assert_eq!((b.best_bid, b.best_ask), (Some(0.49), Some(0.51)));
+        assert_eq!((b.bid_depth_n, b.ask_depth_n), (15.0, 13.0));
+        assert_eq!(
+            b.ts,
+            parse_ws_ts("1700000000000").unwrap(),
+            "book ts is the book's own timestamp, not the envelope's"
+        );
+        assert_eq!(
+            (a.best_bid, a.best_ask, a.bid_depth_n, a.ask_depth_n, a.ts),
+            (b.best_bid, b.best_ask, b.bid_depth_n, b.ask_depth_n, b.ts)
+        );
+        assert_eq!(a.updates_ewma_per_min, b.updates_ewma_per_min);
+
+        // Other topics and unrouted markets are ignored.
+        let mut other = rtds_book(1_700_000_001_000, "0.30", "0.70");
+        other.topic = "activity".to_string();
+        handle_rtds_message(&rtds_state, &routes, &mut rtds_rates, 5, &other);
+        assert_eq!(rtds_state.get("516926").unwrap().best_bid, Some(0.49));
+
+        // Both sources on: the newer book wins whichever stream delivers it.
+        let both = FeedState::reconciling();
+        let (mut wr, mut rr) = (HashMap::new(), HashMap::new());
+        handle_rtds_message(
+            &both,
+            &routes,
+            &mut rr,
+            5,
+            &rtds_book(1_700_000_002_000, "0.47", "0.53"),
+        );
+        handle_ws_message(
+            &both,
+            &routes,
+            &mut wr,
+            5,
+            ws_book(1_700_000_001_000, "0.49", "0.51"),
+        )
+        .unwrap();
+        assert_eq!(
+            both.get("516926").unwrap().best_bid,
+            Some(0.47),
+            "older WS book dropped"
+        );
+        handle_ws_message(
+            &both,
+            &routes,
+            &mut wr,
+            5,
+            ws_book(1_700_000_003_000, "0.46", "0.54"),
+        )
+        .unwrap();
+        assert_eq!(both.get("516926").unwrap().best_bid, Some(0.46));
+        assert_eq!(
+            both.get("516926").unwrap().ts,
+            parse_ws_ts("1700000003000").unwrap()
+        );
+        // A single-source state never drops books on ts.
+        handle_ws_message(
+            &ws_state,
+            &routes,
+            &mut ws_rates,
+            5,
+            ws_book(1_699_999_999_000, "0.45", "0.55"),
+        )
+        .unwrap();
+        assert_eq!(ws_state.get("516926").unwrap().best_bid, Some(0.45));
+    }
+
+    #[test]
+    fn mid_variance_tracks_book_moves() {
+        let state = FeedState::new();
+        state.update_book_owned("m1", 1.0, Some(0.49), Some(0.51), 10.0, 10.0, None);
+        assert_eq!(state.get("m1").unwrap().mid_var_ewma, None);
+        // Unchanged mid: a zero sample.
+        state.update_book_owned("m1", 2.0, Some(0.49), Some(0.51), 10.0, 10.0, None);
+        assert_eq!(state.get("m1").unwrap().mid_var_ewma, Some(0.0));
+        // A one-cent move over 2s: 0.0001 / 2 per second, smoothed in.
+        state.update_book_owned("m1", 4.0, Some(0.50), Some(0.52), 10.0, 10.0, None);
+        let var = state.get("m1").unwrap().mid_var_ewma.unwrap();
+        assert!((var - MID_VAR_ALPHA * 0.00005).abs() < 1e-12, "{var}");
+    }
+
+    #[test]
+    fn price_changes_edit_the_book_levels_between_snapshots() {
+        let state = FeedState::new().with_book_levels(2);
+        let routes = std::sync::Arc::new(RwLock::new(Routes::default()));
+        {
+            let mut r = routes.write();
+            r.by_condition
+                .insert("0xcond".to_string(), "516926".to_string());
+            r.token_for_market
+                .insert("516926".to_string(), "token_yes".to_string());
+        }
+        let lvl = |px: &str, sz: &str| WsPriceLevel {
+            price: px.to_string(),
+            size: sz.to_string(),
+        };
+        let change_for = |asset: &str, px: &str, sz: &str, side: WsSide| PriceChange {
+            asset_id: asset.to_string(),
+            price: px.to_string(),
+            size: sz.to_string(),
+            side,
+            hash: "h".to_string(),
+            best_bid: String::new(),
+            best_ask: String::new(),
+        };
+        let change = |px: &str, sz: &str, side: WsSide| change_for("token_yes", px, sz, side);
+        let delta = |changes: Vec<PriceChange>, ts: &str| {
+            WsMessage::PriceChange(PriceChangeMessage {
+                event_type: "price_change".to_string(),
+                market: "0xcond".to_string(),
+                price_changes: changes,
+                timestamp: ts.to_string(),
+            })
+        };
+        let mut update_rates: HashMap<String, RateTracker> = HashMap::new();
+
+        // A delta before any snapshot has no book to edit.
+        let early = delta(vec![change("0.45", "3", WsSide::Buy)], "1699999999000");
+        handle_ws_message(&state, &routes, &mut update_rates, 5, early).unwrap();
+        assert!(state.get_book("516926").is_none());
+        assert!(state.get("516926").is_none());
+
+        let snapshot = WsMessage::Book(BookMessage {
+            event_type: "book".to_string(),
+            asset_id: "token_yes".to_string(),
+            market: "0xcond".to_string(),
+            bids: vec![lvl("0.48", "5"), lvl("0.49", "10"), lvl("0.47", "2")],
+            asks: vec![lvl("0.52", "4"), lvl("0.51", "9")],
+            timestamp: "1700000000000".to_string(),
+            hash: "h".to_string(),
+        });
+        handle_ws_message(&state, &routes, &mut update_rates, 5, snapshot).unwrap();
+        let book = state
+            .get_book("516926")
+            .expect("snapshot should seed the book");
+        assert_eq!(book.bids, vec![(0.49, 10.0), (0.48, 5.0)]);
+        assert_eq!(book.asks, vec![(0.51, 9.0), (0.52, 4.0)]);
+
+        // New best bid, the best ask pulled, a resized level behind the touch.
+        let msg = delta(
+            vec![
+                change("0.50", "7", WsSide::Buy),
+                change("0.51", "0", WsSide::Sell),
+                change("0.48", "8", WsSide::Buy),
+            ],
+            "1700000000500",
+        );
+        handle_ws_message(&state, &routes, &mut update_rates, 5, msg).unwrap();
+        let book = state.get_book("516926").unwrap();
+        assert_eq!(book.bids, vec![(0.50, 7.0), (0.49, 10.0)]);
+        assert_eq!(book.asks, vec![(0.52, 4.0)]);
+        assert!((book.depth(true, 2) - 17.0).abs() < 1e-9);
+
+        // The touch and depth (over DEPTH_LEVELS, not BOOK_LEVELS) follow the levels.
+        let tob = state.get("516926").unwrap();
+        assert_eq!(tob.best_bid, Some(0.50));
+        assert_eq!(tob.best_ask, Some(0.52));
+        assert!((tob.bid_depth_n - 27.0).abs() < 1e-9);
+        assert!((tob.ask_depth_n - 4.0).abs() < 1e-9);
+        // Touch sizes are the best level's, not the summed depth.
+        assert_eq!(tob.touch_size(true), 7.0);
+        assert_eq!(tob.touch_size(false), 4.0);
+
+        // One message for both outcome tokens: the No token's levels (its bid is the Yes
+        // ask mirrored) must not land in the Yes book.
+        let msg = delta(
+            vec![
+                change_for("token_no", "0.53", "40", WsSide::Buy),
+                change("0.50", "9", WsSide::Buy),
+                change_for("token_no", "0.45", "6", WsSide::Sell),
+            ],
+            "1700000001000",
+        );
+        handle_ws_message(&state, &routes, &mut update_rates, 5, msg).unwrap();
+        let book = state.get_book("516926").unwrap();
+        assert_eq!(book.bids, vec![(0.50, 9.0), (0.49, 10.0)]);
+        assert_eq!(book.asks, vec![(0.52, 4.0)]);
+        let tob = state.get("516926").unwrap();
+        assert_eq!((tob.best_bid, tob.best_ask), (Some(0.50), Some(0.52)));
+    }
 }
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use rand::{rngs::SmallRng, Rng};
+
+use crate::{
+    config::Settings,
+    feed_handler::Tob,
+    paper_broker::{fill_intensity, Side},
+    utils::poisson_sample,
+};
+
+/// Names accepted by `PAPER_FILL_MODEL` / `--paper-fill-model`.
+pub const FILL_MODELS: &[&str] = &[
+    "maker_touch",
+    "optimistic_touch",
+    "queue",
+    "probability_decay",
+];
+
+/// One resting order at one simulation step, as seen by a fill model.
+pub struct FillInput<'a> {
+    pub order_id: &'a str,
+    pub side: Side,
+    /// Unfilled size; a model never fills more than this.
+    pub remaining: f64,
+    /// Ticks behind the touch (0 at or inside it).
+    pub distance_ticks: f64,
+    /// Seconds since the previous simulation step for this market.
+    pub dt: f64,
+    pub activity_score: f64,
+    pub tick: f64,
+    pub tob: &'a Tob,
+}
+
+/// Decides how much of a resting paper order fills in one step. The broker keeps
+/// everything around it (placement latency, min rest, throttling, recording), so a
+/// model only answers "how much fills now".
+pub trait FillModel: Send {
+    fn name(&self) -> &'static str;
+
+    /// Size filled this step, in `[0, input.remaining]`.
+    fn fill_size(&mut self, settings: &Settings, input: &FillInput<'_>, rng: &mut SmallRng) -> f64;
+
+    /// The order left the book; drop any per-order state.
+    fn forget(&mut self, _order_id: &str) {}
+}
+
+pub fn fill_model_for(name: &str) -> Result<Box<dyn FillModel>> {
+    Ok(match name {
+        "maker_touch" => Box::new(MakerTouch),
+        "optimistic_touch" => Box::new(OptimisticTouch),
+        "queue" => Box::new(QueueModel::default()),
+        "probability_decay" => Box::new(ProbabilityDecay),
+        other => {
+            return Err(anyhow!(
+                "unknown paper fill model {other} (expected one of {})",
+                FILL_MODELS.join("|")
+            ))
+        }
+    })
+}
+
+/// Poisson opponent arrivals at `fill_intensity`; each arrival takes 30-100% of what is left.
+pub struct MakerTouch;
+
+impl FillModel for MakerTouch {
+    fn name(&self) -> &'static str {
+        "maker_touch"
+    }
+
+    fn fill_size(&mut self, settings: &Settings, input: &FillInput<'_>, rng: &mut SmallRng) -> f64 {
+        let lambda_dt =
+            fill_intensity(settings, input.distance_ticks, input.activity_score) * input.dt;
+        if poisson_sample(rng, lambda_dt) == 0 {
+            return 0.0;
+        }
+        let frac = (0.3 + 0.7 * rng.random::<f64>()).clamp(0.05, 1.0);
+        (input.remaining * frac)
+            .max(input.tick.min(input.remaining))
+            .min(input.remaining)
+    }
+}
+
+/// Upper bound on maker fills: anything at or inside the touch fills completely, at once.
+pub struct OptimisticTouch;
+
+impl FillModel for OptimisticTouch {
+    fn name(&self) -> &'static str {
+        "optimistic_touch"
+    }
+
+    fn fill_size(&mut self, _: &Settings, input: &FillInput<'_>, _: &mut SmallRng) -> f64 {
+        if input.distance_ticks <= 0.0 {
+            input.remaining
+        } else {
+            0.0
+        }
+    }
+}
+
+/// FIFO queue at the touch: an order joining the touch waits behind the size already
+/// shown there, which trades away at `PAPER_QUEUE_DEPLETION_PER_SEC` x activity. Only
+/// what trades through after the queue ahead is gone fills the order.
+#[derive(Default)]
+pub struct QueueModel {
+    ahead: HashMap<String, f64>,
+}
+
+impl QueueModel {
+    /// Size at the touch on the order's side (the book only carries summed depth).
+    fn touch_size(settings: &Settings, input: &FillInput<'_>) -> f64 {
+        let depth = match input.side {
+            Side::Buy => input.tob.bid_depth_n,
+            Side::Sell => input.tob.ask_depth_n,
+        };
+        depth.max(0.0) / settings.depth_levels.max(1) as f64
+    }
+}
+
+impl FillModel for QueueModel {
+    fn name(&self) -> &'static str {
+        "queue"
+    }
+
+    fn fill_size(&mut self, settings: &Settings, input: &FillInput<'_>, _: &mut SmallRng) -> f64 {
+        if input.distance_ticks > 0.0 {
+            // Behind the touch: lose the place, re-queue on return.
+            self.ahead.remove(input.order_id);
+            return 0.0;
+        }
+        let traded = settings.paper_queue_depletion_per_sec.max(0.0)
+            * input.activity_score.max(settings.paper_fill_activity_floor)
+            * input.dt;
+        let ahead = self
+            .ahead
+            .entry(input.order_id.to_string())
+            .or_insert_with(|| Self::touch_size(settings, input));
+        let through = traded - *ahead;
+        *ahead = (*ahead - traded).max(0.0);
+        through.clamp(0.0, input.remaining)
+    }
+
+    fn forget(&mut self, order_id: &str) {
+        self.ahead.remove(order_id);
+    }
+}
+
+/// Same intensity as `maker_touch`, but a hit fills the whole remainder: the chance of
+/// a fill decays with distance from the touch, the size does not.
+pub struct ProbabilityDecay;
+
+impl FillModel for ProbabilityDecay {
+    fn name(&self) -> &'static str {
+        "probability_decay"
+    }
+
+    fn fill_size(&mut self, settings: &Settings, input: &FillInput<'_>, rng: &mut SmallRng) -> f64 {
+        let lambda_dt =
+            fill_intensity(settings, input.distance_ticks, input.activity_score) * input.dt;
+        let p = 1.0 - (-lambda_dt.max(0.0)).exp();
+        if rng.random::<f64>() < p {
+            input.remaining
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn settings() -> Settings {
+        let mut s = Settings::load().unwrap();
+        s.depth_levels = 1;
+        s.paper_queue_depletion_per_sec = 100.0;
+        s.paper_fill_activity_floor = 0.0;
+        s.paper_poisson_lambda_per_sec = 0.5;
+        s
+    }
+
+    fn at_touch<'a>(tob: &'a Tob, order_id: &'a str, dt: f64) -> FillInput<'a> {
+        FillInput {
+            order_id,
+            side: Side::Buy,
+            remaining: 10.0,
+            distance_ticks: 0.0,
+            dt,
+            activity_score: 1.0,
+            tick: 0.01,
+            tob,
+        }
+    }
+
+    #[test]
+    fn models_produce_different_fills_on_the_same_book() {
+        let s = settings();
+        let tob = Tob {
+            best_bid: Some(0.50),
+            best_ask: Some(0.52),
+            bid_depth_n: 500.0,
+            ask_depth_n: 500.0,
+            ..Tob::empty(0.0)
+        };
+        let input = at_touch(&tob, "o1", 1.0);
+        let fill = |name: &str| {
+            let mut model = fill_model_for(name).unwrap();
+            assert_eq!(model.name(), name);
+            model.fill_size(&s, &input, &mut SmallRng::seed_from_u64(7))
+        };
+
+        // Optimistic: at the touch means filled. Queue: 500 ahead, only 100 traded.
+        assert_eq!(fill("optimistic_touch"), 10.0);
+        assert_eq!(fill("queue"), 0.0);
+        for name in FILL_MODELS {
+            let f = fill(name);
+            assert!((0.0..=10.0).contains(&f), "{name}: {f}");
+        }
+        assert!(fill_model_for("magic").is_err());
+    }
+
+    #[test]
+    fn queue_model_fills_once_the_queue_ahead_has_traded() {
+        let s = settings();
+        let tob = Tob {
+            bid_depth_n: 250.0,
+            ..Tob::empty(0.0)
+        };
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut q = QueueModel::default();
+        // 250 ahead at 100/s: nothing for two seconds, then the overflow.
+        assert_eq!(q.fill_size(&s, &at_touch(&tob, "o1", 1.0), &mut rng), 0.0);
+        assert_eq!(q.fill_size(&s, &at_touch(&tob, "o1", 1.0), &mut rng), 0.0);
+        assert_eq!(
+            q.fill_size(&s, &at_touch(&tob, "o1", 0.5625), &mut rng),
+            6.25
+        );
+        assert_eq!(q.fill_size(&s, &at_touch(&tob, "o1", 1.0), &mut rng), 10.0);
+
+        // Stepping behind the touch loses the place in line.
+        let behind = FillInput {
+            distance_ticks: 2.0,
+            ..at_touch(&tob, "o1", 1.0)
+        };
+        assert_eq!(q.fill_size(&s, &behind, &mut rng), 0.0);
+        assert_eq!(q.fill_size(&s, &at_touch(&tob, "o1", 1.0), &mut rng), 0.0);
+        q.forget("o1");
+        assert!(q.ahead.is_empty());
+    }
+}
@@ -0,0 +1,304 @@
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    feed_handler::FeedState,
+    paper_broker::{PaperBroker, Side, FLAT_QTY_EPS},
+};
+
+/// Dashboard route the `--flatten-all` CLI posts to.
+pub const FLATTEN_PATH: &str = "/api/admin/flatten_all";
+
+/// Requests queued behind a running flatten share its report; more are refused.
+const FLATTEN_QUEUE: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MarketFlatten {
+    pub market_id: String,
+    /// Side of the flattening order ("sell" closes a long).
+    pub side: &'static str,
+    /// Position before the flatten.
+    pub qty: f64,
+    pub filled: f64,
+    /// Position left open (same sign as `qty`).
+    pub remaining: f64,
+    pub price: Option<f64>,
+    /// flattened | partial | no_book | no_liquidity | rejected
+    pub status: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlattenReport {
+    /// Resting orders a cancel was sent for.
+    pub cancelled: usize,
+    pub markets: Vec<MarketFlatten>,
+}
+
+/// Cancel every resting order, then close each nonzero position with one IOC at
+/// the touch, sized down to the visible depth on that side (`DEPTH_LEVELS`).
+/// Whatever the book cannot absorb is reported as `partial`. Self-trade prevention
+/// is left to the broker: an order whose cancel is still in flight
+/// (`SIMULATED_LATENCY_MS`) blocks the IOC it would cross, and is reported.
+pub fn flatten_all(broker: &mut PaperBroker, feed: &FeedState) -> Result<FlattenReport> {
+    let cancelled = cancel_all_resting(broker)?;
+
+    let mut ids: Vec<String> = broker
+        .positions
+        .iter()
+        .filter(|(_, p)| p.qty.abs() > FLAT_QTY_EPS)
+        .map(|(id, _)| id.clone())
+        .collect();
+    ids.sort();
+
+    let mut markets = Vec::with_capacity(ids.len());
+    for market_id in ids {
+        let qty = broker.position_qty(&market_id);
+        let side = if qty > 0.0 { Side::Sell } else { Side::Buy };
+        let mut out = MarketFlatten {
+            market_id: market_id.clone(),
+            side: side.as_str(),
+            qty,
+            filled: 0.0,
+            remaining: qty,
+            price: None,
+            status: "no_book",
+        };
+        let Some(tob) = feed.get(&market_id).filter(|t| !t.closed) else {
+            markets.push(out);
+            continue;
+        };
+        let (touch, depth) = match side {
+            Side::Sell => (tob.best_bid, tob.bid_depth_n),
+            Side::Buy => (tob.best_ask, tob.ask_depth_n),
+        };
+        let Some(price) = touch else {
+            markets.push(out);
+            continue;
+        };
+        let size = qty.abs().min(depth);
+        out.price = Some(price);
+        if size <= FLAT_QTY_EPS {
+            out.status = "no_liquidity";
+            markets.push(out);
+            continue;
+        }
+        match broker.execute_ioc(&market_id, side, price, size, "flatten", &tob)? {
+            Some(fill) => {
+                out.filled = fill.size;
+                out.price = Some(fill.price);
+                out.remaining = broker.position_qty(&market_id);
+                out.status = if out.remaining.abs() <= FLAT_QTY_EPS {
+                    "flattened"
+                } else {
+                    "partial"
+                };
+            }
+            None => out.status = "rejected",
+        }
+        log::info!(
+            "flatten.market market_id={} side={} qty={:.4} filled={:.4} remaining={:.4} status={}",
+            out.market_id,
+            out.side,
+            out.qty,
+            out.filled,
+            out.remaining,
+            out.status
+        );
+        markets.push(out);
+    }
+
+    Ok(FlattenReport { cancelled, markets })
+}
+
+/// Send a cancel for every resting order that does not already have one in flight.
+/// Returns how many were sent.
+pub fn cancel_all_resting(broker: &mut PaperBroker) -> Result<usize> {
+    let open: Vec<String> = broker
+        .orders
+        .values()
+        .filter(|o| o.status == "open" && !broker.cancel_pending(&o.order_id))
+        .map(|o| o.order_id.clone())
+        .collect();
+    for id in &open {
+        broker.cancel(id)?;
+    }
+    Ok(open.len())
+}
+
+pub type FlattenReply = oneshot::Sender<std::result::Result<FlattenReport, String>>;
+
+#[derive(Debug, PartialEq)]
+pub enum FlattenError {
+    /// Enough flattens are already queued.
+    Busy,
+    /// The trade loop is not running (scanner mode, or it exited).
+    Unavailable,
+    /// The flatten ran and failed.
+    Failed(String),
+}
+
+/// Dashboard side of the flatten channel.
+#[derive(Clone)]
+pub struct FlattenHandle {
+    tx: mpsc::Sender<FlattenReply>,
+}
+
+/// Trade-loop side of the flatten channel.
+pub struct FlattenRx {
+    rx: mpsc::Receiver<FlattenReply>,
+}
+
+pub fn flatten_channel() -> (FlattenHandle, FlattenRx) {
+    let (tx, rx) = mpsc::channel(FLATTEN_QUEUE);
+    (FlattenHandle { tx }, FlattenRx { rx })
+}
+
+impl FlattenHandle {
+    /// Ask the trade loop to flatten and wait for its report.
+    pub async fn request(&self) -> std::result::Result<FlattenReport, FlattenError> {
+        let (reply, done) = oneshot::channel();
+        self.tx.try_send(reply).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => FlattenError::Busy,
+            mpsc::error::TrySendError::Closed(_) => FlattenError::Unavailable,
+        })?;
+        match done.await {
+            Ok(Ok(report)) => Ok(report),
+            Ok(Err(e)) => Err(FlattenError::Failed(e)),
+            Err(_) => Err(FlattenError::Unavailable),
+        }
+    }
+}
+
+impl FlattenRx {
+    /// Wait for a flatten request and take every one queued behind it, so a single
+    /// flatten answers them all. `None` once all handles are gone.
+    pub async fn recv(&mut self) -> Option<Vec<FlattenReply>> {
+        let first = self.rx.recv().await?;
+        let mut waiters = vec![first];
+        while let Ok(more) = self.rx.try_recv() {
+            waiters.push(more);
+        }
+        Some(waiters)
+    }
+}
+
+pub fn reply_all(waiters: Vec<FlattenReply>, res: std::result::Result<FlattenReport, String>) {
+    for w in waiters {
+        let _ = w.send(res.clone());
+    }
+}
+
+/// `--flatten-all`: ask the running bot to flatten through its dashboard and return
+/// the JSON report. Errors on connection failure or a non-200 reply.
+pub fn post_flatten_all(host: &str, port: u16) -> Result<String> {
+    let mut stream = std::net::TcpStream::connect((host, port))
+        .with_context(|| format!("connect to dashboard at {host}:{port}"))?;
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(30)))?;
+    write!(
+        stream,
+        "POST {FLATTEN_PATH} HTTP/1.1\r\nHost: {host}:{port}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+    )?;
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp)?;
+    let (head, body) = resp
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow!("malformed HTTP response from dashboard"))?;
+    let status = head.split_whitespace().nth(1).unwrap_or("");
+    if status != "200" {
+        bail!("flatten_all failed: HTTP {status}: {body}");
+    }
+    Ok(body.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Settings, paper_broker::Position, store::temp_store};
+
+    fn broker(latency_ms: f64) -> PaperBroker {
+        let store = temp_store();
+        let mut settings = Settings::load().unwrap();
+        settings.paper_fault_rate = 0.0;
+        settings.paper_non_atomic_fail_rate = 0.0;
+        settings.simulated_latency_ms = latency_ms;
+        PaperBroker::with_seed(settings, store, 7)
+    }
+
+    fn hold(broker: &mut PaperBroker, market_id: &str, qty: f64) {
+        broker.positions.insert(
+            market_id.to_string(),
+            Position {
+                qty,
+                avg_price: 0.50,
+                realized_pnl: 0.0,
+            },
+        );
+    }
+
+    #[test]
+    fn flatten_all_closes_a_multi_market_paper_portfolio() {
+        let mut b = broker(0.0);
+        hold(&mut b, "m1", 10.0);
+        hold(&mut b, "m2", -20.0);
+        hold(&mut b, "m3", 5.0);
+        hold(&mut b, "m4", 0.0);
+        b.place_limit("m1", Side::Buy, 0.40, 10.0, "mm").unwrap();
+        b.place_limit("m2", Side::Sell, 0.60, 10.0, "mm").unwrap();
+
+        let feed = FeedState::new();
+        feed.update_book_owned("m1", 1.0, Some(0.49), Some(0.51), 50.0, 50.0, None);
+        // Only 8 shares offered: the short is closed as far as the book allows.
+        feed.update_book_owned("m2", 1.0, Some(0.49), Some(0.51), 50.0, 8.0, None);
+        // m3 has no book at all.
+
+        let report = flatten_all(&mut b, &feed).unwrap();
+        assert_eq!(report.cancelled, 2);
+        assert_eq!(b.open_order_count(), 0);
+        let got: Vec<(&str, &str, f64, f64, &str)> = report
+            .markets
+            .iter()
+            .map(|m| {
+                (
+                    m.market_id.as_str(),
+                    m.side,
+                    m.filled,
+                    m.remaining,
+                    m.status,
+                )
+            })
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("m1", "sell", 10.0, 0.0, "flattened"),
+                ("m2", "buy", 8.0, -12.0, "partial"),
+                ("m3", "sell", 0.0, 5.0, "no_book"),
+            ]
+        );
+        assert_eq!(report.markets[0].price, Some(0.49));
+        assert_eq!(report.markets[1].price, Some(0.51));
+        assert_eq!(b.position_qty("m1"), 0.0);
+        assert_eq!(b.position_qty("m2"), -12.0);
+    }
+
+    #[test]
+    fn flatten_all_passes_over_own_orders_whose_cancels_are_in_flight() {
+        let mut b = broker(50.0);
+        hold(&mut b, "m1", 10.0);
+        // Our bid at the touch is still resting until its cancel lands.
+        let bid = b.place_limit("m1", Side::Buy, 0.49, 10.0, "mm").unwrap();
+        let feed = FeedState::new();
+        feed.update_book_owned("m1", 1.0, Some(0.49), Some(0.51), 50.0, 50.0, None);
+
+        // Self-trade prevention skips our bid and sells into the external size.
+        let report = flatten_all(&mut b, &feed).unwrap();
+        assert_eq!(report.cancelled, 1);
+        assert_eq!(report.markets[0].status, "flattened");
+        assert_eq!(b.position_qty("m1"), 0.0);
+        assert_eq!(b.counters.self_trades_prevented, 1);
+        assert_eq!(b.orders[&bid].filled_size, 0.0);
+    }
+}
@@ -0,0 +1,433 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{config::Settings, feed_handler::WsLink, store::SqliteStore, utils::now_ts};
+
+use polymarket_hft::client::polymarket::clob::Client as ClobClient;
+use polymarket_hft::client::polymarket::data::Client as DataClient;
+
+/// Timestamp of the last aggregated (CLOB + Data) health check that fully passed.
+///
+/// Shared between the checker task and the broker's safe-mode gate.
+#[derive(Clone, Default)]
+pub struct HealthState {
+    last_ok_ts: Arc<RwLock<Option<f64>>>,
+    // Set once a supervised task exhausts its restarts; the trader stops on it.
+    halted: Arc<RwLock<Option<String>>>,
+}
+
+impl HealthState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_ok(&self, ts: f64) {
+        *self.last_ok_ts.write() = Some(ts);
+    }
+
+    pub fn last_ok_ts(&self) -> Option<f64> {
+        *self.last_ok_ts.read()
+    }
+
+    /// True when a check passed within `max_age_secs` of `now`.
+    pub fn is_fresh(&self, now: f64, max_age_secs: f64) -> bool {
+        self.last_ok_ts()
+            .map(|ts| (now - ts) <= max_age_secs)
+            .unwrap_or(false)
+    }
+
+    pub fn halt(&self, reason: String) {
+        *self.halted.write() = Some(reason);
+    }
+
+    pub fn halted(&self) -> Option<String> {
+        self.halted.read().clone()
+    }
+}
+
+/// Respawn limits for [`spawn_supervised`].
+#[derive(Debug, Clone, Copy)]
+pub struct RestartPolicy {
+    pub max_restarts: u32,
+    pub backoff: Duration,
+}
+
+// Backoff doubles per consecutive restart up to this cap.
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+// A task that stayed up this long counts as healthy again (restart budget resets).
+const STABLE_RUN: Duration = Duration::from_secs(300);
+
+/// Run the task built by `make` and respawn it when it errors or panics.
+///
+/// Exactly one instance runs at a time: the next one is only built after the
+/// previous has finished. Each death sets `component` to `error` in runtime
+/// status; after `max_restarts` consecutive deaths the state is halted and the
+/// supervisor gives up. A clean `Ok(())` exit ends supervision.
+pub fn spawn_supervised<F, Fut>(
+    component: &'static str,
+    store: SqliteStore,
+    state: HealthState,
+    policy: RestartPolicy,
+    mut make: F,
+) -> tokio::task::JoinHandle<()>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut restarts: u32 = 0;
+        let mut backoff = policy.backoff;
+        loop {
+            let started = Instant::now();
+            let detail = match tokio::spawn(make()).await {
+                Ok(Ok(())) => {
+                    log::info!("{component} task stopped");
+                    return;
+                }
+                Ok(Err(e)) => format!("error: {e:#}"),
+                Err(e) if e.is_panic() => "panicked".to_string(),
+                Err(e) => format!("aborted: {e}"),
+            };
+            if started.elapsed() >= STABLE_RUN {
+                restarts = 0;
+                backoff = policy.backoff;
+            }
+            if restarts >= policy.max_restarts {
+                log::error!(
+                    "{component} task died ({detail}); giving up after {restarts} restarts"
+                );
+                store
+                    .upsert_runtime_status(
+                        component,
+                        "error",
+                        "restart_limit_reached",
+                        Some(&detail),
+                        now_ts(),
+                    )
+                    .ok();
+                state.halt(format!("{component} restart limit reached: {detail}"));
+                return;
+            }
+            restarts += 1;
+            log::error!(
+                "{component} task died ({detail}); restart {restarts}/{} in {backoff:?}",
+                policy.max_restarts
+            );
+            store
+                .upsert_runtime_status(
+                    component,
+                    "error",
+                    "task_died_restarting",
+                    Some(&detail),
+                    now_ts(),
+                )
+                .ok();
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+        }
+    })
+}
+
+/// Poll CLOB `/` and Data `/` every `HEALTH_CHECK_INTERVAL_SECS`, independent of the trading loop.
+pub fn spawn_health_checker(settings: Settings, store: SqliteStore, state: HealthState) {
+    tokio::spawn(async move {
+        let clob = ClobClient::new();
+        let data = DataClient::new();
+        let mut tick = tokio::time::interval(std::time::Duration::from_secs_f64(
+            settings.health_check_interval_secs,
+        ));
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tick.tick().await;
+            let (clob_res, data_res) = tokio::join!(clob.get_ok(), data.health());
+            let now = now_ts();
+            let mut failures: Vec<String> = vec![];
+            if let Err(e) = clob_res {
+                failures.push(format!("clob: {e}"));
+            }
+            if let Err(e) = data_res {
+                failures.push(format!("data: {e}"));
+            }
+            if failures.is_empty() {
+                state.record_ok(now);
+                store
+                    .upsert_runtime_status("health", "ok", "clob+data reachable", None, now)
+                    .ok();
+            } else {
+                store
+                    .upsert_runtime_status(
+                        "health",
+                        "error",
+                        "health_check_failed",
+                        Some(&failures.join("; ")),
+                        now,
+                    )
+                    .ok();
+            }
+        }
+    });
+}
+
+/// Decides when a WS outage is long enough to pull resting orders.
+///
+/// Fires once when the link has been down `after_secs`; a reconnect inside that
+/// window ends the outage without firing. After firing, the guard stays
+/// disarmed until the link has been up `rearm_secs`, so a flapping socket
+/// does not spam cancel-alls.
+#[derive(Debug, Clone)]
+pub struct DisconnectGuard {
+    after_secs: f64,
+    rearm_secs: f64,
+    armed: bool,
+    up_since: Option<f64>,
+}
+
+impl DisconnectGuard {
+    pub fn new(after_secs: f64, rearm_secs: f64) -> Self {
+        Self {
+            after_secs,
+            rearm_secs,
+            armed: true,
+            up_since: None,
+        }
+    }
+
+    /// Feed the current link state; true when cancel-all should run now.
+    pub fn observe(&mut self, now: f64, down_since: Option<f64>) -> bool {
+        match down_since {
+            Some(since) => {
+                self.up_since = None;
+                if self.armed && now - since >= self.after_secs {
+                    self.armed = false;
+                    return true;
+                }
+            }
+            None => {
+                let up = *self.up_since.get_or_insert(now);
+                if !self.armed && now - up >= self.rearm_secs {
+                    self.armed = true;
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Watch the WS link and run `cancel_all` on a sustained disconnect (see [`DisconnectGuard`]).
+/// The outcome is stamped on the `cancel_on_disconnect` runtime status.
+pub fn spawn_disconnect_guard<F, Fut>(
+    settings: &Settings,
+    store: SqliteStore,
+    link: Arc<RwLock<WsLink>>,
+    mut cancel_all: F,
+) -> tokio::task::JoinHandle<()>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<serde_json::Value>> + Send + 'static,
+{
+    let mut guard = DisconnectGuard::new(
+        settings.cancel_on_disconnect_after_secs,
+        settings.cancel_on_disconnect_rearm_secs,
+    );
+    let poll =
+        Duration::from_secs_f64((settings.cancel_on_disconnect_after_secs / 4.0).clamp(0.01, 1.0));
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(poll);
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tick.tick().await;
+            let now = now_ts();
+            let down_since = link.read().down_since();
+            if !guard.observe(now, down_since) {
+                continue;
+            }
+            let down_secs = down_since.map(|t| now - t).unwrap_or(0.0);
+            log::warn!("cancel_on_disconnect.fire ws_down_secs={down_secs:.1}");
+            let detail = format!("ws down {down_secs:.1}s");
+            match cancel_all().await {
+                Ok(_) => {
+                    store
+                        .upsert_runtime_status(
+                            "cancel_on_disconnect",
+                            "warn",
+                            "cancelled all resting orders",
+                            Some(&detail),
+                            now,
+                        )
+                        .ok();
+                }
+                Err(e) => {
+                    log::error!("cancel_on_disconnect.failed {e:#}");
+                    store
+                        .upsert_runtime_status(
+                            "cancel_on_disconnect",
+                            "error",
+                            "cancel_all failed",
+                            Some(&format!("{detail}; {e:#}")),
+                            now,
+                        )
+                        .ok();
+                }
+            }
+        }
+    })
+}
+
+/// Cancel-all requests from the disconnect guard; the trader cancels every resting order
+/// and replies with how many it pulled.
+pub type CancelAllRx = mpsc::Receiver<oneshot::Sender<usize>>;
+
+/// `CANCEL_ON_DISCONNECT=1`: run the disconnect guard against the trader's resting orders.
+/// With the option off the sender is dropped, so the receiver closes immediately.
+pub fn spawn_cancel_on_disconnect(
+    settings: &Settings,
+    store: SqliteStore,
+    link: Arc<RwLock<WsLink>>,
+) -> CancelAllRx {
+    let (tx, rx) = mpsc::channel::<oneshot::Sender<usize>>(1);
+    if settings.cancel_on_disconnect {
+        spawn_disconnect_guard(settings, store, link, move || {
+            let tx = tx.clone();
+            async move {
+                let (reply, done) = oneshot::channel();
+                tx.send(reply)
+                    .await
+                    .map_err(|_| anyhow!("trader stopped"))?;
+                let cancelled = done.await.map_err(|_| anyhow!("trader stopped"))?;
+                Ok(serde_json::json!({ "cancelled": cancelled }))
+            }
+        });
+    }
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::temp_store;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn policy(max_restarts: u32) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn killed_task_is_respawned_once() {
+        let spawns = Arc::new(AtomicU32::new(0));
+        let live = Arc::new(AtomicU32::new(0));
+        let state = HealthState::new();
+        let (s, l) = (spawns.clone(), live.clone());
+        spawn_supervised(
+            "feed.ws",
+            temp_store(),
+            state.clone(),
+            policy(3),
+            move || {
+                let n = s.fetch_add(1, Ordering::SeqCst);
+                let live = l.clone();
+                async move {
+                    assert_eq!(live.fetch_add(1, Ordering::SeqCst), 0, "duplicate task");
+                    if n == 0 {
+                        live.fetch_sub(1, Ordering::SeqCst);
+                        panic!("feed died");
+                    }
+                    std::future::pending::<Result<()>>().await
+                }
+            },
+        );
+
+        for _ in 0..200 {
+            if spawns.load(Ordering::SeqCst) >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(spawns.load(Ordering::SeqCst), 2);
+        assert_eq!(live.load(Ordering::SeqCst), 1);
+        assert!(state.halted().is_none());
+    }
+
+    #[tokio::test]
+    async fn restart_limit_halts() {
+        let spawns = Arc::new(AtomicU32::new(0));
+        let state = HealthState::new();
+        let store = temp_store();
+        let s = spawns.clone();
+        let sup = spawn_supervised(
+            "feed.ws",
+            store.clone(),
+            state.clone(),
+            policy(2),
+            move || {
+                s.fetch_add(1, Ordering::SeqCst);
+                async { Err(anyhow::anyhow!("socket closed")) }
+            },
+        );
+        sup.await.unwrap();
+
+        assert_eq!(spawns.load(Ordering::SeqCst), 3);
+        assert!(state.halted().unwrap().contains("socket closed"));
+        let status = store.fetch_runtime_statuses().unwrap();
+        let ws = &status["feed.ws"];
+        assert_eq!(ws["level"], "error");
+        assert_eq!(ws["message"], "restart_limit_reached");
+    }
+
+    #[test]
+    fn disconnect_guard_needs_sustained_outage_and_rearms() {
+        let mut g = DisconnectGuard::new(10.0, 30.0);
+        // Brief drop that reconnects inside the threshold.
+        assert!(!g.observe(100.0, Some(95.0)));
+        assert!(!g.observe(101.0, None));
+        // Sustained outage fires once.
+        assert!(!g.observe(200.0, Some(195.0)));
+        assert!(g.observe(205.0, Some(195.0)));
+        assert!(!g.observe(260.0, Some(195.0)));
+        // A short reconnect does not re-arm it.
+        assert!(!g.observe(261.0, None));
+        assert!(!g.observe(290.0, Some(270.0)));
+        // Stable for the re-arm window, then the next sustained outage fires again.
+        assert!(!g.observe(300.0, None));
+        assert!(!g.observe(330.0, None));
+        assert!(g.observe(400.0, Some(350.0)));
+    }
+
+    #[tokio::test]
+    async fn sustained_disconnect_triggers_cancel_all() {
+        let mut settings = Settings::load().unwrap();
+        settings.cancel_on_disconnect = true;
+        settings.cancel_on_disconnect_after_secs = 0.05;
+        let store = temp_store();
+        // Link went down a while ago and never came back.
+        let link = Arc::new(RwLock::new(WsLink::new(now_ts() - 1.0)));
+        let mut requests = spawn_cancel_on_disconnect(&settings, store.clone(), link);
+
+        let reply = tokio::time::timeout(Duration::from_secs(1), requests.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        reply.send(2).unwrap();
+        let again = tokio::time::timeout(Duration::from_millis(200), requests.recv()).await;
+        assert!(again.is_err(), "fires once per outage");
+        let status = store.fetch_runtime_statuses().unwrap();
+        assert_eq!(status["cancel_on_disconnect"]["level"], "warn");
+    }
+
+    #[tokio::test]
+    async fn cancel_on_disconnect_off_closes_the_channel() {
+        let mut settings = Settings::load().unwrap();
+        settings.cancel_on_disconnect = false;
+        let link = Arc::new(RwLock::new(WsLink::new(now_ts() - 100.0)));
+        let mut requests = spawn_cancel_on_disconnect(&settings, temp_store(), link);
+        assert!(requests.recv().await.is_none());
+    }
+}
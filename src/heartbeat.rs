@@ -0,0 +1,98 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Minimum seconds between heartbeat writes, however fast the loop ticks.
+const HEARTBEAT_MIN_INTERVAL_SECS: f64 = 1.0;
+
+/// Liveness file for an external watchdog: the trade loop touches it every tick and
+/// it is rewritten (at most once a second) with the current unix timestamp. A file
+/// that stops changing means the loop is wedged.
+pub struct Heartbeat {
+    path: Option<PathBuf>,
+    /// `mono_ts` of the last write, so a wall-clock step neither stalls nor floods it.
+    last_write_mono: f64,
+    failed: bool,
+}
+
+impl Heartbeat {
+    /// An empty path disables the heartbeat.
+    pub fn new(path: &str) -> Self {
+        let path = path.trim();
+        Self {
+            path: (!path.is_empty()).then(|| PathBuf::from(path)),
+            last_write_mono: f64::NEG_INFINITY,
+            failed: false,
+        }
+    }
+
+    /// Write the wall-clock `now` if the last write is at least a second old on the
+    /// monotonic clock. Failures are logged once (until a write succeeds again) and
+    /// never stop the loop.
+    pub fn touch(&mut self, now: f64, mono: f64) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if mono - self.last_write_mono < HEARTBEAT_MIN_INTERVAL_SECS {
+            return;
+        }
+        self.last_write_mono = mono;
+        match write_atomic(path, &format!("{now:.3}\n")) {
+            Ok(()) => self.failed = false,
+            Err(e) => {
+                if !self.failed {
+                    self.failed = true;
+                    log::warn!("heartbeat.write_failed path={} err={e}", path.display());
+                }
+            }
+        }
+    }
+}
+
+/// Write to a sibling temp file and rename it over `path`, so a reader never sees a
+/// half-written timestamp.
+fn write_atomic(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut f = std::fs::File::create(&tmp)?;
+    f.write_all(contents.as_bytes())?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeat_updates_across_loop_ticks_at_most_once_a_second() {
+        let dir =
+            std::env::temp_dir().join(format!("superspreader-heartbeat-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("heartbeat");
+        let mut hb = Heartbeat::new(path.to_str().unwrap());
+        let read = || std::fs::read_to_string(&path).unwrap();
+
+        // 250ms loop ticks: the file follows the loop, throttled to ~1s.
+        let mut writes = vec![];
+        for i in 0..9 {
+            let t = 0.25 * i as f64;
+            hb.touch(1000.0 + t, t);
+            if writes.last() != Some(&read()) {
+                writes.push(read());
+            }
+        }
+        assert_eq!(writes, ["1000.000\n", "1001.000\n", "1002.000\n"]);
+        assert!(!dir.join("heartbeat.tmp").exists());
+
+        // The wall clock stepping back an hour doesn't stall the heartbeat.
+        hb.touch(900.0, 3.0);
+        assert_eq!(read(), "900.000\n");
+
+        // An unwritable path is logged, not fatal; a disabled heartbeat writes nothing.
+        let mut broken = Heartbeat::new(dir.join("missing/heartbeat").to_str().unwrap());
+        broken.touch(1000.0, 0.0);
+        assert!(broken.failed);
+        Heartbeat::new("").touch(1000.0, 0.0);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -1,8 +1,8 @@
 use crate::{
     config::Settings,
     feed_handler::Tob,
-    paper_broker::Side,
-    utils::{clamp, round_to_tick},
+    paper_broker::{Fill, Side},
+    utils::clamp,
 };
 
 #[derive(Debug, Clone)]
@@ -12,57 +12,310 @@ pub struct QuoteIntent {
     pub size: f64,
 }
 
+/// Per-level quote size and how it was derived (persisted with the quote snapshot).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderSize {
+    pub qty: f64,
+    /// `base` (flat BASE_ORDER_SIZE), `liquidity`, `liquidity_min` or `liquidity_max` (clamped).
+    pub source: &'static str,
+}
+
+/// Feedback loop from realized spread capture to quote width (`TARGET_CAPTURE_BPS`).
+///
+/// Maker fills are collected per window; when a window closes with fills, the round-trip
+/// capture (twice the mean edge vs fair) is compared with the target and the width
+/// multiplier moves by `gain * relative error`, capped at `max_step` per window and
+/// clamped to its bounds. Errors inside the deadband, and windows without fills, hold.
+/// The multiplier only integrates capture error and is applied before the activity
+/// tightening, so the two adjustments compose instead of chasing each other.
+#[derive(Debug, Clone)]
+pub struct CaptureController {
+    pub width_mult: f64,
+    window_start: Option<f64>,
+    edge_sum_bps: f64,
+    fills: u32,
+}
+
+impl Default for CaptureController {
+    fn default() -> Self {
+        Self {
+            width_mult: 1.0,
+            window_start: None,
+            edge_sum_bps: 0.0,
+            fills: 0,
+        }
+    }
+}
+
+impl CaptureController {
+    /// One maker fill: `edge_bps` is the price improvement vs fair (positive = captured).
+    pub fn observe_fill(&mut self, edge_bps: f64) {
+        if edge_bps.is_finite() {
+            self.edge_sum_bps += edge_bps;
+            self.fills += 1;
+        }
+    }
+
+    /// Close the window if `window_secs` have passed; returns the round-trip capture
+    /// of the closed window when it had fills.
+    pub fn update(&mut self, settings: &Settings, now: f64) -> Option<f64> {
+        let start = *self.window_start.get_or_insert(now);
+        if now - start < settings.capture_window_secs {
+            return None;
+        }
+        self.window_start = Some(now);
+        let fills = std::mem::take(&mut self.fills);
+        let sum = std::mem::take(&mut self.edge_sum_bps);
+        if fills == 0 {
+            return None;
+        }
+        let capture = 2.0 * sum / fills as f64;
+        let target = settings.target_capture_bps;
+        if (target - capture).abs() > settings.capture_deadband_bps {
+            let step = clamp(
+                settings.capture_gain * (target - capture) / target,
+                -settings.capture_max_step,
+                settings.capture_max_step,
+            );
+            self.width_mult = clamp(
+                self.width_mult * (1.0 + step),
+                settings.capture_width_min_mult,
+                settings.capture_width_max_mult,
+            );
+        }
+        Some(capture)
+    }
+}
+
 pub struct HftStrategy {
     settings: Settings,
+    capture: parking_lot::Mutex<CaptureController>,
 }
 
 impl HftStrategy {
     pub fn new(settings: Settings) -> Self {
-        Self { settings }
+        Self {
+            settings,
+            capture: parking_lot::Mutex::new(CaptureController::default()),
+        }
     }
 
+    /// Pick up dashboard changes to the live params (`live_params::LIVE_PARAMS`).
+    pub fn apply_live_params(&mut self, live: &Settings) -> bool {
+        crate::live_params::apply(live, &mut self.settings)
+    }
+
+    /// Feed maker fills (with the fair they were made against) to the capture
+    /// controller and close its window if due. No-op unless `TARGET_CAPTURE_BPS` > 0.
+    pub fn note_maker_fills(&self, now: f64, fair: f64, fills: &[Fill]) {
+        if self.settings.target_capture_bps <= 0.0 {
+            return;
+        }
+        let mut c = self.capture.lock();
+        if fair > 0.0 {
+            for f in fills {
+                let edge = match f.side {
+                    Side::Buy => fair - f.price,
+                    Side::Sell => f.price - fair,
+                };
+                c.observe_fill(edge / fair * 10_000.0);
+            }
+        }
+        let before = c.width_mult;
+        if let Some(capture) = c.update(&self.settings, now) {
+            if c.width_mult != before {
+                log::info!(
+                    "capture.width capture_bps={:.1} target_bps={:.1} width_mult={:.3}",
+                    capture,
+                    self.settings.target_capture_bps,
+                    c.width_mult
+                );
+            }
+        }
+    }
+
+    /// Current multiplier on `MM_QUOTE_WIDTH` from the capture controller (1.0 when off).
+    pub fn width_mult(&self) -> f64 {
+        if self.settings.target_capture_bps > 0.0 {
+            self.capture.lock().width_mult
+        } else {
+            1.0
+        }
+    }
+
+    /// Price tick for this market: the feed's live value, else `PRICE_TICK`.
+    pub fn tick_for(&self, tob: &Tob) -> f64 {
+        tob.tick_size
+            .filter(|t| t.is_finite() && *t > 0.0)
+            .unwrap_or(self.settings.price_tick)
+    }
+
+    /// Reference market and beta for `market_id` (`REFERENCE_ANCHORS`).
+    pub fn reference_for(&self, market_id: &str) -> Option<(&str, f64)> {
+        self.settings
+            .reference_anchors
+            .iter()
+            .find(|a| a.market_id == market_id)
+            .map(|a| (a.reference_market_id.as_str(), a.beta))
+    }
+
+    /// Inventory the skew centres on for `market_id` (`INVENTORY_TARGETS`, else flat),
+    /// held inside `MAX_INVENTORY_USD`.
+    pub fn inventory_target_for(&self, market_id: &str) -> f64 {
+        let cap = self.settings.max_inventory_usd;
+        self.settings
+            .inventory_targets
+            .iter()
+            .find(|t| t.market_id == market_id)
+            .map_or(0.0, |t| clamp(t.qty, -cap, cap))
+    }
+
+    /// Fair value from the book: the mid/last-trade blend, or with `FAIR_SOURCE=microprice`
+    /// the depth-weighted touch (the mid when both sides are empty). With `reference` (its
+    /// book and beta) the result is pulled toward the reference mid: `(1 - beta) * fair +
+    /// beta * ref_mid`. A reference without a two-sided book, or more than
+    /// `REFERENCE_MAX_AGE_SECS` behind `tob`, is ignored.
     pub fn compute_fair(
         &self,
         tob: &Tob,
         ema_last_trade: Option<f64>,
+        reference: Option<(&Tob, f64)>,
     ) -> Option<(f64, &'static str)> {
         let mid = tob.mid()?;
-        let fair = match ema_last_trade {
-            Some(x) if x > 0.0 => 0.7 * mid + 0.3 * x,
-            _ => mid,
+        let (mut fair, mut source) = if self.settings.fair_source == "microprice" {
+            match tob.microprice() {
+                Some(micro) => (micro, "microprice"),
+                None => (mid, "book_mid"),
+            }
+        } else {
+            let w_mid = self.mid_weight(tob, ema_last_trade);
+            let fair = match ema_last_trade {
+                Some(x) if w_mid < 1.0 => w_mid * mid + (1.0 - w_mid) * x,
+                _ => mid,
+            };
+            (fair, "book_mid")
         };
-        Some((
-            clamp(
-                fair,
-                self.settings.price_tick,
-                1.0 - self.settings.price_tick,
-            ),
-            "book_mid",
-        ))
+        let anchor = reference.and_then(|(r, beta)| {
+            let fresh = tob.ts - r.ts <= self.settings.reference_max_age_secs;
+            r.mid()
+                .filter(|m| fresh && m.is_finite() && *m > 0.0)
+                .map(|m| (m, beta))
+        });
+        if let Some((ref_mid, beta)) = anchor {
+            fair = (1.0 - beta) * fair + beta * ref_mid;
+            source = "reference_anchor";
+        }
+        let tick = self.tick_for(tob);
+        Some((clamp(fair, tick, 1.0 - tick), source))
+    }
+
+    /// Confidence in the book mid, in [0, 1]: tight spread and deep touch -> 1.
+    pub fn mid_confidence(&self, tob: &Tob) -> f64 {
+        let (Some(b), Some(a)) = (tob.best_bid, tob.best_ask) else {
+            return 0.0;
+        };
+        let spread_conf = clamp(1.0 - (a - b) / self.settings.fair_wide_spread, 0.0, 1.0);
+        let depth = (tob.bid_depth_n + tob.ask_depth_n).max(0.0);
+        let depth_conf = depth / (depth + self.settings.fair_depth_half);
+        spread_conf * depth_conf
+    }
+
+    /// Weight on the mid in the fair blend (the last trade gets `1 - w`, so they sum to 1).
+    /// 1.0 when there is no usable last trade.
+    pub fn mid_weight(&self, tob: &Tob, ema_last_trade: Option<f64>) -> f64 {
+        let has_trade = ema_last_trade.is_some_and(|x| x.is_finite() && x > 0.0);
+        let max_age = self.settings.fair_trade_max_age_secs;
+        let fresh = max_age <= 0.0 || tob.last_trade_ts.is_some_and(|t| tob.ts - t <= max_age);
+        if !has_trade || !fresh {
+            return 1.0;
+        }
+        let (lo, hi) = (
+            self.settings.fair_mid_weight_min,
+            self.settings.fair_mid_weight_max,
+        );
+        clamp(lo + (hi - lo) * self.mid_confidence(tob), 0.0, 1.0)
+    }
+
+    /// Quote size for a market: `BASE_ORDER_SIZE`, or with `SIZE_SCALE_BY_LIQUIDITY` a
+    /// notional proportional to the market's liquidity, clamped to the min/max notional and
+    /// converted to shares at `price`. Missing or non-positive liquidity falls back to base.
+    pub fn order_size(&self, liquidity_usd: f64, price: f64) -> OrderSize {
+        let base = OrderSize {
+            qty: self.settings.base_order_size,
+            source: "base",
+        };
+        if !self.settings.size_scale_by_liquidity
+            || !liquidity_usd.is_finite()
+            || liquidity_usd <= 0.0
+            || !price.is_finite()
+            || price <= 0.0
+        {
+            return base;
+        }
+        let raw = liquidity_usd * self.settings.size_liquidity_frac;
+        let (notional, source) = if raw < self.settings.min_order_notional_usd {
+            (self.settings.min_order_notional_usd, "liquidity_min")
+        } else if raw > self.settings.max_order_notional_usd {
+            (self.settings.max_order_notional_usd, "liquidity_max")
+        } else {
+            (raw, "liquidity")
+        };
+        OrderSize {
+            qty: notional / price,
+            source,
+        }
+    }
+
+    /// Quote prices one tick inside the touch, `(bid + tick, ask - tick)`, when
+    /// `JOIN_OR_IMPROVE=improve_if_spread_ge_ticks`, the spread is at least
+    /// `IMPROVE_MIN_SPREAD_TICKS` and the improved quotes still straddle `fair` with a
+    /// spread of at least `min_spread_bps`. `None` means join.
+    pub fn improved_touch(
+        &self,
+        bid: f64,
+        ask: f64,
+        fair: f64,
+        tick: f64,
+        min_spread_bps: f64,
+    ) -> Option<(f64, f64)> {
+        if self.settings.join_or_improve != "improve_if_spread_ge_ticks" {
+            return None;
+        }
+        let (b, a) = ((bid / tick).round() as i64, (ask / tick).round() as i64);
+        if a - b < i64::from(self.settings.improve_min_spread_ticks) {
+            return None;
+        }
+        let (ib, ia) = ((b + 1) as f64 * tick, (a - 1) as f64 * tick);
+        let spread_bps = (ia - ib) / (0.5 * (ia + ib)) * 10_000.0;
+        (ib < fair && fair < ia && spread_bps >= min_spread_bps).then_some((ib, ia))
     }
 
     pub fn quote_grid(
         &self,
         fair: f64,
         inv_qty: f64,
+        inv_target: f64,
         imbalance: f64,
         activity_per_min: f64,
+        tick: f64,
     ) -> Vec<QuoteIntent> {
-        // Grid levels: bounded for safety; exact count is config-driven.
-        let levels = self.settings.mm_levels.clamp(1, 10);
-
-        // Quote width is expressed as the base half-spread (MM_QUOTE_WIDTH).
-        // Higher activity tightens the half-spread (more aggressive quoting).
+        // Quote width is expressed as the base half-spread (MM_QUOTE_WIDTH), scaled by
+        // the capture controller. Higher activity tightens the half-spread (more
+        // aggressive quoting).
         let tight = clamp(activity_per_min / 30.0, 0.0, 1.0);
-        let base_half_spread = self.settings.mm_quote_width;
+        let base_half_spread = self.settings.mm_quote_width * self.width_mult();
         let half_spread = clamp(
             base_half_spread * (1.0 - 0.5 * tight),
-            self.settings.price_tick,
-            base_half_spread,
+            tick,
+            base_half_spread.max(tick),
         );
 
-        // Inventory skew: linear, capped.
-        let inv_ratio = clamp(inv_qty / self.settings.max_inventory_usd, -1.0, 1.0);
+        // Inventory skew: linear in the distance from the target, capped.
+        let inv_ratio = clamp(
+            (inv_qty - inv_target) / self.settings.max_inventory_usd,
+            -1.0,
+            1.0,
+        );
         let inv_skew = clamp(
             -inv_ratio * self.settings.inventory_skew_cap,
             -self.settings.inventory_skew_cap,
@@ -78,36 +331,91 @@ impl HftStrategy {
             self.settings.inventory_skew_cap,
         );
 
+        // Levels are whole-tick offsets from a tick-aligned centre.
+        let center = ((fair + skew) / tick).round() as i64;
+        let inner = (half_spread / tick).round().max(1.0) as i64;
+        self.ladder(center - inner, center + inner, tick)
+    }
+
+    /// Short-horizon volatility (price per sqrt-second) from the feed's EWMA mid variance;
+    /// 0 until the book has moved.
+    pub fn volatility(&self, tob: &Tob) -> f64 {
+        tob.mid_var_ewma
+            .filter(|v| v.is_finite() && *v > 0.0)
+            .map_or(0.0, f64::sqrt)
+    }
+
+    /// Avellaneda-Stoikov quotes around `mid` for `inv_qty` shares of inventory (off
+    /// target): reservation price `mid - q * gamma * sigma^2 * T` and half-spread
+    /// `(gamma * sigma^2 * T + 2 / gamma * ln(1 + gamma / k)) / 2`, with `T` =
+    /// `AS_HORIZON_SECS`. Unrounded; `(bid, ask)`.
+    pub fn quote_as(&self, mid: f64, inv_qty: f64, volatility: f64, intensity: f64) -> (f64, f64) {
+        let gamma = self.settings.as_gamma;
+        let risk = gamma * volatility.powi(2) * self.settings.as_horizon_secs;
+        let reservation = mid - inv_qty * risk;
+        let half_spread = 0.5 * (risk + (2.0 / gamma) * (1.0 + gamma / intensity).ln());
+        (reservation - half_spread, reservation + half_spread)
+    }
+
+    /// Quotes for the configured `QUOTE_MODEL`: the skewed grid, or Avellaneda-Stoikov
+    /// fed with the book's volatility and `AS_INTENSITY_K`, laddered out like the grid.
+    pub fn quotes(
+        &self,
+        fair: f64,
+        inv_qty: f64,
+        inv_target: f64,
+        imbalance: f64,
+        tob: &Tob,
+    ) -> Vec<QuoteIntent> {
+        let tick = self.tick_for(tob);
+        if self.settings.quote_model != "as" {
+            return self.quote_grid(
+                fair,
+                inv_qty,
+                inv_target,
+                imbalance,
+                tob.updates_ewma_per_min,
+                tick,
+            );
+        }
+        let (bid, ask) = self.quote_as(
+            fair,
+            inv_qty - inv_target,
+            self.volatility(tob),
+            self.settings.as_intensity_k,
+        );
+        // Round outward so the model's spread is never narrowed onto the grid.
+        let bid = (bid / tick + 1e-9).floor() as i64;
+        let ask = (ask / tick - 1e-9).ceil() as i64;
+        self.ladder(bid, ask.max(bid + 1), tick)
+    }
+
+    /// `MM_LEVELS` bid/ask pairs stepping out from the innermost ticks `bid` and `ask`.
+    /// Levels are whole ticks, so every quote lands on the market's grid whatever its
+    /// tick size; crossed pairs (after clamping into the price range) are dropped.
+    fn ladder(&self, bid: i64, ask: i64, tick: f64) -> Vec<QuoteIntent> {
+        let levels = self.settings.mm_levels.clamp(1, 10);
+        let step = match self.settings.mm_level_step_ticks {
+            0 => ((ask - bid) / 2).max(1),
+            n => i64::from(n),
+        };
+        let max_ticks = (1.0 / tick).round() as i64 - 1;
+
         let mut out = Vec::with_capacity(levels * 2);
         for i in 0..levels {
-            let k = i as f64 + 1.0;
-            let step = half_spread * k;
-            let bid = round_to_tick(
-                clamp(
-                    fair - step + skew,
-                    self.settings.price_tick,
-                    1.0 - self.settings.price_tick,
-                ),
-                self.settings.price_tick,
-            );
-            let ask = round_to_tick(
-                clamp(
-                    fair + step + skew,
-                    self.settings.price_tick,
-                    1.0 - self.settings.price_tick,
-                ),
-                self.settings.price_tick,
-            );
+            let offset = i as i64 * step;
+            let bid = (bid - offset).clamp(1, max_ticks);
+            let ask = (ask + offset).clamp(1, max_ticks);
             // keep non-crossing
             if bid < ask {
                 out.push(QuoteIntent {
                     side: Side::Buy,
-                    price: bid,
+                    price: bid as f64 * tick,
                     size: self.settings.base_order_size,
                 });
                 out.push(QuoteIntent {
                     side: Side::Sell,
-                    price: ask,
+                    price: ask as f64 * tick,
                     size: self.settings.base_order_size,
                 });
             }
@@ -115,3 +423,381 @@ impl HftStrategy {
         out
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(tick: f64, width: f64, step: u32) -> (Vec<f64>, Vec<f64>) {
+        let mut settings = Settings::load().unwrap();
+        settings.mm_levels = 3;
+        settings.mm_level_step_ticks = step;
+        settings.mm_quote_width = width;
+        let strat = HftStrategy::new(settings);
+        let intents = strat.quote_grid(0.5037, 0.0, 0.0, 0.0, 0.0, tick);
+        for q in &intents {
+            let steps = q.price / tick;
+            assert!(
+                (steps - steps.round()).abs() < 1e-9,
+                "price {} not on {} grid",
+                q.price,
+                tick
+            );
+        }
+        let side = |s: Side| {
+            intents
+                .iter()
+                .filter(|q| q.side == s)
+                .map(|q| q.price)
+                .collect::<Vec<_>>()
+        };
+        (side(Side::Buy), side(Side::Sell))
+    }
+
+    fn assert_prices(got: &[f64], want: &[f64]) {
+        assert_eq!(got.len(), want.len(), "{got:?} vs {want:?}");
+        for (g, w) in got.iter().zip(want) {
+            assert!((g - w).abs() < 1e-9, "{got:?} vs {want:?}");
+        }
+    }
+
+    #[test]
+    fn order_size_scales_with_market_liquidity() {
+        let mut settings = Settings::load().unwrap();
+        settings.base_order_size = 10.0;
+        settings.size_scale_by_liquidity = true;
+        settings.size_liquidity_frac = 0.001;
+        settings.min_order_notional_usd = 5.0;
+        settings.max_order_notional_usd = 100.0;
+        let strat = HftStrategy::new(settings);
+
+        // Thin book: 20k * 0.001 = $20 at 0.50 = 40 shares.
+        let thin = strat.order_size(20_000.0, 0.5);
+        assert_eq!(thin.source, "liquidity");
+        assert!((thin.qty - 40.0).abs() < 1e-9);
+
+        // Deep book: $500 is capped at $100 = 200 shares.
+        let deep = strat.order_size(500_000.0, 0.5);
+        assert_eq!(deep.source, "liquidity_max");
+        assert!((deep.qty - 200.0).abs() < 1e-9);
+        assert!(deep.qty > thin.qty);
+
+        // Tiny book floors at the min notional.
+        let tiny = strat.order_size(1_000.0, 0.5);
+        assert_eq!(tiny.source, "liquidity_min");
+        assert!((tiny.qty - 10.0).abs() < 1e-9);
+
+        // Missing liquidity (stored as 0) falls back to the flat base size.
+        for liq in [0.0, f64::NAN] {
+            let s = strat.order_size(liq, 0.5);
+            assert_eq!(s.source, "base");
+            assert!((s.qty - 10.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn grid_levels_are_tick_offsets_at_cent_tick() {
+        // Fair 0.5037 aligns to 0.50; width 0.02 = 2 ticks, then +2 ticks per level.
+        let (bids, asks) = grid(0.01, 0.02, 2);
+        assert_prices(&bids, &[0.48, 0.46, 0.44]);
+        assert_prices(&asks, &[0.52, 0.54, 0.56]);
+    }
+
+    #[test]
+    fn grid_levels_are_tick_offsets_at_tenth_cent_tick() {
+        // Fair 0.5037 aligns to 0.504; width 0.002 = 2 ticks, then +2 ticks per level.
+        let (bids, asks) = grid(0.001, 0.002, 2);
+        assert_prices(&bids, &[0.502, 0.500, 0.498]);
+        assert_prices(&asks, &[0.506, 0.508, 0.510]);
+
+        // A width under one tick still quotes one tick off the centre.
+        let (bids, asks) = grid(0.001, 0.0004, 2);
+        assert_prices(&bids, &[0.503, 0.501, 0.499]);
+        assert_prices(&asks, &[0.505, 0.507, 0.509]);
+    }
+
+    #[test]
+    fn zero_level_step_repeats_the_quote_width() {
+        // Fair 0.5037 aligns to 0.504; width 0.003 = 3 ticks, so levels at 3, 6 and 9 ticks.
+        let (bids, asks) = grid(0.001, 0.003, 0);
+        assert_prices(&bids, &[0.501, 0.498, 0.495]);
+        assert_prices(&asks, &[0.507, 0.510, 0.513]);
+    }
+
+    fn blend_settings() -> Settings {
+        let mut settings = Settings::load().unwrap();
+        settings.fair_source = "blend".to_string();
+        settings.fair_mid_weight_min = 0.4;
+        settings.fair_mid_weight_max = 0.9;
+        settings.fair_wide_spread = 0.06;
+        settings.fair_depth_half = 500.0;
+        settings.fair_trade_max_age_secs = 300.0;
+        settings.price_tick = 0.001;
+        settings
+    }
+
+    fn book(bid: f64, ask: f64, depth: f64, last_trade_ts: Option<f64>) -> Tob {
+        Tob {
+            best_bid: Some(bid),
+            best_ask: Some(ask),
+            bid_depth_n: depth / 2.0,
+            ask_depth_n: depth / 2.0,
+            last_trade_ts,
+            ..Tob::empty(1_000.0)
+        }
+    }
+
+    #[test]
+    fn deep_tight_book_weights_mid_thin_wide_book_weights_last_trade() {
+        let strat = HftStrategy::new(blend_settings());
+        let last = Some(0.60);
+
+        // Deep and tight: 1c spread, 10k shares -> confidence ~0.79, mid weight ~0.80.
+        let deep = book(0.495, 0.505, 10_000.0, Some(990.0));
+        let w_deep = strat.mid_weight(&deep, last);
+        assert!(w_deep > 0.75 && w_deep <= 0.9, "{w_deep}");
+        let (fair_deep, _) = strat.compute_fair(&deep, last, None).unwrap();
+        assert!((fair_deep - (w_deep * 0.5 + (1.0 - w_deep) * 0.6)).abs() < 1e-9);
+
+        // Thin and wide: 5c spread, 50 shares -> confidence ~0.015, mid weight ~0.41.
+        let thin = book(0.475, 0.525, 50.0, Some(990.0));
+        let w_thin = strat.mid_weight(&thin, last);
+        assert!((0.4..0.45).contains(&w_thin), "{w_thin}");
+        let (fair_thin, _) = strat.compute_fair(&thin, last, None).unwrap();
+        assert!(fair_thin > fair_deep, "thin book leans to the 0.60 trade");
+
+        // Spread beyond FAIR_WIDE_SPREAD: no confidence in the mid at all.
+        let broken = book(0.40, 0.60, 10_000.0, Some(990.0));
+        assert!((strat.mid_weight(&broken, last) - 0.4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn fair_is_mid_without_a_recent_trade() {
+        let strat = HftStrategy::new(blend_settings());
+        let thin = book(0.475, 0.525, 50.0, None);
+        for (tob, ema) in [
+            (thin.clone(), None),
+            (thin.clone(), Some(0.6)), // EMA but no trade timestamp
+            (book(0.475, 0.525, 50.0, Some(100.0)), Some(0.6)), // 900s old
+        ] {
+            assert_eq!(strat.mid_weight(&tob, ema), 1.0);
+            let (fair, _) = strat.compute_fair(&tob, ema, None).unwrap();
+            assert!((fair - 0.5).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn anchored_fair_blends_toward_reference_mid_unless_stale() {
+        let mut settings = blend_settings();
+        settings.reference_max_age_secs = 10.0;
+        let strat = HftStrategy::new(settings);
+        let tob = book(0.39, 0.41, 1_000.0, None);
+        let (standalone, src) = strat.compute_fair(&tob, None, None).unwrap();
+        assert!((standalone - 0.40).abs() < 1e-9);
+        assert_eq!(src, "book_mid");
+
+        // Reference mid 0.60, beta 0.25: 0.75 * 0.40 + 0.25 * 0.60 = 0.45.
+        let reference = Tob {
+            ts: 995.0,
+            ..book(0.59, 0.61, 1_000.0, None)
+        };
+        let (fair, src) = strat
+            .compute_fair(&tob, None, Some((&reference, 0.25)))
+            .unwrap();
+        assert!((fair - 0.45).abs() < 1e-9, "{fair}");
+        assert_eq!(src, "reference_anchor");
+
+        // Stale or one-sided reference: standalone fair.
+        let stale = Tob {
+            ts: 980.0,
+            ..reference.clone()
+        };
+        let one_sided = Tob {
+            best_ask: None,
+            ..reference.clone()
+        };
+        for r in [&stale, &one_sided] {
+            let (fair, src) = strat.compute_fair(&tob, None, Some((r, 0.25))).unwrap();
+            assert!((fair - standalone).abs() < 1e-12);
+            assert_eq!(src, "book_mid");
+        }
+    }
+
+    #[test]
+    fn below_target_capture_widens_quotes_window_by_window() {
+        let mut settings = Settings::load().unwrap();
+        settings.mm_levels = 1;
+        settings.mm_quote_width = 0.02;
+        settings.target_capture_bps = 40.0;
+        settings.capture_window_secs = 60.0;
+        settings.capture_gain = 0.5;
+        settings.capture_max_step = 0.1;
+        settings.capture_deadband_bps = 5.0;
+        settings.capture_width_max_mult = 1.25;
+        let strat = HftStrategy::new(settings);
+        let inner_width = |s: &HftStrategy| {
+            let q = s.quote_grid(0.5, 0.0, 0.0, 0.0, 0.0, 0.001);
+            ((q[1].price - q[0].price) / 0.001).round() as i64
+        };
+        let fill = |side: Side, price: f64| Fill {
+            fill_id: "f".into(),
+            order_id: "o".into(),
+            market_id: "m1".into(),
+            side,
+            price,
+            size: 10.0,
+            ts: 0.0,
+        };
+        // 10 bps either side of fair: 20 bps per round trip against a 40 bps target.
+        let thin = [fill(Side::Buy, 0.4995), fill(Side::Sell, 0.5005)];
+
+        strat.note_maker_fills(0.0, 0.5, &[]);
+        let mut widths = vec![inner_width(&strat)];
+        for w in 1..=4 {
+            // Mid-window fills do not move the width until the window closes.
+            strat.note_maker_fills(60.0 * w as f64 - 30.0, 0.5, &thin);
+            assert_eq!(inner_width(&strat), *widths.last().unwrap());
+            strat.note_maker_fills(60.0 * w as f64, 0.5, &thin);
+            widths.push(inner_width(&strat));
+        }
+        // +10% per window (step capped), then held at the 1.25x bound.
+        assert_eq!(widths, [40, 44, 48, 50, 50]);
+
+        // On target (inside the deadband), and an empty window: no change.
+        let on_target = [fill(Side::Buy, 0.499), fill(Side::Sell, 0.501)];
+        strat.note_maker_fills(300.0, 0.5, &on_target);
+        strat.note_maker_fills(360.0, 0.5, &[]);
+        strat.note_maker_fills(420.0, 0.5, &[]);
+        assert_eq!(inner_width(&strat), 50);
+        assert!((strat.width_mult() - 1.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn positive_inventory_target_skews_quotes_up_when_flat() {
+        let mut settings = Settings::load().unwrap();
+        settings.mm_levels = 1;
+        settings.mm_quote_width = 0.01;
+        settings.max_inventory_usd = 10.0;
+        settings.inventory_skew_cap = 0.003;
+        settings.inventory_targets = vec![crate::config::InventoryTarget {
+            market_id: "m1".to_string(),
+            qty: 50.0,
+        }];
+        let strat = HftStrategy::new(settings);
+        // Clamped to the inventory cap.
+        assert_eq!(strat.inventory_target_for("m1"), 10.0);
+        assert_eq!(strat.inventory_target_for("m2"), 0.0);
+
+        let prices = |target: f64| {
+            let q = strat.quote_grid(0.5, 0.0, target, 0.0, 0.0, 0.001);
+            (q[0].price, q[1].price)
+        };
+        let (flat_bid, flat_ask) = prices(0.0);
+        let (bid, ask) = prices(strat.inventory_target_for("m1"));
+        // Flat but short of the target: both sides shift up by the full skew (3 ticks),
+        // so the bid is likelier to fill and the ask less so.
+        assert!((bid - flat_bid - 0.003).abs() < 1e-9, "{bid} vs {flat_bid}");
+        assert!((ask - flat_ask - 0.003).abs() < 1e-9, "{ask} vs {flat_ask}");
+        // At the target the skew is gone.
+        let q = strat.quote_grid(0.5, 10.0, 10.0, 0.0, 0.0, 0.001);
+        assert_eq!((q[0].price, q[1].price), (flat_bid, flat_ask));
+    }
+
+    #[test]
+    fn improves_touch_only_on_wide_profitable_spreads() {
+        let mut settings = Settings::load().unwrap();
+        settings.improve_min_spread_ticks = 3;
+        let join = HftStrategy::new(settings.clone());
+        assert_eq!(join.improved_touch(0.45, 0.55, 0.50, 0.01, 0.0), None);
+
+        settings.join_or_improve = "improve_if_spread_ge_ticks".to_string();
+        let strat = HftStrategy::new(settings);
+        let (b, a) = strat.improved_touch(0.45, 0.55, 0.50, 0.01, 0.0).unwrap();
+        assert!((b - 0.46).abs() < 1e-9 && (a - 0.54).abs() < 1e-9);
+        // Two ticks wide: improving would cross.
+        assert_eq!(strat.improved_touch(0.49, 0.51, 0.50, 0.01, 0.0), None);
+        // Improved spread (0.01 on 0.5 = 200 bps) below the profitable minimum.
+        assert_eq!(strat.improved_touch(0.48, 0.51, 0.495, 0.01, 250.0), None);
+        // Fair at the improved bid: buying there has no edge.
+        assert_eq!(strat.improved_touch(0.45, 0.55, 0.46, 0.01, 0.0), None);
+    }
+
+    fn as_strategy() -> HftStrategy {
+        let mut settings = Settings::load().unwrap();
+        settings.quote_model = "as".to_string();
+        settings.as_gamma = 0.1;
+        settings.as_intensity_k = 100.0;
+        settings.as_horizon_secs = 60.0;
+        HftStrategy::new(settings)
+    }
+
+    #[test]
+    fn as_inventory_pushes_both_quotes_down() {
+        let strat = as_strategy();
+        let (flat_bid, flat_ask) = strat.quote_as(0.50, 0.0, 0.003, 100.0);
+        assert!(flat_bid < 0.50 && 0.50 < flat_ask);
+        assert!(((flat_bid + flat_ask) / 2.0 - 0.50).abs() < 1e-12);
+        let (long_bid, long_ask) = strat.quote_as(0.50, 50.0, 0.003, 100.0);
+        assert!(long_bid < flat_bid && long_ask < flat_ask);
+        // Inventory moves the reservation price, not the spread.
+        assert!(((long_ask - long_bid) - (flat_ask - flat_bid)).abs() < 1e-12);
+        let (short_bid, short_ask) = strat.quote_as(0.50, -50.0, 0.003, 100.0);
+        assert!(short_bid > flat_bid && short_ask > flat_ask);
+
+        // Laddered onto the tick grid, a long book quotes lower on both sides too.
+        let mut tob = book(0.49, 0.51, 100.0, None);
+        tob.tick_size = Some(0.001);
+        tob.mid_var_ewma = Some(0.003f64.powi(2));
+        let inner = |inv: f64| {
+            let q = strat.quotes(0.50, inv, 0.0, 0.0, &tob);
+            (q[0].price, q[1].price)
+        };
+        let ((fb, fa), (lb, la)) = (inner(0.0), inner(50.0));
+        assert!(lb < fb && la < fa, "{lb},{la} vs {fb},{fa}");
+    }
+
+    #[test]
+    fn as_volatility_widens_the_spread() {
+        let strat = as_strategy();
+        let spread = |vol: f64| {
+            let (b, a) = strat.quote_as(0.50, 0.0, vol, 100.0);
+            a - b
+        };
+        assert!(spread(0.0) > 0.0);
+        assert!(spread(0.005) > spread(0.001));
+        assert!(spread(0.001) > spread(0.0));
+        // Deeper, more intense books tighten it.
+        let (b, a) = strat.quote_as(0.50, 0.0, 0.001, 400.0);
+        assert!(a - b < spread(0.001));
+    }
+
+    #[test]
+    fn microprice_fair_leans_with_the_heavier_side() {
+        let mut settings = blend_settings();
+        settings.fair_source = "microprice".to_string();
+        let strat = HftStrategy::new(settings);
+        // 900 bid vs 100 ask: (0.48 * 100 + 0.52 * 900) / 1000 = 0.516.
+        let bid_heavy = Tob {
+            bid_depth_n: 900.0,
+            ask_depth_n: 100.0,
+            ..book(0.48, 0.52, 0.0, None)
+        };
+        let (fair, src) = strat.compute_fair(&bid_heavy, Some(0.40), None).unwrap();
+        assert!((fair - 0.516).abs() < 1e-9, "{fair}");
+        assert_eq!(src, "microprice");
+
+        let ask_heavy = Tob {
+            bid_depth_n: 100.0,
+            ask_depth_n: 900.0,
+            ..bid_heavy.clone()
+        };
+        let (fair, _) = strat.compute_fair(&ask_heavy, None, None).unwrap();
+        assert!((fair - 0.484).abs() < 1e-9, "{fair}");
+
+        // No depth on either side: plain mid (not the blend with the last trade).
+        let empty = book(0.48, 0.52, 0.0, Some(990.0));
+        let (fair, src) = strat.compute_fair(&empty, Some(0.60), None).unwrap();
+        assert!((fair - 0.50).abs() < 1e-9, "{fair}");
+        assert_eq!(src, "book_mid");
+    }
+}
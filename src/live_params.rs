@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::config::Settings;
+
+/// Settings shared between the dashboard (writer) and the trade loop (reader).
+pub type SharedSettings = Arc<RwLock<Settings>>;
+
+/// The only fields `POST /api/admin/set_param` may change; everything else needs a restart.
+pub const LIVE_PARAMS: &[&str] = &[
+    "base_order_size",
+    "mm_reprice_threshold",
+    "max_inventory_usd",
+    "mm_quote_width",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum SetParamError {
+    /// Not in `LIVE_PARAMS`.
+    Unknown,
+    /// Outside the param's live range, or the result fails `Settings::validate`.
+    OutOfRange(String),
+}
+
+pub fn shared(settings: &Settings) -> SharedSettings {
+    Arc::new(RwLock::new(settings.clone()))
+}
+
+fn field<'a>(s: &'a mut Settings, name: &str) -> Option<&'a mut f64> {
+    match name {
+        "base_order_size" => Some(&mut s.base_order_size),
+        "mm_reprice_threshold" => Some(&mut s.mm_reprice_threshold),
+        "max_inventory_usd" => Some(&mut s.max_inventory_usd),
+        "mm_quote_width" => Some(&mut s.mm_quote_width),
+        _ => None,
+    }
+}
+
+fn get(s: &Settings, name: &str) -> Option<f64> {
+    match name {
+        "base_order_size" => Some(s.base_order_size),
+        "mm_reprice_threshold" => Some(s.mm_reprice_threshold),
+        "max_inventory_usd" => Some(s.max_inventory_usd),
+        "mm_quote_width" => Some(s.mm_quote_width),
+        _ => None,
+    }
+}
+
+/// Range a live change must fall in. Tighter than `Settings::validate` so a typo in
+/// the dashboard can't multiply order size or inventory by 1000.
+fn check_range(s: &Settings, name: &str, value: f64) -> Result<(), SetParamError> {
+    let (ok, expected) = match name {
+        "base_order_size" => (value > 0.0 && value <= 10_000.0, "(0, 10000]".to_string()),
+        "mm_reprice_threshold" => (value > 0.0 && value <= 0.5, "(0, 0.5]".to_string()),
+        "max_inventory_usd" => (
+            value > 0.0 && value <= 1_000_000.0,
+            "(0, 1000000]".to_string(),
+        ),
+        "mm_quote_width" => (
+            value >= s.price_tick && value <= 0.5,
+            format!("[{}, 0.5]", s.price_tick),
+        ),
+        _ => return Err(SetParamError::Unknown),
+    };
+    if !value.is_finite() || !ok {
+        return Err(SetParamError::OutOfRange(format!(
+            "{name} must be in {expected} (got {value})"
+        )));
+    }
+    Ok(())
+}
+
+/// Set one allowlisted param; returns the previous value. The change is logged and
+/// picked up by the trade loop on its next tick.
+pub fn set_param(live: &SharedSettings, name: &str, value: f64) -> Result<f64, SetParamError> {
+    let mut s = live.write();
+    check_range(&s, name, value)?;
+    let mut next = s.clone();
+    *field(&mut next, name).ok_or(SetParamError::Unknown)? = value;
+    next.validate()
+        .map_err(|e| SetParamError::OutOfRange(e.to_string()))?;
+    let old = std::mem::replace(field(&mut s, name).ok_or(SetParamError::Unknown)?, value);
+    log::warn!(
+        "settings.param_changed name={} old={} new={}",
+        name,
+        old,
+        value
+    );
+    Ok(old)
+}
+
+/// Copy the live params from `from` into `to`; true if any of them changed.
+pub fn apply(from: &Settings, to: &mut Settings) -> bool {
+    let mut changed = false;
+    for name in LIVE_PARAMS {
+        let (Some(v), Some(dst)) = (get(from, name), field(to, name)) else {
+            continue;
+        };
+        if *dst != v {
+            *dst = v;
+            changed = true;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_param_allows_only_listed_fields_in_range() {
+        let live = shared(&Settings::load().unwrap());
+        let before = live.read().base_order_size;
+        assert_eq!(set_param(&live, "base_order_size", 25.0), Ok(before));
+        assert_eq!(live.read().base_order_size, 25.0);
+
+        assert_eq!(
+            set_param(&live, "loop_ms", 1.0),
+            Err(SetParamError::Unknown)
+        );
+        for (name, bad) in [
+            ("base_order_size", 0.0),
+            ("base_order_size", f64::NAN),
+            ("mm_reprice_threshold", 0.9),
+            ("max_inventory_usd", -5.0),
+            ("mm_quote_width", 0.0),
+        ] {
+            assert!(
+                matches!(
+                    set_param(&live, name, bad),
+                    Err(SetParamError::OutOfRange(_))
+                ),
+                "{name}={bad}"
+            );
+        }
+        assert_eq!(live.read().base_order_size, 25.0);
+    }
+}
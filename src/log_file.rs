@@ -0,0 +1,178 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Size-rotated log file: `app.log` is the live file, `app.log.1` the newest
+/// rotated one, up to `app.log.<max_files>` (older files are dropped).
+///
+/// Rotation only happens between writes, and env_logger hands over each record
+/// in a single write, so a line is never split across files.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: u32,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_bytes: u64, max_files: u32) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("create log dir {}", parent.display()))?;
+        }
+        let file =
+            open_append(path).with_context(|| format!("open log file {}", path.display()))?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes: max_bytes.max(1),
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut p = self.path.clone().into_os_string();
+        p.push(format!(".{n}"));
+        p.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            // No history kept: start the live file over.
+            self.file.set_len(0)?;
+        } else {
+            let _ = std::fs::remove_file(self.rotated(self.max_files));
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+            self.file = open_append(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Log files may carry order ids and wallet addresses: owner read/write, group read.
+fn open_append(path: &Path) -> io::Result<File> {
+    let mut opts = OpenOptions::new();
+    opts.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(0o640);
+    }
+    opts.open(path)
+}
+
+/// Writes every record to stderr and the log file. A failing file write is
+/// reported once on stderr and never drops the stderr copy.
+struct Tee {
+    file: RotatingFile,
+    file_failed: bool,
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stderr().write_all(buf)?;
+        if let Err(e) = self.file.write_all(buf) {
+            if !self.file_failed {
+                self.file_failed = true;
+                eprintln!(
+                    "log_file.write_failed path={} err={e}",
+                    self.file.path.display()
+                );
+            }
+        } else {
+            self.file_failed = false;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()?;
+        let _ = self.file.flush();
+        Ok(())
+    }
+}
+
+/// Initialise env_logger: stderr only, or teed into a rotating file when `path` is non-empty.
+pub fn init_logging(path: &str, max_bytes: u64, max_files: u32) -> Result<()> {
+    let mut builder = env_logger::Builder::from_default_env();
+    let path = path.trim();
+    if !path.is_empty() {
+        let file = RotatingFile::open(Path::new(path), max_bytes, max_files)?;
+        builder.target(env_logger::Target::Pipe(Box::new(Tee {
+            file,
+            file_failed: false,
+        })));
+    }
+    builder.init();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writing_past_max_bytes_rotates_without_losing_lines() {
+        let dir = std::env::temp_dir().join(format!("superspreader-logs-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("app.log");
+        let mut f = RotatingFile::open(&path, 64, 2).unwrap();
+        let lines: Vec<String> = (0..10)
+            .map(|i| format!("line {i:02} {}\n", "x".repeat(16)))
+            .collect();
+        for l in &lines {
+            f.write_all(l.as_bytes()).unwrap();
+        }
+        f.flush().unwrap();
+
+        let read = |p: PathBuf| std::fs::read_to_string(p).unwrap_or_default();
+        let live = read(path.clone());
+        let r1 = read(f.rotated(1));
+        let r2 = read(f.rotated(2));
+        assert!(!f.rotated(3).exists());
+        for chunk in [&live, &r1, &r2] {
+            assert!(!chunk.is_empty());
+            assert!(chunk.len() as u64 <= 64);
+            assert!(chunk.ends_with('\n'));
+        }
+        // Oldest first: every kept line is whole and in order; only whole old files were dropped.
+        let kept = format!("{r2}{r1}{live}");
+        let tail = lines.concat();
+        assert!(tail.ends_with(&kept));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode & 0o007, 0, "log file is world-accessible: {mode:o}");
+        }
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use crate::utils::{clamp, ewma};
+
+/// Adaptive trader loop cadence.
+///
+/// Tracks an EWMA of loop work time and picks the effective interval as
+/// `max(target, work * headroom)`, clamped to `[min, max]`. Changes smaller than
+/// the deadband are ignored so the cadence does not oscillate on noisy work.
+pub struct LoopPacer {
+    target_ms: f64,
+    min_ms: f64,
+    max_ms: f64,
+    interval_ms: f64,
+    work_ewma_ms: Option<f64>,
+    overrun_streak: u32,
+}
+
+const WORK_ALPHA: f64 = 0.2;
+// Leave room above measured work so the loop is not permanently saturated.
+const HEADROOM: f64 = 1.25;
+// Relative change below which the interval is left alone.
+const DEADBAND: f64 = 0.1;
+// Consecutive ticks over target before warning (a single slow tick is ignored).
+const OVERRUN_STREAK: u32 = 5;
+
+impl LoopPacer {
+    pub fn new(target_ms: u64, min_ms: u64, max_ms: u64) -> Self {
+        let (min_ms, max_ms) = (min_ms as f64, (max_ms as f64).max(min_ms as f64));
+        let target_ms = clamp(target_ms as f64, min_ms, max_ms);
+        Self {
+            target_ms,
+            min_ms,
+            max_ms,
+            interval_ms: target_ms,
+            work_ewma_ms: None,
+            overrun_streak: 0,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn interval(&self) -> Duration {
+        ms_to_duration(self.interval_ms)
+    }
+
+    /// Record one loop's work time; returns how long to sleep before the next tick.
+    pub fn record(&mut self, work: Duration) -> Duration {
+        let work_ms = work.as_secs_f64() * 1000.0;
+        let avg = ewma(self.work_ewma_ms, work_ms, WORK_ALPHA);
+        self.work_ewma_ms = Some(avg);
+
+        let desired = clamp(
+            (avg * HEADROOM).max(self.target_ms),
+            self.min_ms,
+            self.max_ms,
+        );
+        // Bounds and the target are always reachable; anything else must clear the deadband.
+        let snap = desired == self.target_ms || desired == self.max_ms;
+        if desired != self.interval_ms
+            && (snap || (desired - self.interval_ms).abs() > DEADBAND * self.interval_ms)
+        {
+            if desired == self.target_ms && self.interval_ms > self.target_ms {
+                log::info!("trader loop back on target interval_ms={:.0}", desired);
+            }
+            self.interval_ms = desired;
+        }
+
+        if work_ms > self.target_ms {
+            self.overrun_streak += 1;
+            if self.overrun_streak == OVERRUN_STREAK {
+                log::warn!(
+                    "trader loop overrunning: work_ewma_ms={:.1} target_ms={:.0} interval_ms={:.0}",
+                    avg,
+                    self.target_ms,
+                    self.interval_ms
+                );
+            }
+        } else {
+            self.overrun_streak = 0;
+        }
+
+        ms_to_duration((self.interval_ms - work_ms).max(0.0))
+    }
+}
+
+fn ms_to_duration(ms: f64) -> Duration {
+    Duration::from_micros((ms * 1000.0).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heavy_work_stretches_interval_within_bounds() {
+        let mut p = LoopPacer::new(50, 10, 500);
+        for _ in 0..20 {
+            p.record(Duration::from_millis(5));
+        }
+        assert_eq!(p.interval(), Duration::from_millis(50));
+
+        for _ in 0..50 {
+            p.record(Duration::from_millis(120));
+        }
+        let stretched = p.interval();
+        assert!(stretched > Duration::from_millis(120), "{stretched:?}");
+        assert!(stretched <= Duration::from_millis(500));
+
+        for _ in 0..50 {
+            p.record(Duration::from_millis(5_000));
+        }
+        assert_eq!(p.interval(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn noisy_work_does_not_oscillate() {
+        let mut p = LoopPacer::new(50, 10, 500);
+        for _ in 0..50 {
+            p.record(Duration::from_millis(100));
+        }
+        let settled = p.interval();
+        let mut changes = 0;
+        let mut last = settled;
+        for i in 0..100 {
+            p.record(Duration::from_millis(if i % 2 == 0 { 95 } else { 105 }));
+            if p.interval() != last {
+                changes += 1;
+                last = p.interval();
+            }
+        }
+        assert_eq!(changes, 0, "settled={settled:?} last={last:?}");
+    }
+}
@@ -5,38 +5,177 @@ mod store;
 // Trading bot modules (implemented next)
 mod bot;
 mod feed_handler;
+mod fill_model;
+mod flatten;
+mod health;
+mod heartbeat;
 mod hft_strategy;
+mod live_params;
+mod log_file;
+mod loop_pacer;
 mod market_selector;
 mod paper_broker;
+mod probe;
+mod replay;
+mod rescan;
 mod risk_engine;
+mod shadow;
 mod utils;
 
 use anyhow::Result;
 use clap::Parser;
 
-use crate::{config::Settings, store::SqliteStore};
+use crate::{
+    config::Settings,
+    store::{BlobEncoding, SqliteStore},
+};
 
 #[derive(Debug, Parser)]
 #[command(name = "superspreader", version)]
 struct Cli {
-    /// Override RUN_MODE (paper|scanner)
+    /// Override RUN_MODE (paper|scanner|backtest)
     #[arg(long)]
     mode: Option<String>,
+
+    /// Override MAX_RUNTIME_SECS: stop cleanly after this many seconds
+    #[arg(long, value_name = "SECS")]
+    max_runtime: Option<f64>,
+
+    /// Force EXECUTION_MODE=bookkeeping (quote and log decisions, but simulate no executions)
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Replay the recorded tape through two configs (KEY=VALUE override files
+    /// layered over the env) and print a PnL comparison, then exit.
+    #[arg(long, num_args = 2, value_names = ["A", "B"])]
+    replay_compare: Option<Vec<String>>,
+
+    /// Print the startup path (reset|rehydrate|fresh) and the stored positions that
+    /// rehydration would load, then exit without trading.
+    #[arg(long)]
+    inspect_state: bool,
+
+    /// Ask the running bot (through its dashboard, DASHBOARD_ENABLE_FLATTEN=1) to cancel all
+    /// resting orders and flatten every position, print the per-market report, then exit.
+    #[arg(long)]
+    flatten_all: bool,
+
+    /// RNG seed for backtests and replays (shared by both compare arms).
+    #[arg(long, default_value_t = 42)]
+    replay_seed: u64,
+
+    /// Max tape rows to replay.
+    #[arg(long, default_value_t = 1_000_000)]
+    replay_limit: usize,
+
+    /// Replay/backtest pacing: `max` (back to back, deterministic) or a multiplier of real
+    /// time (1, 10x).
+    #[arg(
+        long,
+        visible_alias = "speed",
+        default_value = "max",
+        value_name = "SPEED"
+    )]
+    replay_speed: String,
+
+    /// Only replay tape rows with `from_ts <= ts <= to_ts` (unix seconds; either end may be empty).
+    #[arg(long, value_name = "FROM:TO")]
+    replay_range: Option<String>,
+
+    /// Override LOG_FILE: tee logs into this size-rotated file (stderr is kept).
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<String>,
+
+    /// Override PAPER_FILL_MODEL (maker_touch|optimistic_touch|queue|probability_decay)
+    #[arg(long, value_name = "NAME")]
+    paper_fill_model: Option<String>,
 }
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<()> {
-    env_logger::init();
-
     let cli = Cli::parse();
     let mut settings = Settings::load()?;
+    if let Some(path) = &cli.log_file {
+        settings.log_file = path.clone();
+    }
+    log_file::init_logging(
+        &settings.log_file,
+        settings.log_file_max_bytes,
+        settings.log_file_max_files,
+    )?;
     if let Some(m) = cli.mode {
         settings.run_mode = m.to_lowercase();
     }
+    if let Some(secs) = cli.max_runtime {
+        settings.max_runtime_secs = secs;
+    }
+    if cli.dry_run {
+        settings.execution_mode = "bookkeeping".to_string();
+    }
+    if let Some(model) = &cli.paper_fill_model {
+        settings.paper_fill_model = model.to_lowercase();
+    }
+    settings.validate()?;
 
-    let store = SqliteStore::new(&settings.sqlite_path)?;
+    if cli.flatten_all {
+        println!(
+            "{}",
+            flatten::post_flatten_all(&settings.dashboard_host, settings.dashboard_port)?
+        );
+        return Ok(());
+    }
+
+    let store = SqliteStore::new(&settings.sqlite_path)?
+        .with_blob_encoding(BlobEncoding::parse(&settings.sqlite_blob_encoding)?)
+        .with_wal_autocheckpoint(settings.sqlite_wal_autocheckpoint_pages)
+        .with_busy_timeout_ms(settings.sqlite_busy_timeout_ms)
+        .with_write_retries(settings.sqlite_write_retries)
+        .with_write_gate(settings.sqlite_write_gate)
+        .with_bulk_chunk_rows(settings.sqlite_bulk_chunk_rows);
     store.init_db()?;
 
+    if cli.inspect_state {
+        print!("{}", bot::inspect_state(&settings, &store)?);
+        return Ok(());
+    }
+
+    let speed = replay::ReplaySpeed::parse(&cli.replay_speed)?;
+    let range = match &cli.replay_range {
+        Some(r) => replay::ReplayRange::parse(r)?,
+        None => replay::ReplayRange::default(),
+    };
+
+    if let Some(files) = cli.replay_compare {
+        let a = Settings::load_with_overrides(&files[0])?;
+        let b = Settings::load_with_overrides(&files[1])?;
+        let tape = replay::load_tape(&store, range, cli.replay_limit)?;
+        log::info!(
+            "replay.start events={} seed={} speed={:?} range={:?} a={} b={}",
+            tape.len(),
+            cli.replay_seed,
+            speed,
+            range,
+            files[0],
+            files[1]
+        );
+        let (ra, rb) = replay::compare(&a, &b, &tape, cli.replay_seed, speed)?;
+        print!("{}", replay::format_comparison(&ra, &rb));
+        return Ok(());
+    }
+
+    if settings.run_mode == "backtest" {
+        let stats = replay::backtest(
+            &settings,
+            &store,
+            cli.replay_limit,
+            range,
+            cli.replay_seed,
+            speed,
+        )?;
+        print!("{}", replay::format_summary(&stats));
+        return Ok(());
+    }
+
     log::info!(
         "app.start run_mode={} trade_mode={} execution_mode={} sqlite={}",
         settings.run_mode,
@@ -45,13 +184,23 @@ async fn main() -> Result<()> {
         store.path()
     );
 
+    // Manual rescans from the dashboard are handed to the scanner loop.
+    let (rescan_handle, rescan_rx) = rescan::rescan_channel();
+    // Flatten-all requests from the dashboard are handed to the trade loop.
+    let (flatten_handle, flatten_rx) = flatten::flatten_channel();
+    // Live-tunable params: the dashboard writes them, the trade loop reads them each tick.
+    let live = live_params::shared(&settings);
+
     // Start dashboard server (optional) in the background.
     if settings.dashboard_enabled {
         let st = settings.clone();
         let db = store.clone();
+        let live = live.clone();
         let url = format!("http://{}:{}/", st.dashboard_host, st.dashboard_port);
         tokio::spawn(async move {
-            if let Err(e) = dashboard::serve_dashboard(st, db).await {
+            if let Err(e) =
+                dashboard::serve_dashboard(st, db, rescan_handle, flatten_handle, live).await
+            {
                 log::error!("dashboard.error {}", e);
             }
         });
@@ -65,6 +214,6 @@ async fn main() -> Result<()> {
     }
 
     // Run the bot (scanner or full paper trader).
-    bot::run(settings, store).await?;
+    bot::run(settings, store, rescan_rx, flatten_rx, live).await?;
     Ok(())
 }
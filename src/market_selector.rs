@@ -1,10 +1,17 @@
+use std::collections::{HashMap, HashSet};
+
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::{config::Settings, feed_handler::FeedState, store::SqliteStore, utils::now_ts};
+use crate::{
+    config::Settings,
+    feed_handler::FeedState,
+    store::{ScannerCandidate, SqliteStore},
+    utils::now_ts,
+};
 
 use polymarket_hft::client::polymarket::gamma::Client as GammaClient;
-use polymarket_hft::client::polymarket::gamma::GetMarketsRequest;
+use polymarket_hft::client::polymarket::gamma::{GetMarketsRequest, Market};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SelectedMarket {
@@ -17,12 +24,30 @@ pub struct SelectedMarket {
     pub condition_id: Option<String>,
     pub clob_token_id: Option<String>,
     pub microstructure_score: f64,
+    /// Gamma `orderPriceMinTickSize` at selection time (feed seeds its tick cache from this).
+    #[serde(default)]
+    pub tick_size: Option<f64>,
+    /// Reporting category derived from Gamma tags (`politics`, `sports`, `crypto`, `other`).
+    #[serde(default = "default_category")]
+    pub category: String,
+}
+
+fn default_category() -> String {
+    CATEGORY_OTHER.to_string()
 }
 
 pub struct MarketSelector {
     settings: Settings,
     store: SqliteStore,
     feed: FeedState,
+    /// Markets selected on the previous scan; watched for active -> closed transitions.
+    prev_selected: parking_lot::Mutex<HashSet<String>>,
+    /// Gamma listing from the previous scan, for the per-cycle change summary.
+    prev_markets: parking_lot::Mutex<Option<HashMap<String, MarketFields>>>,
+    /// Tags fetched via `get_market_tags` for markets the listing returned untagged.
+    tag_cache: parking_lot::Mutex<HashMap<String, Vec<String>>>,
+    /// Unmodelled Gamma fields already reported (`GAMMA_SCHEMA_DIAGNOSTICS`).
+    unknown_fields_seen: parking_lot::Mutex<HashSet<String>>,
 }
 
 impl MarketSelector {
@@ -31,6 +56,10 @@ impl MarketSelector {
             settings,
             store,
             feed,
+            prev_selected: parking_lot::Mutex::new(HashSet::new()),
+            prev_markets: parking_lot::Mutex::new(None),
+            tag_cache: parking_lot::Mutex::new(HashMap::new()),
+            unknown_fields_seen: parking_lot::Mutex::new(HashSet::new()),
         }
     }
 
@@ -41,21 +70,25 @@ impl MarketSelector {
         let gamma = GammaClient::with_base_url(&self.settings.gamma_base_url)
             .with_context(|| format!("gamma base url {}", self.settings.gamma_base_url))?;
 
-        let req = GetMarketsRequest {
-            limit: Some(1000),
-            offset: Some(0),
-            closed: Some(false),
-            ..Default::default()
-        };
-
-        let markets = gamma.get_markets(req).await.context("gamma.get_markets")?;
+        let markets = gamma
+            .get_markets(open_markets_request())
+            .await
+            .context("gamma.get_markets")?;
+        self.detect_closed_transitions(&gamma, &markets, ts).await;
+        self.log_market_diff(&markets);
+        self.report_unknown_fields(&markets);
 
         let mut eligible: Vec<SelectedMarket> = Vec::new();
         let mut eligible_ids: Vec<String> = Vec::new();
+        // Per-market score breakdown for markets that were filtered out (for debugging selection).
+        let mut excluded: Vec<ScannerCandidate> = Vec::new();
+        let mut spread_by_market: std::collections::HashMap<String, f64> =
+            std::collections::HashMap::new();
+        // Markets whose Gamma price disagrees with the live book (ranked last).
+        let mut divergent: HashSet<String> = HashSet::new();
 
         for m in markets {
-            let active = m.active.unwrap_or(true) && !m.closed.unwrap_or(false);
-            if !active {
+            if !is_quotable(&m) {
                 continue;
             }
 
@@ -67,34 +100,6 @@ impl MarketSelector {
                 .unwrap_or(0.0);
             let liquidity_usd = m.liquidity_num.or(m.liquidity_clob).unwrap_or(0.0);
 
-            if volume_24h_usd < self.settings.min_24h_volume_usd
-                || liquidity_usd < self.settings.min_liquidity_usd
-            {
-                continue;
-            }
-
-            let condition_id = m.condition_id.clone();
-            let clob_token_id =
-                pick_primary_token_id(m.clob_token_ids.as_deref(), m.outcomes.as_deref());
-            if clob_token_id.is_none() {
-                // Can't subscribe/trade without a token id.
-                continue;
-            }
-
-            // end_ts: parse RFC3339 if provided
-            let end_ts = m
-                .end_date_iso
-                .as_deref()
-                .or(m.end_date.as_deref())
-                .and_then(parse_ts_rfc3339);
-
-            let event_id = m
-                .events
-                .as_ref()
-                .and_then(|evs| evs.first())
-                .map(|e| e.id.clone())
-                .unwrap_or_else(|| format!("event:{market_id}"));
-
             // Microstructure metrics from current feed snapshot (may be missing early on).
             let (spread_bps, imbalance_abs, updates_per_min) = self
                 .feed
@@ -111,29 +116,60 @@ impl MarketSelector {
                         return None;
                     }
                     let spread_bps = ((a - b) / mid) * 10_000.0;
-                    let total = tob.bid_depth_5 + tob.ask_depth_5;
-                    let imb = if total > 0.0 {
-                        (tob.bid_depth_5 - tob.ask_depth_5) / total
-                    } else {
-                        0.0
-                    };
-                    Some((spread_bps, imb.abs(), tob.updates_ewma_per_min))
+                    Some((spread_bps, tob.imbalance().abs(), tob.updates_ewma_per_min))
                 })
                 .unwrap_or((0.0, 0.0, 0.0));
 
             // Score per spec: (book_updates/min * spread_bps) + imbalance_ratio
             let microstructure_score = (updates_per_min * spread_bps) + imbalance_abs;
 
+            let condition_id = m.condition_id.clone();
+            let clob_token_id =
+                pick_primary_token_id(m.clob_token_ids.as_deref(), m.outcomes.as_deref());
+
             // For selection, require minimum spread and update rate *if we have them*.
             // If feed hasn't warmed up yet, allow these markets to seed subscriptions.
-            let has_metrics = updates_per_min > 0.0 && spread_bps > 0.0;
-            if has_metrics
-                && (spread_bps < self.settings.min_spread_bps
-                    || updates_per_min < self.settings.min_updates_min)
-            {
+            let th = Thresholds::resolve(&self.settings, &market_tags(&m));
+            let excluded_reason = th.exclusion_reason(
+                volume_24h_usd,
+                liquidity_usd,
+                clob_token_id.is_some(),
+                spread_bps,
+                updates_per_min,
+            );
+            let divergence = self.price_divergence(&m, &market_id);
+            let excluded_reason = excluded_reason.or_else(|| {
+                (divergence.is_some() && self.settings.gamma_price_divergence_action == "skip")
+                    .then_some("stale_gamma_price")
+            });
+            if let Some(reason) = excluded_reason {
+                excluded.push(ScannerCandidate {
+                    market_id,
+                    score: microstructure_score,
+                    volume_24h_usd,
+                    liquidity_usd,
+                    spread_bps,
+                    excluded_reason: Some(reason.to_string()),
+                });
                 continue;
             }
 
+            // end_ts: parse RFC3339 if provided
+            let end_ts = m
+                .end_date_iso
+                .as_deref()
+                .or(m.end_date.as_deref())
+                .and_then(parse_ts_rfc3339);
+
+            let event_id = m
+                .events
+                .as_ref()
+                .and_then(|evs| evs.first())
+                .map(|e| e.id.clone())
+                .unwrap_or_else(|| format!("event:{market_id}"));
+
+            let category = categorize(&self.resolve_tags(&gamma, &m).await);
+
             // Persist to SQLite markets table (dashboard depends on this).
             self.store
                 .upsert_market(
@@ -146,11 +182,16 @@ impl MarketSelector {
                     liquidity_usd,
                     condition_id.as_deref(),
                     clob_token_id.as_deref(),
+                    Some(category),
                     ts,
                 )
                 .ok();
 
             eligible_ids.push(market_id.clone());
+            spread_by_market.insert(market_id.clone(), spread_bps);
+            if divergence.is_some() {
+                divergent.insert(market_id.clone());
+            }
             eligible.push(SelectedMarket {
                 market_id,
                 question: m.question,
@@ -161,36 +202,44 @@ impl MarketSelector {
                 condition_id,
                 clob_token_id,
                 microstructure_score,
+                tick_size: m.order_price_min_tick_size,
+                category: category.to_string(),
             });
         }
 
-        // Sort by microstructure score (fallback tie-breakers: volume/liquidity).
-        eligible.sort_by(|a, b| {
-            b.microstructure_score
-                .partial_cmp(&a.microstructure_score)
-                .unwrap_or(std::cmp::Ordering::Equal)
-                .then_with(|| {
-                    b.volume_24h_usd
-                        .partial_cmp(&a.volume_24h_usd)
-                        .unwrap_or(std::cmp::Ordering::Equal)
-                })
-                .then_with(|| {
-                    b.liquidity_usd
-                        .partial_cmp(&a.liquidity_usd)
-                        .unwrap_or(std::cmp::Ordering::Equal)
-                })
-        });
+        rank_eligible(&mut eligible, &divergent);
 
         let top_n = self
             .settings
             .top_n_markets
             .min(self.settings.max_markets_subscribed);
+        let mut candidates: Vec<ScannerCandidate> = eligible
+            .iter()
+            .enumerate()
+            .map(|(rank, m)| ScannerCandidate {
+                market_id: m.market_id.clone(),
+                score: m.microstructure_score,
+                volume_24h_usd: m.volume_24h_usd,
+                liquidity_usd: m.liquidity_usd,
+                spread_bps: spread_by_market.get(&m.market_id).copied().unwrap_or(0.0),
+                excluded_reason: (rank >= top_n).then(|| "rank_cutoff".to_string()),
+            })
+            .collect();
         let selected = eligible.into_iter().take(top_n).collect::<Vec<_>>();
+        candidates.extend(excluded);
+        let candidates = bound_candidates(candidates, self.settings.scanner_candidates_max);
 
         // Persist scanner/watchlist.
         self.store
             .insert_scanner_snapshot(ts, eligible_ids.len() as i64, selected.len() as i64)
             .ok();
+        self.store
+            .insert_scanner_candidates(
+                ts,
+                &candidates,
+                self.settings.scanner_candidates_retention_secs,
+            )
+            .ok();
         self.store
             .update_watchlist(
                 &selected
@@ -201,8 +250,518 @@ impl MarketSelector {
             )
             .ok();
 
+        *self.prev_selected.lock() = selected.iter().map(|m| m.market_id.clone()).collect();
         Ok(selected)
     }
+
+    /// |Gamma outcome price - live CLOB mid| when it exceeds `GAMMA_PRICE_TOLERANCE`.
+    /// `None` when the check is off or either price is unknown (feed not warmed up).
+    fn price_divergence(&self, m: &Market, market_id: &str) -> Option<f64> {
+        let tol = self.settings.gamma_price_tolerance;
+        if tol <= 0.0 {
+            return None;
+        }
+        let gamma = gamma_outcome_price(
+            m.outcome_prices.as_deref(),
+            m.clob_token_ids.as_deref(),
+            m.outcomes.as_deref(),
+        )?;
+        let clob_mid = self.feed.get(market_id).and_then(|tob| tob.mid())?;
+        let diff = (gamma - clob_mid).abs();
+        if diff <= tol {
+            return None;
+        }
+        log::warn!(
+            "scanner.price_divergence market_id={} gamma={:.4} clob_mid={:.4} diff={:.4} tol={:.4} action={}",
+            market_id,
+            gamma,
+            clob_mid,
+            diff,
+            tol,
+            self.settings.gamma_price_divergence_action
+        );
+        Some(diff)
+    }
+
+    /// Tags for an eligible market: the listing's own (`include_tag`), else one
+    /// `get_market_tags` call whose result is cached for later scans.
+    async fn resolve_tags(&self, gamma: &GammaClient, m: &Market) -> Vec<String> {
+        let listed = market_tags(m);
+        if !listed.is_empty() {
+            return listed;
+        }
+        if let Some(cached) = self.tag_cache.lock().get(&m.id) {
+            return cached.clone();
+        }
+        match gamma.get_market_tags(&m.id).await {
+            Ok(tags) => {
+                let tags = tags
+                    .iter()
+                    .flat_map(|t| [t.slug.as_deref(), t.label.as_deref()])
+                    .flatten()
+                    .map(|t| t.trim().to_lowercase())
+                    .filter(|t| !t.is_empty())
+                    .collect::<Vec<_>>();
+                self.tag_cache.lock().insert(m.id.clone(), tags.clone());
+                tags
+            }
+            Err(e) => {
+                log::debug!("scanner.tags_failed market_id={} err={e}", m.id);
+                vec![]
+            }
+        }
+    }
+
+    /// With `GAMMA_SCHEMA_DIAGNOSTICS`, warn once per Gamma field the client does not
+    /// model. Returns the newly reported field names; never fails the scan.
+    fn report_unknown_fields(&self, markets: &[Market]) -> Vec<String> {
+        if !self.settings.gamma_schema_diagnostics {
+            return vec![];
+        }
+        let mut seen = self.unknown_fields_seen.lock();
+        let mut new_fields = vec![];
+        for m in markets {
+            for field in m.unknown_fields() {
+                if seen.insert(field.clone()) {
+                    let sample = field
+                        .strip_prefix("events[].")
+                        .and_then(|k| m.events.iter().flatten().find_map(|e| e.extra.get(k)))
+                        .or_else(|| m.extra.get(&field));
+                    log::warn!(
+                        "gamma.unknown_field field={} market={} sample={}",
+                        field,
+                        m.id,
+                        sample.map(|v| v.to_string()).unwrap_or_default()
+                    );
+                    new_fields.push(field);
+                }
+            }
+        }
+        new_fields
+    }
+
+    /// Log what changed in the Gamma listing since the previous scan (first scan only primes it).
+    fn log_market_diff(&self, markets: &[Market]) {
+        let next = market_fields(markets);
+        let prev = self.prev_markets.lock().replace(next.clone());
+        let Some(prev) = prev else {
+            return;
+        };
+        let diff = diff_markets(&prev, &next);
+        if !diff.is_empty() {
+            log::info!("scanner.diff {}", diff.summary(5));
+        }
+    }
+
+    /// Flag previously-selected markets, and markets we still hold a position in, that
+    /// closed or stopped accepting orders.
+    ///
+    /// The open-markets listing drops closed markets, so any watched id missing from it
+    /// is re-fetched by id to read its resolution state.
+    async fn detect_closed_transitions(&self, gamma: &GammaClient, markets: &[Market], ts: f64) {
+        let mut prev = self.prev_selected.lock().clone();
+        match self.store.fetch_open_position_markets() {
+            Ok(ids) => prev.extend(ids),
+            Err(e) => log::warn!("open-position lookup failed: {e}"),
+        }
+        if prev.is_empty() {
+            return;
+        }
+        let mut closed: Vec<&Market> = vec![];
+        let mut seen: HashSet<&str> = HashSet::new();
+        for m in markets.iter().filter(|m| prev.contains(&m.id)) {
+            seen.insert(m.id.as_str());
+            if is_closed(m) {
+                closed.push(m);
+            }
+        }
+        let missing: Vec<String> = prev
+            .iter()
+            .filter(|id| !seen.contains(id.as_str()))
+            .cloned()
+            .collect();
+        let refetched = if missing.is_empty() {
+            vec![]
+        } else {
+            let req = GetMarketsRequest {
+                id: Some(missing),
+                ..Default::default()
+            };
+            match gamma.get_markets(req).await {
+                Ok(v) => v,
+                Err(e) => {
+                    log::warn!("closed-market refetch failed: {e}");
+                    vec![]
+                }
+            }
+        };
+        closed.extend(refetched.iter().filter(|m| is_closed(m)));
+
+        for m in closed {
+            let settle = resolution_mark(
+                m.outcome_prices.as_deref(),
+                m.clob_token_ids.as_deref(),
+                m.outcomes.as_deref(),
+                m.last_trade_price,
+            );
+            self.feed.mark_closed(&m.id, settle);
+            let detail = match settle {
+                Some(px) => format!("market_id={} settle={px:.4}", m.id),
+                None => format!("market_id={} settle=unknown", m.id),
+            };
+            self.store
+                .upsert_runtime_status("market", "warn", "closed", Some(&detail), ts)
+                .ok();
+            log::warn!("market closed {detail}");
+        }
+    }
+}
+
+// Volume/liquidity moves smaller than this fraction of the previous value are not reported.
+const DIFF_MIN_REL_MOVE: f64 = 0.10;
+
+/// The Gamma market fields the scanner diff tracks; everything else is ignored as noise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarketFields {
+    pub volume_24h_usd: f64,
+    pub liquidity_usd: f64,
+    pub active: bool,
+    pub closed: bool,
+}
+
+impl From<&Market> for MarketFields {
+    fn from(m: &Market) -> Self {
+        Self {
+            volume_24h_usd: m
+                .volume24hr_clob
+                .or(m.volume24hr)
+                .or(m.volume_num)
+                .unwrap_or(0.0),
+            liquidity_usd: m.liquidity_num.or(m.liquidity_clob).unwrap_or(0.0),
+            active: m.active.unwrap_or(true),
+            closed: m.closed.unwrap_or(false),
+        }
+    }
+}
+
+pub fn market_fields(markets: &[Market]) -> HashMap<String, MarketFields> {
+    markets
+        .iter()
+        .map(|m| (m.id.clone(), MarketFields::from(m)))
+        .collect()
+}
+
+/// Scanner-cycle changes, ids sorted. `changed` lists the tracked fields that moved.
+#[derive(Debug, Default, PartialEq)]
+pub struct MarketDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<(String, Vec<&'static str>)>,
+}
+
+impl MarketDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// One-line summary with at most `max_ids` examples per bucket.
+    pub fn summary(&self, max_ids: usize) -> String {
+        let ids = |v: &[String]| {
+            v.iter()
+                .take(max_ids)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let changed = self
+            .changed
+            .iter()
+            .take(max_ids)
+            .map(|(id, fields)| format!("{id}:{}", fields.join("+")))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "added={} [{}] removed={} [{}] changed={} [{}]",
+            self.added.len(),
+            ids(&self.added),
+            self.removed.len(),
+            ids(&self.removed),
+            self.changed.len(),
+            changed
+        )
+    }
+}
+
+fn moved(prev: f64, next: f64) -> bool {
+    (next - prev).abs() > DIFF_MIN_REL_MOVE * prev.abs().max(1.0)
+}
+
+/// Added/removed markets and tracked-field changes between two scanner snapshots.
+pub fn diff_markets(
+    prev: &HashMap<String, MarketFields>,
+    next: &HashMap<String, MarketFields>,
+) -> MarketDiff {
+    let mut diff = MarketDiff::default();
+    for (id, n) in next {
+        let Some(p) = prev.get(id) else {
+            diff.added.push(id.clone());
+            continue;
+        };
+        let mut fields = vec![];
+        if moved(p.volume_24h_usd, n.volume_24h_usd) {
+            fields.push("volume");
+        }
+        if moved(p.liquidity_usd, n.liquidity_usd) {
+            fields.push("liquidity");
+        }
+        if p.active != n.active {
+            fields.push("active");
+        }
+        if p.closed != n.closed {
+            fields.push("closed");
+        }
+        if !fields.is_empty() {
+            diff.changed.push((id.clone(), fields));
+        }
+    }
+    diff.removed = prev
+        .keys()
+        .filter(|id| !next.contains_key(*id))
+        .cloned()
+        .collect();
+    diff.added.sort();
+    diff.removed.sort();
+    diff.changed.sort();
+    diff
+}
+
+/// Selection thresholds for one market: the globals, with volume/liquidity/max-spread taken
+/// from the first `SELECTOR_TAG_OVERRIDES` entry (in config order) whose tag the market carries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Thresholds {
+    min_24h_volume_usd: f64,
+    min_liquidity_usd: f64,
+    min_spread_bps: f64,
+    max_spread_bps: f64,
+    min_updates_min: f64,
+}
+
+impl Thresholds {
+    fn resolve(settings: &Settings, tags: &[String]) -> Self {
+        let mut th = Self {
+            min_24h_volume_usd: settings.min_24h_volume_usd,
+            min_liquidity_usd: settings.min_liquidity_usd,
+            min_spread_bps: settings.min_spread_bps,
+            max_spread_bps: settings.max_spread_bps,
+            min_updates_min: settings.min_updates_min,
+        };
+        if let Some(o) = settings
+            .selector_tag_overrides
+            .iter()
+            .find(|o| tags.contains(&o.tag))
+        {
+            th.min_24h_volume_usd = o.min_24h_volume_usd.unwrap_or(th.min_24h_volume_usd);
+            th.min_liquidity_usd = o.min_liquidity_usd.unwrap_or(th.min_liquidity_usd);
+            th.max_spread_bps = o.max_spread_bps.unwrap_or(th.max_spread_bps);
+        }
+        th
+    }
+
+    /// Why a market fails selection, if it does. Spread and update-rate checks only apply
+    /// once the feed has metrics; before that the market may seed subscriptions.
+    fn exclusion_reason(
+        &self,
+        volume_24h_usd: f64,
+        liquidity_usd: f64,
+        has_token: bool,
+        spread_bps: f64,
+        updates_per_min: f64,
+    ) -> Option<&'static str> {
+        let has_metrics = updates_per_min > 0.0 && spread_bps > 0.0;
+        if volume_24h_usd < self.min_24h_volume_usd {
+            Some("low_volume")
+        } else if liquidity_usd < self.min_liquidity_usd {
+            Some("low_liquidity")
+        } else if !has_token {
+            // Can't subscribe/trade without a token id.
+            Some("no_token")
+        } else if has_metrics && spread_bps < self.min_spread_bps {
+            Some("low_spread")
+        } else if has_metrics && self.max_spread_bps > 0.0 && spread_bps > self.max_spread_bps {
+            Some("wide_spread")
+        } else if has_metrics && updates_per_min < self.min_updates_min {
+            Some("low_updates")
+        } else {
+            None
+        }
+    }
+}
+
+/// Lowercased tag slugs and labels (plus the legacy category) used to match tag overrides.
+fn market_tags(m: &Market) -> Vec<String> {
+    m.tags
+        .iter()
+        .flatten()
+        .flat_map(|t| [t.slug.as_deref(), t.label.as_deref()])
+        .flatten()
+        .chain(m.category.as_deref())
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+pub const CATEGORY_OTHER: &str = "other";
+
+/// Tag keywords per reporting category; the first category with a matching tag wins.
+const CATEGORY_KEYWORDS: &[(&str, &[&str])] = &[
+    (
+        "crypto",
+        &[
+            "crypto",
+            "bitcoin",
+            "btc",
+            "ethereum",
+            "eth",
+            "solana",
+            "sol",
+            "xrp",
+            "memecoins",
+        ],
+    ),
+    (
+        "sports",
+        &[
+            "sports", "nba", "nfl", "mlb", "nhl", "soccer", "football", "tennis", "golf", "ufc",
+            "f1", "boxing", "cricket", "esports",
+        ],
+    ),
+    (
+        "politics",
+        &[
+            "politics",
+            "elections",
+            "election",
+            "us-politics",
+            "geopolitics",
+            "trump",
+            "congress",
+            "senate",
+            "president",
+        ],
+    ),
+];
+
+/// Map lowercased tag slugs/labels to a reporting category (`other` when nothing matches).
+pub fn categorize(tags: &[String]) -> &'static str {
+    CATEGORY_KEYWORDS
+        .iter()
+        .find(|(_, keywords)| tags.iter().any(|t| keywords.contains(&t.as_str())))
+        .map(|(category, _)| *category)
+        .unwrap_or(CATEGORY_OTHER)
+}
+
+/// The scanner's Gamma listing: open markets whose CLOB book is taking orders, so
+/// frozen books never reach the subscription set.
+fn open_markets_request() -> GetMarketsRequest<'static> {
+    GetMarketsRequest {
+        limit: Some(1000),
+        offset: Some(0),
+        closed: Some(false),
+        include_tag: Some(true),
+        enable_order_book: Some(true),
+        accepting_orders: Some(true),
+        ..Default::default()
+    }
+}
+
+/// Active, open, and (when Gamma says so) on an order book that accepts orders.
+/// Re-checked locally in case the listing filter is ignored.
+fn is_quotable(m: &Market) -> bool {
+    m.active.unwrap_or(true)
+        && !m.closed.unwrap_or(false)
+        && m.enable_order_book.unwrap_or(true)
+        && m.accepting_orders.unwrap_or(true)
+}
+
+fn is_closed(m: &Market) -> bool {
+    m.closed.unwrap_or(false) || !m.active.unwrap_or(true) || !m.accepting_orders.unwrap_or(true)
+}
+
+/// Mark for a closed market's primary token: the resolved outcome (snapped to 0/1),
+/// otherwise the last trade. `None` when Gamma gives neither.
+fn resolution_mark(
+    outcome_prices: Option<&str>,
+    clob_token_ids: Option<&str>,
+    outcomes: Option<&str>,
+    last_trade_price: Option<f64>,
+) -> Option<f64> {
+    let idx = primary_token_index(clob_token_ids, outcomes).unwrap_or(0);
+    let resolved = outcome_prices
+        .map(parse_listish)
+        .and_then(|px| px.get(idx).and_then(|x| x.parse::<f64>().ok()))
+        .and_then(|px| {
+            if px >= 0.99 {
+                Some(1.0)
+            } else if px <= 0.01 {
+                Some(0.0)
+            } else {
+                None
+            }
+        });
+    resolved.or(last_trade_price.filter(|px| px.is_finite()))
+}
+
+/// Gamma `outcomePrices` entry for the primary token (the price Gamma believes it trades at).
+fn gamma_outcome_price(
+    outcome_prices: Option<&str>,
+    clob_token_ids: Option<&str>,
+    outcomes: Option<&str>,
+) -> Option<f64> {
+    let idx = primary_token_index(clob_token_ids, outcomes).unwrap_or(0);
+    outcome_prices
+        .map(parse_listish)
+        .and_then(|px| px.get(idx).and_then(|x| x.parse::<f64>().ok()))
+        .filter(|px| px.is_finite())
+}
+
+/// Sort by microstructure score (fallback tie-breakers: volume/liquidity), with markets whose
+/// Gamma price diverges from the live book ranked after every healthy market.
+fn rank_eligible(eligible: &mut [SelectedMarket], divergent: &HashSet<String>) {
+    eligible.sort_by(|a, b| {
+        divergent
+            .contains(&a.market_id)
+            .cmp(&divergent.contains(&b.market_id))
+            .then_with(|| {
+                b.microstructure_score
+                    .partial_cmp(&a.microstructure_score)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .then_with(|| {
+                b.volume_24h_usd
+                    .partial_cmp(&a.volume_24h_usd)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .then_with(|| {
+                b.liquidity_usd
+                    .partial_cmp(&a.liquidity_usd)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+    });
+}
+
+/// Keep every selected market, then fill up to `max_rows` with the best-scoring near-misses.
+fn bound_candidates(candidates: Vec<ScannerCandidate>, max_rows: usize) -> Vec<ScannerCandidate> {
+    let (mut out, mut near): (Vec<_>, Vec<_>) = candidates
+        .into_iter()
+        .partition(|c| c.excluded_reason.is_none());
+    near.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| b.volume_24h_usd.total_cmp(&a.volume_24h_usd))
+    });
+    let room = max_rows.saturating_sub(out.len());
+    out.extend(near.into_iter().take(room));
+    out
 }
 
 fn parse_ts_rfc3339(s: &str) -> Option<f64> {
@@ -222,17 +781,262 @@ fn parse_listish(s: &str) -> Vec<String> {
 }
 
 fn pick_primary_token_id(clob_token_ids: Option<&str>, outcomes: Option<&str>) -> Option<String> {
+    let toks = clob_token_ids.map(parse_listish).unwrap_or_default();
+    primary_token_index(clob_token_ids, outcomes).map(|i| toks[i].clone())
+}
+
+/// Index of the token we trade: the "Yes" outcome when labelled, else the first token.
+fn primary_token_index(clob_token_ids: Option<&str>, outcomes: Option<&str>) -> Option<usize> {
     let toks = clob_token_ids.map(parse_listish).unwrap_or_default();
     if toks.is_empty() {
         return None;
     }
     let outs = outcomes.map(parse_listish).unwrap_or_default();
     if !outs.is_empty() && outs.len() == toks.len() {
-        for (i, o) in outs.iter().enumerate() {
-            if o.trim().eq_ignore_ascii_case("yes") {
-                return Some(toks[i].clone());
-            }
+        if let Some(i) = outs
+            .iter()
+            .position(|o| o.trim().eq_ignore_ascii_case("yes"))
+        {
+            return Some(i);
         }
     }
-    Some(toks[0].clone())
+    Some(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::temp_store;
+
+    #[test]
+    fn resolution_mark_snaps_resolved_outcome_else_last_trade() {
+        let toks = Some(r#"["t_no","t_yes"]"#);
+        let outs = Some(r#"["No","Yes"]"#);
+        assert_eq!(
+            resolution_mark(Some(r#"["0","1"]"#), toks, outs, Some(0.4)),
+            Some(1.0)
+        );
+        assert_eq!(
+            resolution_mark(Some(r#"["0.995","0.005"]"#), toks, outs, None),
+            Some(0.0)
+        );
+        // Not yet resolved: fall back to the last trade.
+        assert_eq!(
+            resolution_mark(Some(r#"["0.55","0.45"]"#), toks, outs, Some(0.47)),
+            Some(0.47)
+        );
+        assert_eq!(resolution_mark(None, toks, outs, None), None);
+    }
+
+    fn gamma_market(id: &str, volume: f64, liquidity: f64, closed: bool) -> Market {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "question": "q",
+            "volume24hr": volume,
+            "liquidityNum": liquidity,
+            "active": true,
+            "closed": closed,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn scanner_only_lists_markets_accepting_orders() {
+        let req = open_markets_request();
+        assert_eq!(req.accepting_orders, Some(true));
+        assert_eq!(req.enable_order_book, Some(true));
+
+        let with = |key: &str, on: bool| -> Market {
+            serde_json::from_value(serde_json::json!({
+                "id": "1",
+                "active": true,
+                "closed": false,
+                key: on,
+            }))
+            .unwrap()
+        };
+        assert!(is_quotable(&gamma_market("1", 10_000.0, 5_000.0, false)));
+        assert!(is_quotable(&with("acceptingOrders", true)));
+        assert!(!is_quotable(&with("acceptingOrders", false)));
+        assert!(!is_quotable(&with("enableOrderBook", false)));
+        assert!(!is_quotable(&gamma_market("1", 10_000.0, 5_000.0, true)));
+    }
+
+    #[test]
+    fn diff_markets_reports_added_removed_and_tracked_changes() {
+        let prev = market_fields(&[
+            gamma_market("1", 10_000.0, 5_000.0, false),
+            gamma_market("2", 10_000.0, 5_000.0, false),
+            gamma_market("3", 10_000.0, 5_000.0, false),
+            gamma_market("4", 10_000.0, 5_000.0, false),
+        ]);
+        let mut next_markets = vec![
+            // Small drift is noise.
+            gamma_market("1", 10_300.0, 5_100.0, false),
+            gamma_market("2", 25_000.0, 5_000.0, false),
+            gamma_market("3", 10_000.0, 1_000.0, true),
+            gamma_market("5", 1.0, 1.0, false),
+        ];
+        // Untracked fields never count as a change.
+        next_markets[0].question = Some("reworded".to_string());
+        let next = market_fields(&next_markets);
+
+        let d = diff_markets(&prev, &next);
+        assert_eq!(d.added, ["5"]);
+        assert_eq!(d.removed, ["4"]);
+        assert_eq!(
+            d.changed,
+            [
+                ("2".to_string(), vec!["volume"]),
+                ("3".to_string(), vec!["liquidity", "closed"]),
+            ]
+        );
+        assert_eq!(
+            d.summary(5),
+            "added=1 [5] removed=1 [4] changed=2 [2:volume,3:liquidity+closed]"
+        );
+        assert!(diff_markets(&next, &next).is_empty());
+    }
+
+    #[test]
+    fn tag_override_admits_market_global_threshold_rejects() {
+        let mut settings = Settings::load().unwrap();
+        settings.min_24h_volume_usd = 10_000.0;
+        settings.min_liquidity_usd = 20_000.0;
+        settings.max_spread_bps = 300.0;
+        settings.selector_tag_overrides = vec![
+            crate::config::TagOverride {
+                tag: "sports".to_string(),
+                min_24h_volume_usd: Some(1_000.0),
+                min_liquidity_usd: Some(2_000.0),
+                max_spread_bps: Some(800.0),
+            },
+            crate::config::TagOverride {
+                tag: "nba".to_string(),
+                min_24h_volume_usd: Some(50_000.0),
+                min_liquidity_usd: None,
+                max_spread_bps: None,
+            },
+        ];
+        let (vol, liq, spread, upm) = (5_000.0, 5_000.0, 500.0, settings.min_updates_min + 1.0);
+
+        let global = Thresholds::resolve(&settings, &["politics".to_string()]);
+        assert_eq!(
+            global.exclusion_reason(vol, liq, true, spread, upm),
+            Some("low_volume")
+        );
+        assert_eq!(
+            global.exclusion_reason(50_000.0, 50_000.0, true, spread, upm),
+            Some("wide_spread")
+        );
+
+        let sports = Thresholds::resolve(&settings, &["sports".to_string()]);
+        assert_eq!(sports.exclusion_reason(vol, liq, true, spread, upm), None);
+
+        // Several matching tags: the first override in config order wins, whatever the tag order.
+        let both = Thresholds::resolve(&settings, &["nba".to_string(), "sports".to_string()]);
+        assert_eq!(both, sports);
+    }
+
+    #[test]
+    fn categorize_maps_gamma_tags_to_reporting_category() {
+        let m: Market = serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "tags": [
+                {"id": "2", "label": "Politics", "slug": "politics"},
+                {"id": "3", "label": "Bitcoin", "slug": "bitcoin"},
+            ],
+        }))
+        .unwrap();
+        // Crypto is checked first, so a crypto-policy market reports as crypto.
+        assert_eq!(categorize(&market_tags(&m)), "crypto");
+
+        let tags = |ts: &[&str]| ts.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(categorize(&tags(&["nba", "games"])), "sports");
+        assert_eq!(categorize(&tags(&["us-politics"])), "politics");
+        assert_eq!(categorize(&tags(&["weather"])), CATEGORY_OTHER);
+        assert_eq!(categorize(&[]), CATEGORY_OTHER);
+    }
+
+    #[test]
+    fn divergent_gamma_price_is_ranked_last() {
+        let toks = Some(r#"["t_no","t_yes"]"#);
+        let outs = Some(r#"["No","Yes"]"#);
+        assert_eq!(
+            gamma_outcome_price(Some(r#"["0.3","0.7"]"#), toks, outs),
+            Some(0.7)
+        );
+        assert_eq!(gamma_outcome_price(None, toks, outs), None);
+
+        let market = |id: &str, score: f64| SelectedMarket {
+            market_id: id.to_string(),
+            question: None,
+            event_id: None,
+            end_ts: None,
+            volume_24h_usd: 0.0,
+            liquidity_usd: 0.0,
+            condition_id: None,
+            clob_token_id: None,
+            microstructure_score: score,
+            tick_size: None,
+            category: CATEGORY_OTHER.to_string(),
+        };
+        let mut eligible = vec![market("a", 1.0), market("stale", 9.0), market("b", 5.0)];
+        let divergent = HashSet::from(["stale".to_string()]);
+        rank_eligible(&mut eligible, &divergent);
+        let order: Vec<&str> = eligible.iter().map(|m| m.market_id.as_str()).collect();
+        assert_eq!(order, vec!["b", "a", "stale"]);
+    }
+
+    #[test]
+    fn price_divergence_compares_gamma_price_with_clob_mid() {
+        let mut settings = Settings::load().unwrap();
+        settings.gamma_price_tolerance = 0.05;
+        let store = temp_store();
+        let feed = FeedState::new();
+        let mut tob = crate::feed_handler::Tob::empty(0.0);
+        tob.best_bid = Some(0.40);
+        tob.best_ask = Some(0.42);
+        feed.upsert("1", tob);
+        let selector = MarketSelector::new(settings, store, feed);
+
+        let priced = |yes: &str| -> Market {
+            serde_json::from_value(serde_json::json!({
+                "id": "1",
+                "outcomes": r#"["Yes","No"]"#,
+                "outcomePrices": format!(r#"["{yes}","0"]"#),
+                "clobTokenIds": r#"["t_yes","t_no"]"#,
+            }))
+            .unwrap()
+        };
+        // Mid 0.41: 0.44 is within 5 points, 0.70 is 29 points off.
+        assert_eq!(selector.price_divergence(&priced("0.44"), "1"), None);
+        let diff = selector.price_divergence(&priced("0.70"), "1").unwrap();
+        assert!((diff - 0.29).abs() < 1e-9);
+        // No live book yet: nothing to compare against.
+        assert_eq!(selector.price_divergence(&priced("0.70"), "2"), None);
+    }
+
+    #[test]
+    fn unknown_gamma_fields_are_reported_once_in_diagnostic_mode() {
+        let mut settings = Settings::load().unwrap();
+        let store = temp_store();
+        let markets: Vec<Market> = serde_json::from_value(serde_json::json!([{
+            "id": "1",
+            "question": "q",
+            "volumeNum": 10.0,
+            "brandNewField": {"nested": true},
+        }]))
+        .unwrap();
+
+        settings.gamma_schema_diagnostics = false;
+        let lenient = MarketSelector::new(settings.clone(), store.clone(), FeedState::new());
+        assert!(lenient.report_unknown_fields(&markets).is_empty());
+
+        settings.gamma_schema_diagnostics = true;
+        let diag = MarketSelector::new(settings, store, FeedState::new());
+        assert_eq!(diag.report_unknown_fields(&markets), vec!["brandNewField"]);
+        // Already reported: later scans stay quiet.
+        assert!(diag.report_unknown_fields(&markets).is_empty());
+    }
 }
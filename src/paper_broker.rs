@@ -8,9 +8,11 @@ use uuid::Uuid;
 
 use crate::{
     config::Settings,
-    feed_handler::Tob,
+    feed_handler::{BookLevels, Tob, TradePrint},
+    fill_model::{fill_model_for, FillInput, FillModel, MakerTouch},
+    health::HealthState,
     store::SqliteStore,
-    utils::{now_ts, poisson_sample},
+    utils::{now_ts, snap_to_tick, RateTracker},
 };
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -36,10 +38,17 @@ pub struct Order {
     pub price: f64,
     pub size: f64,
     pub created_ts: f64,
-    pub status: String, // open|cancelled|filled|rejected
+    pub status: String, // open|cancelled|expired|filled|rejected|held
     pub filled_size: f64,
     pub last_event_ts: f64,
     pub meta: serde_json::Value,
+    /// GTD expiration (broker clock); `None` rests until cancelled (GTC).
+    pub expires_ts: Option<f64>,
+    /// Broker-clock ts of the latest passive fill (`PAPER_MIN_SECS_BETWEEN_FILLS`).
+    pub last_fill_ts: Option<f64>,
+    /// Size resting ahead of us at our price (`PAPER_FILL_QUEUE_MODEL`); trade prints
+    /// work it off before any of ours fills. `None` until the broker has seen a book.
+    pub queue_ahead: Option<f64>,
 }
 
 #[derive(Debug, Clone)]
@@ -67,36 +76,311 @@ pub struct PaperBroker {
     pub orders: HashMap<String, Order>,
     pub positions: HashMap<String, Position>, // by market_id
     last_sim_ts: HashMap<String, f64>,
+    // Latest strategy fair per market, stamped onto fills for markout analysis.
+    fair_by_market: HashMap<String, f64>,
+    // Live tick per market (else PRICE_TICK); order and fill prices are stored on this grid.
+    tick_by_market: HashMap<String, f64>,
+    // Replay clock; when set, replaces wall-clock time for order/fill timestamps.
+    clock: Option<f64>,
+    // Safe-mode gate (SAFE_MODE=1; None = no health source wired, nothing gated).
+    health: Option<HealthState>,
+    safe_mode_blocked: bool,
+    // Cancels in flight: order_id -> ts at which the cancel lands (SIMULATED_LATENCY_MS).
+    pending_cancels: HashMap<String, f64>,
+    // Sub-`MIN_FILL_NOTIONAL_USD` partials not yet recorded, by order_id.
+    dust_fills: HashMap<String, DustFill>,
+    // Passive fill simulator (`PAPER_FILL_MODEL`).
+    fill_model: Box<dyn FillModel>,
+    // `PAPER_FILL_QUEUE_MODEL`: latest book and price levels per market (queue at
+    // placement) and the trade prints not yet run against resting orders.
+    book_by_market: HashMap<String, Tob>,
+    levels_by_market: HashMap<String, BookLevels>,
+    pending_trades: HashMap<String, Vec<TradePrint>>,
     pub counters: BrokerCounters,
+    // Recent fill/cancel rates (event ts on the broker clock), for eval logs.
+    fill_rate: RateTracker,
+    cancel_rate: RateTracker,
+}
+
+/// Decay window for the broker's fill/cancel rates.
+const ACTIVITY_RATE_WINDOW_SECS: f64 = 600.0;
+
+/// Size resting ahead of a new order at `price`: none if it improves its side's touch,
+/// else from the level book the size at better prices plus the size already at
+/// `price`; without a level book, the touch size.
+fn resting_ahead(tob: &Tob, levels: Option<&BookLevels>, side: Side, price: f64) -> f64 {
+    let is_bid = side == Side::Buy;
+    let touch = if is_bid { tob.best_bid } else { tob.best_ask };
+    let improves = touch.is_none_or(|t| match side {
+        Side::Buy => price > t + 1e-9,
+        Side::Sell => price < t - 1e-9,
+    });
+    if improves {
+        return 0.0;
+    }
+    let ladder = levels
+        .map(|b| if is_bid { &b.bids } else { &b.asks })
+        .filter(|l| !l.is_empty());
+    match ladder {
+        Some(ladder) => ladder
+            .iter()
+            .filter(|&&(px, _)| match side {
+                Side::Buy => px >= price - 1e-9,
+                Side::Sell => px <= price + 1e-9,
+            })
+            .map(|&(_, sz)| sz.max(0.0))
+            .sum(),
+        None => tob.touch_size(is_bid).max(0.0),
+    }
+}
+
+/// Positions smaller than this count as flat.
+pub const FLAT_QTY_EPS: f64 = 1e-9;
+
+/// Passive partials of one order awaiting a fill record (sizes summed, ts of the latest).
+struct DustFill {
+    fill: Fill,
+    // Maker fee of the partials so far (negative = rebate).
+    fee: f64,
+    // Fill meta as of the first partial (fair at fill for markouts).
+    meta: serde_json::Value,
+    partials: u64,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct BrokerCounters {
     pub orders_placed: u64,
     pub orders_cancelled: u64,
+    /// GTD orders pulled locally at their expiration.
+    pub orders_expired: u64,
     pub fills: u64,
     pub filled_qty: f64,
     pub rejected_orders: u64,
     pub cancel_failures: u64,
+    pub held_orders: u64,
+    /// Dust partials merged into another fill record instead of recorded on their own.
+    pub aggregated_partials: u64,
+    /// IOCs that skipped our own resting orders in their price range.
+    pub self_trades_prevented: u64,
+    /// Fill opportunities skipped because the order filled less than
+    /// `PAPER_MIN_SECS_BETWEEN_FILLS` ago.
+    pub fills_throttled: u64,
+    /// Placements rejected at `MAX_OPEN_ORDERS_GLOBAL` (also counted in `rejected_orders`).
+    pub global_cap_rejections: u64,
 }
 
 impl PaperBroker {
     pub fn new(settings: Settings, store: SqliteStore) -> Self {
+        Self::with_seed(settings, store, rand::random())
+    }
+
+    /// Broker with a fixed RNG seed, so fault/fill simulation is reproducible.
+    pub fn with_seed(settings: Settings, store: SqliteStore, seed: u64) -> Self {
+        // Settings::validate rejects unknown names; fall back rather than panic here.
+        let fill_model =
+            fill_model_for(&settings.paper_fill_model).unwrap_or_else(|_| Box::new(MakerTouch));
         Self {
             settings,
             store,
-            rng: SmallRng::seed_from_u64(rand::random()),
+            rng: SmallRng::seed_from_u64(seed),
             orders: HashMap::new(),
             positions: HashMap::new(),
             last_sim_ts: HashMap::new(),
+            fair_by_market: HashMap::new(),
+            tick_by_market: HashMap::new(),
+            clock: None,
+            health: None,
+            safe_mode_blocked: false,
+            pending_cancels: HashMap::new(),
+            dust_fills: HashMap::new(),
+            fill_model,
+            book_by_market: HashMap::new(),
+            levels_by_market: HashMap::new(),
+            pending_trades: HashMap::new(),
             counters: BrokerCounters::default(),
+            fill_rate: RateTracker::new(ACTIVITY_RATE_WINDOW_SECS),
+            cancel_rate: RateTracker::new(ACTIVITY_RATE_WINDOW_SECS),
+        }
+    }
+
+    /// Swap the passive fill simulator (per-order model state starts over).
+    #[cfg(test)]
+    pub fn set_fill_model(&mut self, model: Box<dyn FillModel>) {
+        self.fill_model = model;
+    }
+
+    /// Drive the broker from an external clock (tape replay) instead of wall time.
+    pub fn set_clock(&mut self, ts: f64) {
+        self.clock = Some(ts);
+    }
+
+    /// Recorded fills per hour, time-decayed to the broker clock.
+    pub fn fill_rate_per_hour(&self) -> f64 {
+        self.fill_rate.per_min(self.now()) * 60.0
+    }
+
+    /// Cancels per hour, time-decayed to the broker clock.
+    pub fn cancel_rate_per_hour(&self) -> f64 {
+        self.cancel_rate.per_min(self.now()) * 60.0
+    }
+
+    fn now(&self) -> f64 {
+        self.clock.unwrap_or_else(now_ts)
+    }
+
+    fn latency_secs(&self) -> f64 {
+        self.settings.simulated_latency_ms.max(0.0) / 1000.0
+    }
+
+    /// True while a cancel for `order_id` has been sent but not yet landed.
+    pub fn cancel_pending(&self, order_id: &str) -> bool {
+        self.pending_cancels.contains_key(order_id)
+    }
+
+    /// Apply every in-flight cancel that has landed by `ts`.
+    fn settle_pending_cancels(&mut self, ts: f64) -> Result<()> {
+        let due: Vec<(String, f64)> = self
+            .pending_cancels
+            .iter()
+            .filter(|(_, eff)| **eff <= ts)
+            .map(|(id, eff)| (id.clone(), *eff))
+            .collect();
+        for (id, eff) in due {
+            self.pending_cancels.remove(&id);
+            self.finish_cancel(&id, eff)?;
+        }
+        Ok(())
+    }
+
+    /// Land all in-flight cancels now (shutdown: no more fills are simulated).
+    pub fn flush_pending_cancels(&mut self) -> Result<()> {
+        self.settle_pending_cancels(f64::INFINITY)
+    }
+
+    /// Attach the health checker state used by the safe-mode gate.
+    pub fn set_health_gate(&mut self, health: HealthState) {
+        self.health = Some(health);
+    }
+
+    /// With `SAFE_MODE=1`, orders require a passing health check within
+    /// `HEALTH_MAX_AGE_SECS`; without a health source attached (replay) nothing is gated.
+    /// Status is written only on block/unblock transitions; on recovery the held orders
+    /// are cancelled, since the strategy requotes at current prices rather than sending
+    /// stale ones.
+    fn safe_mode_blocks(&mut self, ts: f64) -> Result<bool> {
+        let Some(health) = self.health.as_ref().filter(|_| self.settings.safe_mode) else {
+            return Ok(false);
+        };
+        let fresh = health.is_fresh(ts, self.settings.health_max_age_secs);
+        if fresh == self.safe_mode_blocked {
+            self.safe_mode_blocked = !fresh;
+            let (level, message) = if fresh {
+                ("ok", "health recovered; orders released")
+            } else {
+                ("error", "health stale; holding orders")
+            };
+            self.store
+                .upsert_runtime_status("safe_mode", level, message, None, ts)
+                .ok();
+            if fresh {
+                self.cancel_held_orders(ts)?;
+            }
+        }
+        Ok(!fresh)
+    }
+
+    fn cancel_held_orders(&mut self, ts: f64) -> Result<()> {
+        for o in self.orders.values_mut().filter(|o| o.status == "held") {
+            o.status = "cancelled".to_string();
+            o.last_event_ts = ts;
+            self.store
+                .update_order_status(&o.order_id, &o.status, Some(o.filled_size), ts)?;
         }
+        Ok(())
     }
 
     pub fn position_qty(&self, market_id: &str) -> f64 {
         self.positions.get(market_id).map(|p| p.qty).unwrap_or(0.0)
     }
 
+    /// Distinct markets with a non-flat position.
+    pub fn markets_with_inventory(&self) -> usize {
+        self.positions
+            .values()
+            .filter(|p| p.qty.abs() > FLAT_QTY_EPS)
+            .count()
+    }
+
+    /// Record the strategy fair for a market so subsequent fills carry `fair_at_fill`.
+    pub fn note_fair(&mut self, market_id: &str, fair: f64) {
+        if fair.is_finite() {
+            self.fair_by_market.insert(market_id.to_string(), fair);
+        }
+    }
+
+    /// Gross and net USD exposure across all markets, marked at the latest noted fair
+    /// (the average entry price for markets without one).
+    pub fn exposure_usd(&self) -> (f64, f64) {
+        let mut gross = 0.0;
+        let mut net = 0.0;
+        for (market_id, p) in &self.positions {
+            let mark = self
+                .fair_by_market
+                .get(market_id)
+                .copied()
+                .unwrap_or(p.avg_price);
+            gross += (p.qty * mark).abs();
+            net += p.qty * mark;
+        }
+        (gross, net)
+    }
+
+    pub fn note_tick(&mut self, market_id: &str, tick: f64) {
+        if tick.is_finite() && tick > 0.0 {
+            self.tick_by_market.insert(market_id.to_string(), tick);
+        }
+    }
+
+    /// Price levels of `market_id`'s book, used to seed the queue ahead of new orders
+    /// (`PAPER_FILL_QUEUE_MODEL`).
+    pub fn note_book(&mut self, market_id: &str, levels: BookLevels) {
+        self.levels_by_market.insert(market_id.to_string(), levels);
+    }
+
+    /// Public trade prints for `market_id`, run against its resting orders on the next
+    /// fill simulation (`PAPER_FILL_QUEUE_MODEL`).
+    pub fn note_trades(&mut self, market_id: &str, prints: &[TradePrint]) {
+        if prints.is_empty() {
+            return;
+        }
+        self.pending_trades
+            .entry(market_id.to_string())
+            .or_default()
+            .extend_from_slice(prints);
+    }
+
+    /// `price` on the market's tick grid, without float noise. Off-grid prices move to the
+    /// passive side (buys down, sells up), so snapping never makes an order more aggressive
+    /// than asked; on-grid prices only lose representation error.
+    fn grid_price(&self, market_id: &str, side: Side, price: f64) -> f64 {
+        snap_to_tick(price, self.tick_for(market_id), side == Side::Sell)
+    }
+
+    /// The market's own tick (see [`Self::note_tick`]), else `PRICE_TICK`.
+    fn tick_for(&self, market_id: &str) -> f64 {
+        self.tick_by_market
+            .get(market_id)
+            .copied()
+            .unwrap_or(self.settings.price_tick)
+    }
+
+    fn fill_meta(&self, market_id: &str, mut meta: serde_json::Value) -> serde_json::Value {
+        if let Some(fair) = self.fair_by_market.get(market_id) {
+            meta["fair_at_fill"] = json!(fair);
+        }
+        meta
+    }
+
     pub fn realized_pnl_total(&self) -> f64 {
         self.positions.values().map(|p| p.realized_pnl).sum()
     }
@@ -123,10 +407,99 @@ impl PaperBroker {
         size: f64,
         strategy: &str,
     ) -> Result<String> {
-        let ts = now_ts();
-        // Random "server faults" and non-atomic fails.
-        if self.rng.random::<f64>() < self.settings.paper_fault_rate {
-            self.counters.rejected_orders += 1;
+        self.place_order(market_id, side, price, size, strategy, None)
+    }
+
+    /// Good-til-date limit order: expired locally once the broker clock reaches
+    /// `expires_ts` (see [`Self::expire_orders`]).
+    pub fn place_gtd(
+        &mut self,
+        market_id: &str,
+        side: Side,
+        price: f64,
+        size: f64,
+        strategy: &str,
+        expires_ts: f64,
+    ) -> Result<String> {
+        self.place_order(market_id, side, price, size, strategy, Some(expires_ts))
+    }
+
+    /// Resting orders across all markets.
+    pub fn open_order_count(&self) -> usize {
+        self.orders.values().filter(|o| o.status == "open").count()
+    }
+
+    /// Placements left before `MAX_OPEN_ORDERS_GLOBAL` (`usize::MAX` when off).
+    pub fn open_order_capacity(&self) -> usize {
+        match self.settings.max_open_orders_global {
+            0 => usize::MAX,
+            cap => cap.saturating_sub(self.open_order_count()),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record_rejected(
+        &mut self,
+        market_id: &str,
+        side: Side,
+        price: f64,
+        size: f64,
+        ts: f64,
+        expires_ts: Option<f64>,
+        meta: serde_json::Value,
+    ) -> Result<String> {
+        self.counters.rejected_orders += 1;
+        let oid = Uuid::new_v4().to_string();
+        let o = Order {
+            order_id: oid.clone(),
+            market_id: market_id.to_string(),
+            side,
+            price,
+            size,
+            created_ts: ts,
+            status: "rejected".to_string(),
+            filled_size: 0.0,
+            last_event_ts: ts,
+            expires_ts,
+            last_fill_ts: None,
+            queue_ahead: None,
+            meta,
+        };
+        self.store.insert_order(
+            &o.order_id,
+            &o.market_id,
+            o.side.as_str(),
+            o.price,
+            o.size,
+            o.created_ts,
+            &o.status,
+            o.filled_size,
+            &o.meta,
+        )?;
+        self.orders.insert(oid.clone(), o);
+        Ok(oid)
+    }
+
+    fn place_order(
+        &mut self,
+        market_id: &str,
+        side: Side,
+        price: f64,
+        size: f64,
+        strategy: &str,
+        expires_ts: Option<f64>,
+    ) -> Result<String> {
+        let ts = self.now();
+        let price = self.grid_price(market_id, side, price);
+        let with_tif = |mut meta: serde_json::Value| {
+            if let Some(exp) = expires_ts {
+                meta["tif"] = json!("gtd");
+                meta["expires_ts"] = json!(exp);
+            }
+            meta
+        };
+        if self.safe_mode_blocks(ts)? {
+            self.counters.held_orders += 1;
             let oid = Uuid::new_v4().to_string();
             let o = Order {
                 order_id: oid.clone(),
@@ -135,10 +508,13 @@ impl PaperBroker {
                 price,
                 size,
                 created_ts: ts,
-                status: "rejected".to_string(),
+                status: "held".to_string(),
                 filled_size: 0.0,
                 last_event_ts: ts,
-                meta: json!({"strategy": strategy, "reason": "paper_fault"}),
+                expires_ts,
+                last_fill_ts: None,
+                queue_ahead: None,
+                meta: with_tif(json!({"strategy": strategy, "reason": "safe_mode"})),
             };
             self.store.insert_order(
                 &o.order_id,
@@ -154,9 +530,54 @@ impl PaperBroker {
             self.orders.insert(oid.clone(), o);
             return Ok(oid);
         }
+        // Global resting-order ceiling. The broker is the only writer of `orders`, so
+        // counting them here is exact however many placements a tick makes.
+        let cap = self.settings.max_open_orders_global;
+        if cap > 0 && self.open_order_count() >= cap {
+            self.counters.global_cap_rejections += 1;
+            self.store
+                .upsert_runtime_status(
+                    "risk",
+                    "warn",
+                    "max_open_orders_global",
+                    Some(&format!("{} open, cap {cap}", self.open_order_count())),
+                    ts,
+                )
+                .ok();
+            let meta = json!({"strategy": strategy, "reason": "max_open_orders_global"});
+            return self.record_rejected(
+                market_id,
+                side,
+                price,
+                size,
+                ts,
+                expires_ts,
+                with_tif(meta),
+            );
+        }
+        // Random "server faults" and non-atomic fails.
+        if self.rng.random::<f64>() < self.settings.paper_fault_rate {
+            let meta = json!({"strategy": strategy, "reason": "paper_fault"});
+            return self.record_rejected(
+                market_id,
+                side,
+                price,
+                size,
+                ts,
+                expires_ts,
+                with_tif(meta),
+            );
+        }
 
         let oid = Uuid::new_v4().to_string();
         self.counters.orders_placed += 1;
+        let queue_ahead = if self.settings.paper_fill_queue_model {
+            self.book_by_market
+                .get(market_id)
+                .map(|book| resting_ahead(book, self.levels_by_market.get(market_id), side, price))
+        } else {
+            None
+        };
         let o = Order {
             order_id: oid.clone(),
             market_id: market_id.to_string(),
@@ -167,7 +588,10 @@ impl PaperBroker {
             status: "open".to_string(),
             filled_size: 0.0,
             last_event_ts: ts,
-            meta: json!({"strategy": strategy}),
+            expires_ts,
+            last_fill_ts: None,
+            queue_ahead,
+            meta: with_tif(json!({"strategy": strategy})),
         };
         self.store.insert_order(
             &o.order_id,
@@ -203,7 +627,7 @@ impl PaperBroker {
     }
 
     pub fn cancel(&mut self, order_id: &str) -> Result<()> {
-        let ts = now_ts();
+        let ts = self.now();
         let Some(o) = self.orders.get_mut(order_id) else {
             return Ok(());
         };
@@ -228,11 +652,150 @@ impl PaperBroker {
             )?;
             return Ok(());
         }
+        let latency = self.latency_secs();
+        if latency > 0.0 {
+            // The order keeps resting (and can fill) until the cancel lands.
+            self.pending_cancels
+                .entry(order_id.to_string())
+                .or_insert(ts + latency);
+            return Ok(());
+        }
+        self.finish_cancel(order_id, ts)
+    }
+
+    fn finish_cancel(&mut self, order_id: &str, ts: f64) -> Result<()> {
+        let Some(o) = self.orders.get_mut(order_id) else {
+            return Ok(());
+        };
+        if o.status != "open" {
+            return Ok(());
+        }
         o.status = "cancelled".to_string();
         o.last_event_ts = ts;
         self.counters.orders_cancelled += 1;
+        self.cancel_rate.record(ts);
+        self.fill_model.forget(order_id);
         self.store
-            .update_order_status(&o.order_id, &o.status, Some(o.filled_size))?;
+            .update_order_status(&o.order_id, &o.status, Some(o.filled_size), ts)?;
+        if let Some(dust) = self.dust_fills.remove(order_id) {
+            self.insert_fill_record(dust)?;
+        }
+        Ok(())
+    }
+
+    /// Expire every open GTD order whose expiration is at or before `ts` (broker clock,
+    /// so replay expires against tape time). Expired orders stop resting and filling.
+    pub fn expire_orders(&mut self, ts: f64) -> Result<()> {
+        let mut due: Vec<(String, f64)> = self
+            .orders
+            .values()
+            .filter(|o| o.status == "open")
+            .filter_map(|o| {
+                o.expires_ts
+                    .filter(|exp| *exp <= ts)
+                    .map(|exp| (o.order_id.clone(), exp))
+            })
+            .collect();
+        due.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        for (id, exp) in due {
+            let Some(o) = self.orders.get_mut(&id) else {
+                continue;
+            };
+            o.status = "expired".to_string();
+            o.last_event_ts = exp;
+            self.counters.orders_expired += 1;
+            self.fill_model.forget(&id);
+            self.store
+                .update_order_status(&o.order_id, &o.status, Some(o.filled_size), exp)?;
+            // Nothing left to cancel; a pending cancel would otherwise count it twice.
+            self.pending_cancels.remove(&id);
+            if let Some(dust) = self.dust_fills.remove(&id) {
+                self.insert_fill_record(dust)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record any buffered dust partials (shutdown: orders that could not be cancelled).
+    pub fn flush_dust_fills(&mut self) -> Result<()> {
+        let mut ids: Vec<String> = self.dust_fills.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            if let Some(dust) = self.dust_fills.remove(&id) {
+                self.insert_fill_record(dust)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge a passive partial into the order's pending dust and record it once the
+    /// aggregate reaches `MIN_FILL_NOTIONAL_USD` or the order is done.
+    ///
+    /// Sizes are summed, so recorded fills always add up to the order's filled size.
+    /// Returns the recorded fill, if any.
+    fn record_passive_fill(
+        &mut self,
+        fill: Fill,
+        fee: f64,
+        strategy: serde_json::Value,
+        order_done: bool,
+    ) -> Result<Option<Fill>> {
+        let dust = match self.dust_fills.remove(&fill.order_id) {
+            Some(mut acc) => {
+                acc.fill.size += fill.size;
+                acc.fee += fee;
+                acc.fill.ts = fill.ts;
+                acc.partials += 1;
+                self.counters.aggregated_partials += 1;
+                acc
+            }
+            None => DustFill {
+                meta: self.fill_meta(
+                    &fill.market_id,
+                    json!({
+                        "strategy": strategy,
+                        "fill_model": self.fill_model.name(),
+                        "liquidity": "maker",
+                    }),
+                ),
+                fill,
+                fee,
+                partials: 1,
+            },
+        };
+        let notional = dust.fill.price * dust.fill.size;
+        if !order_done && notional < self.settings.min_fill_notional_usd {
+            self.dust_fills.insert(dust.fill.order_id.clone(), dust);
+            return Ok(None);
+        }
+        let fill = dust.fill.clone();
+        self.insert_fill_record(dust)?;
+        Ok(Some(fill))
+    }
+
+    fn insert_fill_record(&mut self, dust: DustFill) -> Result<()> {
+        let DustFill {
+            fill,
+            fee,
+            mut meta,
+            partials,
+        } = dust;
+        meta["fee"] = json!(fee);
+        if partials > 1 {
+            meta["aggregated_partials"] = json!(partials);
+        }
+        self.store.insert_fill(
+            &fill.fill_id,
+            &fill.order_id,
+            &fill.market_id,
+            fill.side.as_str(),
+            fill.price,
+            fill.size,
+            fill.ts,
+            &meta,
+        )?;
+        self.counters.fills += 1;
+        self.fill_rate.record(fill.ts);
         Ok(())
     }
 
@@ -247,10 +810,42 @@ impl PaperBroker {
         strategy: &str,
         tob: &Tob,
     ) -> Result<Option<Fill>> {
-        if self.settings.execution_mode == "shadow" {
+        self.ioc(market_id, side, price, size, strategy, None, tob)
+    }
+
+    /// IOC that closes a position for a risk exit; `reason` (`stop_loss`, ...) is
+    /// recorded on the order and fill meta.
+    pub fn execute_exit_ioc(
+        &mut self,
+        market_id: &str,
+        side: Side,
+        price: f64,
+        size: f64,
+        reason: &str,
+        tob: &Tob,
+    ) -> Result<Option<Fill>> {
+        self.ioc(market_id, side, price, size, "exit", Some(reason), tob)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn ioc(
+        &mut self,
+        market_id: &str,
+        side: Side,
+        price: f64,
+        size: f64,
+        strategy: &str,
+        reason: Option<&str>,
+        tob: &Tob,
+    ) -> Result<Option<Fill>> {
+        if self.settings.execution_mode != "paper" {
             return Ok(None);
         }
-        let ts = now_ts();
+        if let Some(tick) = tob.tick_size {
+            self.note_tick(market_id, tick);
+        }
+        let price = self.grid_price(market_id, side, price);
+        let ts = self.now();
         let (Some(bid), Some(ask)) = (tob.best_bid, tob.best_ask) else {
             return Ok(None);
         };
@@ -261,6 +856,50 @@ impl PaperBroker {
             _ => {}
         }
 
+        // Self-trade prevention (skip-own): the feed book only holds external orders, so
+        // the IOC takes at most what it shows up to `price`; our own resting orders in
+        // that range are passed over, never matched.
+        let size = if self.settings.self_trade_prevention {
+            let own = self.own_crossable_size(market_id, side, price);
+            let external = self.external_crossable_size(market_id, side, price, tob);
+            let take = size.min(external);
+            if own > 0.0 {
+                self.counters.self_trades_prevented += 1;
+                log::info!(
+                    "paper.stp market_id={} side={} px={:.4} size={:.4} own_skipped={:.4} external={:.4} strategy={}",
+                    market_id,
+                    side.as_str(),
+                    price,
+                    size,
+                    own,
+                    external,
+                    strategy
+                );
+            }
+            if take <= FLAT_QTY_EPS {
+                let why = if own > 0.0 {
+                    "self_trade"
+                } else {
+                    "no_liquidity"
+                };
+                self.store.insert_order(
+                    &Uuid::new_v4().to_string(),
+                    market_id,
+                    side.as_str(),
+                    price,
+                    size,
+                    ts,
+                    "rejected",
+                    0.0,
+                    &json!({"strategy": strategy, "type": "ioc", "reason": why, "own_resting": own}),
+                )?;
+                return Ok(None);
+            }
+            take
+        } else {
+            size
+        };
+
         let oid = Uuid::new_v4().to_string();
         let mut o = Order {
             order_id: oid.clone(),
@@ -272,8 +911,14 @@ impl PaperBroker {
             status: "filled".to_string(),
             filled_size: size,
             last_event_ts: ts,
+            expires_ts: None,
+            last_fill_ts: Some(ts),
+            queue_ahead: None,
             meta: json!({"strategy": strategy, "type": "ioc"}),
         };
+        if let Some(reason) = reason {
+            o.meta["reason"] = json!(reason);
+        }
 
         let fill = Fill {
             fill_id: Uuid::new_v4().to_string(),
@@ -285,8 +930,9 @@ impl PaperBroker {
             ts,
         };
 
-        self.apply_fill(&fill, tob)?;
+        let fee = self.apply_fill(&fill, tob, self.settings.taker_fee_bps)?;
         self.counters.fills += 1;
+        self.fill_rate.record(ts);
         self.counters.filled_qty += size;
 
         self.store.insert_order(
@@ -300,6 +946,13 @@ impl PaperBroker {
             o.filled_size,
             &o.meta,
         )?;
+        let mut fill_meta = self.fill_meta(
+            market_id,
+            json!({"strategy": strategy, "type": "ioc", "liquidity": "taker", "fee": fee}),
+        );
+        if let Some(reason) = reason {
+            fill_meta["reason"] = json!(reason);
+        }
         self.store.insert_fill(
             &fill.fill_id,
             &fill.order_id,
@@ -308,7 +961,7 @@ impl PaperBroker {
             fill.price,
             fill.size,
             fill.ts,
-            &json!({"strategy": strategy, "type":"ioc"}),
+            &fill_meta,
         )?;
 
         o.meta["fill_id"] = json!(fill.fill_id);
@@ -316,16 +969,63 @@ impl PaperBroker {
         Ok(Some(fill))
     }
 
+    /// Unfilled size of our own open orders on the other side that an order at
+    /// `price` would trade against (asks at or below a buy, bids at or above a sell).
+    pub fn own_crossable_size(&self, market_id: &str, side: Side, price: f64) -> f64 {
+        self.orders
+            .values()
+            .filter(|o| o.market_id == market_id && o.status == "open" && o.side != side)
+            .filter(|o| match side {
+                Side::Buy => o.price <= price + 1e-12,
+                Side::Sell => o.price >= price - 1e-12,
+            })
+            .map(|o| (o.size - o.filled_size).max(0.0))
+            .sum()
+    }
+
+    /// External size an order at `price` can take on the other side: the crossable
+    /// levels of the market's level book when known, else the touch size. Our own
+    /// orders are simulated, so they never show up in either.
+    fn external_crossable_size(&self, market_id: &str, side: Side, price: f64, tob: &Tob) -> f64 {
+        let ladder = self
+            .levels_by_market
+            .get(market_id)
+            .map(|b| if side == Side::Buy { &b.asks } else { &b.bids })
+            .filter(|l| !l.is_empty());
+        match ladder {
+            Some(ladder) => ladder
+                .iter()
+                .filter(|&&(px, _)| match side {
+                    Side::Buy => px <= price + 1e-9,
+                    Side::Sell => px >= price - 1e-9,
+                })
+                .map(|&(_, sz)| sz.max(0.0))
+                .sum(),
+            None => tob.touch_size(side == Side::Sell).max(0.0),
+        }
+    }
+
     pub fn simulate_fills_for_market(
         &mut self,
         market_id: &str,
         tob: &Tob,
         activity_score: f64,
     ) -> Result<Vec<Fill>> {
-        if self.settings.execution_mode == "shadow" {
+        if self.settings.execution_mode != "paper" {
             return Ok(vec![]);
         }
-        let now = now_ts();
+        if let Some(tick) = tob.tick_size {
+            self.note_tick(market_id, tick);
+        }
+        let now = self.now();
+        self.settle_pending_cancels(now)?;
+        self.expire_orders(now)?;
+        let queue_model = self.settings.paper_fill_queue_model;
+        if queue_model {
+            self.book_by_market
+                .insert(market_id.to_string(), tob.clone());
+        }
+        let latency = self.latency_secs();
         let prev = self.last_sim_ts.get(market_id).copied().unwrap_or(now);
         let dt = (now - prev).max(0.0);
         self.last_sim_ts.insert(market_id.to_string(), now);
@@ -340,24 +1040,42 @@ impl PaperBroker {
             return Ok(vec![]);
         }
 
-        // We simulate passive maker fills driven by Poisson-arrival opponent orders.
-        // Each resting order has a fill intensity based on distance-to-touch and activity.
-        let tick = self.settings.price_tick.max(1e-6);
-        let base_lambda = self.settings.paper_poisson_lambda_per_sec.max(0.0);
+        // Passive maker fills: the broker gates each resting order (latency, min rest,
+        // throttle) and the configured fill model decides how much of it fills this step.
+        let tick = self.tick_for(market_id).max(1e-6);
+        // Queue model: every order sees the same prints since the last step.
+        let prints = if queue_model {
+            self.pending_trades.remove(market_id).unwrap_or_default()
+        } else {
+            vec![]
+        };
+        let flow_scale = activity_score.max(self.settings.paper_fill_activity_floor);
 
         let mut fills_out: Vec<Fill> = vec![];
-        let order_ids: Vec<String> = self
+        // Visit orders in a stable order so seeded runs consume the RNG identically.
+        let mut open: Vec<&Order> = self
             .orders
-            .iter()
-            .filter(|(_, o)| o.market_id == market_id && o.status == "open")
-            .map(|(k, _)| k.clone())
+            .values()
+            .filter(|o| o.market_id == market_id && o.status == "open")
             .collect();
+        open.sort_by(|a, b| {
+            a.created_ts
+                .total_cmp(&b.created_ts)
+                .then(a.price.total_cmp(&b.price))
+                .then(a.side.as_str().cmp(b.side.as_str()))
+        });
+        let order_ids: Vec<String> = open.iter().map(|o| o.order_id.clone()).collect();
 
         for oid in order_ids {
             // Compute fill proposal while holding a mutable borrow to the order only.
             let proposal: Option<(Fill, String, f64, serde_json::Value)> = (|| {
                 let o = self.orders.get_mut(&oid)?;
-                let rested = (now - o.created_ts).max(0.0);
+                // Not on the book until the placement latency has elapsed.
+                let live_ts = o.created_ts + latency;
+                if now < live_ts {
+                    return None;
+                }
+                let rested = (now - live_ts).max(0.0);
                 if rested < self.settings.paper_min_rest_secs {
                     return None;
                 }
@@ -365,29 +1083,66 @@ impl PaperBroker {
                 if remaining <= 0.0 {
                     return None;
                 }
+                // One resting order is refilled by the next taker, not every book update.
+                // Checked before sampling so seeded live and replay runs throttle alike.
+                if let Some(last) = o.last_fill_ts {
+                    if now - last < self.settings.paper_min_secs_between_fills {
+                        self.counters.fills_throttled += 1;
+                        return None;
+                    }
+                }
 
                 let distance_ticks = match o.side {
                     Side::Buy => ((best_bid - o.price) / tick).max(0.0),
                     Side::Sell => ((o.price - best_ask) / tick).max(0.0),
                 };
 
-                let at_touch_factor = (-0.7 * distance_ticks).exp();
-                let intensity = base_lambda * activity_score.max(0.05) * at_touch_factor;
-                let lambda_dt = intensity * dt;
-
-                let n = poisson_sample(&mut self.rng, lambda_dt);
-                if n == 0 {
+                let fill_size = if queue_model {
+                    // Prints at or through our price, scaled by activity, first trade
+                    // away the queue ahead of us; only the rest reaches our order.
+                    let printed: f64 = prints
+                        .iter()
+                        .filter(|p| match o.side {
+                            Side::Buy => p.price <= o.price + 1e-9,
+                            Side::Sell => p.price >= o.price - 1e-9,
+                        })
+                        .map(|p| p.size)
+                        .sum();
+                    let flow = printed * flow_scale;
+                    let (side, price) = (o.side, o.price);
+                    // Placed before the broker saw a book: queue behind the current one.
+                    let ahead = o.queue_ahead.get_or_insert_with(|| {
+                        resting_ahead(tob, self.levels_by_market.get(market_id), side, price)
+                    });
+                    let through = flow - *ahead;
+                    *ahead = (*ahead - flow).max(0.0);
+                    through.clamp(0.0, remaining)
+                } else {
+                    let input = FillInput {
+                        order_id: &o.order_id,
+                        side: o.side,
+                        remaining,
+                        distance_ticks,
+                        dt,
+                        activity_score,
+                        tick,
+                        tob,
+                    };
+                    self.fill_model
+                        .fill_size(&self.settings, &input, &mut self.rng)
+                        .min(remaining)
+                };
+                if fill_size <= 0.0 {
                     return None;
                 }
 
-                let frac = (0.3 + 0.7 * self.rng.random::<f64>()).clamp(0.05, 1.0);
-                let fill_size = (remaining * frac).max(tick.min(remaining)).min(remaining);
-
                 o.filled_size += fill_size;
                 if o.filled_size + 1e-12 >= o.size {
                     o.status = "filled".to_string();
+                    self.fill_model.forget(&o.order_id);
                 }
                 o.last_event_ts = now;
+                o.last_fill_ts = Some(now);
 
                 let fill = Fill {
                     fill_id: Uuid::new_v4().to_string(),
@@ -409,35 +1164,34 @@ impl PaperBroker {
                 continue;
             };
 
-            self.apply_fill(&fill, tob)?;
-            self.counters.fills += 1;
+            // Positions and order sizes move on every partial; only the fill record is batched.
+            let fee = self.apply_fill(&fill, tob, self.settings.maker_fee_bps)?;
             self.counters.filled_qty += fill.size;
 
-            self.store
-                .update_order_status(&fill.order_id, &status, Some(filled_size_total))?;
-            self.store.insert_fill(
-                &fill.fill_id,
+            self.store.update_order_status(
                 &fill.order_id,
-                &fill.market_id,
-                fill.side.as_str(),
-                fill.price,
-                fill.size,
+                &status,
+                Some(filled_size_total),
                 fill.ts,
-                &json!({"strategy": strat, "fill_model": "maker_touch"}),
             )?;
-            fills_out.push(fill);
+            if let Some(recorded) = self.record_passive_fill(fill, fee, strat, status != "open")? {
+                fills_out.push(recorded);
+            }
         }
 
         Ok(fills_out)
     }
 
-    fn apply_fill(&mut self, fill: &Fill, tob: &Tob) -> Result<()> {
+    /// Book `fill` into its position, charging `fee_bps` of notional (maker or taker
+    /// rate) against realized PnL. Returns the fee paid (negative = rebate).
+    fn apply_fill(&mut self, fill: &Fill, tob: &Tob, fee_bps: f64) -> Result<f64> {
         let pos = self.positions.entry(fill.market_id.clone()).or_default();
 
-        // Execution costs (fees=0, but slippage+latency modeled as a per-fill penalty).
+        // Execution costs: slippage+latency modeled as a per-fill penalty, plus the fee.
         let cost_bps = (self.settings.slippage_bps + self.settings.latency_bps).max(0.0);
         let exec_cost = (cost_bps / 10_000.0) * fill.price * fill.size;
-        pos.realized_pnl -= exec_cost;
+        let fee = (fee_bps / 10_000.0) * fill.price * fill.size;
+        pos.realized_pnl -= exec_cost + fee;
 
         match fill.side {
             Side::Buy => {
@@ -500,8 +1254,635 @@ impl PaperBroker {
             }
         }
 
-        // Keep mark in meta? (snapshots handle mark separately).
-        let _ = tob;
-        Ok(())
+        // Publish the new position now so /api/positions doesn't lag until the next snapshot tick.
+        let mark = tob
+            .mark(&self.settings.mark_source)
+            .unwrap_or(pos.avg_price);
+        self.store.insert_fill_position(
+            fill.ts,
+            &fill.market_id,
+            pos.qty,
+            pos.avg_price,
+            mark,
+            (mark - pos.avg_price) * pos.qty,
+            pos.realized_pnl,
+        )?;
+        Ok(fee)
+    }
+}
+
+/// Poisson arrival rate (per second) of opponent flow that fills a resting order
+/// `distance_ticks` behind the touch.
+///
+/// `lambda * max(activity, floor) * exp(-decay * distance)`: an order at the touch
+/// sees the full rate, each tick further back multiplies it by `exp(-decay)`.
+pub fn fill_intensity(settings: &Settings, distance_ticks: f64, activity_score: f64) -> f64 {
+    let base_lambda = settings.paper_poisson_lambda_per_sec.max(0.0);
+    let activity = activity_score.max(settings.paper_fill_activity_floor);
+    let decay = (-settings.paper_fill_decay_per_tick * distance_ticks.max(0.0)).exp();
+    base_lambda * activity * decay
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::temp_store;
+
+    fn broker() -> (PaperBroker, SqliteStore) {
+        let store = temp_store();
+        let mut settings = Settings::load().unwrap();
+        settings.paper_fault_rate = 0.0;
+        settings.paper_non_atomic_fail_rate = 0.0;
+        (PaperBroker::with_seed(settings, store.clone(), 1), store)
+    }
+
+    #[test]
+    fn stale_health_holds_orders_in_safe_mode_until_recovered() {
+        let (mut b, store) = broker();
+        b.settings.safe_mode = true;
+        let health = HealthState::new();
+        b.set_health_gate(health.clone());
+        b.set_clock(10_000.0);
+
+        health.record_ok(10_000.0 - b.settings.health_max_age_secs - 1.0);
+        let oid = b.place_limit("m1", Side::Buy, 0.45, 10.0, "mm").unwrap();
+        assert_eq!(b.orders[&oid].status, "held");
+        assert_eq!(b.counters.held_orders, 1);
+        assert_eq!(b.counters.orders_placed, 0);
+        let status = store.fetch_runtime_statuses().unwrap();
+        assert_eq!(status["safe_mode"]["level"], "error");
+
+        health.record_ok(10_000.0);
+        let held = oid;
+        let oid = b.place_limit("m1", Side::Buy, 0.45, 10.0, "mm").unwrap();
+        assert_eq!(b.orders[&oid].status, "open");
+        assert_eq!(b.orders[&held].status, "cancelled");
+        let status = store.fetch_runtime_statuses().unwrap();
+        assert_eq!(status["safe_mode"]["level"], "ok");
+    }
+
+    #[test]
+    fn placements_past_global_open_order_cap_are_rejected() {
+        let (mut b, store) = broker();
+        b.settings.max_open_orders_global = 3;
+        for (i, m) in ["m1", "m2", "m3"].iter().enumerate() {
+            assert_eq!(b.open_order_capacity(), 3 - i);
+            let oid = b.place_limit(m, Side::Buy, 0.45, 10.0, "mm").unwrap();
+            assert_eq!(b.orders[&oid].status, "open");
+        }
+        assert_eq!(b.open_order_capacity(), 0);
+
+        let oid = b
+            .place_gtd("m4", Side::Sell, 0.55, 10.0, "mm", 1e12)
+            .unwrap();
+        assert_eq!(b.orders[&oid].status, "rejected");
+        assert_eq!(b.orders[&oid].meta["reason"], "max_open_orders_global");
+        assert_eq!(b.open_order_count(), 3);
+        assert_eq!(b.counters.orders_placed, 3);
+        assert_eq!(b.counters.global_cap_rejections, 1);
+        assert_eq!(b.counters.rejected_orders, 1);
+        let status = store.fetch_runtime_statuses().unwrap();
+        assert_eq!(status["risk"]["level"], "warn");
+        assert_eq!(status["risk"]["message"], "max_open_orders_global");
+
+        // Rejections do not count toward the cap; a filled-out order frees a slot.
+        let first = b
+            .orders
+            .values()
+            .find(|o| o.market_id == "m1")
+            .unwrap()
+            .order_id
+            .clone();
+        b.orders.get_mut(&first).unwrap().status = "filled".into();
+        assert_eq!(b.open_order_capacity(), 1);
+        let oid = b.place_limit("m4", Side::Sell, 0.55, 10.0, "mm").unwrap();
+        assert_eq!(b.orders[&oid].status, "open");
+    }
+
+    #[test]
+    fn paper_is_not_gated_unless_safe_mode_is_on() {
+        let (mut b, _store) = broker();
+        b.set_health_gate(HealthState::new());
+        let oid = b.place_limit("m1", Side::Sell, 0.55, 10.0, "mm").unwrap();
+        assert_eq!(b.orders[&oid].status, "open");
+        assert_eq!(b.counters.held_orders, 0);
+
+        // Safe mode without a health source (replay) has nothing to gate on.
+        let (mut b, _store) = broker();
+        b.settings.safe_mode = true;
+        let oid = b.place_limit("m1", Side::Sell, 0.55, 10.0, "mm").unwrap();
+        assert_eq!(b.orders[&oid].status, "open");
+    }
+
+    fn latency_broker() -> PaperBroker {
+        let (mut b, _store) = broker();
+        b.settings.simulated_latency_ms = 200.0;
+        b.settings.paper_min_rest_secs = 0.0;
+        b.settings.paper_poisson_lambda_per_sec = 1000.0;
+        b
+    }
+
+    fn tob(bid: f64, ask: f64, ts: f64) -> Tob {
+        Tob {
+            best_bid: Some(bid),
+            best_ask: Some(ask),
+            bid_depth_n: 100.0,
+            ask_depth_n: 100.0,
+            ..Tob::empty(ts)
+        }
+    }
+
+    #[test]
+    fn order_is_not_fillable_until_placement_latency_elapses() {
+        let mut b = latency_broker();
+        b.set_clock(0.0);
+        let oid = b.place_limit("m1", Side::Buy, 0.50, 10.0, "mm").unwrap();
+        b.simulate_fills_for_market("m1", &tob(0.50, 0.52, 0.0), 1.0)
+            .unwrap();
+        b.set_clock(0.1);
+        let fills = b
+            .simulate_fills_for_market("m1", &tob(0.50, 0.52, 0.1), 1.0)
+            .unwrap();
+        assert!(fills.is_empty());
+        assert_eq!(b.orders[&oid].filled_size, 0.0);
+    }
+
+    #[test]
+    fn pending_cancel_fills_at_old_price_only_if_book_unchanged() {
+        for (moved, expect_fill) in [(false, true), (true, false)] {
+            let mut b = latency_broker();
+            b.set_clock(0.0);
+            let oid = b.place_limit("m1", Side::Buy, 0.50, 10.0, "mm").unwrap();
+            b.simulate_fills_for_market("m1", &tob(0.50, 0.52, 0.0), 1.0)
+                .unwrap();
+
+            // Order is live at 0.2; cancel sent at 0.3 lands at 0.5.
+            b.set_clock(0.3);
+            b.cancel(&oid).unwrap();
+            assert_eq!(b.orders[&oid].status, "open");
+            assert!(b.cancel_pending(&oid));
+
+            let book = if moved {
+                tob(0.60, 0.62, 0.4)
+            } else {
+                tob(0.50, 0.52, 0.4)
+            };
+            b.set_clock(0.4);
+            let fills = b.simulate_fills_for_market("m1", &book, 1.0).unwrap();
+            assert_eq!(!fills.is_empty(), expect_fill, "moved={moved}");
+            assert!(fills.iter().all(|f| (f.price - 0.50).abs() < 1e-12));
+
+            b.set_clock(0.6);
+            b.simulate_fills_for_market("m1", &book, 1.0).unwrap();
+            assert!(!b.cancel_pending(&oid));
+            assert_ne!(b.orders[&oid].status, "open");
+        }
+    }
+
+    /// Chance of at least one Poisson arrival within one second.
+    fn fill_probability(settings: &Settings, distance_ticks: f64, activity: f64) -> f64 {
+        1.0 - (-fill_intensity(settings, distance_ticks, activity)).exp()
+    }
+
+    #[test]
+    fn gtd_order_stops_filling_after_expiration() {
+        let (mut b, store) = broker();
+        b.settings.execution_mode = "paper".to_string();
+        b.settings.paper_min_rest_secs = 0.0;
+        b.settings.paper_poisson_lambda_per_sec = 1000.0;
+        b.set_clock(0.0);
+        let oid = b
+            .place_gtd("m1", Side::Buy, 0.50, 1_000_000.0, "mm", 1.0)
+            .unwrap();
+        b.simulate_fills_for_market("m1", &tob(0.50, 0.52, 0.0), 1.0)
+            .unwrap();
+
+        for i in 1..=5 {
+            let ts = i as f64 * 0.1;
+            b.set_clock(ts);
+            b.simulate_fills_for_market("m1", &tob(0.50, 0.52, ts), 1.0)
+                .unwrap();
+        }
+        assert_eq!(b.orders[&oid].status, "open");
+        let filled = b.orders[&oid].filled_size;
+        assert!(filled > 0.0);
+
+        // Replay clock passes the expiration: the order is pulled, not filled.
+        for ts in [1.0, 1.5, 2.0] {
+            b.set_clock(ts);
+            let fills = b
+                .simulate_fills_for_market("m1", &tob(0.50, 0.52, ts), 1.0)
+                .unwrap();
+            assert!(fills.is_empty());
+        }
+        let o = &b.orders[&oid];
+        assert_eq!(o.status, "expired");
+        assert_eq!(o.filled_size, filled);
+        assert_eq!(o.last_event_ts, 1.0);
+        assert_eq!(b.counters.orders_expired, 1);
+        assert_eq!(b.counters.orders_cancelled, 0);
+        let expired = store.fetch_recent_orders(10, Some("expired")).unwrap();
+        assert_eq!(expired.len(), 1);
+    }
+
+    #[test]
+    fn fill_probability_decreases_with_distance_from_touch() {
+        let settings = Settings::load().unwrap();
+        let probs: Vec<f64> = (0..=8)
+            .map(|d| fill_probability(&settings, d as f64, 1.0))
+            .collect();
+        for w in probs.windows(2) {
+            assert!(w[1] < w[0], "{probs:?}");
+        }
+        assert!(probs[0] > 5.0 * probs[4], "{probs:?}");
+        // Inside the spread counts as at the touch.
+        assert_eq!(fill_probability(&settings, -2.0, 1.0), probs[0]);
+        // More activity fills more readily at the same distance.
+        assert!(fill_probability(&settings, 2.0, 3.0) > probs[2]);
+    }
+
+    /// Records the distance each fill decision was asked about; never fills.
+    struct DistanceProbe(std::sync::Arc<parking_lot::Mutex<Vec<f64>>>);
+
+    impl FillModel for DistanceProbe {
+        fn name(&self) -> &'static str {
+            "distance_probe"
+        }
+
+        fn fill_size(&mut self, _: &Settings, input: &FillInput<'_>, _: &mut SmallRng) -> f64 {
+            self.0.lock().push(input.distance_ticks);
+            0.0
+        }
+    }
+
+    #[test]
+    fn distance_to_touch_is_counted_in_the_markets_own_ticks() {
+        let (mut b, _store) = broker();
+        b.settings.price_tick = 0.01;
+        b.settings.paper_min_rest_secs = 0.0;
+        b.settings.simulated_latency_ms = 0.0;
+        let seen = std::sync::Arc::new(parking_lot::Mutex::new(vec![]));
+        b.set_fill_model(Box::new(DistanceProbe(seen.clone())));
+        let book = Tob {
+            tick_size: Some(0.001),
+            ..tob(0.500, 0.520, 0.0)
+        };
+
+        b.set_clock(0.0);
+        b.simulate_fills_for_market("m1", &book, 1.0).unwrap();
+        // Three 0.001 ticks behind the bid, not 0.3 of a global 0.01 tick.
+        b.place_limit("m1", Side::Buy, 0.497, 10.0, "mm").unwrap();
+        b.set_clock(1.0);
+        b.simulate_fills_for_market("m1", &book, 1.0).unwrap();
+        let seen = seen.lock();
+        assert_eq!(seen.len(), 1);
+        assert!((seen[0] - 3.0).abs() < 1e-9, "{seen:?}");
+    }
+
+    #[test]
+    fn dust_partials_aggregate_into_one_recorded_fill() {
+        let (mut b, store) = broker();
+        b.settings.paper_min_rest_secs = 0.0;
+        b.settings.paper_poisson_lambda_per_sec = 1000.0;
+        // Above the whole order's notional: only completion records a fill.
+        b.settings.min_fill_notional_usd = 100.0;
+        b.set_clock(0.0);
+        let oid = b.place_limit("m1", Side::Buy, 0.50, 10.0, "mm").unwrap();
+        b.simulate_fills_for_market("m1", &tob(0.50, 0.52, 0.0), 1.0)
+            .unwrap();
+
+        let mut recorded = vec![];
+        for i in 1..=500 {
+            let ts = i as f64 * 0.1;
+            b.set_clock(ts);
+            recorded.extend(
+                b.simulate_fills_for_market("m1", &tob(0.50, 0.52, ts), 1.0)
+                    .unwrap(),
+            );
+            if b.orders[&oid].status == "filled" {
+                break;
+            }
+        }
+
+        assert_eq!(b.orders[&oid].status, "filled");
+        assert!(b.counters.aggregated_partials >= 2, "{:?}", b.counters);
+        assert_eq!(recorded.len(), 1);
+        assert!((recorded[0].size - 10.0).abs() < 1e-9);
+        assert!((b.position_qty("m1") - 10.0).abs() < 1e-9);
+        let rows = store.fetch_recent_fills(10).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert!((rows[0]["size"].as_f64().unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(
+            rows[0]["meta"]["aggregated_partials"].as_u64(),
+            Some(b.counters.aggregated_partials + 1)
+        );
+    }
+
+    #[test]
+    fn fill_updates_live_position_before_snapshot_tick() {
+        let (mut b, store) = broker();
+        b.settings.execution_mode = "paper".to_string();
+        b.settings.mark_source = "mid".to_string();
+        b.set_clock(100.0);
+        // Last periodic snapshot: flat, tagged with its event.
+        store
+            .insert_position_snapshot(99.0, "m1", Some("e1"), 0.0, 0.0, 0.5, 0.0, 0.0)
+            .unwrap();
+
+        b.execute_ioc("m1", Side::Buy, 0.52, 4.0, "snipe", &tob(0.50, 0.52, 100.0))
+            .unwrap()
+            .expect("crossing IOC fills");
+
+        let rows = store.fetch_latest_positions(10).unwrap();
+        assert_eq!(rows.len(), 1);
+        let live = &rows[0];
+        assert_eq!(live["event_id"], "e1");
+        assert_eq!(live["ts"], 100.0);
+        assert!((live["position"].as_f64().unwrap() - b.position_qty("m1")).abs() < 1e-9);
+        assert!((live["position"].as_f64().unwrap() - 4.0).abs() < 1e-9);
+        assert!((live["mark_price"].as_f64().unwrap() - 0.51).abs() < 1e-9);
+        assert!(
+            (live["realized_pnl"].as_f64().unwrap() - b.positions["m1"].realized_pnl).abs() < 1e-9
+        );
+    }
+
+    #[test]
+    fn ioc_skips_own_resting_size_and_takes_only_external_liquidity() {
+        let (mut b, store) = broker();
+        b.settings.execution_mode = "paper".to_string();
+        b.set_clock(100.0);
+        // Our ask rests at the touch; a snipe buy at the ask would lift it.
+        let own = b.place_limit("m1", Side::Sell, 0.52, 5.0, "mm").unwrap();
+        assert!((b.own_crossable_size("m1", Side::Buy, 0.52) - 5.0).abs() < 1e-9);
+        assert_eq!(b.own_crossable_size("m1", Side::Buy, 0.51), 0.0);
+        assert_eq!(b.own_crossable_size("m1", Side::Sell, 0.40), 0.0);
+        assert_eq!(b.own_crossable_size("m2", Side::Buy, 0.52), 0.0);
+
+        // 2 external shares at the ask: the buy takes those and passes over ours.
+        let book = Tob {
+            ask_touch_size: Some(2.0),
+            ..tob(0.50, 0.52, 100.0)
+        };
+        let fill = b
+            .execute_ioc("m1", Side::Buy, 0.52, 3.0, "snipe", &book)
+            .unwrap()
+            .unwrap();
+        assert!((fill.size - 2.0).abs() < 1e-9);
+        assert_eq!(b.counters.self_trades_prevented, 1);
+        assert!((b.position_qty("m1") - 2.0).abs() < 1e-9);
+        assert_eq!(b.orders[&own].status, "open");
+        assert_eq!(b.orders[&own].filled_size, 0.0);
+
+        // Only our own size at the ask: nothing to take.
+        let book = Tob {
+            ask_touch_size: Some(0.0),
+            ..tob(0.50, 0.52, 100.0)
+        };
+        let fill = b
+            .execute_ioc("m1", Side::Buy, 0.52, 3.0, "snipe", &book)
+            .unwrap();
+        assert!(fill.is_none());
+        let rejected = store.fetch_recent_orders(10, Some("rejected")).unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0]["meta"]["reason"], "self_trade");
+
+        // With the level book known, every external level up to the price counts.
+        b.note_book(
+            "m1",
+            BookLevels {
+                bids: vec![(0.50, 1.0)],
+                asks: vec![(0.52, 2.0), (0.53, 4.0), (0.54, 9.0)],
+            },
+        );
+        let fill = b
+            .execute_ioc("m1", Side::Buy, 0.53, 10.0, "snipe", &book)
+            .unwrap()
+            .unwrap();
+        assert!((fill.size - 6.0).abs() < 1e-9);
+
+        b.settings.self_trade_prevention = false;
+        let fill = b
+            .execute_ioc("m1", Side::Buy, 0.52, 3.0, "snipe", &book)
+            .unwrap();
+        assert!(fill.is_some());
+    }
+
+    #[test]
+    fn rapid_fill_opportunities_on_one_order_are_throttled() {
+        let mut b = latency_broker();
+        b.settings.simulated_latency_ms = 0.0;
+        b.settings.paper_min_secs_between_fills = 1.0;
+        b.set_clock(0.0);
+        let oid = b.place_limit("m1", Side::Buy, 0.50, 1000.0, "mm").unwrap();
+        b.simulate_fills_for_market("m1", &tob(0.50, 0.52, 0.0), 1.0)
+            .unwrap();
+
+        // 100 book updates 10ms apart: the intensity would fill on nearly every one.
+        let mut fill_ts = vec![];
+        for i in 1..=100 {
+            let ts = i as f64 * 0.01;
+            b.set_clock(ts);
+            b.simulate_fills_for_market("m1", &tob(0.50, 0.52, ts), 1.0)
+                .unwrap();
+            if b.orders[&oid].last_fill_ts == Some(ts) {
+                fill_ts.push(ts);
+            }
+        }
+        assert_eq!(fill_ts.len(), 1, "{fill_ts:?}");
+        assert!(b.counters.fills_throttled >= 90, "{:?}", b.counters);
+        assert_eq!(b.orders[&oid].status, "open");
+
+        // Once the gap has passed the order can fill again.
+        b.set_clock(fill_ts[0] + 1.5);
+        b.simulate_fills_for_market("m1", &tob(0.50, 0.52, fill_ts[0] + 1.5), 1.0)
+            .unwrap();
+        assert_eq!(b.orders[&oid].last_fill_ts, Some(fill_ts[0] + 1.5));
+    }
+
+    #[test]
+    fn broker_delegates_passive_fills_to_the_configured_model() {
+        let mut outcomes = vec![];
+        for name in ["optimistic_touch", "queue"] {
+            let mut b = latency_broker();
+            b.settings.simulated_latency_ms = 0.0;
+            b.settings.paper_fill_model = name.to_string();
+            b.settings.depth_levels = 1;
+            b.set_fill_model(crate::fill_model::fill_model_for(name).unwrap());
+            b.set_clock(0.0);
+            let oid = b.place_limit("m1", Side::Buy, 0.50, 10.0, "mm").unwrap();
+            b.simulate_fills_for_market("m1", &tob(0.50, 0.52, 0.0), 1.0)
+                .unwrap();
+            b.set_clock(1.0);
+            b.simulate_fills_for_market("m1", &tob(0.50, 0.52, 1.0), 1.0)
+                .unwrap();
+            outcomes.push(b.orders[&oid].filled_size);
+            if b.orders[&oid].filled_size > 0.0 {
+                let rows = b.store.fetch_recent_fills(10).unwrap();
+                assert_eq!(rows[0]["meta"]["fill_model"], name);
+            }
+        }
+        // Same book, same order: optimistic fills it, the queue (100 ahead) does not yet.
+        assert_eq!(outcomes, vec![10.0, 0.0]);
+    }
+
+    #[test]
+    fn stored_order_and_fill_prices_align_to_market_tick() {
+        let (mut b, store) = broker();
+        b.settings.execution_mode = "paper".to_string();
+        b.settings.price_tick = 0.001;
+        b.note_tick("m1", 0.01);
+        b.set_clock(10.0);
+        // Grid arithmetic leaves noise (47 * 0.01 = 0.47000000000000003).
+        b.place_limit("m1", Side::Buy, 47.0 * 0.01, 5.0, "mm")
+            .unwrap();
+        b.place_limit("m2", Side::Sell, 0.5234, 5.0, "mm").unwrap();
+        b.place_limit("m3", Side::Buy, 0.5238, 5.0, "mm").unwrap();
+        let book = Tob {
+            tick_size: Some(0.01),
+            ..tob(0.50, 0.52, 10.0)
+        };
+        b.execute_ioc("m1", Side::Buy, 0.52 + 1e-15, 2.0, "snipe", &book)
+            .unwrap()
+            .expect("crossing IOC fills");
+
+        let orders = store.fetch_recent_orders(10, None).unwrap();
+        let price_of = |mid: &str, strat: &str| {
+            orders
+                .iter()
+                .find(|o| o["market_id"] == mid && o["meta"]["strategy"] == strat)
+                .and_then(|o| o["price"].as_f64())
+                .unwrap()
+        };
+        assert_eq!(price_of("m1", "mm"), 0.47);
+        assert_eq!(price_of("m1", "snipe"), 0.52);
+        // No live tick for m2/m3: PRICE_TICK grid, snapped away from the touch.
+        assert_eq!(price_of("m2", "mm"), 0.524);
+        assert_eq!(price_of("m3", "mm"), 0.523);
+        let fills = store.fetch_recent_fills(10).unwrap();
+        assert_eq!(fills[0]["price"].as_f64(), Some(0.52));
+    }
+
+    #[test]
+    fn queue_model_fills_from_trade_prints_after_the_queue_ahead() {
+        let (mut b, _store) = broker();
+        b.settings.paper_fill_queue_model = true;
+        b.settings.simulated_latency_ms = 0.0;
+        b.settings.paper_min_rest_secs = 0.0;
+        b.settings.paper_min_secs_between_fills = 0.0;
+        let print = |price: f64, size: f64| TradePrint { price, size };
+
+        b.set_clock(0.0);
+        b.simulate_fills_for_market("m1", &tob(0.50, 0.52, 0.0), 1.0)
+            .unwrap();
+        let oid = b.place_limit("m1", Side::Buy, 0.50, 10.0, "mm").unwrap();
+        assert_eq!(b.orders[&oid].queue_ahead, Some(100.0));
+
+        let step = |b: &mut PaperBroker, ts: f64, prints: &[TradePrint]| {
+            b.note_trades("m1", prints);
+            b.set_clock(ts);
+            b.simulate_fills_for_market("m1", &tob(0.50, 0.52, ts), 1.0)
+                .unwrap();
+            (b.orders[&oid].filled_size, b.orders[&oid].status.clone())
+        };
+        // 60 of the 100 ahead trade away: nothing reaches us yet.
+        assert_eq!(
+            step(&mut b, 1.0, &[print(0.50, 60.0)]),
+            (0.0, "open".into())
+        );
+        assert_eq!(b.orders[&oid].queue_ahead, Some(40.0));
+        // The queue clears and 5 of the 45 printed spill into our order.
+        let (filled, status) = step(&mut b, 2.0, &[print(0.50, 45.0)]);
+        assert!((filled - 5.0).abs() < 1e-9, "{filled}");
+        assert_eq!(status, "open");
+        // A print above our bid never trades through it.
+        let (filled, _) = step(&mut b, 3.0, &[print(0.51, 50.0)]);
+        assert!((filled - 5.0).abs() < 1e-9, "{filled}");
+        // A sweep through our price takes the rest.
+        let (filled, status) = step(&mut b, 4.0, &[print(0.49, 30.0)]);
+        assert!((filled - 10.0).abs() < 1e-9, "{filled}");
+        assert_eq!(status, "filled");
+    }
+
+    #[test]
+    fn queue_ahead_is_seeded_from_the_levels_at_and_before_our_price() {
+        let (mut b, _store) = broker();
+        b.settings.paper_fill_queue_model = true;
+        b.set_clock(0.0);
+        let book = Tob {
+            bid_touch_size: Some(30.0),
+            ..tob(0.50, 0.52, 0.0)
+        };
+        b.simulate_fills_for_market("m1", &book, 1.0).unwrap();
+        let ahead = |b: &mut PaperBroker, side: Side, price: f64| {
+            let oid = b.place_limit("m1", side, price, 5.0, "mm").unwrap();
+            b.orders[&oid].queue_ahead
+        };
+
+        // No level book yet: the touch size.
+        assert_eq!(ahead(&mut b, Side::Buy, 0.50), Some(30.0));
+
+        b.note_book(
+            "m1",
+            BookLevels {
+                bids: vec![(0.50, 30.0), (0.49, 20.0), (0.48, 50.0)],
+                asks: vec![(0.52, 15.0), (0.53, 25.0)],
+            },
+        );
+        // Behind the whole touch and the 0.49 level we join.
+        assert_eq!(ahead(&mut b, Side::Buy, 0.49), Some(50.0));
+        // Between levels: only the better ones are ahead.
+        assert_eq!(ahead(&mut b, Side::Buy, 0.485), Some(50.0));
+        assert_eq!(ahead(&mut b, Side::Sell, 0.53), Some(40.0));
+        // Improving the touch: first in line.
+        assert_eq!(ahead(&mut b, Side::Buy, 0.51), Some(0.0));
+    }
+
+    #[test]
+    fn round_trip_at_one_price_loses_exactly_the_fees() {
+        let (mut b, store) = broker();
+        b.settings.slippage_bps = 0.0;
+        b.settings.latency_bps = 0.0;
+        b.settings.taker_fee_bps = 100.0;
+        b.settings.maker_fee_bps = -20.0;
+        let book = tob(0.50, 0.50, 0.0);
+
+        b.execute_ioc("m1", Side::Buy, 0.50, 10.0, "snipe", &book)
+            .unwrap()
+            .unwrap();
+        b.execute_ioc("m1", Side::Sell, 0.50, 10.0, "snipe", &book)
+            .unwrap()
+            .unwrap();
+        assert!(b.position_qty("m1").abs() < 1e-9);
+        // Two taker legs of 10 @ 0.50 at 100 bps: 0.05 each.
+        assert!(
+            (b.realized_pnl_total() + 0.10).abs() < 1e-9,
+            "{}",
+            b.realized_pnl_total()
+        );
+        let rows = store.fetch_recent_fills(10).unwrap();
+        assert_eq!(rows.len(), 2);
+        for r in &rows {
+            assert_eq!(r["meta"]["liquidity"], "taker");
+            assert!((r["meta"]["fee"].as_f64().unwrap() - 0.05).abs() < 1e-9);
+        }
+
+        // A resting fill earns the maker rebate instead.
+        b.settings.simulated_latency_ms = 0.0;
+        b.settings.paper_min_rest_secs = 0.0;
+        b.set_fill_model(Box::new(crate::fill_model::OptimisticTouch));
+        b.set_clock(0.0);
+        b.simulate_fills_for_market("m2", &tob(0.50, 0.52, 0.0), 1.0)
+            .unwrap();
+        b.place_limit("m2", Side::Buy, 0.50, 10.0, "mm").unwrap();
+        b.set_clock(1.0);
+        let fills = b
+            .simulate_fills_for_market("m2", &tob(0.50, 0.52, 1.0), 1.0)
+            .unwrap();
+        assert_eq!(fills.len(), 1);
+        assert!((b.positions["m2"].realized_pnl - 0.01).abs() < 1e-9);
+        let rows = store.fetch_recent_fills(10).unwrap();
+        let maker = rows.iter().find(|r| r["market_id"] == "m2").unwrap();
+        assert_eq!(maker["meta"]["liquidity"], "maker");
+        assert!((maker["meta"]["fee"].as_f64().unwrap() + 0.01).abs() < 1e-9);
     }
 }
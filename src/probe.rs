@@ -0,0 +1,382 @@
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::Result;
+
+use crate::{
+    config::Settings,
+    feed_handler::FeedState,
+    hft_strategy::HftStrategy,
+    market_selector::SelectedMarket,
+    paper_broker::{PaperBroker, Side},
+    utils::round_to_tick,
+};
+
+/// A probe the scheduler wants placed: `distance_ticks` behind the touch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbePlan {
+    pub market_id: String,
+    pub side: Side,
+    pub price: f64,
+    pub size: f64,
+    pub distance_ticks: u32,
+}
+
+/// How one probe ended, measured from placement on the monotonic clock.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeOutcome {
+    pub order_id: String,
+    pub market_id: String,
+    pub side: Side,
+    pub distance_ticks: u32,
+    pub filled: bool,
+    /// Seconds to fill, or to cancel/expiry when it did not fill.
+    pub secs: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProbeStats {
+    pub placed: u64,
+    pub filled: u64,
+    pub unfilled: u64,
+    pub fill_secs_sum: f64,
+}
+
+impl ProbeStats {
+    pub fn fill_rate(&self) -> f64 {
+        let done = self.filled + self.unfilled;
+        if done == 0 {
+            0.0
+        } else {
+            self.filled as f64 / done as f64
+        }
+    }
+
+    pub fn mean_fill_secs(&self) -> Option<f64> {
+        (self.filled > 0).then(|| self.fill_secs_sum / self.filled as f64)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Outstanding {
+    plan: ProbePlan,
+    placed_mono: f64,
+}
+
+/// Opt-in (`PROBE_ORDERS=1`) fill calibration on the paper broker: a tiny GTD order
+/// a fixed number of ticks from touch, at most one outstanding, no more often than
+/// `PROBE_INTERVAL_SECS` and `PROBE_MAX_PER_HOUR`, sized down to
+/// `PROBE_MAX_NOTIONAL_USD`. Unfilled probes expire after `PROBE_TTL_SECS`.
+/// Scheduling times are `mono_ts`; order timestamps are on the broker clock.
+pub struct ProbeScheduler {
+    enabled: bool,
+    interval_secs: f64,
+    max_per_hour: usize,
+    size: f64,
+    max_notional_usd: f64,
+    distance_ticks: u32,
+    ttl_secs: f64,
+    /// Placement times inside the trailing hour.
+    recent: VecDeque<f64>,
+    outstanding: HashMap<String, Outstanding>,
+    next_buy: bool,
+    stats: ProbeStats,
+}
+
+impl ProbeScheduler {
+    /// Shadow mode places nothing, probes included.
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            enabled: settings.probe_orders && settings.execution_mode != "shadow",
+            interval_secs: settings.probe_interval_secs,
+            max_per_hour: settings.probe_max_per_hour,
+            size: settings.probe_size,
+            max_notional_usd: settings.probe_max_notional_usd,
+            distance_ticks: settings.probe_distance_ticks,
+            ttl_secs: settings.probe_ttl_secs,
+            recent: VecDeque::new(),
+            outstanding: HashMap::new(),
+            next_buy: true,
+            stats: ProbeStats::default(),
+        }
+    }
+
+    /// The next probe for this book, or `None` when disabled, one is still
+    /// outstanding, or the interval/hourly cap has not freed up. Sides alternate.
+    pub fn plan(
+        &mut self,
+        market_id: &str,
+        (best_bid, best_ask): (f64, f64),
+        tick: f64,
+        mono: f64,
+    ) -> Option<ProbePlan> {
+        if !self.enabled || !self.outstanding.is_empty() {
+            return None;
+        }
+        while self.recent.front().is_some_and(|&t| mono - t >= 3600.0) {
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= self.max_per_hour {
+            return None;
+        }
+        if self
+            .recent
+            .back()
+            .is_some_and(|&t| mono - t < self.interval_secs)
+        {
+            return None;
+        }
+
+        let offset = self.distance_ticks as f64 * tick;
+        let (side, price) = if self.next_buy {
+            (Side::Buy, round_to_tick(best_bid - offset, tick))
+        } else {
+            (Side::Sell, round_to_tick(best_ask + offset, tick))
+        };
+        if price < tick || price > 1.0 - tick {
+            return None;
+        }
+        let size = self.size.min(self.max_notional_usd / price);
+        if size <= 0.0 {
+            return None;
+        }
+        Some(ProbePlan {
+            market_id: market_id.to_string(),
+            side,
+            price,
+            size,
+            distance_ticks: self.distance_ticks,
+        })
+    }
+
+    /// Record that `plan` went out as `order_id`; this is what the caps count.
+    pub fn placed(&mut self, order_id: &str, plan: ProbePlan, mono: f64) {
+        log::info!(
+            "probe.placed order_id={order_id} market_id={} side={} px={:.4} sz={:.4} ticks={}",
+            plan.market_id,
+            plan.side.as_str(),
+            plan.price,
+            plan.size,
+            plan.distance_ticks
+        );
+        self.recent.push_back(mono);
+        self.next_buy = !self.next_buy;
+        self.stats.placed += 1;
+        self.outstanding.insert(
+            order_id.to_string(),
+            Outstanding {
+                plan,
+                placed_mono: mono,
+            },
+        );
+    }
+
+    /// Outstanding probes older than `PROBE_TTL_SECS`.
+    pub fn expired(&self, mono: f64) -> Vec<String> {
+        let mut ids: Vec<String> = self
+            .outstanding
+            .iter()
+            .filter(|(_, o)| mono - o.placed_mono >= self.ttl_secs)
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Close out a probe and log its outcome with the running stats. `None` for
+    /// order ids that are not probes.
+    pub fn finished(&mut self, order_id: &str, filled: bool, mono: f64) -> Option<ProbeOutcome> {
+        let o = self.outstanding.remove(order_id)?;
+        let secs = (mono - o.placed_mono).max(0.0);
+        if filled {
+            self.stats.filled += 1;
+            self.stats.fill_secs_sum += secs;
+        } else {
+            self.stats.unfilled += 1;
+        }
+        log::info!(
+            "probe.outcome order_id={order_id} side={} ticks={} filled={filled} secs={secs:.3} fill_rate={:.3} mean_fill_secs={:.3}",
+            o.plan.side.as_str(),
+            o.plan.distance_ticks,
+            self.stats.fill_rate(),
+            self.stats.mean_fill_secs().unwrap_or(0.0)
+        );
+        Some(ProbeOutcome {
+            order_id: order_id.to_string(),
+            market_id: o.plan.market_id,
+            side: o.plan.side,
+            distance_ticks: o.plan.distance_ticks,
+            filled,
+            secs,
+        })
+    }
+
+    /// One trader tick: expire probes past their TTL, close out the ones the broker
+    /// has finished (filled, expired, cancelled by a market-wide pull, rejected), then
+    /// place the next one in the first quoted market with a two-sided book.
+    pub fn step(
+        &mut self,
+        broker: &mut PaperBroker,
+        strat: &HftStrategy,
+        feed: &FeedState,
+        markets: &[SelectedMarket],
+        now: f64,
+        mono: f64,
+    ) -> Result<Vec<ProbeOutcome>> {
+        if !self.enabled {
+            return Ok(Vec::new());
+        }
+        if !self.expired(mono).is_empty() {
+            // GTD expiry, unlike a cancel, has no latency and cannot fail.
+            broker.expire_orders(now)?;
+        }
+        let mut ids: Vec<String> = self.outstanding.keys().cloned().collect();
+        ids.sort();
+        let mut outcomes = Vec::new();
+        for id in ids {
+            match broker.orders.get(&id).map(|o| o.status.as_str()) {
+                Some("open" | "held") => {}
+                status => outcomes.extend(self.finished(&id, status == Some("filled"), mono)),
+            }
+        }
+
+        for m in markets {
+            let Some(tob) = feed.get(&m.market_id) else {
+                continue;
+            };
+            let (Some(bid), Some(ask)) = (tob.best_bid, tob.best_ask) else {
+                continue;
+            };
+            if tob.closed || ask <= bid {
+                continue;
+            }
+            if let Some(plan) = self.plan(&m.market_id, (bid, ask), strat.tick_for(&tob), mono) {
+                let order_id = broker.place_gtd(
+                    &plan.market_id,
+                    plan.side,
+                    plan.price,
+                    plan.size,
+                    "probe",
+                    now + self.ttl_secs,
+                )?;
+                self.placed(&order_id, plan, mono);
+            }
+            break;
+        }
+        Ok(outcomes)
+    }
+
+    pub fn stats(&self) -> ProbeStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler() -> ProbeScheduler {
+        let mut settings = Settings::load().unwrap();
+        settings.probe_orders = true;
+        settings.probe_interval_secs = 60.0;
+        settings.probe_max_per_hour = 3;
+        settings.probe_size = 20.0;
+        settings.probe_max_notional_usd = 2.0;
+        settings.probe_distance_ticks = 2;
+        settings.probe_ttl_secs = 30.0;
+        ProbeScheduler::from_settings(&settings)
+    }
+
+    #[test]
+    fn probe_placement_respects_size_and_frequency_caps() {
+        let book = (0.50, 0.52);
+        let mut p = scheduler();
+
+        // Two ticks behind the touch, sized down to the $2 notional cap.
+        let plan = p.plan("m1", book, 0.01, 0.0).unwrap();
+        assert_eq!((plan.side, plan.price), (Side::Buy, 0.48));
+        assert!((plan.size * plan.price - 2.0).abs() < 1e-9);
+        p.placed("o1", plan, 0.0);
+
+        // One outstanding at a time, even once the interval has passed.
+        assert!(p.plan("m1", book, 0.01, 120.0).is_none());
+        assert_eq!(p.expired(29.0), Vec::<String>::new());
+        assert_eq!(p.expired(30.0), ["o1"]);
+        let out = p.finished("o1", false, 30.0).unwrap();
+        assert!(!out.filled && out.secs == 30.0);
+
+        // Interval: nothing before 60s after the last placement.
+        assert!(p.plan("m1", book, 0.01, 59.0).is_none());
+        let plan = p.plan("m1", book, 0.01, 60.0).unwrap();
+        assert_eq!((plan.side, plan.price), (Side::Sell, 0.54));
+        p.placed("o2", plan, 60.0);
+        p.finished("o2", true, 65.0).unwrap();
+        let plan = p.plan("m1", book, 0.01, 120.0).unwrap();
+        p.placed("o3", plan, 120.0);
+        p.finished("o3", true, 121.0).unwrap();
+
+        // Hourly cap: three placed in the trailing hour, the fourth waits for the first to age out.
+        assert!(p.plan("m1", book, 0.01, 3599.0).is_none());
+        assert!(p.plan("m1", book, 0.01, 3600.0).is_some());
+
+        let stats = p.stats();
+        assert_eq!((stats.placed, stats.filled, stats.unfilled), (3, 2, 1));
+        assert_eq!(stats.mean_fill_secs(), Some(3.0));
+
+        // Off by default.
+        let settings = Settings::load().unwrap();
+        assert!(!settings.probe_orders);
+        let mut off = ProbeScheduler::from_settings(&settings);
+        assert!(off.plan("m1", book, 0.01, 0.0).is_none());
+    }
+
+    #[test]
+    fn probes_go_through_the_paper_broker_and_expire_at_ttl() {
+        let mut settings = Settings::load().unwrap();
+        settings.paper_fault_rate = 0.0;
+        settings.paper_non_atomic_fail_rate = 0.0;
+        settings.probe_orders = true;
+        settings.probe_ttl_secs = 30.0;
+        let mut broker = PaperBroker::new(settings.clone(), crate::store::temp_store());
+        broker.set_clock(1000.0);
+        let strat = HftStrategy::new(settings.clone());
+        let feed = FeedState::new();
+        feed.update_book_owned("m1", 1000.0, Some(0.50), Some(0.52), 50.0, 50.0, None);
+        let markets = [SelectedMarket {
+            market_id: "m1".to_string(),
+            question: None,
+            event_id: None,
+            end_ts: None,
+            volume_24h_usd: 0.0,
+            liquidity_usd: 0.0,
+            condition_id: None,
+            clob_token_id: None,
+            microstructure_score: 0.0,
+            tick_size: None,
+            category: "other".to_string(),
+        }];
+        let mut p = ProbeScheduler::from_settings(&settings);
+
+        assert!(p
+            .step(&mut broker, &strat, &feed, &markets, 1000.0, 0.0)
+            .unwrap()
+            .is_empty());
+        let o = broker.orders.values().next().unwrap().clone();
+        assert_eq!((o.side, o.status.as_str()), (Side::Buy, "open"));
+        assert_eq!(o.meta["strategy"], "probe");
+        assert_eq!(o.expires_ts, Some(1030.0));
+
+        // Still resting before the TTL; gone, unfilled, once it passes.
+        assert!(p
+            .step(&mut broker, &strat, &feed, &markets, 1029.0, 29.0)
+            .unwrap()
+            .is_empty());
+        broker.set_clock(1030.0);
+        let out = p
+            .step(&mut broker, &strat, &feed, &markets, 1030.0, 30.0)
+            .unwrap();
+        assert_eq!(out.len(), 1);
+        assert!(!out[0].filled && out[0].secs == 30.0);
+        assert_eq!(broker.orders[&o.order_id].status, "expired");
+        assert_eq!(p.stats().unfilled, 1);
+    }
+}
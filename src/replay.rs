@@ -0,0 +1,556 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{
+    bot::{trade_one_market, TraderCtx},
+    config::Settings,
+    feed_handler::{FeedState, Tob},
+    hft_strategy::HftStrategy,
+    market_selector::SelectedMarket,
+    paper_broker::PaperBroker,
+    risk_engine::RiskEngine,
+    shadow::ShadowBook,
+    store::SqliteStore,
+};
+
+#[derive(Debug, Clone)]
+pub struct TapeEvent {
+    pub ts: f64,
+    pub market_id: String,
+    pub tob: Tob,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayStats {
+    pub pnl: f64,
+    pub realized: f64,
+    pub unrealized: f64,
+    pub fills: u64,
+    pub sharpe: f64,
+    pub max_drawdown: f64,
+}
+
+/// `--replay-speed`: `max` replays events back to back (no sleeps); a multiplier `k`
+/// sleeps each inter-event tape gap divided by `k` (1 = real time). Only wall-clock
+/// pacing changes: the strategy and broker always run on the tape clock.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    Max,
+    Times(f64),
+}
+
+impl ReplaySpeed {
+    /// `max`, or a positive multiplier (`10` or `10x`).
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim().to_ascii_lowercase();
+        if s == "max" {
+            return Ok(Self::Max);
+        }
+        let k = s
+            .strip_suffix('x')
+            .unwrap_or(&s)
+            .parse::<f64>()
+            .ok()
+            .filter(|k| k.is_finite() && *k > 0.0)
+            .ok_or_else(|| anyhow!("--replay-speed must be max or a multiplier > 0 (got {s})"))?;
+        Ok(Self::Times(k))
+    }
+
+    /// Wall-clock pause before an event `gap` tape seconds after the previous one.
+    fn pause_secs(self, gap: f64) -> f64 {
+        match self {
+            Self::Max => 0.0,
+            Self::Times(k) => gap.max(0.0) / k,
+        }
+    }
+}
+
+/// `--replay-range <from_ts>:<to_ts>`: tape unix seconds, inclusive; either end may
+/// be left empty (`:1700003600`, `1700000000:`).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplayRange {
+    pub from: Option<f64>,
+    pub to: Option<f64>,
+}
+
+impl ReplayRange {
+    pub fn parse(s: &str) -> Result<Self> {
+        let (from, to) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("--replay-range must be <from_ts>:<to_ts> (got {s})"))?;
+        let bound = |x: &str| -> Result<Option<f64>> {
+            let x = x.trim();
+            if x.is_empty() {
+                return Ok(None);
+            }
+            x.parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite())
+                .map(Some)
+                .ok_or_else(|| anyhow!("--replay-range bound is not a timestamp ({x})"))
+        };
+        let range = Self {
+            from: bound(from)?,
+            to: bound(to)?,
+        };
+        if let (Some(f), Some(t)) = (range.from, range.to) {
+            if f > t {
+                return Err(anyhow!("--replay-range from {f} is after to {t}"));
+            }
+        }
+        Ok(range)
+    }
+}
+
+/// Load recorded `tob` tape rows in `range` (oldest first, at most `limit`). Rows with
+/// unreadable payloads are skipped.
+pub fn load_tape(store: &SqliteStore, range: ReplayRange, limit: usize) -> Result<Vec<TapeEvent>> {
+    let rows = store.fetch_tape("tob", (range.from, range.to), limit)?;
+    let mut out = Vec::with_capacity(rows.len());
+    for r in rows {
+        let (Some(ts), Some(market_id)) = (
+            r.get("ts").and_then(|x| x.as_f64()),
+            r.get("market_id").and_then(|x| x.as_str()),
+        ) else {
+            continue;
+        };
+        let Some(payload) = r.get("payload") else {
+            continue;
+        };
+        let Ok(tob) = serde_json::from_value::<Tob>(payload.clone()) else {
+            continue;
+        };
+        out.push(TapeEvent {
+            ts,
+            market_id: market_id.to_string(),
+            tob,
+        });
+    }
+    Ok(out)
+}
+
+/// Replay `tape` through a fresh broker/strategy pair seeded with `seed`.
+///
+/// Orders, fills and quote telemetry go to a throwaway SQLite file, so the live
+/// DB is never touched and two arms never share state.
+pub fn run_arm(
+    settings: &Settings,
+    tape: &[TapeEvent],
+    seed: u64,
+    speed: ReplaySpeed,
+) -> Result<ReplayStats> {
+    run_arm_paced(settings, tape, seed, speed, &mut |secs| {
+        std::thread::sleep(std::time::Duration::from_secs_f64(secs))
+    })
+}
+
+fn run_arm_paced(
+    settings: &Settings,
+    tape: &[TapeEvent],
+    seed: u64,
+    speed: ReplaySpeed,
+    sleep: &mut dyn FnMut(f64),
+) -> Result<ReplayStats> {
+    let path = std::env::temp_dir().join(format!(
+        "superspreader-replay-{}.sqlite",
+        uuid::Uuid::new_v4()
+    ));
+    let path_str = path.to_string_lossy().to_string();
+    let store = SqliteStore::new(&path_str)?;
+    store.init_db()?;
+
+    let out = replay_into(settings, &store, tape, seed, speed, sleep);
+
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{path_str}{suffix}"));
+    }
+    out
+}
+
+fn replay_into(
+    settings: &Settings,
+    store: &SqliteStore,
+    tape: &[TapeEvent],
+    seed: u64,
+    speed: ReplaySpeed,
+    sleep: &mut dyn FnMut(f64),
+) -> Result<ReplayStats> {
+    let mut broker = PaperBroker::with_seed(settings.clone(), store.clone(), seed);
+    let strat = HftStrategy::new(settings.clone());
+    let risk = RiskEngine::new(settings.clone());
+
+    let mut last_quote_ts: HashMap<String, f64> = HashMap::new();
+    let mut last_fair: HashMap<String, f64> = HashMap::new();
+    let mut last_imb_sign: HashMap<String, i32> = HashMap::new();
+    let mut shadow = ShadowBook::default();
+    let mut marks: HashMap<String, f64> = HashMap::new();
+    // Tape books land in a feed state as the stream would write them, so the trade
+    // path (and reference anchors) read books exactly as in paper trading.
+    let feed = FeedState::new();
+
+    // Equity sampled once per tape second (same cadence as live pnl snapshots).
+    let mut equity: Vec<f64> = vec![];
+    let mut last_sample_sec: Option<i64> = None;
+    let mut prev_ts: Option<f64> = None;
+
+    for ev in tape {
+        if let Some(prev) = prev_ts {
+            let pause = speed.pause_secs(ev.ts - prev);
+            if pause > 0.0 {
+                sleep(pause);
+            }
+        }
+        prev_ts = Some(ev.ts);
+        broker.set_clock(ev.ts);
+        let m = SelectedMarket {
+            market_id: ev.market_id.clone(),
+            question: None,
+            event_id: None,
+            end_ts: None,
+            volume_24h_usd: 0.0,
+            liquidity_usd: 0.0,
+            condition_id: None,
+            clob_token_id: None,
+            microstructure_score: 0.0,
+            tick_size: None,
+            category: "other".to_string(),
+        };
+        let mut ctx = TraderCtx {
+            settings,
+            store,
+            risk: &risk,
+            strat: &strat,
+            broker: &mut broker,
+            now: ev.ts,
+            mono: ev.ts,
+            last_quote_ts: &mut last_quote_ts,
+            last_fair: &mut last_fair,
+            last_imb_sign: &mut last_imb_sign,
+            shadow: &mut shadow,
+        };
+        feed.upsert(&ev.market_id, ev.tob.clone());
+        let Some(tob) = feed.get(&ev.market_id) else {
+            continue;
+        };
+        let reference = strat
+            .reference_for(&ev.market_id)
+            .and_then(|(r, beta)| feed.get(r).map(|t| (t, beta)));
+        trade_one_market(&mut ctx, &m, &tob, reference.as_ref().map(|(t, b)| (t, *b)))
+            .with_context(|| format!("replay market={} ts={}", ev.market_id, ev.ts))?;
+
+        if let Some(mid) = tob.mark(&settings.mark_source) {
+            marks.insert(ev.market_id.clone(), mid);
+        }
+        let sec = ev.ts.floor() as i64;
+        if last_sample_sec != Some(sec) {
+            last_sample_sec = Some(sec);
+            equity.push(mark_equity(&broker, &marks).2);
+        }
+    }
+
+    let (unrealized, realized, pnl) = mark_equity(&broker, &marks);
+    equity.push(pnl);
+    Ok(ReplayStats {
+        pnl,
+        realized,
+        unrealized,
+        fills: broker.counters.fills,
+        sharpe: sharpe(&equity),
+        max_drawdown: max_drawdown(&equity),
+    })
+}
+
+fn mark_equity(broker: &PaperBroker, marks: &HashMap<String, f64>) -> (f64, f64, f64) {
+    let realized = broker.realized_pnl_total();
+    let unrealized: f64 = broker
+        .positions
+        .iter()
+        .map(|(mid, p)| {
+            let mark = marks.get(mid).copied().unwrap_or(p.avg_price);
+            (mark - p.avg_price) * p.qty
+        })
+        .sum();
+    (unrealized, realized, unrealized + realized)
+}
+
+/// Per-sample Sharpe of equity increments (not annualized; 0 when flat).
+fn sharpe(equity: &[f64]) -> f64 {
+    let rets: Vec<f64> = equity.windows(2).map(|w| w[1] - w[0]).collect();
+    if rets.len() < 2 {
+        return 0.0;
+    }
+    let n = rets.len() as f64;
+    let mean = rets.iter().sum::<f64>() / n;
+    let var = rets.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    let sd = var.sqrt();
+    if sd > 1e-12 {
+        mean / sd
+    } else {
+        0.0
+    }
+}
+
+fn max_drawdown(equity: &[f64]) -> f64 {
+    let mut peak = f64::NEG_INFINITY;
+    let mut dd: f64 = 0.0;
+    for &e in equity {
+        peak = peak.max(e);
+        dd = dd.max(peak - e);
+    }
+    dd
+}
+
+/// `RUN_MODE=backtest`: replay the recorded `tob` tape (oldest first, within `range`)
+/// through one arm of `settings`. Errors on an empty tape rather than reporting zeros.
+pub fn backtest(
+    settings: &Settings,
+    store: &SqliteStore,
+    limit: usize,
+    range: ReplayRange,
+    seed: u64,
+    speed: ReplaySpeed,
+) -> Result<ReplayStats> {
+    let tape = load_tape(store, range, limit)?;
+    if tape.is_empty() {
+        return Err(anyhow!(
+            "backtest: no tape rows in {range:?} (record some with TAPE_RECORD_ENABLED=1)"
+        ));
+    }
+    log::info!(
+        "backtest.start events={} seed={} speed={:?} range={:?} from_ts={} to_ts={}",
+        tape.len(),
+        seed,
+        speed,
+        range,
+        tape[0].ts,
+        tape[tape.len() - 1].ts
+    );
+    let stats = run_arm(settings, &tape, seed, speed)?;
+    log::info!(
+        "backtest.done fills={} pnl={:.4} sharpe={:.4}",
+        stats.fills,
+        stats.pnl,
+        stats.sharpe
+    );
+    Ok(stats)
+}
+
+pub fn format_summary(s: &ReplayStats) -> String {
+    let rows: [(&str, f64); 6] = [
+        ("pnl", s.pnl),
+        ("realized", s.realized),
+        ("unrealized", s.unrealized),
+        ("fills", s.fills as f64),
+        ("sharpe", s.sharpe),
+        ("max_drawdown", s.max_drawdown),
+    ];
+    let mut out = format!("{:<14}{:>14}\n", "metric", "value");
+    for (name, v) in rows {
+        out.push_str(&format!("{:<14}{:>14.4}\n", name, v));
+    }
+    out
+}
+
+/// Run both arms over the same tape with the same seed and render a side-by-side table.
+pub fn compare(
+    a: &Settings,
+    b: &Settings,
+    tape: &[TapeEvent],
+    seed: u64,
+    speed: ReplaySpeed,
+) -> Result<(ReplayStats, ReplayStats)> {
+    let ra = run_arm(a, tape, seed, speed)?;
+    let rb = run_arm(b, tape, seed, speed)?;
+    Ok((ra, rb))
+}
+
+pub fn format_comparison(a: &ReplayStats, b: &ReplayStats) -> String {
+    let rows: [(&str, f64, f64); 6] = [
+        ("pnl", a.pnl, b.pnl),
+        ("realized", a.realized, b.realized),
+        ("unrealized", a.unrealized, b.unrealized),
+        ("fills", a.fills as f64, b.fills as f64),
+        ("sharpe", a.sharpe, b.sharpe),
+        ("max_drawdown", a.max_drawdown, b.max_drawdown),
+    ];
+    let mut out = format!(
+        "{:<14}{:>14}{:>14}{:>14}\n",
+        "metric", "A", "B", "delta(B-A)"
+    );
+    for (name, va, vb) in rows {
+        out.push_str(&format!(
+            "{:<14}{:>14.4}{:>14.4}{:>14.4}\n",
+            name,
+            va,
+            vb,
+            vb - va
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::temp_store;
+
+    fn synthetic_tape() -> Vec<TapeEvent> {
+        let t0 = 1_700_000_000.0;
+        (0..600)
+            .map(|i| {
+                let ts = t0 + i as f64 * 0.25;
+                let drift = 0.01 * ((i as f64) / 40.0).sin();
+                let bid = ((0.48 + drift) * 1000.0).round() / 1000.0;
+                TapeEvent {
+                    ts,
+                    market_id: if i % 2 == 0 { "m1" } else { "m2" }.to_string(),
+                    tob: Tob {
+                        best_bid: Some(bid),
+                        best_ask: Some(bid + 0.04),
+                        bid_depth_n: 100.0,
+                        ask_depth_n: 90.0,
+                        ts,
+                        updates_ewma_per_min: 30.0,
+                        last_trade_ema: Some(bid + 0.02),
+                        last_trade_ts: Some(ts),
+                        tick_size: None,
+                        closed: false,
+                        settle_price: None,
+                        polled: false,
+                        mid_var_ewma: None,
+                        bid_touch_size: None,
+                        ask_touch_size: None,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_configs_produce_zero_delta() {
+        let settings = Settings::load().unwrap();
+        let tape = synthetic_tape();
+        let (a, b) = compare(&settings, &settings, &tape, 7, ReplaySpeed::Max).unwrap();
+        assert!(a.fills > 0, "tape should generate fills: {a:?}");
+        assert_eq!(a, b);
+        assert!(format_comparison(&a, &b).contains("0.0000"));
+    }
+
+    #[test]
+    fn replay_speed_changes_pacing_but_not_pnl() {
+        let settings = Settings::load().unwrap();
+        let tape = synthetic_tape();
+
+        let mut max_pauses = vec![];
+        let fast = run_arm_paced(&settings, &tape, 7, ReplaySpeed::Max, &mut |s| {
+            max_pauses.push(s)
+        })
+        .unwrap();
+        assert!(max_pauses.is_empty());
+
+        // Real time: every 0.25s tape gap is honored (recorded here rather than slept).
+        let mut pauses = vec![];
+        let real = run_arm_paced(&settings, &tape, 7, ReplaySpeed::Times(1.0), &mut |s| {
+            pauses.push(s)
+        })
+        .unwrap();
+        assert_eq!(pauses.len(), tape.len() - 1);
+        assert!(pauses.iter().all(|p| (p - 0.25).abs() < 1e-9));
+        assert!(fast.fills > 0);
+        assert_eq!(fast, real);
+
+        // 10x really sleeps (a tenth of each gap) and still lands on the same PnL.
+        let short = &tape[..9];
+        let started = std::time::Instant::now();
+        let paced = run_arm(&settings, short, 7, ReplaySpeed::parse("10x").unwrap()).unwrap();
+        assert!(started.elapsed() >= std::time::Duration::from_millis(200));
+        assert_eq!(
+            paced,
+            run_arm(&settings, short, 7, ReplaySpeed::Max).unwrap()
+        );
+    }
+
+    #[test]
+    fn load_tape_applies_the_range_before_the_limit() {
+        let store = temp_store();
+        for ev in synthetic_tape() {
+            let payload = serde_json::to_value(&ev.tob).unwrap();
+            store
+                .insert_tape(ev.ts, &ev.market_id, "tob", &payload)
+                .unwrap();
+        }
+        let tape = synthetic_tape();
+        let late = tape[tape.len() - 3].ts;
+        let range = ReplayRange {
+            from: Some(late),
+            to: None,
+        };
+
+        // The three newest rows, though a limit of 3 from the start would miss them all.
+        let got = load_tape(&store, range, 3).unwrap();
+        let want: Vec<f64> = tape[tape.len() - 3..].iter().map(|e| e.ts).collect();
+        assert_eq!(got.iter().map(|e| e.ts).collect::<Vec<_>>(), want);
+        assert_eq!(
+            load_tape(&store, ReplayRange::default(), 3).unwrap()[0].ts,
+            tape[0].ts
+        );
+    }
+
+    #[test]
+    fn backtest_replays_the_stored_tape_to_a_deterministic_pnl() {
+        let store = temp_store();
+        let tape = synthetic_tape();
+        for ev in &tape {
+            let payload = serde_json::to_value(&ev.tob).unwrap();
+            store
+                .insert_tape(ev.ts, &ev.market_id, "tob", &payload)
+                .unwrap();
+        }
+        let settings = Settings::load().unwrap();
+        let all = ReplayRange::default();
+
+        let first = backtest(&settings, &store, 10_000, all, 7, ReplaySpeed::Max).unwrap();
+        assert!(first.fills > 0, "tape should generate fills: {first:?}");
+        assert!(first.pnl.is_finite());
+        // Same tape, seed and config: the same final PnL, run after run, and the same
+        // as replaying the in-memory tape (the stored rows round-trip exactly).
+        let again = backtest(&settings, &store, 10_000, all, 7, ReplaySpeed::Max).unwrap();
+        assert_eq!(first, again);
+        assert_eq!(
+            first,
+            run_arm(&settings, &tape, 7, ReplaySpeed::Max).unwrap()
+        );
+        assert!(format_summary(&first).contains("sharpe"));
+
+        let empty = ReplayRange::parse("1:2").unwrap();
+        assert!(backtest(&settings, &store, 10_000, empty, 7, ReplaySpeed::Max).is_err());
+
+        // A late window is found however small the limit is next to the whole tape.
+        let late = ReplayRange {
+            from: Some(tape[500].ts),
+            to: None,
+        };
+        assert_eq!(
+            backtest(&settings, &store, 100, late, 7, ReplaySpeed::Max).unwrap(),
+            run_arm(&settings, &tape[500..], 7, ReplaySpeed::Max).unwrap()
+        );
+    }
+
+    #[test]
+    fn replay_speed_and_range_parse() {
+        assert_eq!(ReplaySpeed::parse("MAX").unwrap(), ReplaySpeed::Max);
+        assert_eq!(ReplaySpeed::parse("10").unwrap(), ReplaySpeed::Times(10.0));
+        assert_eq!(ReplaySpeed::parse("0.5x").unwrap(), ReplaySpeed::Times(0.5));
+        assert!(ReplaySpeed::parse("0").is_err());
+        assert!(ReplaySpeed::parse("fast").is_err());
+
+        let r = ReplayRange::parse("100:200").unwrap();
+        assert_eq!((r.from, r.to), (Some(100.0), Some(200.0)));
+        let open = ReplayRange::parse(":200").unwrap();
+        assert_eq!((open.from, open.to), (None, Some(200.0)));
+        assert!(ReplayRange::parse("200:100").is_err());
+        assert!(ReplayRange::parse("100").is_err());
+        assert!(ReplayRange::parse("a:b").is_err());
+    }
+}
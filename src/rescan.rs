@@ -0,0 +1,108 @@
+use tokio::sync::{mpsc, oneshot};
+
+/// Pending manual rescans beyond this are refused; the queued ones are coalesced
+/// into a single scan anyway, so more slots would only add latency.
+const RESCAN_QUEUE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanCounts {
+    pub eligible: i64,
+    pub top: i64,
+}
+
+pub type ScanReply = oneshot::Sender<std::result::Result<ScanCounts, String>>;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RescanError {
+    /// Enough rescans are already queued; this click would not change the result.
+    Busy,
+    /// The scanner loop is not running (or dropped the request).
+    Unavailable,
+    /// The scan ran and failed.
+    Failed(String),
+}
+
+/// Dashboard side of the manual rescan channel.
+#[derive(Clone)]
+pub struct RescanHandle {
+    tx: mpsc::Sender<ScanReply>,
+}
+
+/// Scanner side of the manual rescan channel.
+pub struct RescanRx {
+    rx: mpsc::Receiver<ScanReply>,
+}
+
+pub fn rescan_channel() -> (RescanHandle, RescanRx) {
+    let (tx, rx) = mpsc::channel(RESCAN_QUEUE);
+    (RescanHandle { tx }, RescanRx { rx })
+}
+
+impl RescanHandle {
+    /// Ask the scanner loop to run now and wait for that scan's counts.
+    pub async fn request(&self) -> std::result::Result<ScanCounts, RescanError> {
+        let (reply, done) = oneshot::channel();
+        self.tx.try_send(reply).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => RescanError::Busy,
+            mpsc::error::TrySendError::Closed(_) => RescanError::Unavailable,
+        })?;
+        match done.await {
+            Ok(Ok(counts)) => Ok(counts),
+            Ok(Err(e)) => Err(RescanError::Failed(e)),
+            Err(_) => Err(RescanError::Unavailable),
+        }
+    }
+}
+
+impl RescanRx {
+    /// Wait for a rescan request, then take every request queued behind it so one
+    /// scan answers all of them (debounces rapid clicks). `None` once all handles are gone.
+    pub async fn recv(&mut self) -> Option<Vec<ScanReply>> {
+        let first = self.rx.recv().await?;
+        let mut waiters = vec![first];
+        while let Ok(more) = self.rx.try_recv() {
+            waiters.push(more);
+        }
+        Some(waiters)
+    }
+}
+
+pub fn reply_all(waiters: Vec<ScanReply>, res: std::result::Result<ScanCounts, String>) {
+    for w in waiters {
+        let _ = w.send(res.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn queued_requests_share_one_scan_and_overflow_is_busy() {
+        let (handle, mut rx) = rescan_channel();
+        let mut pending = Vec::new();
+        for _ in 0..RESCAN_QUEUE {
+            let h = handle.clone();
+            pending.push(tokio::spawn(async move { h.request().await }));
+        }
+        // Let every request land in the queue before the scanner picks them up.
+        while handle.tx.capacity() > 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(handle.request().await, Err(RescanError::Busy));
+
+        let waiters = rx.recv().await.unwrap();
+        assert_eq!(waiters.len(), RESCAN_QUEUE);
+        let counts = ScanCounts {
+            eligible: 12,
+            top: 3,
+        };
+        reply_all(waiters, Ok(counts));
+        for p in pending {
+            assert_eq!(p.await.unwrap(), Ok(counts));
+        }
+
+        drop(rx);
+        assert_eq!(handle.request().await, Err(RescanError::Unavailable));
+    }
+}
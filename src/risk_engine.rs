@@ -1,4 +1,12 @@
-use crate::{config::Settings, feed_handler::Tob};
+use std::collections::HashMap;
+
+use parking_lot::Mutex;
+
+use crate::{
+    config::Settings,
+    feed_handler::Tob,
+    paper_broker::{PaperBroker, Position, Side, FLAT_QTY_EPS},
+};
 
 #[derive(Debug, Clone)]
 pub struct RiskDecision {
@@ -6,35 +14,157 @@ pub struct RiskDecision {
     pub reason: Option<&'static str>,
 }
 
+/// Whole-book exposure against `MAX_GROSS_EXPOSURE_USD` / `MAX_NET_EXPOSURE_USD`, and
+/// the number of markets held against `MAX_MARKETS_WITH_INVENTORY`.
+#[derive(Debug, Clone)]
+pub struct PortfolioDecision {
+    pub gross_usd: f64,
+    pub net_usd: f64,
+    pub markets_with_inventory: usize,
+    max_gross_usd: f64,
+    max_net_usd: f64,
+    max_markets_with_inventory: usize,
+}
+
+impl PortfolioDecision {
+    /// Size of a quote that may go out given the market's current position.
+    ///
+    /// The part that shrinks the position (up to flat) is always allowed. The rest
+    /// opens or extends risk and is dropped if it would take gross exposure over
+    /// its cap, or net exposure over its cap and further from zero, or if it would
+    /// open a position in a new market while the market cap is reached.
+    pub fn allowed_size(&self, side: Side, position_qty: f64, size: f64, price: f64) -> f64 {
+        if self.blocks_new_market(position_qty) {
+            return 0.0;
+        }
+        let dir = match side {
+            Side::Buy => 1.0,
+            Side::Sell => -1.0,
+        };
+        let reducing = size.min((-dir * position_qty).max(0.0));
+        let extra = size - reducing;
+        if extra <= 0.0 {
+            return size;
+        }
+        let gross_ok =
+            self.max_gross_usd <= 0.0 || self.gross_usd + extra * price <= self.max_gross_usd;
+        let net_after = (self.net_usd + dir * size * price).abs();
+        let net_ok = self.max_net_usd <= 0.0
+            || net_after <= self.max_net_usd
+            || net_after <= self.net_usd.abs();
+        if gross_ok && net_ok {
+            size
+        } else {
+            reducing
+        }
+    }
+
+    /// Whether a market holding `position_qty` is flat while inventory is already held
+    /// in `MAX_MARKETS_WITH_INVENTORY` others: nothing may open there.
+    pub fn blocks_new_market(&self, position_qty: f64) -> bool {
+        self.max_markets_with_inventory > 0
+            && position_qty.abs() <= FLAT_QTY_EPS
+            && self.markets_with_inventory >= self.max_markets_with_inventory
+    }
+
+    /// Which cap (if any) is already reached, for status reporting.
+    pub fn capped_reason(&self) -> Option<&'static str> {
+        if self.max_gross_usd > 0.0 && self.gross_usd >= self.max_gross_usd {
+            Some("gross_exposure_cap")
+        } else if self.max_net_usd > 0.0 && self.net_usd.abs() >= self.max_net_usd {
+            Some("net_exposure_cap")
+        } else if self.max_markets_with_inventory > 0
+            && self.markets_with_inventory >= self.max_markets_with_inventory
+        {
+            Some("max_markets_with_inventory")
+        } else {
+            None
+        }
+    }
+}
+
 pub struct RiskEngine {
     settings: Settings,
+    // Last stop-loss/take-profit exit per market (monotonic ts), for `EXIT_DEBOUNCE_SECS`.
+    last_exit: Mutex<HashMap<String, f64>>,
 }
 
 impl RiskEngine {
     pub fn new(settings: Settings) -> Self {
-        Self { settings }
+        Self {
+            settings,
+            last_exit: Mutex::new(HashMap::new()),
+        }
     }
 
-    pub fn can_quote(
+    /// Stop-loss / take-profit check for one position valued at `exit_px` (the touch a
+    /// flattening IOC would take): `stop_loss` once the unrealized loss reaches
+    /// `STOP_LOSS_PCT` of cost, `take_profit` once the gain reaches `TAKE_PROFIT_PCT`.
+    /// A market that exited within `EXIT_DEBOUNCE_SECS` of `now` (see [`Self::note_exit`])
+    /// is skipped.
+    pub fn position_exit(
         &self,
-        tob: &Tob,
-        now_ts: f64,
-        is_active_market: bool,
-        min_profitable_spread_bps: f64,
-    ) -> RiskDecision {
+        market_id: &str,
+        pos: &Position,
+        exit_px: f64,
+        now: f64,
+    ) -> Option<&'static str> {
+        let (sl, tp) = (self.settings.stop_loss_pct, self.settings.take_profit_pct);
+        if (sl <= 0.0 && tp <= 0.0) || pos.qty.abs() <= FLAT_QTY_EPS || pos.avg_price <= 0.0 {
+            return None;
+        }
+        let cost = pos.qty.abs() * pos.avg_price;
+        let ret_pct = (exit_px - pos.avg_price) * pos.qty / cost * 100.0;
+        let reason = if sl > 0.0 && ret_pct <= -sl {
+            "stop_loss"
+        } else if tp > 0.0 && ret_pct >= tp {
+            "take_profit"
+        } else {
+            return None;
+        };
+        if self
+            .last_exit
+            .lock()
+            .get(market_id)
+            .is_some_and(|t| now - t < self.settings.exit_debounce_secs)
+        {
+            return None;
+        }
+        Some(reason)
+    }
+
+    /// An exit IOC for `market_id` filled at `now`: start its debounce. Exits that take
+    /// nothing are retried on the next tick instead.
+    pub fn note_exit(&self, market_id: &str, now: f64) {
+        self.last_exit.lock().insert(market_id.to_string(), now);
+    }
+
+    /// Why this book is too old to act on, if it is: past `REJECT_FEED_LAG_MS`, or an
+    /// inactive market's book past `MAX_FEED_LAG_SECS`.
+    pub fn feed_lag(&self, tob: &Tob, now_ts: f64, is_active_market: bool) -> Option<&'static str> {
         let feed_lag_ms = ((now_ts - tob.ts).max(0.0) * 1000.0) as u64;
         if feed_lag_ms > self.settings.reject_feed_lag_ms {
-            return RiskDecision {
-                ok: false,
-                reason: Some("feed_lag"),
-            };
+            return Some("feed_lag");
         }
 
         let stale_secs = (now_ts - tob.ts).max(0.0);
         if !is_active_market && stale_secs > self.settings.max_feed_lag_secs {
+            return Some("feed_lag_max");
+        }
+        None
+    }
+
+    pub fn can_quote(
+        &self,
+        tob: &Tob,
+        now_ts: f64,
+        is_active_market: bool,
+        min_profitable_spread_bps: f64,
+    ) -> RiskDecision {
+        if let Some(reason) = self.feed_lag(tob, now_ts, is_active_market) {
             return RiskDecision {
                 ok: false,
-                reason: Some("feed_lag_max"),
+                reason: Some(reason),
             };
         }
 
@@ -59,6 +189,15 @@ impl RiskEngine {
             };
         }
         let spread_bps = ((ask - bid) / mid) * 10_000.0;
+        // Too wide to trust, as opposed to too tight to earn (below).
+        if self.settings.reject_max_spread_bps > 0.0
+            && spread_bps > self.settings.reject_max_spread_bps
+        {
+            return RiskDecision {
+                ok: false,
+                reason: Some("abnormal_spread"),
+            };
+        }
         if spread_bps < min_profitable_spread_bps {
             return RiskDecision {
                 ok: false,
@@ -66,12 +205,7 @@ impl RiskEngine {
             };
         }
 
-        let total = tob.bid_depth_5 + tob.ask_depth_5;
-        let imbalance = if total > 0.0 {
-            (tob.bid_depth_5 - tob.ask_depth_5) / total
-        } else {
-            0.0
-        };
+        let imbalance = tob.imbalance();
         if imbalance.abs() > self.settings.reject_abs_imbalance {
             return RiskDecision {
                 ok: false,
@@ -84,4 +218,139 @@ impl RiskEngine {
             reason: None,
         }
     }
+
+    /// Snapshot of whole-book exposure; quotes are then sized with
+    /// [`PortfolioDecision::allowed_size`].
+    pub fn check_portfolio(&self, broker: &PaperBroker) -> PortfolioDecision {
+        let (gross_usd, net_usd) = broker.exposure_usd();
+        PortfolioDecision {
+            gross_usd,
+            net_usd,
+            markets_with_inventory: broker.markets_with_inventory(),
+            max_gross_usd: self.settings.max_gross_exposure_usd,
+            max_net_usd: self.settings.max_net_exposure_usd,
+            max_markets_with_inventory: self.settings.max_markets_with_inventory,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::temp_store;
+
+    fn engine() -> RiskEngine {
+        let mut settings = Settings::load().unwrap();
+        settings.reject_max_spread_bps = 10_000.0;
+        settings.reject_abs_imbalance = 1.0;
+        RiskEngine::new(settings)
+    }
+
+    fn tob(bid: f64, ask: f64, ts: f64) -> Tob {
+        Tob {
+            best_bid: Some(bid),
+            best_ask: Some(ask),
+            bid_depth_n: 100.0,
+            ask_depth_n: 100.0,
+            ..Tob::empty(ts)
+        }
+    }
+
+    #[test]
+    fn absurd_spread_is_rejected_separately_from_unprofitable() {
+        let risk = engine();
+        let d = risk.can_quote(&tob(0.10, 0.90, 100.0), 100.0, true, 50.0);
+        assert!(!d.ok);
+        assert_eq!(d.reason, Some("abnormal_spread"));
+
+        // A normal wide-enough book passes; a tight one fails the profitability check instead.
+        assert!(
+            risk.can_quote(&tob(0.48, 0.52, 100.0), 100.0, true, 50.0)
+                .ok
+        );
+        let tight = risk.can_quote(&tob(0.499, 0.501, 100.0), 100.0, true, 50.0);
+        assert_eq!(tight.reason, Some("unprofitable_spread"));
+    }
+
+    #[test]
+    fn gross_cap_blocks_new_longs_but_allows_sells() {
+        let store = temp_store();
+        let mut settings = Settings::load().unwrap();
+        settings.max_gross_exposure_usd = 1000.0;
+        settings.max_net_exposure_usd = 0.0;
+        let risk = RiskEngine::new(settings.clone());
+        let mut broker = PaperBroker::with_seed(settings, store, 1);
+        for (m, qty) in [("m1", 1200.0), ("m2", 800.0)] {
+            broker.positions.insert(
+                m.to_string(),
+                crate::paper_broker::Position {
+                    qty,
+                    avg_price: 0.5,
+                    realized_pnl: 0.0,
+                },
+            );
+        }
+
+        let p = risk.check_portfolio(&broker);
+        assert!((p.gross_usd - 1000.0).abs() < 1e-9);
+        assert_eq!(p.capped_reason(), Some("gross_exposure_cap"));
+        // Adding to a long, or opening elsewhere, is risk-increasing.
+        assert_eq!(p.allowed_size(Side::Buy, 1200.0, 50.0, 0.5), 0.0);
+        assert_eq!(p.allowed_size(Side::Buy, 0.0, 50.0, 0.5), 0.0);
+        // Selling down a long is allowed, but only as far as flat.
+        assert_eq!(p.allowed_size(Side::Sell, 1200.0, 50.0, 0.5), 50.0);
+        assert_eq!(p.allowed_size(Side::Sell, 30.0, 50.0, 0.5), 30.0);
+        // A sell with no long to reduce would open a short.
+        assert_eq!(p.allowed_size(Side::Sell, 0.0, 50.0, 0.5), 0.0);
+
+        // Under the cap both sides quote normally.
+        let mut loose = p.clone();
+        loose.gross_usd = 500.0;
+        assert_eq!(loose.capped_reason(), None);
+        assert_eq!(loose.allowed_size(Side::Buy, 1200.0, 50.0, 0.5), 50.0);
+    }
+
+    #[test]
+    fn position_exits_on_stop_or_target_and_debounce() {
+        let mut settings = Settings::load().unwrap();
+        settings.stop_loss_pct = 10.0;
+        settings.take_profit_pct = 20.0;
+        settings.exit_debounce_secs = 30.0;
+        let risk = RiskEngine::new(settings);
+        let pos = |qty: f64| Position {
+            qty,
+            avg_price: 0.50,
+            realized_pnl: 0.0,
+        };
+
+        // Long 10 @ 0.50: -4% and +16% stay inside the bands.
+        assert_eq!(risk.position_exit("a", &pos(10.0), 0.48, 0.0), None);
+        assert_eq!(risk.position_exit("a", &pos(10.0), 0.58, 0.0), None);
+        assert_eq!(
+            risk.position_exit("a", &pos(10.0), 0.44, 0.0),
+            Some("stop_loss")
+        );
+        // Until an exit fills the check keeps firing.
+        assert_eq!(
+            risk.position_exit("a", &pos(10.0), 0.44, 0.0),
+            Some("stop_loss")
+        );
+        risk.note_exit("a", 0.0);
+        // Same market inside the debounce: quiet, even on a deeper loss.
+        assert_eq!(risk.position_exit("a", &pos(10.0), 0.40, 10.0), None);
+        assert_eq!(
+            risk.position_exit("a", &pos(10.0), 0.40, 31.0),
+            Some("stop_loss")
+        );
+        // A short gains as the price falls.
+        assert_eq!(
+            risk.position_exit("b", &pos(-10.0), 0.38, 0.0),
+            Some("take_profit")
+        );
+        assert_eq!(
+            risk.position_exit("c", &pos(-10.0), 0.57, 0.0),
+            Some("stop_loss")
+        );
+        assert_eq!(risk.position_exit("d", &pos(0.0), 0.10, 0.0), None);
+    }
 }
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+/// Hypothetical book for `EXECUTION_MODE=shadow`: the quotes the strategy would
+/// have shown, filled only when the live book trades through them. Nothing here
+/// touches the broker; it exists to score the model against the market.
+#[derive(Debug, Default)]
+pub struct ShadowBook {
+    markets: HashMap<String, ShadowMarket>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct ShadowMarket {
+    bid: Option<f64>,
+    ask: Option<f64>,
+    size: f64,
+    position: f64,
+    cash: f64,
+    mark: f64,
+    fills: u64,
+    /// `mono` time of the last recorded intent / divergence log line.
+    last_record: Option<f64>,
+    last_log: Option<f64>,
+}
+
+impl ShadowMarket {
+    fn pnl(&self) -> f64 {
+        self.cash + self.position * self.mark
+    }
+}
+
+/// One shadow step for one market, as recorded in `shadow_intents`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowStep {
+    /// Hypothetical fills this step (previous intents the book crossed).
+    pub fills: u32,
+    pub position: f64,
+    /// Cash plus position marked at mid.
+    pub pnl: f64,
+}
+
+impl ShadowBook {
+    /// Fill the intents left from the previous step against the current book, then
+    /// stand the new ones. A bid fills when the ask comes down to it, an ask when the
+    /// bid comes up to it; each fills at the intent price, up to the size resting at
+    /// the touch that crossed it (`touch_sizes` = bid, ask).
+    pub fn step(
+        &mut self,
+        market_id: &str,
+        (best_bid, best_ask): (f64, f64),
+        (bid_size, ask_size): (f64, f64),
+        (target_bid, target_ask): (Option<f64>, Option<f64>),
+        size: f64,
+    ) -> ShadowStep {
+        let m = self.markets.entry(market_id.to_string()).or_default();
+        let mut fills = 0;
+        if let Some(px) = m.bid.filter(|&px| best_ask <= px) {
+            let qty = m.size.min(ask_size);
+            if qty > 0.0 {
+                m.position += qty;
+                m.cash -= px * qty;
+                fills += 1;
+            }
+        }
+        if let Some(px) = m.ask.filter(|&px| best_bid >= px) {
+            let qty = m.size.min(bid_size);
+            if qty > 0.0 {
+                m.position -= qty;
+                m.cash += px * qty;
+                fills += 1;
+            }
+        }
+        m.fills += fills as u64;
+        m.mark = 0.5 * (best_bid + best_ask);
+        m.bid = target_bid;
+        m.ask = target_ask;
+        m.size = size.max(0.0);
+        ShadowStep {
+            fills,
+            position: m.position,
+            pnl: m.pnl(),
+        }
+    }
+
+    pub fn position(&self, market_id: &str) -> f64 {
+        self.markets.get(market_id).map_or(0.0, |m| m.position)
+    }
+
+    /// True (and restarts the interval) when `interval` seconds have passed since the
+    /// last recorded intent for this market, or none has been recorded yet.
+    pub fn record_due(&mut self, market_id: &str, mono: f64, interval: f64) -> bool {
+        let m = self.markets.entry(market_id.to_string()).or_default();
+        due(&mut m.last_record, mono, interval)
+    }
+
+    /// Same as `record_due`, for the divergence log line.
+    pub fn log_due(&mut self, market_id: &str, mono: f64, interval: f64) -> bool {
+        let m = self.markets.entry(market_id.to_string()).or_default();
+        due(&mut m.last_log, mono, interval)
+    }
+
+    /// (hypothetical fills, PnL at the last marks) across all markets.
+    pub fn totals(&self) -> (u64, f64) {
+        self.markets
+            .values()
+            .fold((0, 0.0), |(n, pnl), m| (n + m.fills, pnl + m.pnl()))
+    }
+}
+
+fn due(last: &mut Option<f64>, mono: f64, interval: f64) -> bool {
+    if last.is_some_and(|t| mono - t < interval) {
+        return false;
+    }
+    *last = Some(mono);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intents_fill_only_when_the_book_crosses_them() {
+        let mut book = ShadowBook::default();
+        let step = book.step(
+            "m1",
+            (0.50, 0.52),
+            (100.0, 100.0),
+            (Some(0.49), Some(0.53)),
+            10.0,
+        );
+        assert_eq!(step.fills, 0);
+        assert_eq!(step.pnl, 0.0);
+
+        // Ask drops onto our bid: long 10 at 0.49, marked at the 0.485 mid.
+        let step = book.step(
+            "m1",
+            (0.48, 0.49),
+            (100.0, 100.0),
+            (Some(0.47), Some(0.50)),
+            10.0,
+        );
+        assert_eq!(step.fills, 1);
+        assert_eq!(step.position, 10.0);
+        assert!((step.pnl + 0.05).abs() < 1e-9);
+
+        // Bid lifts through our ask: flat again, 0.01 x 10 captured.
+        let step = book.step("m1", (0.50, 0.51), (100.0, 100.0), (None, None), 10.0);
+        assert_eq!(step.fills, 1);
+        assert_eq!(book.position("m1"), 0.0);
+        assert!((step.pnl - 0.1).abs() < 1e-9);
+        let (fills, pnl) = book.totals();
+        assert_eq!(fills, 2);
+        assert!((pnl - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fills_are_capped_at_the_size_on_the_crossing_touch() {
+        let mut book = ShadowBook::default();
+        book.step(
+            "m1",
+            (0.50, 0.52),
+            (100.0, 100.0),
+            (Some(0.49), Some(0.53)),
+            10.0,
+        );
+
+        // Only 4 shares offered at the 0.49 ask that crossed our bid.
+        let step = book.step("m1", (0.48, 0.49), (100.0, 4.0), (None, Some(0.53)), 10.0);
+        assert_eq!(step.fills, 1);
+        assert_eq!(step.position, 4.0);
+
+        // An empty touch fills nothing.
+        let step = book.step("m1", (0.53, 0.54), (0.0, 100.0), (None, None), 10.0);
+        assert_eq!(step.fills, 0);
+        assert_eq!(step.position, 4.0);
+    }
+
+    #[test]
+    fn record_and_log_are_throttled_per_market() {
+        let mut book = ShadowBook::default();
+        assert!(book.record_due("m1", 100.0, 1.0));
+        assert!(!book.record_due("m1", 100.5, 1.0));
+        assert!(book.record_due("m2", 100.5, 1.0));
+        assert!(book.record_due("m1", 101.0, 1.0));
+        // The log throttle is independent of the record one.
+        assert!(book.log_due("m1", 101.0, 60.0));
+        assert!(!book.log_due("m1", 160.0, 60.0));
+        assert!(book.log_due("m1", 161.0, 60.0));
+        // A zero interval records every step.
+        assert!(book.record_due("m1", 101.0, 0.0));
+    }
+}
@@ -1,12 +1,336 @@
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
-use rusqlite::{params, Connection, OptionalExtension};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
 use serde_json::Value as JsonValue;
 
+/// One market's scanner score breakdown; `excluded_reason = None` means it was selected.
+#[derive(Debug, Clone)]
+pub struct ScannerCandidate {
+    pub market_id: String,
+    pub score: f64,
+    pub volume_24h_usd: f64,
+    pub liquidity_usd: f64,
+    pub spread_bps: f64,
+    pub excluded_reason: Option<String>,
+}
+
+/// A market position from flat back to flat, with its realized PnL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundTrip {
+    pub market_id: String,
+    pub open_ts: f64,
+    pub close_ts: f64,
+    pub pnl: f64,
+}
+
+#[derive(Debug, Clone)]
+struct OpenTrip {
+    /// Signed quantity (long > 0).
+    qty: f64,
+    avg_price: f64,
+    pnl: f64,
+    open_ts: f64,
+}
+
+/// Folds fills (in time order per market) into closed round trips at average cost.
+/// A fill that flips the position closes the trip at flat and opens the next one
+/// with the remainder.
+#[derive(Debug, Default)]
+pub struct RoundTripBuilder {
+    open: HashMap<String, OpenTrip>,
+    closed: Vec<RoundTrip>,
+}
+
+impl RoundTripBuilder {
+    pub fn push(&mut self, market_id: &str, side: &str, price: f64, size: f64, ts: f64) {
+        let sign = if side.eq_ignore_ascii_case("buy") {
+            1.0
+        } else {
+            -1.0
+        };
+        if size <= 0.0 {
+            return;
+        }
+        let Some(t) = self.open.get_mut(market_id) else {
+            self.open.insert(
+                market_id.to_string(),
+                OpenTrip {
+                    qty: sign * size,
+                    avg_price: price,
+                    pnl: 0.0,
+                    open_ts: ts,
+                },
+            );
+            return;
+        };
+        if t.qty * sign > 0.0 {
+            let held = t.qty.abs();
+            t.avg_price = (t.avg_price * held + price * size) / (held + size);
+            t.qty += sign * size;
+            return;
+        }
+        let close = size.min(t.qty.abs());
+        t.pnl += close * (price - t.avg_price) * t.qty.signum();
+        t.qty += sign * close;
+        if t.qty.abs() > 1e-9 {
+            return;
+        }
+        let t = self.open.remove(market_id).expect("open trip");
+        self.closed.push(RoundTrip {
+            market_id: market_id.to_string(),
+            open_ts: t.open_ts,
+            close_ts: ts,
+            pnl: t.pnl,
+        });
+        if size - close > 1e-9 {
+            self.push(market_id, side, price, size - close, ts);
+        }
+    }
+
+    pub fn closed(&self) -> &[RoundTrip] {
+        &self.closed
+    }
+}
+
+/// Encoding for the `meta_json` / `payload_json` blobs. Each row records its own encoding
+/// (`meta_enc` / `payload_enc`), so switching does not strand rows written before.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlobEncoding {
+    /// serde JSON text (the historical format).
+    #[default]
+    Json,
+    /// MessagePack bytes stored as a BLOB; much smaller for high-frequency tape.
+    Msgpack,
+}
+
+impl BlobEncoding {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "msgpack" => Ok(Self::Msgpack),
+            other => anyhow::bail!("SQLITE_BLOB_ENCODING must be json|msgpack (got {other})"),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Msgpack => "msgpack",
+        }
+    }
+
+    fn encode(self, v: &JsonValue) -> Result<rusqlite::types::Value> {
+        Ok(match self {
+            Self::Json => rusqlite::types::Value::Text(serde_json::to_string(v)?),
+            Self::Msgpack => rusqlite::types::Value::Blob(rmp_serde::to_vec_named(v)?),
+        })
+    }
+}
+
+/// Decode a stored blob by its row's encoding flag; `None` if it is unreadable.
+fn decode_blob(enc: &str, raw: rusqlite::types::ValueRef<'_>) -> Option<JsonValue> {
+    use rusqlite::types::ValueRef;
+    match (enc, raw) {
+        ("msgpack", ValueRef::Blob(b)) => rmp_serde::from_slice(b).ok(),
+        (_, ValueRef::Text(b)) => serde_json::from_slice(b).ok(),
+        _ => None,
+    }
+}
+
+/// A row of `quote_state`: a market's requote memory as of its last requote.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteStateRow {
+    pub market_id: String,
+    /// Wall-clock ts of the last requote.
+    pub ts: f64,
+    pub fair: f64,
+    pub imb_sign: i32,
+}
+
+/// A row of `shadow_intents` (see `SqliteStore::insert_shadow_intent`).
+pub struct ShadowIntentRow<'a> {
+    pub ts: f64,
+    pub market_id: &'a str,
+    pub best_bid: f64,
+    pub best_ask: f64,
+    pub mid: f64,
+    pub fair: f64,
+    pub fair_source: &'a str,
+    /// fair - mid.
+    pub divergence: f64,
+    pub flagged: bool,
+    pub target_bid: Option<f64>,
+    pub target_ask: Option<f64>,
+    pub size: f64,
+    pub hypo_fills: u32,
+    pub hypo_position: f64,
+    pub hypo_pnl: f64,
+}
+
 #[derive(Clone)]
 pub struct SqliteStore {
     path: String,
+    encoding: BlobEncoding,
+    /// `PRAGMA wal_autocheckpoint` pages applied to every connection (`None` = SQLite default).
+    wal_autocheckpoint: Option<u32>,
+    /// `PRAGMA busy_timeout` applied to every connection: SQLite's own wait on a lock.
+    busy_timeout_ms: u32,
+    /// Extra attempts for a write that still fails with `SQLITE_BUSY`/`SQLITE_LOCKED`.
+    write_retries: u32,
+    /// Orders this process's writes; shared by every clone of the store.
+    gate: Arc<WriteGate>,
+    /// Writes from this handle yield to any waiting foreground write (see `background`).
+    background: bool,
+    /// Rows per transaction for bulk scanner writes, so each holds the gate briefly.
+    bulk_chunk_rows: usize,
+    /// Test stores delete their files once the last clone is dropped.
+    #[cfg(test)]
+    temp: Option<Arc<TempDb>>,
+}
+
+#[cfg(test)]
+struct TempDb(String);
+
+#[cfg(test)]
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", self.0));
+        }
+    }
+}
+
+/// A fresh, initialized store under the temp dir, removed when its last clone is dropped.
+#[cfg(test)]
+pub(crate) fn temp_store() -> SqliteStore {
+    let path = std::env::temp_dir()
+        .join(format!("superspreader-{}.sqlite", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .to_string();
+    let mut store = SqliteStore::new(&path).unwrap();
+    store.temp = Some(Arc::new(TempDb(path)));
+    store.init_db().unwrap();
+    store
+}
+
+/// In-process write ordering. Foreground writes (trader, dashboard) wait only for
+/// the write in progress; background writes (scanner) also wait while any
+/// foreground write is queued, so a large scanner refresh cannot starve the trade
+/// loop. Disabled, every write goes straight to SQLite's own locking.
+#[derive(Debug)]
+struct WriteGate {
+    enabled: std::sync::atomic::AtomicBool,
+    state: parking_lot::Mutex<GateState>,
+    cv: parking_lot::Condvar,
+}
+
+#[derive(Debug, Default)]
+struct GateState {
+    writing: bool,
+    foreground_waiting: usize,
+}
+
+impl WriteGate {
+    fn new() -> Self {
+        Self {
+            enabled: std::sync::atomic::AtomicBool::new(true),
+            state: parking_lot::Mutex::new(GateState::default()),
+            cv: parking_lot::Condvar::new(),
+        }
+    }
+
+    fn run<T>(&self, background: bool, op: impl FnOnce() -> T) -> T {
+        if !self.enabled.load(std::sync::atomic::Ordering::Relaxed) {
+            return op();
+        }
+        {
+            let mut st = self.state.lock();
+            if background {
+                while st.writing || st.foreground_waiting > 0 {
+                    self.cv.wait(&mut st);
+                }
+            } else {
+                st.foreground_waiting += 1;
+                while st.writing {
+                    self.cv.wait(&mut st);
+                }
+                st.foreground_waiting -= 1;
+            }
+            st.writing = true;
+        }
+        // Released even if `op` panics, so one failed write cannot wedge the rest.
+        struct Release<'a>(&'a WriteGate);
+        impl Drop for Release<'_> {
+            fn drop(&mut self) {
+                self.0.state.lock().writing = false;
+                self.0.cv.notify_all();
+            }
+        }
+        let _release = Release(self);
+        op()
+    }
+}
+
+/// First backoff between write retries; doubles per attempt.
+const WRITE_RETRY_BASE_MS: u64 = 25;
+
+/// `PRAGMA user_version` once `init_db`'s one-off data backfills have run.
+const SCHEMA_VERSION: i64 = 1;
+
+/// Result row of `PRAGMA wal_checkpoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointStats {
+    /// The checkpoint could not complete because a reader/writer held the WAL.
+    pub busy: bool,
+    /// Frames in the WAL before the checkpoint.
+    pub log_frames: i64,
+    /// Frames copied back into the main database.
+    pub checkpointed_frames: i64,
+}
+
+/// Whether `e` is a transient lock (`SQLITE_BUSY`/`SQLITE_LOCKED`) rather than a real failure.
+fn is_locked(e: &anyhow::Error) -> bool {
+    e.chain().any(|c| {
+        matches!(
+            c.downcast_ref::<rusqlite::Error>()
+                .and_then(rusqlite::Error::sqlite_error_code),
+            Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+        )
+    })
+}
+
+/// `op`, re-run up to `retries` more times (25ms, 50ms, ... apart) while it fails on a lock.
+fn retry_locked<T>(retries: u32, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < retries && is_locked(&e) => {
+                let delay = WRITE_RETRY_BASE_MS << attempt.min(6);
+                log::warn!(
+                    "sqlite.locked retry={} delay_ms={delay} err={e:#}",
+                    attempt + 1
+                );
+                backoff(std::time::Duration::from_millis(delay));
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Sleep between write retries. Store writes are called from the trade loop and other
+/// runtime tasks, so on a multi-threaded runtime the worker's queued tasks are handed
+/// to another thread first instead of stalling behind the backoff.
+fn backoff(delay: std::time::Duration) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(h) if h.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| std::thread::sleep(delay))
+        }
+        _ => std::thread::sleep(delay),
+    }
 }
 
 impl SqliteStore {
@@ -29,9 +353,65 @@ impl SqliteStore {
         // to reduce write frequency.
         Ok(Self {
             path: path.to_string(),
+            encoding: BlobEncoding::Json,
+            wal_autocheckpoint: None,
+            busy_timeout_ms: 5_000,
+            write_retries: 3,
+            gate: Arc::new(WriteGate::new()),
+            background: false,
+            bulk_chunk_rows: 200,
+            #[cfg(test)]
+            temp: None,
         })
     }
 
+    /// Encoding for newly written meta/payload blobs (reads handle both).
+    pub fn with_blob_encoding(mut self, encoding: BlobEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// WAL size (pages) at which the committing connection checkpoints automatically; 0 disables.
+    pub fn with_wal_autocheckpoint(mut self, pages: u32) -> Self {
+        self.wal_autocheckpoint = Some(pages);
+        self
+    }
+
+    /// How long SQLite waits on a locked database before returning `SQLITE_BUSY`.
+    pub fn with_busy_timeout_ms(mut self, ms: u32) -> Self {
+        self.busy_timeout_ms = ms;
+        self
+    }
+
+    /// Retries (with backoff) for writes that still hit a lock after the busy timeout.
+    pub fn with_write_retries(mut self, retries: u32) -> Self {
+        self.write_retries = retries;
+        self
+    }
+
+    /// Turn the in-process write gate on or off for every clone of this store.
+    pub fn with_write_gate(self, enabled: bool) -> Self {
+        self.gate
+            .enabled
+            .store(enabled, std::sync::atomic::Ordering::Relaxed);
+        self
+    }
+
+    /// Rows per transaction for bulk background writes (at least 1).
+    pub fn with_bulk_chunk_rows(mut self, rows: usize) -> Self {
+        self.bulk_chunk_rows = rows.max(1);
+        self
+    }
+
+    /// A handle whose writes give way to queued foreground writes. The scanner uses
+    /// one so its refresh-tick upserts never sit in front of the trade loop.
+    pub fn background(&self) -> Self {
+        Self {
+            background: true,
+            ..self.clone()
+        }
+    }
+
     pub fn path(&self) -> &str {
         &self.path
     }
@@ -39,14 +419,60 @@ impl SqliteStore {
     fn open_conn(&self) -> Result<Connection> {
         let conn =
             Connection::open(&self.path).with_context(|| format!("open sqlite {}", self.path))?;
+        // Before journal_mode: switching to WAL itself needs the lock.
+        conn.busy_timeout(std::time::Duration::from_millis(
+            self.busy_timeout_ms as u64,
+        ))?;
         conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA synchronous=NORMAL;")?;
+        if let Some(pages) = self.wal_autocheckpoint {
+            conn.execute_batch(&format!("PRAGMA wal_autocheckpoint={pages};"))?;
+        }
         Ok(conn)
     }
 
-    pub fn init_db(&self) -> Result<()> {
+    /// Run a write on a fresh connection, retrying with backoff while it fails with
+    /// `SQLITE_BUSY`/`SQLITE_LOCKED`. Any other error is returned as is. Each attempt
+    /// is one `BEGIN IMMEDIATE` transaction, so a failed attempt rolls back whole and
+    /// a retry never re-applies half of a multi-statement write. Each attempt holds
+    /// the write gate; backoff sleeps do not.
+    fn write<T>(&self, mut op: impl FnMut(&Connection) -> Result<T>) -> Result<T> {
+        retry_locked(self.write_retries, || {
+            self.gate.run(self.background, || {
+                let mut conn = self.open_conn()?;
+                let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                let out = op(&tx)?;
+                tx.commit()?;
+                Ok(out)
+            })
+        })
+    }
+
+    /// Explicit WAL checkpoint. `PASSIVE` copies what it can without waiting on
+    /// readers or writers (never stalls the caller on locks, but leaves the WAL
+    /// file at its high-water size); `TRUNCATE` also resets the file to zero bytes
+    /// once every frame is copied, and reports `busy` if a reader is in the way.
+    pub fn wal_checkpoint(&self, truncate: bool) -> Result<CheckpointStats> {
         let conn = self.open_conn()?;
-        conn.execute_batch(
-            r#"
+        let mode = if truncate { "TRUNCATE" } else { "PASSIVE" };
+        let (busy, log_frames, checkpointed_frames) =
+            conn.query_row(&format!("PRAGMA wal_checkpoint({mode})"), [], |r| {
+                Ok((
+                    r.get::<_, i64>(0)?,
+                    r.get::<_, i64>(1)?,
+                    r.get::<_, i64>(2)?,
+                ))
+            })?;
+        Ok(CheckpointStats {
+            busy: busy != 0,
+            log_frames,
+            checkpointed_frames,
+        })
+    }
+
+    pub fn init_db(&self) -> Result<()> {
+        self.write(|conn| {
+            conn.execute_batch(
+                r#"
  CREATE TABLE IF NOT EXISTS markets (
    market_id TEXT PRIMARY KEY,
    question TEXT,
@@ -57,7 +483,8 @@ impl SqliteStore {
    liquidity_usd REAL,
    condition_id TEXT,
    clob_token_id TEXT,
-   updated_ts REAL
+   updated_ts REAL,
+   category TEXT
  );
  
  CREATE TABLE IF NOT EXISTS orders (
@@ -69,7 +496,10 @@ impl SqliteStore {
    created_ts REAL,
    status TEXT,
    filled_size REAL,
-   meta_json TEXT
+   meta_json TEXT,
+   meta_enc TEXT NOT NULL DEFAULT 'json',
+   cancelled_ts REAL,
+   filled_ts REAL
  );
  
  CREATE TABLE IF NOT EXISTS fills (
@@ -80,7 +510,8 @@ impl SqliteStore {
    price REAL,
    size REAL,
    ts REAL,
-   meta_json TEXT
+   meta_json TEXT,
+   meta_enc TEXT NOT NULL DEFAULT 'json'
  );
  
  CREATE TABLE IF NOT EXISTS tape (
@@ -88,7 +519,8 @@ impl SqliteStore {
    ts REAL,
    market_id TEXT,
    kind TEXT,
-   payload_json TEXT
+   payload_json TEXT,
+   payload_enc TEXT NOT NULL DEFAULT 'json'
  );
  
  CREATE INDEX IF NOT EXISTS idx_tape_ts ON tape(ts);
@@ -106,6 +538,10 @@ impl SqliteStore {
    realized_pnl REAL
  );
  
+ CREATE INDEX IF NOT EXISTS idx_positions_market ON position_snapshots(market_id, id);
+ CREATE INDEX IF NOT EXISTS idx_positions_market_ts ON position_snapshots(market_id, ts);
+ CREATE INDEX IF NOT EXISTS idx_positions_ts ON position_snapshots(ts);
+
  CREATE TABLE IF NOT EXISTS pnl_snapshots (
    id INTEGER PRIMARY KEY AUTOINCREMENT,
    ts REAL,
@@ -128,12 +564,35 @@ impl SqliteStore {
    width REAL,
    skew REAL,
    target_bid REAL,
-   target_ask REAL
+   target_ask REAL,
+   order_size REAL,
+   size_source TEXT
  );
  
  CREATE INDEX IF NOT EXISTS idx_quotes_ts ON quote_snapshots(ts);
  CREATE INDEX IF NOT EXISTS idx_quotes_market ON quote_snapshots(market_id, ts);
  
+ CREATE TABLE IF NOT EXISTS shadow_intents (
+   id INTEGER PRIMARY KEY AUTOINCREMENT,
+   ts REAL,
+   market_id TEXT,
+   best_bid REAL,
+   best_ask REAL,
+   mid REAL,
+   fair REAL,
+   fair_source TEXT,
+   divergence REAL,
+   flagged INTEGER,
+   target_bid REAL,
+   target_ask REAL,
+   size REAL,
+   hypo_fills INTEGER,
+   hypo_position REAL,
+   hypo_pnl REAL
+ );
+
+ CREATE INDEX IF NOT EXISTS idx_shadow_intents_ts ON shadow_intents(ts);
+
  CREATE TABLE IF NOT EXISTS scanner_snapshots (
    id INTEGER PRIMARY KEY AUTOINCREMENT,
    ts REAL,
@@ -142,6 +601,19 @@ impl SqliteStore {
  );
  
  CREATE INDEX IF NOT EXISTS idx_scanner_ts ON scanner_snapshots(ts);
+
+ CREATE TABLE IF NOT EXISTS scanner_candidates (
+   id INTEGER PRIMARY KEY AUTOINCREMENT,
+   ts REAL,
+   market_id TEXT,
+   score REAL,
+   volume_24h_usd REAL,
+   liquidity_usd REAL,
+   spread_bps REAL,
+   excluded_reason TEXT
+ );
+
+ CREATE INDEX IF NOT EXISTS idx_scanner_candidates_ts ON scanner_candidates(ts);
  
  CREATE TABLE IF NOT EXISTS watchlist (
    rank INTEGER PRIMARY KEY,
@@ -156,22 +628,101 @@ impl SqliteStore {
    message TEXT,
    detail TEXT
  );
+
+ CREATE TABLE IF NOT EXISTS quote_state (
+   market_id TEXT PRIMARY KEY,
+   ts REAL,
+   fair REAL,
+   imb_sign INTEGER
+ );
  "#,
-        )?;
-        Ok(())
+            )?;
+            // Databases created before blob encodings: every existing row is JSON.
+            for (table, column) in [
+                ("orders", "meta_enc"),
+                ("fills", "meta_enc"),
+                ("tape", "payload_enc"),
+            ] {
+                let exists: bool = conn.query_row(
+                    "SELECT COUNT(*) > 0 FROM pragma_table_info(?) WHERE name = ?",
+                    params![table, column],
+                    |r| r.get(0),
+                )?;
+                if !exists {
+                    conn.execute_batch(&format!(
+                        "ALTER TABLE {table} ADD COLUMN {column} TEXT NOT NULL DEFAULT 'json'"
+                    ))?;
+                }
+            }
+            // Columns added later; older rows leave them NULL.
+            for (table, column, ty) in [
+                ("quote_snapshots", "order_size", "REAL"),
+                ("quote_snapshots", "size_source", "TEXT"),
+                ("markets", "category", "TEXT"),
+                ("orders", "cancelled_ts", "REAL"),
+                ("orders", "filled_ts", "REAL"),
+            ] {
+                let exists: bool = conn.query_row(
+                    "SELECT COUNT(*) > 0 FROM pragma_table_info(?) WHERE name = ?",
+                    params![table, column],
+                    |r| r.get(0),
+                )?;
+                if !exists {
+                    conn.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {ty}"))?;
+                }
+            }
+            // Older builds wrote a shared placeholder for markets without an event id.
+            // One-off backfill: `user_version` records that it ran, so startup does not
+            // rescan the snapshot tables every time.
+            let version: i64 = conn.query_row("PRAGMA user_version", [], |r| r.get(0))?;
+            if version < SCHEMA_VERSION {
+                conn.execute_batch(&format!(
+                    r#"
+ UPDATE position_snapshots SET event_id = NULL WHERE event_id = 'event:unknown';
+ UPDATE quote_snapshots SET event_id = NULL WHERE event_id = 'event:unknown';
+ PRAGMA user_version = {SCHEMA_VERSION};
+ "#
+                ))?;
+            }
+            Ok(())
+        })
     }
 
     pub fn clear_trading_state(&self) -> Result<()> {
-        let conn = self.open_conn()?;
-        conn.execute_batch(
-            r#"
+        self.write(|conn| {
+            conn.execute_batch(
+                r#"
  DELETE FROM orders;
  DELETE FROM fills;
  DELETE FROM position_snapshots;
  DELETE FROM pnl_snapshots;
+ DELETE FROM quote_state;
  "#,
-        )?;
-        Ok(())
+            )?;
+            Ok(())
+        })
+    }
+
+    /// `clear_trading_state` plus quote/scanner/watchlist history, so a reset
+    /// dashboard shows no stale quotes. The `markets` metadata cache is only
+    /// dropped when `include_market_cache` is set (it is slow to rebuild).
+    pub fn clear_all_state(&self, include_market_cache: bool) -> Result<()> {
+        self.clear_trading_state()?;
+        self.write(|conn| {
+            conn.execute_batch(
+                r#"
+ DELETE FROM quote_snapshots;
+ DELETE FROM shadow_intents;
+ DELETE FROM scanner_snapshots;
+ DELETE FROM scanner_candidates;
+ DELETE FROM watchlist;
+ "#,
+            )?;
+            if include_market_cache {
+                conn.execute("DELETE FROM markets", [])?;
+            }
+            Ok(())
+        })
     }
 
     pub fn upsert_runtime_status(
@@ -182,9 +733,9 @@ impl SqliteStore {
         detail: Option<&str>,
         ts: f64,
     ) -> Result<()> {
-        let conn = self.open_conn()?;
-        conn.execute(
-            r#"
+        self.write(|conn| {
+            conn.execute(
+                r#"
  INSERT INTO runtime_status(component, ts, level, message, detail)
  VALUES(?,?,?,?,?)
  ON CONFLICT(component) DO UPDATE SET
@@ -193,9 +744,10 @@ impl SqliteStore {
    message=excluded.message,
    detail=excluded.detail
  "#,
-            params![component, ts, level, message, detail],
-        )?;
-        Ok(())
+                params![component, ts, level, message, detail],
+            )?;
+            Ok(())
+        })
     }
 
     pub fn insert_scanner_snapshot(
@@ -204,27 +756,122 @@ impl SqliteStore {
         eligible_count: i64,
         top_count: i64,
     ) -> Result<()> {
-        let conn = self.open_conn()?;
-        conn.execute(
-            "INSERT INTO scanner_snapshots(ts, eligible_count, top_count) VALUES(?,?,?)",
-            params![ts, eligible_count, top_count],
-        )?;
-        Ok(())
+        self.write(|conn| {
+            conn.execute(
+                "INSERT INTO scanner_snapshots(ts, eligible_count, top_count) VALUES(?,?,?)",
+                params![ts, eligible_count, top_count],
+            )?;
+            Ok(())
+        })
     }
 
-    pub fn update_watchlist(&self, market_ids: &[String], ts: f64) -> Result<()> {
-        let mut conn = self.open_conn()?;
-        let tx = conn.transaction()?;
-        tx.execute("DELETE FROM watchlist", [])?;
+    /// Write one scanner cycle's candidates and drop rows older than `retention_secs`.
+    pub fn insert_scanner_candidates(
+        &self,
+        ts: f64,
+        candidates: &[ScannerCandidate],
+        retention_secs: f64,
+    ) -> Result<()> {
+        // One transaction per chunk, so a large refresh lets queued trader writes in
+        // between chunks; the retention sweep runs with the last one.
+        let mut chunks = candidates.chunks(self.bulk_chunk_rows).peekable();
+        loop {
+            let rows = chunks.next().unwrap_or(&[]);
+            let last = chunks.peek().is_none();
+            self.insert_scanner_candidate_chunk(ts, rows, last.then_some(retention_secs))?;
+            if last {
+                return Ok(());
+            }
+        }
+    }
+
+    fn insert_scanner_candidate_chunk(
+        &self,
+        ts: f64,
+        candidates: &[ScannerCandidate],
+        retention_secs: Option<f64>,
+    ) -> Result<()> {
+        self.write(|conn| {
         {
-            let mut stmt =
-                tx.prepare("INSERT INTO watchlist(rank, market_id, ts) VALUES(?,?,?)")?;
-            for (i, mid) in market_ids.iter().enumerate() {
-                stmt.execute(params![i as i64 + 1, mid, ts])?;
+            let mut stmt = conn.prepare(
+                r#"
+ INSERT INTO scanner_candidates(ts, market_id, score, volume_24h_usd, liquidity_usd, spread_bps, excluded_reason)
+ VALUES(?,?,?,?,?,?,?)
+ "#,
+            )?;
+            for c in candidates {
+                stmt.execute(params![
+                    ts,
+                    c.market_id,
+                    c.score,
+                    c.volume_24h_usd,
+                    c.liquidity_usd,
+                    c.spread_bps,
+                    c.excluded_reason
+                ])?;
             }
         }
-        tx.commit()?;
+        if let Some(retention_secs) = retention_secs {
+            conn.execute(
+                "DELETE FROM scanner_candidates WHERE ts < ?",
+                params![ts - retention_secs.max(0.0)],
+            )?;
+        }
         Ok(())
+        })
+    }
+
+    /// Replace the saved requote memory of these markets (others are kept).
+    pub fn upsert_quote_states(&self, rows: &[QuoteStateRow]) -> Result<()> {
+        self.write(|conn| {
+            {
+                let mut stmt = conn.prepare(
+                    r#"
+ INSERT INTO quote_state(market_id, ts, fair, imb_sign)
+ VALUES(?,?,?,?)
+ ON CONFLICT(market_id) DO UPDATE SET
+   ts=excluded.ts,
+   fair=excluded.fair,
+   imb_sign=excluded.imb_sign
+ "#,
+                )?;
+                for r in rows {
+                    stmt.execute(params![r.market_id, r.ts, r.fair, r.imb_sign])?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    pub fn fetch_quote_states(&self) -> Result<Vec<QuoteStateRow>> {
+        let conn = self.open_conn()?;
+        let mut stmt = conn
+            .prepare("SELECT market_id, ts, fair, imb_sign FROM quote_state ORDER BY market_id")?;
+        let mut rows = stmt.query([])?;
+        let mut out = vec![];
+        while let Some(r) = rows.next()? {
+            out.push(QuoteStateRow {
+                market_id: r.get(0)?,
+                ts: r.get(1)?,
+                fair: r.get(2)?,
+                imb_sign: r.get(3)?,
+            });
+        }
+        Ok(out)
+    }
+
+    pub fn update_watchlist(&self, market_ids: &[String], ts: f64) -> Result<()> {
+        self.write(|conn| {
+            conn.execute("DELETE FROM watchlist", [])?;
+            {
+                let mut stmt =
+                    conn.prepare("INSERT INTO watchlist(rank, market_id, ts) VALUES(?,?,?)")?;
+                for (i, mid) in market_ids.iter().enumerate() {
+                    stmt.execute(params![i as i64 + 1, mid, ts])?;
+                }
+            }
+            Ok(())
+        })
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -239,16 +886,17 @@ impl SqliteStore {
         liquidity_usd: f64,
         condition_id: Option<&str>,
         clob_token_id: Option<&str>,
+        category: Option<&str>,
         updated_ts: f64,
     ) -> Result<()> {
-        let conn = self.open_conn()?;
-        conn.execute(
-            r#"
+        self.write(|conn| {
+            conn.execute(
+                r#"
  INSERT INTO markets(
    market_id, question, event_id, active, end_ts,
-   volume_24h_usd, liquidity_usd, condition_id, clob_token_id, updated_ts
+   volume_24h_usd, liquidity_usd, condition_id, clob_token_id, updated_ts, category
  )
- VALUES(?,?,?,?,?,?,?,?,?,?)
+ VALUES(?,?,?,?,?,?,?,?,?,?,?)
  ON CONFLICT(market_id) DO UPDATE SET
    question=excluded.question,
    event_id=excluded.event_id,
@@ -258,22 +906,25 @@ impl SqliteStore {
    liquidity_usd=excluded.liquidity_usd,
    condition_id=excluded.condition_id,
    clob_token_id=excluded.clob_token_id,
-   updated_ts=excluded.updated_ts
+   updated_ts=excluded.updated_ts,
+   category=COALESCE(excluded.category, markets.category)
  "#,
-            params![
-                market_id,
-                question,
-                event_id,
-                if active { 1 } else { 0 },
-                end_ts,
-                volume_24h_usd,
-                liquidity_usd,
-                condition_id,
-                clob_token_id,
-                updated_ts
-            ],
-        )?;
-        Ok(())
+                params![
+                    market_id,
+                    question,
+                    event_id,
+                    if active { 1 } else { 0 },
+                    end_ts,
+                    volume_24h_usd,
+                    liquidity_usd,
+                    condition_id,
+                    clob_token_id,
+                    updated_ts,
+                    category
+                ],
+            )?;
+            Ok(())
+        })
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -289,11 +940,16 @@ impl SqliteStore {
         filled_size: f64,
         meta: &JsonValue,
     ) -> Result<()> {
-        let conn = self.open_conn()?;
+        self.write(|conn| {
         conn.execute(
              r#"
- INSERT OR REPLACE INTO orders(order_id, market_id, side, price, size, created_ts, status, filled_size, meta_json)
- VALUES(?,?,?,?,?,?,?,?,?)
+ INSERT INTO orders(order_id, market_id, side, price, size, created_ts, status, filled_size, meta_json, meta_enc, filled_ts)
+ VALUES(?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,CASE WHEN ?7 = 'filled' THEN ?6 END)
+ ON CONFLICT(order_id) DO UPDATE SET
+   market_id=excluded.market_id, side=excluded.side, price=excluded.price, size=excluded.size,
+   created_ts=excluded.created_ts, status=excluded.status, filled_size=excluded.filled_size,
+   meta_json=excluded.meta_json, meta_enc=excluded.meta_enc,
+   filled_ts=COALESCE(orders.filled_ts, excluded.filled_ts)
  "#,
              params![
                  order_id,
@@ -304,34 +960,38 @@ impl SqliteStore {
                  created_ts,
                  status,
                  filled_size,
-                 serde_json::to_string(meta)?
+                 self.encoding.encode(meta)?,
+                 self.encoding.as_str()
              ],
          )?;
         Ok(())
+        })
     }
 
+    /// Move an order to `status` at `ts`. The first transition to cancelled/expired
+    /// stamps `cancelled_ts` and the first to filled stamps `filled_ts`; repeats keep
+    /// the original time, so the row stays one order however often it is updated.
     pub fn update_order_status(
         &self,
         order_id: &str,
         status: &str,
         filled_size: Option<f64>,
+        ts: f64,
     ) -> Result<()> {
-        let conn = self.open_conn()?;
-        match filled_size {
-            None => {
-                conn.execute(
-                    "UPDATE orders SET status=? WHERE order_id=?",
-                    params![status, order_id],
-                )?;
-            }
-            Some(fs) => {
-                conn.execute(
-                    "UPDATE orders SET status=?, filled_size=? WHERE order_id=?",
-                    params![status, fs, order_id],
-                )?;
-            }
-        }
-        Ok(())
+        self.write(|conn| {
+            conn.execute(
+                r#"
+ UPDATE orders SET
+   status=?1,
+   filled_size=COALESCE(?2, filled_size),
+   cancelled_ts=CASE WHEN ?1 IN ('cancelled', 'expired') THEN COALESCE(cancelled_ts, ?3) ELSE cancelled_ts END,
+   filled_ts=CASE WHEN ?1 = 'filled' THEN COALESCE(filled_ts, ?3) ELSE filled_ts END
+ WHERE order_id=?4
+ "#,
+                params![status, filled_size, ts, order_id],
+            )?;
+            Ok(())
+        })
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -346,11 +1006,11 @@ impl SqliteStore {
         ts: f64,
         meta: &JsonValue,
     ) -> Result<()> {
-        let conn = self.open_conn()?;
+        self.write(|conn| {
         conn.execute(
             r#"
- INSERT OR REPLACE INTO fills(fill_id, order_id, market_id, side, price, size, ts, meta_json)
- VALUES(?,?,?,?,?,?,?,?)
+ INSERT OR REPLACE INTO fills(fill_id, order_id, market_id, side, price, size, ts, meta_json, meta_enc)
+ VALUES(?,?,?,?,?,?,?,?,?)
  "#,
             params![
                 fill_id,
@@ -360,10 +1020,34 @@ impl SqliteStore {
                 price,
                 size,
                 ts,
-                serde_json::to_string(meta)?
+                self.encoding.encode(meta)?,
+                self.encoding.as_str()
             ],
         )?;
         Ok(())
+        })
+    }
+
+    pub fn insert_tape(
+        &self,
+        ts: f64,
+        market_id: &str,
+        kind: &str,
+        payload: &JsonValue,
+    ) -> Result<()> {
+        self.write(|conn| {
+            conn.execute(
+            "INSERT INTO tape(ts, market_id, kind, payload_json, payload_enc) VALUES(?,?,?,?,?)",
+            params![
+                ts,
+                market_id,
+                kind,
+                self.encoding.encode(payload)?,
+                self.encoding.as_str()
+            ],
+        )?;
+            Ok(())
+        })
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -371,7 +1055,7 @@ impl SqliteStore {
         &self,
         ts: f64,
         market_id: &str,
-        event_id: &str,
+        event_id: Option<&str>,
         tob_best_bid: Option<f64>,
         tob_best_ask: Option<f64>,
         mid: Option<f64>,
@@ -382,51 +1066,127 @@ impl SqliteStore {
         skew: f64,
         target_bid: Option<f64>,
         target_ask: Option<f64>,
+        order_size: Option<f64>,
+        size_source: Option<&str>,
     ) -> Result<()> {
-        let conn = self.open_conn()?;
-        conn.execute(
-            r#"
+        self.write(|conn| {
+            conn.execute(
+                r#"
  INSERT INTO quote_snapshots(
    ts, market_id, event_id,
    tob_best_bid, tob_best_ask,
    mid, fair, fair_source,
    inv_qty, width, skew,
-   target_bid, target_ask
+   target_bid, target_ask,
+   order_size, size_source
  )
- VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?)
+ VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
  "#,
-            params![
-                ts,
-                market_id,
-                event_id,
-                tob_best_bid,
-                tob_best_ask,
-                mid,
-                fair,
-                fair_source,
-                inv_qty,
-                width,
-                skew,
-                target_bid,
-                target_ask
-            ],
-        )?;
-        Ok(())
+                params![
+                    ts,
+                    market_id,
+                    event_id,
+                    tob_best_bid,
+                    tob_best_ask,
+                    mid,
+                    fair,
+                    fair_source,
+                    inv_qty,
+                    width,
+                    skew,
+                    target_bid,
+                    target_ask,
+                    order_size,
+                    size_source
+                ],
+            )?;
+            Ok(())
+        })
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub fn insert_position_snapshot(
-        &self,
-        ts: f64,
+    /// One `EXECUTION_MODE=shadow` decision with the book it was made against.
+    pub fn insert_shadow_intent(&self, row: &ShadowIntentRow<'_>) -> Result<()> {
+        self.write(|conn| {
+            conn.execute(
+                r#"
+ INSERT INTO shadow_intents(
+   ts, market_id, best_bid, best_ask, mid,
+   fair, fair_source, divergence, flagged,
+   target_bid, target_ask, size,
+   hypo_fills, hypo_position, hypo_pnl
+ )
+ VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
+ "#,
+                params![
+                    row.ts,
+                    row.market_id,
+                    row.best_bid,
+                    row.best_ask,
+                    row.mid,
+                    row.fair,
+                    row.fair_source,
+                    row.divergence,
+                    row.flagged,
+                    row.target_bid,
+                    row.target_ask,
+                    row.size,
+                    row.hypo_fills,
+                    row.hypo_position,
+                    row.hypo_pnl
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    pub fn fetch_recent_shadow_intents(&self, limit: usize) -> Result<Vec<JsonValue>> {
+        let conn = self.open_conn()?;
+        let mut stmt = conn.prepare(
+            r#"
+ SELECT ts, market_id, best_bid, best_ask, mid, fair, fair_source, divergence, flagged,
+        target_bid, target_ask, size, hypo_fills, hypo_position, hypo_pnl
+ FROM shadow_intents
+ ORDER BY id DESC
+ LIMIT ?
+ "#,
+        )?;
+        let mut rows = stmt.query(params![limit as i64])?;
+        let mut out = vec![];
+        while let Some(r) = rows.next()? {
+            out.push(serde_json::json!({
+                "ts": r.get::<_, f64>(0)?,
+                "market_id": r.get::<_, String>(1)?,
+                "best_bid": r.get::<_, f64>(2)?,
+                "best_ask": r.get::<_, f64>(3)?,
+                "mid": r.get::<_, f64>(4)?,
+                "fair": r.get::<_, f64>(5)?,
+                "fair_source": r.get::<_, String>(6)?,
+                "divergence": r.get::<_, f64>(7)?,
+                "flagged": r.get::<_, bool>(8)?,
+                "target_bid": r.get::<_, Option<f64>>(9)?,
+                "target_ask": r.get::<_, Option<f64>>(10)?,
+                "size": r.get::<_, f64>(11)?,
+                "hypo_fills": r.get::<_, i64>(12)?,
+                "hypo_position": r.get::<_, f64>(13)?,
+                "hypo_pnl": r.get::<_, f64>(14)?,
+            }));
+        }
+        Ok(out)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert_position_snapshot(
+        &self,
+        ts: f64,
         market_id: &str,
-        event_id: &str,
+        event_id: Option<&str>,
         position: f64,
         avg_price: f64,
         mark_price: f64,
         unrealized_pnl: f64,
         realized_pnl: f64,
     ) -> Result<()> {
-        let conn = self.open_conn()?;
+        self.write(|conn| {
         conn.execute(
              r#"
  INSERT INTO position_snapshots(ts, market_id, event_id, position, avg_price, mark_price, unrealized_pnl, realized_pnl)
@@ -437,6 +1197,45 @@ impl SqliteStore {
              ],
          )?;
         Ok(())
+        })
+    }
+
+    /// Position row written on a fill, between snapshot ticks.
+    ///
+    /// Same table as the periodic snapshots (so `fetch_latest_positions` picks it up
+    /// immediately); the broker doesn't know the event, so it is carried over from the
+    /// market's previous row (NULL if none).
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert_fill_position(
+        &self,
+        ts: f64,
+        market_id: &str,
+        position: f64,
+        avg_price: f64,
+        mark_price: f64,
+        unrealized_pnl: f64,
+        realized_pnl: f64,
+    ) -> Result<()> {
+        self.write(|conn| {
+        conn.execute(
+            r#"
+ INSERT INTO position_snapshots(ts, market_id, event_id, position, avg_price, mark_price, unrealized_pnl, realized_pnl)
+ SELECT ?1, ?2,
+        (SELECT event_id FROM position_snapshots WHERE market_id = ?2 ORDER BY id DESC LIMIT 1),
+        ?3, ?4, ?5, ?6, ?7
+ "#,
+            params![
+                ts,
+                market_id,
+                position,
+                avg_price,
+                mark_price,
+                unrealized_pnl,
+                realized_pnl
+            ],
+        )?;
+        Ok(())
+        })
     }
 
     pub fn insert_pnl_snapshot(
@@ -446,12 +1245,13 @@ impl SqliteStore {
         total_realized: f64,
         total_pnl: f64,
     ) -> Result<()> {
-        let conn = self.open_conn()?;
+        self.write(|conn| {
         conn.execute(
              "INSERT INTO pnl_snapshots(ts, total_unrealized, total_realized, total_pnl) VALUES(?,?,?,?)",
              params![ts, total_unrealized, total_realized, total_pnl],
          )?;
         Ok(())
+        })
     }
 
     // ---- Dashboard queries (read-only) ----
@@ -493,6 +1293,34 @@ impl SqliteStore {
         Ok(row)
     }
 
+    /// Candidates from the most recent scanner cycle (selected first, then by score).
+    pub fn fetch_latest_scanner_candidates(&self, limit: usize) -> Result<Vec<JsonValue>> {
+        let conn = self.open_conn()?;
+        let mut stmt = conn.prepare(
+            r#"
+ SELECT ts, market_id, score, volume_24h_usd, liquidity_usd, spread_bps, excluded_reason
+ FROM scanner_candidates
+ WHERE ts = (SELECT MAX(ts) FROM scanner_candidates)
+ ORDER BY (excluded_reason IS NOT NULL) ASC, score DESC, volume_24h_usd DESC
+ LIMIT ?
+ "#,
+        )?;
+        let mut rows = stmt.query(params![limit as i64])?;
+        let mut out = vec![];
+        while let Some(r) = rows.next()? {
+            out.push(serde_json::json!({
+                "ts": r.get::<_, f64>(0)?,
+                "market_id": r.get::<_, String>(1)?,
+                "score": r.get::<_, f64>(2)?,
+                "volume_24h_usd": r.get::<_, f64>(3)?,
+                "liquidity_usd": r.get::<_, f64>(4)?,
+                "spread_bps": r.get::<_, f64>(5)?,
+                "excluded_reason": r.get::<_, Option<String>>(6)?,
+            }));
+        }
+        Ok(out)
+    }
+
     pub fn fetch_latest_tape_ts(&self) -> Result<Option<f64>> {
         let conn = self.open_conn()?;
         let v: Option<f64> = conn
@@ -502,6 +1330,40 @@ impl SqliteStore {
         Ok(v)
     }
 
+    /// Tape rows of `kind` with `from <= ts <= to` (either bound optional) in ascending
+    /// `ts` order (oldest first), for replay. The range is applied before `limit`.
+    pub fn fetch_tape(
+        &self,
+        kind: &str,
+        (from, to): (Option<f64>, Option<f64>),
+        limit: usize,
+    ) -> Result<Vec<JsonValue>> {
+        let conn = self.open_conn()?;
+        let mut stmt = conn.prepare(
+            r#"
+ SELECT ts, market_id, payload_json, payload_enc
+ FROM tape
+ WHERE kind = ?1
+   AND (?2 IS NULL OR ts >= ?2)
+   AND (?3 IS NULL OR ts <= ?3)
+ ORDER BY ts ASC, id ASC
+ LIMIT ?4
+ "#,
+        )?;
+        let mut rows = stmt.query(params![kind, from, to, limit as i64])?;
+        let mut out = vec![];
+        while let Some(r) = rows.next()? {
+            let enc: String = r.get(3)?;
+            let payload = decode_blob(&enc, r.get_ref(2)?).unwrap_or(JsonValue::Null);
+            out.push(serde_json::json!({
+                "ts": r.get::<_, f64>(0)?,
+                "market_id": r.get::<_, String>(1)?,
+                "payload": payload,
+            }));
+        }
+        Ok(out)
+    }
+
     pub fn fetch_latest_market_update_ts(&self) -> Result<Option<f64>> {
         let conn = self.open_conn()?;
         let v: Option<f64> = conn
@@ -543,7 +1405,8 @@ impl SqliteStore {
         let mut stmt = conn.prepare(
             r#"
  SELECT w.rank, w.market_id, w.ts as watch_ts,
-        m.question, m.event_id, m.active, m.end_ts, m.volume_24h_usd, m.liquidity_usd, m.updated_ts
+        m.question, m.event_id, m.active, m.end_ts, m.volume_24h_usd, m.liquidity_usd, m.updated_ts,
+        m.category
  FROM watchlist w
  LEFT JOIN markets m ON m.market_id = w.market_id
  ORDER BY w.rank ASC
@@ -564,11 +1427,36 @@ impl SqliteStore {
                 "volume_24h_usd": r.get::<_, Option<f64>>(7)?,
                 "liquidity_usd": r.get::<_, Option<f64>>(8)?,
                 "updated_ts": r.get::<_, Option<f64>>(9)?,
+                "category": r.get::<_, Option<String>>(10)?.unwrap_or_else(|| "other".to_string()),
             }));
         }
         Ok(out)
     }
 
+    /// Markets whose latest position snapshot is not flat.
+    pub fn fetch_open_position_markets(&self) -> Result<Vec<String>> {
+        let conn = self.open_conn()?;
+        let mut stmt = conn.prepare(
+            r#"
+ WITH latest AS (
+   SELECT market_id, MAX(id) AS id_max
+   FROM position_snapshots
+   GROUP BY market_id
+ )
+ SELECT ps.market_id
+ FROM position_snapshots ps
+ JOIN latest ON latest.id_max = ps.id
+ WHERE ABS(ps.position) > 1e-9
+ "#,
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut out = vec![];
+        while let Some(r) = rows.next()? {
+            out.push(r.get::<_, String>(0)?);
+        }
+        Ok(out)
+    }
+
     pub fn fetch_latest_positions(&self, limit: usize) -> Result<Vec<JsonValue>> {
         let conn = self.open_conn()?;
         let mut stmt = conn.prepare(
@@ -597,7 +1485,7 @@ impl SqliteStore {
         while let Some(r) = rows.next()? {
             out.push(serde_json::json!({
                 "market_id": r.get::<_, String>(0)?,
-                "event_id": r.get::<_, String>(1)?,
+                "event_id": r.get::<_, Option<String>>(1)?,
                 "position": r.get::<_, f64>(2)?,
                 "avg_price": r.get::<_, f64>(3)?,
                 "mark_price": r.get::<_, f64>(4)?,
@@ -609,6 +1497,234 @@ impl SqliteStore {
         Ok(out)
     }
 
+    /// Markets whose position went to zero in the last `window_secs` and are still flat.
+    ///
+    /// Closes come from snapshot history (a flat row right after a non-flat one), not
+    /// from the latest row alone. A market that reopened after closing is excluded;
+    /// if it closed again, the later close is reported. Newest closes first.
+    ///
+    /// Only flat rows inside the window are scanned (`idx_positions_ts`); each one's
+    /// predecessor and its market's latest row are index lookups.
+    pub fn fetch_recently_closed(&self, limit: usize, window_secs: f64) -> Result<Vec<JsonValue>> {
+        let since = crate::utils::now_ts() - window_secs.max(0.0);
+        let conn = self.open_conn()?;
+        let mut stmt = conn.prepare(
+            r#"
+ WITH hist AS MATERIALIZED (
+   SELECT r.id,
+          r.market_id,
+          (
+            SELECT p.position
+            FROM position_snapshots p
+            WHERE p.market_id = r.market_id AND p.id < r.id
+            ORDER BY p.id DESC
+            LIMIT 1
+          ) AS prev_position
+   FROM position_snapshots r
+   WHERE r.ts >= ?1 AND r.position = 0
+ ),
+ closes AS (
+   SELECT market_id, MAX(id) AS close_id
+   FROM hist
+   WHERE prev_position IS NOT NULL AND prev_position != 0
+   GROUP BY market_id
+ )
+ SELECT ps.market_id,
+        ps.event_id,
+        ps.realized_pnl,
+        cs.ts,
+        h.prev_position
+ FROM closes c
+ JOIN position_snapshots ps ON ps.id = (
+   SELECT MAX(id) FROM position_snapshots WHERE market_id = c.market_id
+ )
+ JOIN position_snapshots cs ON cs.id = c.close_id
+ JOIN hist h ON h.id = c.close_id
+ WHERE ps.position = 0
+ ORDER BY cs.ts DESC
+ LIMIT ?2
+ "#,
+        )?;
+        let mut rows = stmt.query(params![since, limit as i64])?;
+        let mut out = vec![];
+        while let Some(r) = rows.next()? {
+            out.push(serde_json::json!({
+                "market_id": r.get::<_, String>(0)?,
+                "event_id": r.get::<_, Option<String>>(1)?,
+                "realized_pnl": r.get::<_, f64>(2)?,
+                "closed_ts": r.get::<_, f64>(3)?,
+                "closed_position": r.get::<_, f64>(4)?,
+            }));
+        }
+        Ok(out)
+    }
+
+    /// Per-market PnL for markets with a position snapshot in the last `window_secs`.
+    ///
+    /// Totals come from each market's latest snapshot (realized + unrealized), so flat
+    /// markets still report their realized PnL. `window_pnl` is the change since the
+    /// last snapshot before the window (or since zero if the market is newer).
+    /// Sorted by `total_pnl`, best first.
+    pub fn fetch_pnl_by_market(&self, window_secs: f64) -> Result<Vec<JsonValue>> {
+        let since = crate::utils::now_ts() - window_secs.max(0.0);
+        let conn = self.open_conn()?;
+        let mut stmt = conn.prepare(
+            r#"
+ WITH latest AS (
+   SELECT market_id, MAX(id) AS id_max
+   FROM position_snapshots
+   GROUP BY market_id
+ )
+ SELECT ps.market_id,
+        ps.event_id,
+        ps.position,
+        ps.unrealized_pnl,
+        ps.realized_pnl,
+        ps.ts,
+        COALESCE((
+          SELECT b.unrealized_pnl + b.realized_pnl
+          FROM position_snapshots b
+          WHERE b.market_id = ps.market_id AND b.ts < ?1
+          ORDER BY b.ts DESC, b.id DESC
+          LIMIT 1
+        ), 0)
+ FROM latest
+ JOIN position_snapshots ps ON ps.id = latest.id_max
+ WHERE ps.ts >= ?1
+ ORDER BY ps.unrealized_pnl + ps.realized_pnl DESC
+ "#,
+        )?;
+        let mut rows = stmt.query(params![since])?;
+        let mut out = vec![];
+        while let Some(r) = rows.next()? {
+            let unrealized: f64 = r.get(3)?;
+            let realized: f64 = r.get(4)?;
+            let base_total: f64 = r.get(6)?;
+            let total = unrealized + realized;
+            out.push(serde_json::json!({
+                "market_id": r.get::<_, String>(0)?,
+                "event_id": r.get::<_, Option<String>>(1)?,
+                "position": r.get::<_, f64>(2)?,
+                "unrealized_pnl": unrealized,
+                "realized_pnl": realized,
+                "total_pnl": total,
+                "window_pnl": total - base_total,
+                "ts": r.get::<_, f64>(5)?,
+            }));
+        }
+        Ok(out)
+    }
+
+    /// `fetch_pnl_by_market` summed per event: each market's latest snapshot (if it
+    /// falls in the window) counts once toward its event. Markets without an event id
+    /// are not lumped together; each is reported as its own group with `event_id: null`.
+    pub fn fetch_pnl_by_event(&self, window_secs: f64) -> Result<Vec<JsonValue>> {
+        let since = crate::utils::now_ts() - window_secs.max(0.0);
+        let conn = self.open_conn()?;
+        let mut stmt = conn.prepare(
+            r#"
+ WITH latest AS (
+   SELECT market_id, MAX(id) AS id_max
+   FROM position_snapshots
+   GROUP BY market_id
+ ),
+ per_market AS (
+   SELECT ps.market_id,
+          ps.event_id,
+          ps.position,
+          ps.unrealized_pnl,
+          ps.realized_pnl,
+          ps.ts,
+          COALESCE((
+            SELECT b.unrealized_pnl + b.realized_pnl
+            FROM position_snapshots b
+            WHERE b.market_id = ps.market_id AND b.ts < ?1
+            ORDER BY b.ts DESC, b.id DESC
+            LIMIT 1
+          ), 0) AS base_total
+   FROM latest
+   JOIN position_snapshots ps ON ps.id = latest.id_max
+   WHERE ps.ts >= ?1
+ )
+ SELECT MAX(event_id),
+        GROUP_CONCAT(market_id, char(31)),
+        SUM(ABS(position)),
+        SUM(unrealized_pnl),
+        SUM(realized_pnl),
+        SUM(base_total),
+        MAX(ts)
+ FROM per_market
+ GROUP BY COALESCE(event_id, char(31) || market_id)
+ ORDER BY SUM(unrealized_pnl + realized_pnl) DESC
+ "#,
+        )?;
+        let mut rows = stmt.query(params![since])?;
+        let mut out = vec![];
+        while let Some(r) = rows.next()? {
+            let mut markets: Vec<String> = r
+                .get::<_, String>(1)?
+                .split('\u{1f}')
+                .map(str::to_string)
+                .collect();
+            markets.sort();
+            let unrealized: f64 = r.get(3)?;
+            let realized: f64 = r.get(4)?;
+            let base_total: f64 = r.get(5)?;
+            let total = unrealized + realized;
+            out.push(serde_json::json!({
+                "event_id": r.get::<_, Option<String>>(0)?,
+                "market_count": markets.len(),
+                "markets": markets,
+                "gross_position": r.get::<_, f64>(2)?,
+                "unrealized_pnl": unrealized,
+                "realized_pnl": realized,
+                "total_pnl": total,
+                "window_pnl": total - base_total,
+                "ts": r.get::<_, f64>(6)?,
+            }));
+        }
+        Ok(out)
+    }
+
+    /// Whole-DB aggregates for `/api/summary`: fill count and notional, cancels, and the
+    /// win rate over closed round trips. One ordered pass over `fills` feeds both the
+    /// totals and the [`RoundTripBuilder`].
+    pub fn fetch_session_stats(&self) -> Result<JsonValue> {
+        let conn = self.open_conn()?;
+        let cancels: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM orders WHERE status = 'cancelled'",
+            [],
+            |r| r.get(0),
+        )?;
+        let mut stmt = conn.prepare(
+            "SELECT market_id, side, price, size, ts FROM fills ORDER BY market_id, ts, rowid",
+        )?;
+        let mut rows = stmt.query([])?;
+        let (mut fills, mut notional) = (0u64, 0.0);
+        let mut trips = RoundTripBuilder::default();
+        while let Some(r) = rows.next()? {
+            let market_id: String = r.get(0)?;
+            let side: String = r.get(1)?;
+            let price: f64 = r.get(2)?;
+            let size: f64 = r.get(3)?;
+            fills += 1;
+            notional += price * size;
+            trips.push(&market_id, &side, price, size, r.get(4)?);
+        }
+        let closed = trips.closed();
+        let wins = closed.iter().filter(|t| t.pnl > 0.0).count();
+        let win_rate = (!closed.is_empty()).then(|| wins as f64 / closed.len() as f64);
+        Ok(serde_json::json!({
+            "total_fills": fills,
+            "total_cancels": cancels,
+            "total_notional": notional,
+            "round_trips": closed.len(),
+            "round_trip_wins": wins,
+            "win_rate": win_rate,
+            "round_trip_pnl": closed.iter().map(|t| t.pnl).sum::<f64>(),
+        }))
+    }
+
     pub fn fetch_latest_quotes(&self, limit: usize) -> Result<Vec<JsonValue>> {
         let conn = self.open_conn()?;
         let mut stmt = conn.prepare(
@@ -631,7 +1747,9 @@ impl SqliteStore {
         q.target_bid,
         q.target_ask,
         q.ts,
-        m.question
+        m.question,
+        q.order_size,
+        q.size_source
  FROM quote_snapshots q
  JOIN latest ON latest.id_max = q.id
  LEFT JOIN markets m ON m.market_id = q.market_id
@@ -644,7 +1762,7 @@ impl SqliteStore {
         while let Some(r) = rows.next()? {
             out.push(serde_json::json!({
                 "market_id": r.get::<_, String>(0)?,
-                "event_id": r.get::<_, String>(1)?,
+                "event_id": r.get::<_, Option<String>>(1)?,
                 "tob_best_bid": r.get::<_, Option<f64>>(2)?,
                 "tob_best_ask": r.get::<_, Option<f64>>(3)?,
                 "mid": r.get::<_, Option<f64>>(4)?,
@@ -657,6 +1775,8 @@ impl SqliteStore {
                 "target_ask": r.get::<_, Option<f64>>(11)?,
                 "ts": r.get::<_, f64>(12)?,
                 "question": r.get::<_, Option<String>>(13)?,
+                "order_size": r.get::<_, Option<f64>>(14)?,
+                "size_source": r.get::<_, Option<String>>(15)?,
             }));
         }
         Ok(out)
@@ -667,39 +1787,42 @@ impl SqliteStore {
         limit: usize,
         status: Option<&str>,
     ) -> Result<Vec<JsonValue>> {
-        let conn = self.open_conn()?;
-        let (sql, params_vec): (&str, Vec<rusqlite::types::Value>) = match status {
-            None => (
-                r#"
- SELECT order_id, market_id, side, price, size, created_ts, status, filled_size, meta_json
- FROM orders
- ORDER BY created_ts DESC
- LIMIT ?
- "#,
-                vec![rusqlite::types::Value::Integer(limit as i64)],
-            ),
-            Some(st) => (
-                r#"
- SELECT order_id, market_id, side, price, size, created_ts, status, filled_size, meta_json
- FROM orders
- WHERE status = ?
- ORDER BY created_ts DESC
- LIMIT ?
- "#,
-                vec![
-                    rusqlite::types::Value::Text(st.to_string()),
-                    rusqlite::types::Value::Integer(limit as i64),
-                ],
-            ),
-        };
+        Ok(self.fetch_orders_page(limit, status, None)?.0)
+    }
+
+    /// Newest-first page of orders older than `cursor` (keyset on `created_ts, order_id`),
+    /// plus the cursor for the next page (`None` once history is exhausted).
+    pub fn fetch_orders_page(
+        &self,
+        limit: usize,
+        status: Option<&str>,
+        cursor: Option<&str>,
+    ) -> Result<(Vec<JsonValue>, Option<String>)> {
+        use rusqlite::types::Value;
+        let mut sql = String::from(
+            "SELECT order_id, market_id, side, price, size, created_ts, status, filled_size, meta_json, meta_enc, cancelled_ts, filled_ts\n FROM orders\n WHERE 1=1",
+        );
+        let mut params_vec: Vec<Value> = vec![];
+        if let Some(st) = status {
+            sql.push_str(" AND status = ?");
+            params_vec.push(Value::Text(st.to_string()));
+        }
+        if let Some(c) = cursor {
+            let (ts, id) = decode_cursor(c)?;
+            sql.push_str(" AND (created_ts < ? OR (created_ts = ? AND order_id < ?))");
+            params_vec.extend([Value::Real(ts), Value::Real(ts), Value::Text(id)]);
+        }
+        sql.push_str("\n ORDER BY created_ts DESC, order_id DESC\n LIMIT ?");
+        // One extra row tells us whether another page exists.
+        params_vec.push(Value::Integer(limit as i64 + 1));
 
-        let mut stmt = conn.prepare(sql)?;
+        let conn = self.open_conn()?;
+        let mut stmt = conn.prepare(&sql)?;
         let mut rows = stmt.query(rusqlite::params_from_iter(params_vec))?;
         let mut out = vec![];
         while let Some(r) = rows.next()? {
-            let meta_json: String = r.get(8)?;
-            let meta: JsonValue =
-                serde_json::from_str(&meta_json).unwrap_or_else(|_| serde_json::json!({}));
+            let enc: String = r.get(9)?;
+            let meta = decode_blob(&enc, r.get_ref(8)?).unwrap_or_else(|| serde_json::json!({}));
             out.push(serde_json::json!({
                 "order_id": r.get::<_, String>(0)?,
                 "market_id": r.get::<_, String>(1)?,
@@ -709,28 +1832,45 @@ impl SqliteStore {
                 "created_ts": r.get::<_, f64>(5)?,
                 "status": r.get::<_, String>(6)?,
                 "filled_size": r.get::<_, f64>(7)?,
+                "cancelled_ts": r.get::<_, Option<f64>>(10)?,
+                "filled_ts": r.get::<_, Option<f64>>(11)?,
                 "meta": meta,
             }));
         }
-        Ok(out)
+        let next = next_cursor(&mut out, limit, "created_ts", "order_id");
+        Ok((out, next))
     }
 
     pub fn fetch_recent_fills(&self, limit: usize) -> Result<Vec<JsonValue>> {
+        Ok(self.fetch_fills_page(limit, None)?.0)
+    }
+
+    /// Newest-first page of fills older than `cursor` (keyset on `ts, fill_id`).
+    pub fn fetch_fills_page(
+        &self,
+        limit: usize,
+        cursor: Option<&str>,
+    ) -> Result<(Vec<JsonValue>, Option<String>)> {
+        use rusqlite::types::Value;
+        let mut sql = String::from(
+            "SELECT fill_id, order_id, market_id, side, price, size, ts, meta_json, meta_enc\n FROM fills\n WHERE 1=1",
+        );
+        let mut params_vec: Vec<Value> = vec![];
+        if let Some(c) = cursor {
+            let (ts, id) = decode_cursor(c)?;
+            sql.push_str(" AND (ts < ? OR (ts = ? AND fill_id < ?))");
+            params_vec.extend([Value::Real(ts), Value::Real(ts), Value::Text(id)]);
+        }
+        sql.push_str("\n ORDER BY ts DESC, fill_id DESC\n LIMIT ?");
+        params_vec.push(Value::Integer(limit as i64 + 1));
+
         let conn = self.open_conn()?;
-        let mut stmt = conn.prepare(
-            r#"
- SELECT fill_id, order_id, market_id, side, price, size, ts, meta_json
- FROM fills
- ORDER BY ts DESC
- LIMIT ?
- "#,
-        )?;
-        let mut rows = stmt.query(params![limit as i64])?;
+        let mut stmt = conn.prepare(&sql)?;
+        let mut rows = stmt.query(rusqlite::params_from_iter(params_vec))?;
         let mut out = vec![];
         while let Some(r) = rows.next()? {
-            let meta_json: String = r.get(7)?;
-            let meta: JsonValue =
-                serde_json::from_str(&meta_json).unwrap_or_else(|_| serde_json::json!({}));
+            let enc: String = r.get(8)?;
+            let meta = decode_blob(&enc, r.get_ref(7)?).unwrap_or_else(|| serde_json::json!({}));
             out.push(serde_json::json!({
                 "fill_id": r.get::<_, String>(0)?,
                 "order_id": r.get::<_, String>(1)?,
@@ -742,6 +1882,1021 @@ impl SqliteStore {
                 "meta": meta,
             }));
         }
-        Ok(out)
+        let next = next_cursor(&mut out, limit, "ts", "fill_id");
+        Ok((out, next))
+    }
+
+    /// Histogram of `price - fair_at_fill` over fills in the last `window_secs`.
+    ///
+    /// Fills without a `fair_at_fill` meta field are skipped (and counted as such).
+    pub fn fetch_fill_markout_histogram(
+        &self,
+        bucket_width: f64,
+        window_secs: f64,
+    ) -> Result<JsonValue> {
+        if !bucket_width.is_finite() || bucket_width <= 0.0 {
+            anyhow::bail!("bucket_width must be > 0 (got {bucket_width})");
+        }
+        let since = crate::utils::now_ts() - window_secs.max(0.0);
+        let conn = self.open_conn()?;
+        let mut stmt = conn.prepare(
+            r#"
+ SELECT price, meta_json, meta_enc
+ FROM fills
+ WHERE ts >= ?
+ "#,
+        )?;
+        let mut rows = stmt.query(params![since])?;
+        let mut counts: std::collections::BTreeMap<i64, u64> = std::collections::BTreeMap::new();
+        let mut markouts: Vec<f64> = vec![];
+        let mut skipped = 0u64;
+        while let Some(r) = rows.next()? {
+            let price: f64 = r.get(0)?;
+            // Decoded here rather than json_extract: msgpack rows are not JSON.
+            let enc: String = r.get(2)?;
+            let fair = decode_blob(&enc, r.get_ref(1)?)
+                .and_then(|m| m.get("fair_at_fill").and_then(|x| x.as_f64()));
+            let Some(fair) = fair.filter(|x| x.is_finite()) else {
+                skipped += 1;
+                continue;
+            };
+            let bucket = ((price - fair) / bucket_width).floor() as i64;
+            *counts.entry(bucket).or_default() += 1;
+            markouts.push(price - fair);
+        }
+        let total = markouts.len() as u64;
+        // p10/p50/p90 of the raw markouts; null when no fill had a fair.
+        let pcts = crate::utils::percentiles(&mut markouts, &[10.0, 50.0, 90.0]);
+        let buckets = counts
+            .into_iter()
+            .map(|(b, n)| {
+                serde_json::json!({
+                    "lo": b as f64 * bucket_width,
+                    "hi": (b + 1) as f64 * bucket_width,
+                    "count": n,
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(serde_json::json!({
+            "bucket_width": bucket_width,
+            "window_secs": window_secs,
+            "total": total,
+            "skipped": skipped,
+            "p10": pcts.first(),
+            "p50": pcts.get(1),
+            "p90": pcts.get(2),
+            "buckets": buckets,
+        }))
+    }
+
+    /// Paper fills vs the public trade tape over the last `lookback_secs`.
+    ///
+    /// A paper fill is *matched* when a public trade in the same market printed at or
+    /// through its price (buy: trade <= price, sell: trade >= price) within `window_secs`
+    /// of it; unmatched fills suggest the fill model is over-optimistic. A public trade
+    /// is *missed* when it printed at or through our latest quote (quote snapshot at most
+    /// `window_secs` old) and we got no paper fill in that market within `window_secs`.
+    /// Public trades are only on the tape with `TAPE_RECORD_ENABLED=1`.
+    pub fn fetch_fill_realism(&self, window_secs: f64, lookback_secs: f64) -> Result<JsonValue> {
+        if !window_secs.is_finite() || window_secs < 0.0 {
+            anyhow::bail!("window_secs must be >= 0 (got {window_secs})");
+        }
+        let since = crate::utils::now_ts() - lookback_secs.max(0.0);
+        let conn = self.open_conn()?;
+        type Fill = (String, String, f64, f64); // fill_id, side, price, ts
+        type Quote = (f64, Option<f64>, Option<f64>); // ts, target_bid, target_ask
+
+        let mut fills: std::collections::HashMap<String, Vec<Fill>> = Default::default();
+        let mut stmt =
+            conn.prepare("SELECT fill_id, market_id, side, price, ts FROM fills WHERE ts >= ?")?;
+        let mut rows = stmt.query(params![since - window_secs])?;
+        while let Some(r) = rows.next()? {
+            fills.entry(r.get(1)?).or_default().push((
+                r.get(0)?,
+                r.get::<_, String>(2)?.to_lowercase(),
+                r.get(3)?,
+                r.get(4)?,
+            ));
+        }
+
+        let mut trades: Vec<(String, f64, f64)> = vec![]; // market_id, price, ts
+        let mut stmt = conn.prepare(
+            "SELECT market_id, ts, payload_json, payload_enc FROM tape WHERE kind = 'trade' AND ts >= ?",
+        )?;
+        let mut rows = stmt.query(params![since - window_secs])?;
+        while let Some(r) = rows.next()? {
+            let enc: String = r.get(3)?;
+            let price = decode_blob(&enc, r.get_ref(2)?)
+                .and_then(|p| p.get("price").and_then(|x| x.as_f64()));
+            if let Some(price) = price {
+                trades.push((r.get(0)?, price, r.get(1)?));
+            }
+        }
+
+        let mut quotes: std::collections::HashMap<String, Vec<Quote>> = Default::default();
+        let mut stmt = conn.prepare(
+            "SELECT market_id, ts, target_bid, target_ask FROM quote_snapshots WHERE ts >= ? ORDER BY ts ASC",
+        )?;
+        let mut rows = stmt.query(params![since - window_secs])?;
+        while let Some(r) = rows.next()? {
+            quotes
+                .entry(r.get(0)?)
+                .or_default()
+                .push((r.get(1)?, r.get(2)?, r.get(3)?));
+        }
+
+        const EPS: f64 = 1e-9;
+        let mut paper_fills = 0u64;
+        let mut unmatched: Vec<JsonValue> = vec![];
+        for (market, fs) in &fills {
+            for (fill_id, side, price, ts) in fs.iter().filter(|f| f.3 >= since) {
+                paper_fills += 1;
+                let matched = trades.iter().any(|t| {
+                    t.0 == *market
+                        && (t.2 - ts).abs() <= window_secs
+                        && match side.as_str() {
+                            "buy" => t.1 <= price + EPS,
+                            _ => t.1 >= price - EPS,
+                        }
+                });
+                if !matched {
+                    unmatched.push(serde_json::json!({
+                        "fill_id": fill_id, "market_id": market, "side": side,
+                        "price": price, "ts": ts,
+                    }));
+                }
+            }
+        }
+
+        let mut tape_trades = 0u64;
+        let mut missed: Vec<JsonValue> = vec![];
+        for (market, price, ts) in trades.iter().filter(|t| t.2 >= since) {
+            tape_trades += 1;
+            let quote = quotes.get(market).and_then(|qs| {
+                qs.iter()
+                    .rev()
+                    .find(|q| q.0 <= *ts && ts - q.0 <= window_secs)
+            });
+            let Some((_, bid, ask)) = quote else {
+                continue;
+            };
+            let through =
+                bid.is_some_and(|b| *price <= b + EPS) || ask.is_some_and(|a| *price >= a - EPS);
+            let filled = fills
+                .get(market)
+                .is_some_and(|fs| fs.iter().any(|f| (f.3 - ts).abs() <= window_secs));
+            if through && !filled {
+                missed.push(serde_json::json!({
+                    "market_id": market, "price": price, "ts": ts,
+                    "quote_bid": bid, "quote_ask": ask,
+                }));
+            }
+        }
+
+        let by_ts = |a: &JsonValue, b: &JsonValue| {
+            b["ts"]
+                .as_f64()
+                .unwrap_or(0.0)
+                .total_cmp(&a["ts"].as_f64().unwrap_or(0.0))
+        };
+        unmatched.sort_by(by_ts);
+        missed.sort_by(by_ts);
+        let unmatched_rate = if paper_fills > 0 {
+            unmatched.len() as f64 / paper_fills as f64
+        } else {
+            0.0
+        };
+        Ok(serde_json::json!({
+            "window_secs": window_secs,
+            "lookback_secs": lookback_secs,
+            "paper_fills": paper_fills,
+            "unmatched_fills": unmatched.len(),
+            "unmatched_fill_rate": unmatched_rate,
+            "tape_trades": tape_trades,
+            "missed_trades": missed.len(),
+            "unmatched_examples": unmatched.into_iter().take(20).collect::<Vec<_>>(),
+            "missed_examples": missed.into_iter().take(20).collect::<Vec<_>>(),
+        }))
+    }
+}
+
+/// Trim the look-ahead row from a page fetched with `limit + 1` and, if it was there,
+/// return the cursor for the last row kept.
+fn next_cursor(
+    rows: &mut Vec<JsonValue>,
+    limit: usize,
+    ts_key: &str,
+    id_key: &str,
+) -> Option<String> {
+    if rows.len() <= limit {
+        return None;
+    }
+    rows.truncate(limit);
+    let last = rows.last()?;
+    let ts = last.get(ts_key)?.as_f64()?;
+    let id = last.get(id_key)?.as_str()?;
+    Some(encode_cursor(ts, id))
+}
+
+/// Page cursors are opaque to clients: hex of `"<ts>|<id>"` (f64 `Display` round-trips exactly).
+fn encode_cursor(ts: f64, id: &str) -> String {
+    format!("{ts}|{id}")
+        .bytes()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+pub(crate) fn decode_cursor(cursor: &str) -> Result<(f64, String)> {
+    let invalid = || anyhow::anyhow!("invalid cursor");
+    if !cursor.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(cursor.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(invalid)?;
+    let text = String::from_utf8(bytes).map_err(|_| invalid())?;
+    let (ts, id) = text.split_once('|').ok_or_else(invalid)?;
+    let ts = ts.parse::<f64>().map_err(|_| invalid())?;
+    Ok((ts, id.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_placeholder_backfill_runs_once() {
+        let store = temp_store();
+        let conn = store.open_conn().unwrap();
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        // A placeholder written after the backfill is left alone on the next start.
+        store
+            .insert_position_snapshot(1.0, "m1", Some("event:unknown"), 1.0, 0.5, 0.5, 0.0, 0.0)
+            .unwrap();
+        store.init_db().unwrap();
+        let event: Option<String> = conn
+            .query_row("SELECT event_id FROM position_snapshots", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(event.as_deref(), Some("event:unknown"));
+    }
+
+    #[test]
+    fn fill_markout_histogram_buckets_and_skips_missing_fair() {
+        let store = temp_store();
+        let now = crate::utils::now_ts();
+        let fills = [
+            ("f1", 0.500, Some(0.500)),
+            ("f2", 0.502, Some(0.500)),
+            ("f3", 0.507, Some(0.500)),
+            ("f4", 0.4925, Some(0.500)),
+            ("f5", 0.520, None),
+        ];
+        for (id, px, fair) in fills {
+            let meta = match fair {
+                Some(f) => serde_json::json!({"strategy": "mm", "fair_at_fill": f}),
+                None => serde_json::json!({"strategy": "mm"}),
+            };
+            store
+                .insert_fill(id, "o", "m1", "buy", px, 1.0, now, &meta)
+                .unwrap();
+        }
+        // Outside the window: ignored entirely.
+        store
+            .insert_fill(
+                "old",
+                "o",
+                "m1",
+                "buy",
+                0.9,
+                1.0,
+                now - 7200.0,
+                &serde_json::json!({"fair_at_fill": 0.5}),
+            )
+            .unwrap();
+
+        let h = store.fetch_fill_markout_histogram(0.005, 3600.0).unwrap();
+        assert_eq!(h["total"], 4);
+        assert_eq!(h["skipped"], 1);
+        assert!((h["p50"].as_f64().unwrap() - 0.001).abs() < 1e-9);
+        let counts: Vec<(f64, u64)> = h["buckets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| (b["lo"].as_f64().unwrap(), b["count"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(counts.len(), 3);
+        assert!((counts[0].0 + 0.010).abs() < 1e-9);
+        assert_eq!(counts[0].1, 1);
+        assert!(counts[1].0.abs() < 1e-9);
+        assert_eq!(counts[1].1, 2);
+        assert!((counts[2].0 - 0.005).abs() < 1e-9);
+        assert_eq!(counts[2].1, 1);
+
+        assert!(store.fetch_fill_markout_histogram(0.0, 3600.0).is_err());
+    }
+
+    #[test]
+    fn scanner_candidates_persist_scores_reasons_and_retention() {
+        let store = temp_store();
+        let cand = |id: &str, score: f64, reason: Option<&str>| ScannerCandidate {
+            market_id: id.to_string(),
+            score,
+            volume_24h_usd: 50_000.0,
+            liquidity_usd: 25_000.0,
+            spread_bps: 120.0,
+            excluded_reason: reason.map(|r| r.to_string()),
+        };
+
+        store
+            .insert_scanner_candidates(1000.0, &[cand("old", 1.0, None)], 600.0)
+            .unwrap();
+        store
+            .insert_scanner_candidates(
+                2000.0,
+                &[
+                    cand("near", 9.0, Some("rank_cutoff")),
+                    cand("top", 5.0, None),
+                    cand("thin", 0.0, Some("low_liquidity")),
+                ],
+                600.0,
+            )
+            .unwrap();
+
+        let rows = store.fetch_latest_scanner_candidates(10).unwrap();
+        let ids: Vec<&str> = rows
+            .iter()
+            .map(|r| r["market_id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, vec!["top", "near", "thin"]);
+        assert_eq!(rows[0]["score"], 5.0);
+        assert!(rows[0]["excluded_reason"].is_null());
+        assert_eq!(rows[1]["excluded_reason"], "rank_cutoff");
+        assert_eq!(rows[2]["excluded_reason"], "low_liquidity");
+        assert_eq!(rows[2]["spread_bps"], 120.0);
+
+        // The first cycle is past retention and was pruned.
+        let conn = store.open_conn().unwrap();
+        let n: i64 = conn
+            .query_row("SELECT COUNT(*) FROM scanner_candidates", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(n, 3);
+    }
+
+    #[test]
+    fn clear_all_state_resets_quotes_and_optionally_markets() {
+        let store = temp_store();
+        let count = |table: &str| -> i64 {
+            store
+                .open_conn()
+                .unwrap()
+                .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |r| r.get(0))
+                .unwrap()
+        };
+        let seed = || {
+            store
+                .insert_fill(
+                    "f1",
+                    "o1",
+                    "m1",
+                    "buy",
+                    0.5,
+                    1.0,
+                    1.0,
+                    &serde_json::json!({}),
+                )
+                .unwrap();
+            store
+                .insert_quote_snapshot(
+                    1.0,
+                    "m1",
+                    Some("e1"),
+                    Some(0.49),
+                    Some(0.51),
+                    Some(0.5),
+                    Some(0.5),
+                    "mid",
+                    0.0,
+                    0.02,
+                    0.0,
+                    Some(0.49),
+                    Some(0.51),
+                    None,
+                    None,
+                )
+                .unwrap();
+            store.insert_scanner_snapshot(1.0, 1, 1).unwrap();
+            store.update_watchlist(&["m1".to_string()], 1.0).unwrap();
+            store
+                .upsert_market(
+                    "m1", None, None, true, None, 0.0, 0.0, None, None, None, 1.0,
+                )
+                .unwrap();
+        };
+        let cleared = ["fills", "quote_snapshots", "scanner_snapshots", "watchlist"];
+
+        seed();
+        store.clear_all_state(false).unwrap();
+        for t in cleared {
+            assert_eq!(count(t), 0, "{t}");
+        }
+        assert_eq!(count("markets"), 1);
+
+        seed();
+        store.clear_all_state(true).unwrap();
+        for t in cleared.iter().chain(&["markets"]) {
+            assert_eq!(count(t), 0, "{t}");
+        }
+    }
+
+    #[test]
+    fn open_position_markets_follow_the_latest_snapshot() {
+        let store = temp_store();
+        let snap = |ts: f64, m: &str, pos: f64| {
+            store
+                .insert_position_snapshot(ts, m, None, pos, 0.5, 0.5, 0.0, 0.0)
+                .unwrap();
+        };
+        snap(1.0, "m1", 10.0);
+        snap(2.0, "m1", 0.0);
+        snap(1.0, "m2", 0.0);
+        snap(2.0, "m2", -5.0);
+        assert_eq!(store.fetch_open_position_markets().unwrap(), vec!["m2"]);
+    }
+
+    #[test]
+    fn pnl_by_market_uses_latest_snapshot_and_keeps_flat_markets() {
+        let store = temp_store();
+        let now = crate::utils::now_ts();
+        let snap = |ts: f64, m: &str, pos: f64, u: f64, r: f64| {
+            store
+                .insert_position_snapshot(ts, m, Some("e1"), pos, 0.5, 0.5, u, r)
+                .unwrap();
+        };
+        // m1: open position, pnl 1.0 before the window, 3.0 now.
+        snap(now - 7200.0, "m1", 10.0, 0.5, 0.5);
+        snap(now - 60.0, "m1", 10.0, 1.0, 1.5);
+        snap(now - 10.0, "m1", 10.0, 1.0, 2.0);
+        // m2: flat, realized only, first seen inside the window.
+        snap(now - 30.0, "m2", 0.0, 0.0, -2.5);
+        // m3: last activity before the window.
+        snap(now - 7200.0, "m3", 5.0, 4.0, 0.0);
+
+        let rows = store.fetch_pnl_by_market(3600.0).unwrap();
+        let ids: Vec<&str> = rows
+            .iter()
+            .map(|r| r["market_id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, vec!["m1", "m2"]);
+        assert_eq!(rows[0]["total_pnl"], 3.0);
+        assert_eq!(rows[0]["window_pnl"], 2.0);
+        assert_eq!(rows[1]["position"], 0.0);
+        assert_eq!(rows[1]["realized_pnl"], -2.5);
+        assert_eq!(rows[1]["total_pnl"], -2.5);
+        assert_eq!(rows[1]["window_pnl"], -2.5);
+    }
+
+    #[test]
+    fn pnl_by_event_sums_latest_snapshot_per_market() {
+        let store = temp_store();
+        let now = crate::utils::now_ts();
+        let snap = |ts: f64, m: &str, e: Option<&str>, pos: f64, u: f64, r: f64| {
+            store
+                .insert_position_snapshot(ts, m, e, pos, 0.5, 0.5, u, r)
+                .unwrap();
+        };
+        // e1: two legs; only each leg's latest snapshot counts.
+        snap(now - 7200.0, "m1", Some("e1"), 10.0, 0.5, 0.5);
+        snap(now - 10.0, "m1", Some("e1"), 10.0, 1.0, 2.0);
+        snap(now - 60.0, "m2", Some("e1"), -4.0, 0.5, 0.0);
+        snap(now - 20.0, "m2", Some("e1"), -4.0, -0.5, 1.0);
+        // e2: one leg in the window, one whose last activity is older.
+        snap(now - 30.0, "m3", Some("e2"), 2.0, 0.25, 0.0);
+        snap(now - 7200.0, "m4", Some("e2"), 5.0, 4.0, 0.0);
+        // No event id: never merged with each other.
+        snap(now - 15.0, "m5", None, 1.0, -1.0, 0.0);
+        snap(now - 15.0, "m6", None, 0.0, 0.0, -2.0);
+
+        let rows = store.fetch_pnl_by_event(3600.0).unwrap();
+        assert_eq!(rows.len(), 4);
+        let e1 = &rows[0];
+        assert_eq!(e1["event_id"], "e1");
+        assert_eq!(e1["markets"], serde_json::json!(["m1", "m2"]));
+        assert_eq!(e1["gross_position"], 14.0);
+        assert_eq!(e1["unrealized_pnl"], 0.5);
+        assert_eq!(e1["realized_pnl"], 3.0);
+        assert_eq!(e1["total_pnl"], 3.5);
+        // m1 had 1.0 before the window; m2 had nothing.
+        assert_eq!(e1["window_pnl"], 2.5);
+
+        assert_eq!(rows[1]["event_id"], "e2");
+        assert_eq!(rows[1]["market_count"], 1);
+        assert_eq!(rows[1]["total_pnl"], 0.25);
+
+        let unassigned: Vec<&JsonValue> = rows[2..].iter().collect();
+        assert!(unassigned.iter().all(|r| r["event_id"].is_null()));
+        assert_eq!(unassigned[0]["markets"], serde_json::json!(["m5"]));
+        assert_eq!(unassigned[1]["markets"], serde_json::json!(["m6"]));
+    }
+
+    #[test]
+    fn recently_closed_follows_close_then_reopen() {
+        let store = temp_store();
+        let now = crate::utils::now_ts();
+        let snap = |ts: f64, m: &str, pos: f64, r: f64| {
+            store
+                .insert_position_snapshot(ts, m, Some("e1"), pos, 0.5, 0.5, 0.0, r)
+                .unwrap();
+        };
+        // m1: closed, then reopened -> open again, not "closed".
+        snap(now - 300.0, "m1", 5.0, 0.0);
+        snap(now - 200.0, "m1", 0.0, 1.0);
+        snap(now - 100.0, "m1", 3.0, 1.0);
+        // m2: close, reopen, close again -> reported at the second close.
+        snap(now - 300.0, "m2", -4.0, 0.0);
+        snap(now - 250.0, "m2", 0.0, 0.5);
+        snap(now - 200.0, "m2", 2.0, 0.5);
+        snap(now - 50.0, "m2", 0.0, 1.25);
+        snap(now - 10.0, "m2", 0.0, 1.25);
+        // m3: never held a position.
+        snap(now - 20.0, "m3", 0.0, -0.1);
+        // m4: closed before the window.
+        snap(now - 9000.0, "m4", 1.0, 0.0);
+        snap(now - 8000.0, "m4", 0.0, 0.3);
+
+        let rows = store.fetch_recently_closed(10, 3600.0).unwrap();
+        assert_eq!(rows.len(), 1, "{rows:?}");
+        assert_eq!(rows[0]["market_id"], "m2");
+        assert_eq!(rows[0]["realized_pnl"], 1.25);
+        assert_eq!(rows[0]["closed_ts"], now - 50.0);
+        assert_eq!(rows[0]["closed_position"], 2.0);
+
+        assert_eq!(store.fetch_recently_closed(10, 10_000.0).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn orders_page_through_history_with_cursor() {
+        let store = temp_store();
+        let meta = serde_json::json!({});
+        // o2/o3 share a timestamp: the order_id tiebreak keeps paging stable.
+        for (id, ts) in [
+            ("o1", 100.0),
+            ("o2", 200.0),
+            ("o3", 200.0),
+            ("o4", 300.0),
+            ("o5", 400.0),
+        ] {
+            store
+                .insert_order(id, "m1", "buy", 0.5, 10.0, ts, "open", 0.0, &meta)
+                .unwrap();
+        }
+
+        let (page1, cursor) = store.fetch_orders_page(3, None, None).unwrap();
+        let ids = |rows: &[JsonValue]| {
+            rows.iter()
+                .map(|r| r["order_id"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&page1), ["o5", "o4", "o3"]);
+        let cursor = cursor.expect("more history");
+        assert!(!cursor.contains("o3"), "cursor should be opaque: {cursor}");
+
+        // A newer order arriving between requests does not shift the next page.
+        store
+            .insert_order("o6", "m1", "buy", 0.5, 10.0, 500.0, "open", 0.0, &meta)
+            .unwrap();
+        let (page2, next) = store.fetch_orders_page(3, None, Some(&cursor)).unwrap();
+        assert_eq!(ids(&page2), ["o2", "o1"]);
+        assert!(next.is_none());
+
+        assert!(store.fetch_orders_page(3, None, Some("zz")).is_err());
+    }
+
+    #[test]
+    fn order_transitions_record_cancel_and_fill_times_once() {
+        let store = temp_store();
+        let meta = serde_json::json!({});
+        store
+            .insert_order("o1", "m1", "buy", 0.5, 10.0, 100.0, "open", 0.0, &meta)
+            .unwrap();
+        store
+            .update_order_status("o1", "cancelled", Some(0.0), 101.5)
+            .unwrap();
+        // A repeated terminal update and a re-insert keep the first cancel time.
+        store
+            .update_order_status("o1", "cancelled", Some(0.0), 130.0)
+            .unwrap();
+        store
+            .insert_order("o1", "m1", "buy", 0.5, 10.0, 100.0, "cancelled", 0.0, &meta)
+            .unwrap();
+        // IOC fills are inserted already filled.
+        store
+            .insert_order("o2", "m1", "sell", 0.5, 5.0, 200.0, "filled", 5.0, &meta)
+            .unwrap();
+        store
+            .insert_order("o3", "m1", "buy", 0.4, 5.0, 300.0, "open", 0.0, &meta)
+            .unwrap();
+        store
+            .update_order_status("o3", "filled", Some(5.0), 302.0)
+            .unwrap();
+
+        let rows = store.fetch_recent_orders(10, None).unwrap();
+        assert_eq!(rows.len(), 3);
+        let row = |id: &str| rows.iter().find(|r| r["order_id"] == id).unwrap().clone();
+        assert_eq!(row("o1")["cancelled_ts"], 101.5);
+        assert!(row("o1")["filled_ts"].is_null());
+        assert_eq!(row("o2")["filled_ts"], 200.0);
+        assert_eq!(row("o3")["filled_ts"], 302.0);
+        assert!(row("o3")["cancelled_ts"].is_null());
+        assert_eq!(store.fetch_session_stats().unwrap()["total_cancels"], 1);
+    }
+
+    #[test]
+    fn meta_and_tape_round_trip_in_both_encodings() {
+        let json_store = temp_store();
+        let meta = serde_json::json!({"strategy": "mm", "fair_at_fill": 0.51, "tags": [1, 2]});
+        json_store
+            .insert_fill("f1", "o1", "m1", "buy", 0.5, 10.0, 100.0, &meta)
+            .unwrap();
+        json_store.insert_tape(100.0, "m1", "book", &meta).unwrap();
+
+        // Same database, switched to msgpack: old JSON rows stay readable next to new ones.
+        let mp_store = json_store.clone().with_blob_encoding(BlobEncoding::Msgpack);
+        mp_store
+            .insert_fill("f2", "o2", "m1", "sell", 0.52, 5.0, 200.0, &meta)
+            .unwrap();
+        mp_store.insert_tape(200.0, "m1", "book", &meta).unwrap();
+        mp_store
+            .insert_order("o2", "m1", "sell", 0.52, 5.0, 200.0, "filled", 5.0, &meta)
+            .unwrap();
+
+        let conn = mp_store.open_conn().unwrap();
+        let kind: String = conn
+            .query_row(
+                "SELECT typeof(meta_json) FROM fills WHERE fill_id='f2'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(kind, "blob");
+
+        for store in [&json_store, &mp_store] {
+            let fills = store.fetch_recent_fills(10).unwrap();
+            assert_eq!(fills.len(), 2);
+            assert!(fills.iter().all(|f| f["meta"] == meta));
+            let tape = store.fetch_tape("book", (None, None), 10).unwrap();
+            assert!(tape.iter().all(|t| t["payload"] == meta));
+            assert_eq!(
+                store.fetch_recent_orders(10, None).unwrap()[0]["meta"],
+                meta
+            );
+        }
+        let hist = mp_store.fetch_fill_markout_histogram(0.01, 1e12).unwrap();
+        assert_eq!(hist["total"], 2);
+    }
+
+    #[test]
+    fn fill_realism_flags_unbacked_fills_and_missed_trades() {
+        let store = temp_store();
+        let t0 = crate::utils::now_ts() - 600.0;
+        let quote = |ts: f64, market: &str, bid: f64, ask: f64| {
+            store
+                .insert_quote_snapshot(
+                    ts,
+                    market,
+                    Some("e1"),
+                    Some(bid),
+                    Some(ask),
+                    Some(0.5 * (bid + ask)),
+                    Some(0.5 * (bid + ask)),
+                    "mid",
+                    0.0,
+                    ask - bid,
+                    0.0,
+                    Some(bid),
+                    Some(ask),
+                    None,
+                    None,
+                )
+                .unwrap();
+        };
+        let fill = |id: &str, market: &str, side: &str, px: f64, ts: f64| {
+            store
+                .insert_fill(id, "o", market, side, px, 1.0, ts, &serde_json::json!({}))
+                .unwrap();
+        };
+        let trade = |market: &str, px: f64, ts: f64| {
+            store
+                .insert_tape(ts, market, "trade", &serde_json::json!({"price": px}))
+                .unwrap();
+        };
+
+        // m1: our bid filled and a public trade printed through it right after.
+        quote(t0, "m1", 0.49, 0.51);
+        fill("f1", "m1", "buy", 0.49, t0 + 1.0);
+        trade("m1", 0.48, t0 + 2.0);
+        // m2: a sell fill with no print nearby, then a print through our ask with no fill.
+        fill("f2", "m2", "sell", 0.60, t0 + 1.0);
+        quote(t0 + 18.0, "m2", 0.56, 0.58);
+        trade("m2", 0.60, t0 + 20.0);
+
+        let r = store.fetch_fill_realism(5.0, 3600.0).unwrap();
+        assert_eq!(r["paper_fills"], 2);
+        assert_eq!(r["unmatched_fills"], 1);
+        assert_eq!(r["unmatched_examples"][0]["fill_id"], "f2");
+        assert_eq!(r["tape_trades"], 2);
+        assert_eq!(r["missed_trades"], 1);
+        assert_eq!(r["missed_examples"][0]["market_id"], "m2");
+
+        // A wider window pairs the m2 fill with the later print.
+        let wide = store.fetch_fill_realism(30.0, 3600.0).unwrap();
+        assert_eq!(wide["unmatched_fills"], 0);
+        assert_eq!(wide["missed_trades"], 0);
+    }
+
+    #[test]
+    fn wal_checkpoint_runs_and_keeps_db_consistent() {
+        let store = temp_store().with_wal_autocheckpoint(0);
+        let conn = store.open_conn().unwrap();
+        let pages: i64 = conn
+            .query_row("PRAGMA wal_autocheckpoint", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(pages, 0);
+
+        for i in 0..50 {
+            store
+                .insert_fill(
+                    &format!("f{i}"),
+                    "o1",
+                    "m1",
+                    "buy",
+                    0.5,
+                    1.0,
+                    i as f64,
+                    &serde_json::json!({}),
+                )
+                .unwrap();
+        }
+        let passive = store.wal_checkpoint(false).unwrap();
+        assert!(!passive.busy);
+        assert!(passive.log_frames > 0);
+        assert_eq!(passive.checkpointed_frames, passive.log_frames);
+
+        let truncated = store.wal_checkpoint(true).unwrap();
+        assert!(!truncated.busy);
+        assert_eq!(truncated.log_frames, 0);
+
+        let ok: String = conn
+            .query_row("PRAGMA integrity_check", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(ok, "ok");
+        assert_eq!(store.fetch_recent_fills(100).unwrap().len(), 50);
+    }
+
+    #[test]
+    fn position_snapshot_with_null_event_id_round_trips() {
+        let store = temp_store();
+        let ts = crate::utils::now_ts();
+        for m in ["m1", "m2"] {
+            store
+                .insert_position_snapshot(ts, m, None, 1.0, 0.5, 0.5, 0.0, 0.0)
+                .unwrap();
+        }
+        // Fill rows carry the previous row's event over: still NULL, never a placeholder.
+        store
+            .insert_fill_position(ts + 1.0, "m1", 2.0, 0.5, 0.5, 0.0, 0.0)
+            .unwrap();
+
+        let latest = store.fetch_latest_positions(10).unwrap();
+        assert_eq!(latest.len(), 2);
+        assert!(latest.iter().all(|r| r["event_id"].is_null()));
+
+        // Unknown-event markets stay separate rows, not one "unknown" bucket.
+        let by_market = store.fetch_pnl_by_market(3600.0).unwrap();
+        let mut ids: Vec<&str> = by_market
+            .iter()
+            .map(|r| r["market_id"].as_str().unwrap())
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["m1", "m2"]);
+        assert!(by_market.iter().all(|r| r["event_id"].is_null()));
+    }
+
+    #[test]
+    fn session_stats_count_fills_cancels_and_round_trip_wins() {
+        let store = temp_store();
+        let empty = store.fetch_session_stats().unwrap();
+        assert_eq!(empty["total_fills"], 0);
+        assert!(empty["win_rate"].is_null());
+
+        let fill = |n: u32, m: &str, side: &str, px: f64, sz: f64| {
+            store
+                .insert_fill(
+                    &format!("f{n}"),
+                    "o",
+                    m,
+                    side,
+                    px,
+                    sz,
+                    n as f64,
+                    &serde_json::json!({}),
+                )
+                .unwrap();
+        };
+        // m1: long 20 at 0.45 sold 25 at 0.55 (+2.0, flips short 5), bought back at 0.60 (-0.25).
+        fill(1, "m1", "buy", 0.40, 10.0);
+        fill(2, "m1", "buy", 0.50, 10.0);
+        fill(3, "m1", "sell", 0.55, 25.0);
+        fill(4, "m1", "buy", 0.60, 5.0);
+        // m2: scratch round trip (not a win); m3: still open.
+        fill(5, "m2", "buy", 0.50, 4.0);
+        fill(6, "m2", "sell", 0.50, 4.0);
+        fill(7, "m3", "buy", 0.20, 3.0);
+        for (id, status) in [("o1", "cancelled"), ("o2", "cancelled"), ("o3", "open")] {
+            store
+                .insert_order(
+                    id,
+                    "m1",
+                    "buy",
+                    0.5,
+                    1.0,
+                    0.0,
+                    status,
+                    0.0,
+                    &serde_json::json!({}),
+                )
+                .unwrap();
+        }
+
+        let stats = store.fetch_session_stats().unwrap();
+        assert_eq!(stats["total_fills"], 7);
+        assert_eq!(stats["total_cancels"], 2);
+        assert!((stats["total_notional"].as_f64().unwrap() - 30.35).abs() < 1e-9);
+        assert_eq!(stats["round_trips"], 3);
+        assert_eq!(stats["round_trip_wins"], 1);
+        assert!((stats["win_rate"].as_f64().unwrap() - 1.0 / 3.0).abs() < 1e-9);
+        assert!((stats["round_trip_pnl"].as_f64().unwrap() - 1.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn busy_writes_succeed_after_retry_and_real_errors_are_not_retried() {
+        let code = |c| {
+            anyhow::Error::from(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(c),
+                None,
+            ))
+        };
+
+        // Busy, then locked, then through.
+        let mut attempts = 0;
+        let out = retry_locked(3, || {
+            attempts += 1;
+            match attempts {
+                1 => Err(code(rusqlite::ffi::SQLITE_BUSY)),
+                2 => Err(code(rusqlite::ffi::SQLITE_LOCKED).context("insert fill")),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(out.unwrap(), 3);
+
+        // Retries are bounded.
+        let mut attempts = 0;
+        let out: Result<()> = retry_locked(2, || {
+            attempts += 1;
+            Err(code(rusqlite::ffi::SQLITE_BUSY))
+        });
+        assert!(out.is_err());
+        assert_eq!(attempts, 3);
+
+        // A genuine error surfaces on the first attempt.
+        let store = temp_store().with_write_retries(5);
+        store
+            .insert_order(
+                "o1",
+                "m1",
+                "buy",
+                0.5,
+                1.0,
+                0.0,
+                "open",
+                0.0,
+                &serde_json::json!({}),
+            )
+            .unwrap();
+        let mut attempts = 0;
+        let err = store
+            .write(|conn| {
+                attempts += 1;
+                conn.execute("INSERT INTO orders(order_id) VALUES('o1')", [])?;
+                Ok(())
+            })
+            .unwrap_err();
+        assert!(!is_locked(&err));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn a_retried_write_does_not_reapply_its_earlier_statements() {
+        let store = temp_store().with_write_retries(2);
+        let mut attempts = 0;
+        store
+            .write(|conn| {
+                attempts += 1;
+                conn.execute(
+                    "INSERT INTO tape(ts, market_id, kind, payload_json) VALUES(1.0, 'm1', 'trade', '{}')",
+                    [],
+                )?;
+                if attempts == 1 {
+                    return Err(rusqlite::Error::SqliteFailure(
+                        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+                        None,
+                    )
+                    .into());
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(attempts, 2);
+        assert_eq!(
+            store.fetch_tape("trade", (None, None), 10).unwrap().len(),
+            1
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn write_backoff_runs_on_a_multi_threaded_runtime() {
+        let mut attempts = 0;
+        let out = retry_locked(1, || {
+            attempts += 1;
+            if attempts == 1 {
+                Err(rusqlite::Error::SqliteFailure(
+                    rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+                    None,
+                )
+                .into())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(out.unwrap(), 2);
+    }
+
+    #[test]
+    fn trader_writes_are_not_starved_by_a_large_scanner_upsert() {
+        let store = temp_store().with_bulk_chunk_rows(50);
+        let total = 20_000;
+        let candidates: Vec<ScannerCandidate> = (0..total)
+            .map(|i| ScannerCandidate {
+                market_id: format!("m{i}"),
+                score: 1.0,
+                volume_24h_usd: 0.0,
+                liquidity_usd: 0.0,
+                spread_bps: 0.0,
+                excluded_reason: None,
+            })
+            .collect();
+        let count = |store: &SqliteStore| -> i64 {
+            store
+                .open_conn()
+                .unwrap()
+                .query_row("SELECT COUNT(*) FROM scanner_candidates", [], |r| r.get(0))
+                .unwrap()
+        };
+
+        let scanner = store.background();
+        let bulk =
+            std::thread::spawn(move || scanner.insert_scanner_candidates(1.0, &candidates, 60.0));
+        while count(&store) == 0 {
+            std::thread::yield_now();
+        }
+
+        // Each trader write waits for at most the chunk in flight, not the whole refresh.
+        for i in 0..20 {
+            store
+                .insert_order(
+                    &format!("o{i}"),
+                    "m1",
+                    "buy",
+                    0.5,
+                    1.0,
+                    i as f64,
+                    "open",
+                    0.0,
+                    &serde_json::json!({}),
+                )
+                .unwrap();
+        }
+        assert_eq!(store.fetch_recent_orders(50, None).unwrap().len(), 20);
+        assert!(
+            count(&store) < total,
+            "trader writes waited for the whole scanner upsert"
+        );
+
+        bulk.join().unwrap().unwrap();
+        assert_eq!(count(&store), total);
     }
 }
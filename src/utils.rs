@@ -1,6 +1,9 @@
 use rand::Rng;
 use rand_distr::{Distribution, Poisson};
 
+/// Wall-clock unix seconds, for timestamps that are persisted, shown or compared with
+/// exchange/feed times. It can step backward or forward (NTP), so intervals measured
+/// inside the process use [`mono_ts`] instead.
 pub fn now_ts() -> f64 {
     let now = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -8,22 +11,87 @@ pub fn now_ts() -> f64 {
     now.as_secs_f64()
 }
 
+/// Monotonic seconds since the first call in this process (`Instant`-based): never goes
+/// backward, meaningless outside the process. Use for intervals, never persist it.
+pub fn mono_ts() -> f64 {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START
+        .get_or_init(std::time::Instant::now)
+        .elapsed()
+        .as_secs_f64()
+}
+
 pub fn clamp(x: f64, lo: f64, hi: f64) -> f64 {
     x.max(lo).min(hi)
 }
 
+pub fn ewma(prev: Option<f64>, x: f64, alpha: f64) -> f64 {
+    match prev {
+        None => x,
+        Some(p) => alpha * x + (1.0 - alpha) * p,
+    }
+}
+
+/// Percentiles of `xs` (each `p` in 0..=100, clamped) by linear interpolation between
+/// closest ranks: rank = p/100 * (n-1), as numpy's default. Sorts `xs` in place, so the
+/// input need not be pre-sorted. Returns an empty Vec for empty input.
+pub fn percentiles(xs: &mut [f64], ps: &[f64]) -> Vec<f64> {
+    if xs.is_empty() {
+        return vec![];
+    }
+    xs.sort_by(f64::total_cmp);
+    let last = (xs.len() - 1) as f64;
+    ps.iter()
+        .map(|p| {
+            let rank = clamp(*p, 0.0, 100.0) / 100.0 * last;
+            let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+            xs[lo] + (xs[hi] - xs[lo]) * (rank - lo as f64)
+        })
+        .collect()
+}
+
+/// Decimal places needed to write `tick` exactly (0.01 -> 2, 0.001 -> 3, 0.0005 -> 4).
+pub fn tick_decimals(tick: f64) -> u32 {
+    if !tick.is_finite() || tick <= 0.0 {
+        return 6;
+    }
+    (0..=10)
+        .find(|d| {
+            let scaled = tick * 10f64.powi(*d as i32);
+            (scaled - scaled.round()).abs() < 1e-9
+        })
+        .unwrap_or(10)
+}
+
+/// Round to `decimals` places, leaving the nearest f64 to that decimal (no `0.47000000000000003`).
+pub fn round_dp(x: f64, decimals: u32) -> f64 {
+    if !x.is_finite() {
+        return x;
+    }
+    let scale = 10f64.powi(decimals as i32);
+    (x * scale).round() / scale
+}
+
+/// Snap `price` to the nearest multiple of `tick`, written with the tick's precision.
 pub fn round_to_tick(price: f64, tick: f64) -> f64 {
-    if tick <= 0.0 {
+    if !price.is_finite() || !tick.is_finite() || tick <= 0.0 {
         return price;
     }
-    (price / tick).round() * tick
+    round_dp((price / tick).round() * tick, tick_decimals(tick))
 }
 
-pub fn ewma(prev: Option<f64>, x: f64, alpha: f64) -> f64 {
-    match prev {
-        None => x,
-        Some(p) => alpha * x + (1.0 - alpha) * p,
+/// Snap `price` down (`up == false`) or up to a multiple of `tick`. Prices already on the
+/// grid up to float noise stay put.
+pub fn snap_to_tick(price: f64, tick: f64, up: bool) -> f64 {
+    if !price.is_finite() || !tick.is_finite() || tick <= 0.0 {
+        return price;
     }
+    let steps = if up {
+        (price / tick - 1e-9).ceil()
+    } else {
+        (price / tick + 1e-9).floor()
+    };
+    round_dp(steps * tick, tick_decimals(tick))
 }
 
 pub fn poisson_sample(rng: &mut impl Rng, lambda: f64) -> u64 {
@@ -33,3 +101,108 @@ pub fn poisson_sample(rng: &mut impl Rng, lambda: f64) -> u64 {
     let d = Poisson::new(lambda.max(0.0)).unwrap();
     d.sample(rng) as u64
 }
+
+/// Time-decayed event rate. Each event adds one to a count that decays as
+/// `exp(-dt / window_secs)`, so under steady arrivals `per_min` sits near `60 / gap`
+/// and once events stop it falls toward zero. Timestamps older than the latest event
+/// count as arriving at the latest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateTracker {
+    window_secs: f64,
+    count: f64,
+    last_ts: Option<f64>,
+}
+
+impl RateTracker {
+    pub fn new(window_secs: f64) -> Self {
+        Self {
+            window_secs: window_secs.max(1e-3),
+            count: 0.0,
+            last_ts: None,
+        }
+    }
+
+    pub fn record(&mut self, ts: f64) {
+        self.count = self.decayed(ts) + 1.0;
+        self.last_ts = Some(self.last_ts.map_or(ts, |t| t.max(ts)));
+    }
+
+    /// Events per minute as of `now`.
+    pub fn per_min(&self, now: f64) -> f64 {
+        self.decayed(now) * 60.0 / self.window_secs
+    }
+
+    fn decayed(&self, now: f64) -> f64 {
+        match self.last_ts {
+            None => 0.0,
+            Some(t) => self.count * (-(now - t).max(0.0) / self.window_secs).exp(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_interpolate_unsorted_input() {
+        let mut xs = vec![15.0, 20.0, 35.0, 40.0, 50.0];
+        xs.reverse();
+        assert_eq!(
+            percentiles(&mut xs, &[0.0, 25.0, 40.0, 50.0, 100.0]),
+            vec![15.0, 20.0, 29.0, 35.0, 50.0]
+        );
+        assert_eq!(xs, vec![15.0, 20.0, 35.0, 40.0, 50.0]);
+        assert_eq!(percentiles(&mut [7.0], &[50.0, 99.0]), vec![7.0, 7.0]);
+        assert!(percentiles(&mut [], &[50.0]).is_empty());
+    }
+
+    #[test]
+    fn prices_snap_to_tick_without_float_noise() {
+        assert_eq!(tick_decimals(0.01), 2);
+        assert_eq!(tick_decimals(0.001), 3);
+        assert_eq!(tick_decimals(0.0005), 4);
+        assert_eq!(round_to_tick(47.0 * 0.01, 0.01), 0.47);
+        assert_eq!(round_to_tick(0.4712, 0.01), 0.47);
+        assert_eq!(round_to_tick(0.4716, 0.001), 0.472);
+        assert_eq!(round_to_tick(0.12345, 0.0005), 0.1235);
+        assert_eq!(snap_to_tick(0.4716, 0.01, false), 0.47);
+        assert_eq!(snap_to_tick(0.4712, 0.01, true), 0.48);
+        assert_eq!(snap_to_tick(47.0 * 0.01, 0.01, true), 0.47);
+        assert_eq!(round_dp(0.505 + 1e-12, 3), 0.505);
+        assert!(round_to_tick(f64::NAN, 0.01).is_nan());
+    }
+
+    #[test]
+    fn rate_tracker_follows_bursts_and_decays_through_gaps() {
+        let mut r = RateTracker::new(60.0);
+        assert_eq!(r.per_min(0.0), 0.0);
+
+        // Steady 1/s for ten windows: ~60/min.
+        for i in 0..600 {
+            r.record(i as f64);
+        }
+        assert!(
+            (r.per_min(599.5) - 60.0).abs() < 1.0,
+            "{}",
+            r.per_min(599.5)
+        );
+
+        // A gap: one window later the rate has decayed by e, five windows later it is ~0.
+        let at_stop = r.per_min(599.0);
+        assert!((r.per_min(659.0) - at_stop / std::f64::consts::E).abs() < 1e-9);
+        assert!(r.per_min(899.0) < 0.5);
+        assert!(r.per_min(10_000.0) < 1e-6);
+
+        // A burst of 30 events at once reads as 30 per 60s window, then halves in 60 ln 2.
+        let mut b = RateTracker::new(60.0);
+        for _ in 0..30 {
+            b.record(100.0);
+        }
+        assert!((b.per_min(100.0) - 30.0).abs() < 1e-9);
+        assert!((b.per_min(100.0 + 60.0 * std::f64::consts::LN_2) - 15.0).abs() < 1e-9);
+        // A late (out-of-order) event does not move the clock backward.
+        b.record(50.0);
+        assert!((b.per_min(100.0) - 31.0).abs() < 1e-9);
+    }
+}